@@ -0,0 +1,107 @@
+//! Measures the parsing of a large `cargo metadata` output.
+//!
+//! Run through `cargo bench --bench parse --features test-util`. Parsing the output into a
+//! `serde_json::Value` tree, which is what earlier versions of this crate did, is compared with
+//! the typed parser. Lookups through a [`Locator`] borrow the strings from the output, while
+//! [`Metadata::parse`] copies them into the returned value. Besides the time per run, the
+//! number of heap allocations is reported.
+//!
+//! The output is generated through `fixtures::dependency_chain` by default. To measure the
+//! output of a real project instead, save it with `cargo metadata --format-version 1` and pass
+//! its path in the `BOOTLOADER_LOCATOR_BENCH_METADATA` environment variable. The lookup is only
+//! measured for the generated output, since it searches for one of the generated packages.
+
+use bootloader_locator::{fixtures, invoker::MockInvoker, Context, Locator, Metadata};
+use serde_json::Value;
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    env, fs,
+    hint::black_box,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+/// The number of packages of the generated output, about the size of a large workspace.
+const PACKAGES: usize = 2000;
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Runs the given function repeatedly and reports the average time and allocations per run.
+fn measure(name: &str, mut f: impl FnMut()) {
+    const MIN_DURATION: Duration = Duration::from_secs(2);
+    f();
+    let allocations_before = ALLOCATIONS.load(Ordering::Relaxed);
+    f();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations_before;
+
+    let start = Instant::now();
+    let mut runs = 0;
+    while start.elapsed() < MIN_DURATION {
+        f();
+        runs += 1;
+    }
+    println!(
+        "{:<30} {:>10.2?} per run, {:>7} allocations per run",
+        name,
+        start.elapsed() / runs,
+        allocations
+    );
+}
+
+fn main() {
+    if let Some(path) = env::var_os("BOOTLOADER_LOCATOR_BENCH_METADATA") {
+        let output = fs::read_to_string(path).unwrap();
+        println!("parsing {} KiB of JSON", output.len() / 1024);
+        measure_parse(&output);
+        return;
+    }
+
+    let output = fixtures::dependency_chain(PACKAGES).to_string();
+    println!(
+        "parsing {} packages ({} KiB of JSON)",
+        PACKAGES,
+        output.len() / 1024
+    );
+    measure_parse(&output);
+
+    let mock = MockInvoker::new();
+    mock.output(output.as_str());
+    let mut context = Context::from_env();
+    context.invoker(mock);
+    let mut locator = Locator::new("package-1");
+    locator
+        .manifest_path("/project/Cargo.toml")
+        .context(context);
+    // the mock copies the output for every lookup, which is measured separately
+    measure("copying the output", || {
+        black_box(black_box(&output).clone());
+    });
+    measure("Locator::locate", || {
+        black_box(locator.locate().unwrap());
+    });
+}
+
+fn measure_parse(output: &str) {
+    measure("serde_json::Value", || {
+        black_box(serde_json::from_str::<Value>(black_box(output)).unwrap());
+    });
+    measure("Metadata::parse", || {
+        black_box(Metadata::parse(black_box(output)).unwrap());
+    });
+}
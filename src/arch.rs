@@ -0,0 +1,109 @@
+//! The target architectures of the build, image, and run pipelines.
+//!
+//! The [`build`](crate::build), [`image`](crate::image), and [`runner`](crate::runner) modules
+//! default to x86_64, the only architecture that the `bootloader` crate supports. Selecting a
+//! different [`Arch`] changes the defaults that depend on the architecture, e.g. the QEMU
+//! executable, so that bootloaders for other architectures can use the same pipelines.
+
+use crate::ParseValueError;
+use std::{fmt, str};
+
+/// A target architecture of a bootloader.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Arch {
+    /// The 64-bit x86 architecture. This is the default.
+    #[default]
+    X86_64,
+    /// The 64-bit ARM architecture.
+    Aarch64,
+    /// The 64-bit RISC-V architecture.
+    Riscv64,
+}
+
+impl Arch {
+    /// Returns the name of the architecture as used in target triples, e.g. `x86_64`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Arch::X86_64 => "x86_64",
+            Arch::Aarch64 => "aarch64",
+            Arch::Riscv64 => "riscv64",
+        }
+    }
+
+    /// Returns the QEMU executable that emulates the architecture, e.g. `qemu-system-x86_64`.
+    pub fn qemu_binary(self) -> &'static str {
+        match self {
+            Arch::X86_64 => "qemu-system-x86_64",
+            Arch::Aarch64 => "qemu-system-aarch64",
+            Arch::Riscv64 => "qemu-system-riscv64",
+        }
+    }
+
+    /// Returns the file name of the target specification that bootloaders for the architecture
+    /// ship in their package directory, e.g. `x86_64-bootloader.json`.
+    pub fn bootloader_target(self) -> String {
+        format!("{}-bootloader.json", self.name())
+    }
+
+    /// Returns the object format of executables for the architecture, as passed to
+    /// `objcopy -I`, e.g. `elf64-x86-64`.
+    pub fn objcopy_input_format(self) -> &'static str {
+        match self {
+            Arch::X86_64 => "elf64-x86-64",
+            Arch::Aarch64 => "elf64-littleaarch64",
+            Arch::Riscv64 => "elf64-littleriscv",
+        }
+    }
+
+    /// Returns the architecture name of `objcopy`, as passed to `--binary-architecture`, e.g.
+    /// `i386:x86-64`.
+    pub fn objcopy_binary_architecture(self) -> &'static str {
+        match self {
+            Arch::X86_64 => "i386:x86-64",
+            Arch::Aarch64 => "aarch64",
+            Arch::Riscv64 => "riscv:rv64",
+        }
+    }
+}
+
+impl fmt::Display for Arch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Parses the [`name`](Arch::name) of an architecture, e.g. `x86_64`.
+impl str::FromStr for Arch {
+    type Err = ParseValueError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "x86_64" => Ok(Arch::X86_64),
+            "aarch64" => Ok(Arch::Aarch64),
+            "riscv64" => Ok(Arch::Riscv64),
+            _ => Err(ParseValueError::new(s, &["x86_64", "aarch64", "riscv64"])),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_display_names() {
+        for arch in [Arch::X86_64, Arch::Aarch64, Arch::Riscv64] {
+            assert_eq!(arch.to_string().parse::<Arch>().unwrap(), arch);
+        }
+        assert!("x86-64".parse::<Arch>().is_err());
+    }
+
+    #[test]
+    fn defaults_match_the_x86_64_bootloader() {
+        let arch = Arch::default();
+        assert_eq!(arch.qemu_binary(), "qemu-system-x86_64");
+        assert_eq!(arch.bootloader_target(), "x86_64-bootloader.json");
+        assert_eq!(arch.objcopy_input_format(), "elf64-x86-64");
+        assert_eq!(arch.objcopy_binary_architecture(), "i386:x86-64");
+    }
+}
@@ -0,0 +1,412 @@
+//! Locates the executables produced by `cargo build` through its JSON messages.
+//!
+//! The paths of build artifacts depend on the target directory, the compilation target, and
+//! the profile, some of which can be set through cargo configuration files. Instead of
+//! computing the paths, the [`ArtifactLocator`] runs `cargo build --message-format json` and
+//! collects the executable paths that cargo reports in its `compiler-artifact` messages.
+
+use crate::{build::absolute, process, Context, PackageId};
+use serde::Deserialize;
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    convert, fmt, io,
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::Duration,
+};
+
+/// Builds the binaries of a package and returns the paths of the produced executables.
+///
+/// ```no_run
+/// use bootloader_locator::{artifacts::ArtifactLocator, locate_bootloader};
+///
+/// let bootloader_manifest = locate_bootloader("bootloader")?;
+/// let executables = ArtifactLocator::new(bootloader_manifest)
+///     .targets(["x86_64-unknown-none"])
+///     .profile("release")
+///     .locate()?;
+/// for executable in executables.iter() {
+///     println!("{} ({:?}, {}): {}", executable.name, executable.target, executable.profile,
+///         executable.path.display());
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct ArtifactLocator {
+    manifest_path: PathBuf,
+    bins: Vec<String>,
+    targets: Vec<String>,
+    profile: Option<String>,
+    target_dir: Option<PathBuf>,
+    args: Vec<String>,
+    context: Option<Context>,
+}
+
+impl ArtifactLocator {
+    /// Creates a locator for the executables of the package with the given manifest.
+    pub fn new(manifest_path: impl Into<PathBuf>) -> Self {
+        ArtifactLocator {
+            manifest_path: manifest_path.into(),
+            bins: Vec::new(),
+            targets: Vec::new(),
+            profile: None,
+            target_dir: None,
+            args: Vec::new(),
+            context: None,
+        }
+    }
+
+    /// Restricts the build to the binary targets with the given names, passed as `--bin`.
+    ///
+    /// By default, all binaries of the package are built.
+    pub fn bins<I, S>(&mut self, bins: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.bins = bins.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Sets the compilation targets, passed as `--target`.
+    ///
+    /// Both target triples and paths to target specification files are supported. By default,
+    /// cargo builds for the `build.target` of its configuration or for the host.
+    pub fn targets<I, S>(&mut self, targets: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.targets = targets.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Sets the profile, passed as `--profile`, e.g. `release`.
+    ///
+    /// Defaults to the `dev` profile.
+    pub fn profile(&mut self, profile: impl Into<String>) -> &mut Self {
+        self.profile = Some(profile.into());
+        self
+    }
+
+    /// Sets the directory for the build artifacts, passed as `--target-dir`.
+    pub fn target_dir(&mut self, target_dir: impl Into<PathBuf>) -> &mut Self {
+        self.target_dir = Some(target_dir.into());
+        self
+    }
+
+    /// Sets additional arguments that are passed to `cargo build`, e.g. `-Zbuild-std=core`.
+    pub fn args<I, S>(&mut self, args: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args = args.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Sets the context that is used for running `cargo build`.
+    ///
+    /// The feature options of the context are passed to the build. Defaults to
+    /// [`Context::from_env`], evaluated when the build runs.
+    pub fn context(&mut self, context: Context) -> &mut Self {
+        self.context = Some(context);
+        self
+    }
+
+    /// Runs the build and returns the executables reported by cargo.
+    pub fn locate(&self) -> Result<Executables, ArtifactError> {
+        let context = match &self.context {
+            Some(context) => Cow::Borrowed(context),
+            None => Cow::Owned(Context::from_env()),
+        };
+        let manifest_path = absolute(&self.manifest_path)?;
+
+        let mut cmd = context.cargo_command("build");
+        cmd.arg("--message-format").arg("json");
+        cmd.arg("--manifest-path").arg(&manifest_path);
+        for bin in &self.bins {
+            cmd.arg("--bin").arg(bin);
+        }
+        for target in &self.targets {
+            cmd.arg("--target").arg(target);
+        }
+        if let Some(profile) = &self.profile {
+            cmd.arg("--profile").arg(profile);
+        }
+        if let Some(target_dir) = &self.target_dir {
+            cmd.arg("--target-dir").arg(absolute(target_dir)?);
+        }
+        if !context.features.is_empty() {
+            cmd.arg("--features").arg(context.features.join(","));
+        }
+        if context.all_features {
+            cmd.arg("--all-features");
+        }
+        if context.no_default_features {
+            cmd.arg("--no-default-features");
+        }
+        cmd.args(&self.args);
+        // cargo looks up its configuration (e.g. `.cargo/config.toml`) starting from the working
+        // directory
+        if let Some(package_dir) = manifest_path.parent() {
+            cmd.current_dir(package_dir);
+        }
+        cmd.stdin(Stdio::null());
+        cmd.stdout(Stdio::piped());
+        cmd.stderr(Stdio::piped());
+
+        let output = match process::capture(cmd.spawn()?, &context)? {
+            Some(output) => output,
+            None => {
+                return Err(ArtifactError::TimedOut {
+                    timeout: context.timeout.unwrap_or_default(),
+                })
+            }
+        };
+        if !output.status.success() {
+            return Err(ArtifactError::Failed {
+                stderr: output.stderr,
+            });
+        }
+        Executables::from_messages(&String::from_utf8_lossy(&output.stdout), &self.targets)
+    }
+}
+
+/// The executables reported by a `cargo build` run, keyed by target and profile.
+///
+/// Returned by [`ArtifactLocator::locate`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Executables {
+    executables: BTreeMap<(Option<String>, String, String), Executable>,
+}
+
+impl Executables {
+    /// Collects the executables from the JSON messages of `cargo build --message-format json`.
+    ///
+    /// The given targets are the values passed as `--target` to the build, which are used for
+    /// assigning the executables to their target. Executables of other targets, e.g. of a
+    /// `build.target` configuration, are detected from the layout of the target directory.
+    /// Lines that are not JSON objects are ignored.
+    pub fn from_messages<S: AsRef<str>>(
+        stdout: &str,
+        targets: &[S],
+    ) -> Result<Self, ArtifactError> {
+        let targets: Vec<String> = targets
+            .iter()
+            .map(|target| target_dir_name(target.as_ref()))
+            .collect();
+        let mut executables = BTreeMap::new();
+        for line in stdout.lines().filter(|line| line.starts_with('{')) {
+            let message: Message =
+                serde_json::from_str(line).map_err(|err| ArtifactError::InvalidOutput {
+                    message: err.to_string(),
+                })?;
+            if message.reason != "compiler-artifact" {
+                continue;
+            }
+            let (package_id, target, path) =
+                match (message.package_id, message.target, message.executable) {
+                    (Some(package_id), Some(target), Some(path)) => (package_id, target, path),
+                    _ => continue,
+                };
+            if !target.kind.iter().any(|kind| kind == "bin") {
+                continue;
+            }
+            let package_id =
+                package_id
+                    .parse::<PackageId>()
+                    .map_err(|err| ArtifactError::InvalidOutput {
+                        message: err.to_string(),
+                    })?;
+            let (compile_target, profile) = classify(&path, &targets);
+            let executable = Executable {
+                package_id,
+                name: target.name,
+                target: compile_target,
+                profile,
+                path,
+            };
+            let key = (
+                executable.target.clone(),
+                executable.profile.clone(),
+                executable.name.clone(),
+            );
+            executables.insert(key, executable);
+        }
+        Ok(Executables { executables })
+    }
+
+    /// Returns the path of the executable with the given name for the given target and profile
+    /// directory.
+    ///
+    /// The target is a target triple, the path of a target specification file, or `None` for
+    /// host builds. The profile is the name of the profile directory, e.g. `debug` for the
+    /// `dev` profile.
+    pub fn get(&self, target: Option<&str>, profile: &str, name: &str) -> Option<&Path> {
+        let key = (
+            target.map(target_dir_name),
+            profile.to_owned(),
+            name.to_owned(),
+        );
+        self.executables
+            .get(&key)
+            .map(|executable| executable.path.as_path())
+    }
+
+    /// Returns all executables, sorted by target, profile, and name.
+    pub fn iter(&self) -> impl Iterator<Item = &Executable> {
+        self.executables.values()
+    }
+
+    /// Returns the number of executables.
+    pub fn len(&self) -> usize {
+        self.executables.len()
+    }
+
+    /// Returns whether no executables were reported.
+    pub fn is_empty(&self) -> bool {
+        self.executables.is_empty()
+    }
+}
+
+/// An executable produced by `cargo build`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Executable {
+    /// The ID of the package that the binary belongs to.
+    pub package_id: PackageId,
+    /// The name of the binary target.
+    pub name: String,
+    /// The name of the target directory, i.e. the target triple or the file stem of the target
+    /// specification. `None` for host builds.
+    pub target: Option<String>,
+    /// The name of the profile directory, e.g. `debug` for the `dev` profile.
+    pub profile: String,
+    /// The path of the executable.
+    pub path: PathBuf,
+}
+
+/// A message of the JSON output of `cargo build`.
+#[derive(Deserialize)]
+struct Message {
+    reason: String,
+    #[serde(default)]
+    package_id: Option<String>,
+    #[serde(default)]
+    target: Option<MessageTarget>,
+    #[serde(default)]
+    executable: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+struct MessageTarget {
+    name: String,
+    kind: Vec<String>,
+}
+
+/// Returns the name of the directory that cargo uses for the given `--target` value.
+///
+/// For target specification files, this is the file stem.
+fn target_dir_name(target: &str) -> String {
+    if target.ends_with(".json") {
+        if let Some(stem) = Path::new(target).file_stem() {
+            return stem.to_string_lossy().into_owned();
+        }
+    }
+    target.to_owned()
+}
+
+/// Determines the target and profile directory of an executable from its path, which is
+/// `<target-dir>/<profile>/<name>` for host builds and `<target-dir>/<target>/<profile>/<name>`
+/// otherwise.
+fn classify(path: &Path, targets: &[String]) -> (Option<String>, String) {
+    let name = |path: Option<&Path>| {
+        path.and_then(Path::file_name)
+            .map(|name| name.to_string_lossy().into_owned())
+    };
+    let profile_dir = path.parent();
+    let profile = name(profile_dir).unwrap_or_default();
+    let parent = profile_dir.and_then(Path::parent);
+    let target = match name(parent) {
+        Some(parent_name) if targets.contains(&parent_name) => Some(parent_name),
+        // cargo creates a `CACHEDIR.TAG` file in the root of the target directory, which is the
+        // parent of the target triple directory for cross builds
+        Some(parent_name)
+            if parent
+                .and_then(Path::parent)
+                .is_some_and(|dir| dir.join("CACHEDIR.TAG").is_file()) =>
+        {
+            Some(parent_name)
+        }
+        _ => None,
+    };
+    (target, profile)
+}
+
+/// Failed to build the package or to read the reported artifacts.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ArtifactError {
+    /// An I/O error that occurred while trying to execute `cargo build`.
+    Io(io::Error),
+    /// The command `cargo build` did not exit successfully.
+    Failed {
+        /// The standard error output of `cargo build`.
+        ///
+        /// Only the first megabyte of the output is kept.
+        stderr: Vec<u8>,
+    },
+    /// The command `cargo build` did not finish within the timeout of the [`Context`].
+    ///
+    /// The process was killed.
+    TimedOut {
+        /// The timeout that expired.
+        timeout: Duration,
+    },
+    /// The JSON output of `cargo build` could not be parsed.
+    InvalidOutput {
+        /// The error message of the parser.
+        message: String,
+    },
+}
+
+impl fmt::Display for ArtifactError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArtifactError::Io(err) => write!(f, "Failed to execute `cargo build`: {}", err),
+            ArtifactError::Failed { stderr } => write!(
+                f,
+                "`cargo build` was not successful: {}",
+                String::from_utf8_lossy(stderr)
+            ),
+            ArtifactError::TimedOut { timeout } => write!(
+                f,
+                "`cargo build` did not finish within {:?} and was killed",
+                timeout
+            ),
+            ArtifactError::InvalidOutput { message } => write!(
+                f,
+                "Failed to parse `cargo build` output as JSON: {}",
+                message
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ArtifactError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ArtifactError::Io(err) => Some(err),
+            ArtifactError::Failed { stderr: _ } => None,
+            ArtifactError::TimedOut { timeout: _ } => None,
+            ArtifactError::InvalidOutput { message: _ } => None,
+        }
+    }
+}
+
+impl convert::From<io::Error> for ArtifactError {
+    fn from(source: io::Error) -> Self {
+        ArtifactError::Io(source)
+    }
+}
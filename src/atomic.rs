@@ -0,0 +1,155 @@
+//! Atomic writes of generated files.
+//!
+//! Files are written to a temporary file in the same directory first, which is then renamed to
+//! the final path. Renames within a directory are atomic, so concurrent readers either see the
+//! previous file or the complete new one, never a partially written file. Every write uses its
+//! own temporary file, since concurrent writers would otherwise rename a file that another one
+//! is still writing.
+
+use crate::process;
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// Writes the given contents to the given file atomically.
+#[cfg(feature = "cache")]
+pub(crate) fn write(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut file = AtomicFile::create(path)?;
+    file.write_all(contents)?;
+    file.commit()
+}
+
+/// Creates the given file atomically through a function that writes it at the given path.
+///
+/// This is useful for files that are written by external tools. The function is called with the
+/// path of the temporary file, which is renamed to the final path if the function succeeds and
+/// removed otherwise.
+#[cfg(feature = "image")]
+pub(crate) fn write_with<T, E>(
+    path: &Path,
+    write: impl FnOnce(&Path) -> Result<T, E>,
+) -> Result<T, E>
+where
+    E: From<io::Error>,
+{
+    let temp_path = temp_path(path);
+    let result = write(&temp_path).and_then(|value| {
+        fs::File::open(&temp_path)?.sync_all()?;
+        fs::rename(&temp_path, path)?;
+        Ok(value)
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// A file that is written incrementally and only appears at its path once it's committed.
+///
+/// The temporary file is removed if the file is dropped without being committed.
+pub(crate) struct AtomicFile {
+    path: PathBuf,
+    temp_path: PathBuf,
+    file: fs::File,
+    committed: bool,
+}
+
+impl AtomicFile {
+    /// Creates the temporary file for the given path.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let temp_path = temp_path(path);
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)?;
+        Ok(AtomicFile {
+            path: path.to_owned(),
+            temp_path,
+            file,
+            committed: false,
+        })
+    }
+
+    /// Returns the final path of the file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Moves the written file to its final path.
+    pub fn commit(mut self) -> io::Result<()> {
+        self.file.sync_all()?;
+        fs::rename(&self.temp_path, &self.path)?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.temp_path);
+        }
+    }
+}
+
+/// Returns a unique temporary path in the directory of the given path.
+fn temp_path(path: &Path) -> PathBuf {
+    let file_name = path.file_name().unwrap_or_else(|| "file".as_ref());
+    path.with_file_name(process::unique_file_name(&format!(
+        "{}.tmp",
+        file_name.to_string_lossy()
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp::TempDir;
+
+    #[test]
+    fn file_appears_on_commit() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("out.log");
+        let mut file = AtomicFile::create(&path).unwrap();
+        file.write_all(b"partial").unwrap();
+        assert!(!path.exists());
+        file.commit().unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"partial");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn dropped_file_leaves_nothing_behind() {
+        let dir = TempDir::new().unwrap();
+        let mut file = AtomicFile::create(&dir.path().join("out.log")).unwrap();
+        file.write_all(b"partial").unwrap();
+        drop(file);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "image")]
+    fn failed_write_keeps_previous_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.write("image.bin", "previous").unwrap();
+        let result = write_with(&path, |temp_path| {
+            fs::write(temp_path, "torn")?;
+            Err::<(), _>(io::Error::other("interrupted"))
+        });
+        assert!(result.is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"previous");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
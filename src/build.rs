@@ -0,0 +1,328 @@
+//! Builds a located bootloader together with a kernel.
+//!
+//! This implements the build flow of the `bootloader` crate before version 0.11, where the
+//! bootloader is compiled as a binary that embeds the kernel. Starting with version 0.11, disk
+//! images are created through the `DiskImageBuilder` of the `bootloader` crate instead, see
+//! [`LocatedPackage::detect_kind`](crate::LocatedPackage::detect_kind).
+
+use crate::{arch::Arch, artifacts::Executables, layout::LayoutStrategy, process, Context};
+use std::{
+    borrow::Cow,
+    convert, fmt, io,
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::Duration,
+};
+
+/// Compiles the bootloader with an embedded kernel.
+///
+/// ```no_run
+/// use bootloader_locator::{build::BootloaderBuilder, locate_bootloader};
+///
+/// let bootloader_manifest = locate_bootloader("bootloader")?;
+/// let artifacts = BootloaderBuilder::new(bootloader_manifest, "target/x86_64-os/debug/kernel")
+///     .kernel_manifest("Cargo.toml")
+///     .build()?;
+/// println!("{}", artifacts.bootloader_elf.display());
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct BootloaderBuilder {
+    bootloader_manifest: PathBuf,
+    kernel_binary: PathBuf,
+    kernel_manifest: Option<PathBuf>,
+    arch: Arch,
+    target: Option<PathBuf>,
+    target_dir: Option<PathBuf>,
+    layout: LayoutStrategy,
+    features: Vec<String>,
+    context: Option<Context>,
+}
+
+impl BootloaderBuilder {
+    /// Creates a builder for the bootloader with the given manifest and the given kernel ELF
+    /// executable.
+    ///
+    /// For kernel packages with multiple binary targets, the executable of a specific target is
+    /// returned by [`Metadata::kernel_binary`](crate::Metadata::kernel_binary).
+    pub fn new(bootloader_manifest: impl Into<PathBuf>, kernel_binary: impl Into<PathBuf>) -> Self {
+        BootloaderBuilder {
+            bootloader_manifest: bootloader_manifest.into(),
+            kernel_binary: kernel_binary.into(),
+            kernel_manifest: None,
+            arch: Arch::default(),
+            target: None,
+            target_dir: None,
+            layout: LayoutStrategy::default(),
+            features: Vec::new(),
+            context: None,
+        }
+    }
+
+    /// Sets the manifest of the kernel, which the bootloader reads its configuration from.
+    ///
+    /// Defaults to the manifest path of the [`context`](Self::context).
+    pub fn kernel_manifest(&mut self, kernel_manifest: impl Into<PathBuf>) -> &mut Self {
+        self.kernel_manifest = Some(kernel_manifest.into());
+        self
+    }
+
+    /// Sets the architecture that the bootloader is compiled for.
+    ///
+    /// Defaults to [`Arch::X86_64`]. The architecture determines the default
+    /// [`target`](Self::target).
+    pub fn arch(&mut self, arch: Arch) -> &mut Self {
+        self.arch = arch;
+        self
+    }
+
+    /// Sets the target specification that the bootloader is compiled for.
+    ///
+    /// Defaults to the [`Arch::bootloader_target`] file of the [`arch`](Self::arch) in the
+    /// directory of the bootloader, e.g. `x86_64-bootloader.json`.
+    pub fn target(&mut self, target: impl Into<PathBuf>) -> &mut Self {
+        self.target = Some(target.into());
+        self
+    }
+
+    /// Sets the directory for the build artifacts.
+    ///
+    /// Defaults to the `bootloader` directory of the [`layout`](Self::layout) in the directory
+    /// of the kernel manifest, i.e. `target/bootloader` for the default layout.
+    pub fn target_dir(&mut self, target_dir: impl Into<PathBuf>) -> &mut Self {
+        self.target_dir = Some(target_dir.into());
+        self
+    }
+
+    /// Sets the directory layout that determines the default [`target_dir`](Self::target_dir).
+    pub fn layout(&mut self, layout: LayoutStrategy) -> &mut Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Sets additional features of the bootloader crate that are enabled for the build.
+    ///
+    /// The `binary` feature is always enabled.
+    pub fn features<I, S>(&mut self, features: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.features = features.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Sets the context that is used for running `cargo build`.
+    ///
+    /// The build requires a nightly toolchain, which can be selected through
+    /// [`Context::toolchain`]. Defaults to [`Context::from_env`], evaluated when the build runs.
+    pub fn context(&mut self, context: Context) -> &mut Self {
+        self.context = Some(context);
+        self
+    }
+
+    /// Runs the build and returns the paths of the produced artifacts.
+    pub fn build(&self) -> Result<Artifacts, BuildError> {
+        let context = match &self.context {
+            Some(context) => Cow::Borrowed(context),
+            None => Cow::Owned(Context::from_env()),
+        };
+        if !self.kernel_binary.is_file() {
+            return Err(BuildError::KernelNotFound {
+                path: self.kernel_binary.clone(),
+            });
+        }
+        let kernel_manifest = self
+            .kernel_manifest
+            .as_deref()
+            .or(context.manifest_path.as_deref())
+            .ok_or(BuildError::KernelManifestNotSet)?;
+        let kernel_manifest = absolute(kernel_manifest)?;
+        let bootloader_manifest = absolute(&self.bootloader_manifest)?;
+        let bootloader_dir = bootloader_manifest
+            .parent()
+            .unwrap_or_else(|| Path::new("."));
+        let target = match &self.target {
+            Some(target) => absolute(target)?,
+            None => bootloader_dir.join(self.arch.bootloader_target()),
+        };
+        let target_dir = match &self.target_dir {
+            Some(target_dir) => absolute(target_dir)?,
+            None => self.layout.dir(
+                kernel_manifest.parent().unwrap_or_else(|| Path::new(".")),
+                "bootloader",
+            ),
+        };
+
+        let mut features = vec![String::from("binary")];
+        features.extend(self.features.iter().cloned());
+
+        let mut cmd = context.cargo_command("build");
+        cmd.arg("--message-format").arg("json");
+        cmd.arg("--manifest-path").arg(&bootloader_manifest);
+        cmd.arg("--bin").arg("bootloader");
+        cmd.arg("--release");
+        cmd.arg("--target").arg(&target);
+        cmd.arg("--target-dir").arg(&target_dir);
+        cmd.arg("--features").arg(features.join(","));
+        cmd.arg("-Zbuild-std=core");
+        cmd.arg("-Zbuild-std-features=compiler-builtins-mem");
+        cmd.current_dir(bootloader_dir);
+        cmd.env("KERNEL", absolute(&self.kernel_binary)?);
+        cmd.env("KERNEL_MANIFEST", &kernel_manifest);
+        // the flags of the kernel build must not be applied to the bootloader
+        cmd.env("RUSTFLAGS", "");
+        cmd.env_remove("CARGO_ENCODED_RUSTFLAGS");
+        cmd.stdin(Stdio::null());
+        cmd.stdout(Stdio::piped());
+        cmd.stderr(Stdio::piped());
+
+        let output = match process::capture(cmd.spawn()?, &context)? {
+            Some(output) => output,
+            None => {
+                return Err(BuildError::TimedOut {
+                    timeout: context.timeout.unwrap_or_default(),
+                })
+            }
+        };
+        if !output.status.success() {
+            return Err(BuildError::Failed {
+                stderr: output.stderr,
+            });
+        }
+
+        // use the path reported by cargo, since the configuration of the bootloader might
+        // change the layout of the target directory
+        let target_name = target
+            .file_stem()
+            .map(|stem| stem.to_owned())
+            .unwrap_or_else(|| target.clone().into_os_string());
+        let expected_path = target_dir
+            .join(&target_name)
+            .join("release")
+            .join("bootloader");
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let reported = Executables::from_messages(&stdout, &[target.to_string_lossy()])
+            .ok()
+            .and_then(|executables| {
+                let executable = executables.iter().find(|e| e.name == "bootloader")?;
+                Some(executable.path.clone())
+            });
+        let bootloader_elf = match reported {
+            Some(path) => path,
+            None if expected_path.is_file() => expected_path,
+            None => {
+                return Err(BuildError::ArtifactNotFound {
+                    path: expected_path,
+                })
+            }
+        };
+
+        Ok(Artifacts { bootloader_elf })
+    }
+}
+
+/// Makes the given path absolute by joining it to the current working directory.
+///
+/// The build runs in the bootloader directory, so relative paths would be interpreted wrongly.
+pub(crate) fn absolute(path: &Path) -> io::Result<PathBuf> {
+    if path.is_absolute() {
+        Ok(path.to_owned())
+    } else {
+        Ok(std::env::current_dir()?.join(path))
+    }
+}
+
+/// The artifacts produced by [`BootloaderBuilder::build`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Artifacts {
+    /// The bootloader ELF executable with the embedded kernel.
+    ///
+    /// It can be turned into a bootable disk image by converting it to a flat binary, e.g.
+    /// through `llvm-objcopy -O binary`.
+    pub bootloader_elf: PathBuf,
+}
+
+/// Failed to build the bootloader.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum BuildError {
+    /// An I/O error that occurred while trying to execute `cargo build`.
+    Io(io::Error),
+    /// The given kernel executable does not exist.
+    KernelNotFound {
+        /// The path of the kernel executable as given by the caller.
+        path: PathBuf,
+    },
+    /// No kernel manifest was given and the context has no manifest path either.
+    KernelManifestNotSet,
+    /// The command `cargo build` did not exit successfully.
+    Failed {
+        /// The standard error output of `cargo build`.
+        ///
+        /// Only the first megabyte of the output is kept.
+        stderr: Vec<u8>,
+    },
+    /// The command `cargo build` did not finish within the timeout of the [`Context`].
+    ///
+    /// The process was killed.
+    TimedOut {
+        /// The timeout that expired.
+        timeout: Duration,
+    },
+    /// The build succeeded, but the bootloader executable was not found at the expected path.
+    ArtifactNotFound {
+        /// The expected path of the bootloader executable.
+        path: PathBuf,
+    },
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::Io(err) => write!(f, "Failed to execute `cargo build`: {}", err),
+            BuildError::KernelNotFound { path } => {
+                write!(f, "No kernel executable found at `{}`", path.display())
+            }
+            BuildError::KernelManifestNotSet => write!(
+                f,
+                "The kernel manifest path is required for building the bootloader, but was not \
+                set"
+            ),
+            BuildError::Failed { stderr } => write!(
+                f,
+                "`cargo build` for the bootloader was not successful: {}",
+                String::from_utf8_lossy(stderr)
+            ),
+            BuildError::TimedOut { timeout } => write!(
+                f,
+                "`cargo build` for the bootloader did not finish within {:?} and was killed",
+                timeout
+            ),
+            BuildError::ArtifactNotFound { path } => write!(
+                f,
+                "The bootloader build did not produce the expected executable at `{}`",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BuildError::Io(err) => Some(err),
+            BuildError::KernelNotFound { path: _ } => None,
+            BuildError::KernelManifestNotSet => None,
+            BuildError::Failed { stderr: _ } => None,
+            BuildError::TimedOut { timeout: _ } => None,
+            BuildError::ArtifactNotFound { path: _ } => None,
+        }
+    }
+}
+
+impl convert::From<io::Error> for BuildError {
+    fn from(source: io::Error) -> Self {
+        BuildError::Io(source)
+    }
+}
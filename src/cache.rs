@@ -0,0 +1,246 @@
+//! Persistent cache for the results of [`Locator::locate`](crate::Locator::locate).
+//!
+//! Build scripts are rerun on every rebuild, even if the dependency graph didn't change. With
+//! a cache file set through [`Locator::cache_file`](crate::Locator::cache_file), the located
+//! manifest path is stored on disk and returned directly as long as the `Cargo.toml` and
+//! `Cargo.lock` files of the project stay unchanged, without running `cargo metadata`.
+//!
+//! The cache is keyed by SHA-256 hashes of these files and of the lookup settings, which stay
+//! the same across builds with different Rust versions. Changes that are not reflected in them,
+//! e.g. a `[patch]` entry in a cargo configuration file, are not detected, so the cache should
+//! be cleared through [`invalidate`] in that case.
+
+use crate::atomic;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// The version of the cache file format.
+///
+/// Cache files with other versions are ignored and overwritten.
+const FORMAT_VERSION: u32 = 3;
+
+/// Returns the default cache file for the project with the given manifest, i.e.
+/// `target/bootloader-locator/cache.json` in the directory of the `Cargo.lock` file.
+///
+/// The `manifest_path` can point either to a `Cargo.toml` file or to the directory containing
+/// it. If no `Cargo.lock` file exists yet, the directory of the manifest is used.
+pub fn default_cache_file(manifest_path: &Path) -> PathBuf {
+    let manifest_dir = if manifest_path.is_dir() {
+        manifest_path
+    } else {
+        manifest_path.parent().unwrap_or_else(|| Path::new("."))
+    };
+    let root = manifest_dir
+        .ancestors()
+        .find(|dir| dir.join("Cargo.lock").is_file())
+        .unwrap_or(manifest_dir);
+    root.join("target")
+        .join("bootloader-locator")
+        .join("cache.json")
+}
+
+/// Removes all cached results by deleting the given cache file.
+///
+/// Succeeds if the file doesn't exist.
+pub fn invalidate(cache_file: &Path) -> io::Result<()> {
+    match fs::remove_file(cache_file) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheFile {
+    version: u32,
+    /// The hash of the `Cargo.lock` and `Cargo.toml` files that the results are valid for.
+    inputs: String,
+    /// The located manifest paths, keyed by the hash of the lookup settings.
+    results: BTreeMap<String, CachedResult>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedResult {
+    manifest_path: PathBuf,
+    /// The time at which the result was stored, in seconds since the Unix epoch.
+    stored_at: u64,
+}
+
+/// The cache keys of a lookup.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Key {
+    inputs: String,
+    lookup: String,
+}
+
+impl Key {
+    /// Creates the cache key for a lookup with the given serialized settings on the given
+    /// manifest.
+    ///
+    /// All `Cargo.toml` and `Cargo.lock` files in the directory of the manifest and its parents
+    /// are hashed, since they might belong to the workspace of the manifest. Returns `None` if
+    /// one of them can't be read.
+    pub fn new(manifest_path: &Path, settings: &str) -> Option<Self> {
+        let mut inputs = Sha256::new();
+        for dir in manifest_path.parent()?.ancestors() {
+            for name in ["Cargo.toml", "Cargo.lock"] {
+                let path = dir.join(name);
+                match fs::read(&path) {
+                    Ok(content) => {
+                        update(&mut inputs, path.to_string_lossy().as_bytes());
+                        update(&mut inputs, &content);
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                    Err(_) => return None,
+                }
+            }
+        }
+        let mut lookup = Sha256::new();
+        update(&mut lookup, manifest_path.to_string_lossy().as_bytes());
+        update(&mut lookup, settings.as_bytes());
+        Some(Key {
+            inputs: hex(inputs),
+            lookup: hex(lookup),
+        })
+    }
+}
+
+/// Adds the given bytes to the hash, prefixed with their length so that the boundaries between
+/// consecutive values are part of the hash.
+fn update(hasher: &mut Sha256, bytes: &[u8]) {
+    hasher.update((bytes.len() as u64).to_le_bytes());
+    hasher.update(bytes);
+}
+
+fn hex(hasher: Sha256) -> String {
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Returns the cached result for the given key together with the time it was stored, if it
+/// exists and is still valid.
+pub(crate) fn lookup(cache_file: &Path, key: &Key) -> Option<(PathBuf, SystemTime)> {
+    let cache = read(cache_file)?;
+    if cache.inputs != key.inputs {
+        return None;
+    }
+    let result = cache.results.get(&key.lookup)?;
+    if !result.manifest_path.is_file() {
+        return None;
+    }
+    let stored_at = UNIX_EPOCH + Duration::from_secs(result.stored_at);
+    Some((result.manifest_path.clone(), stored_at))
+}
+
+/// Stores the given result in the cache file.
+///
+/// Results for other inputs are discarded, so that the file doesn't grow indefinitely.
+pub(crate) fn store(cache_file: &Path, key: &Key, manifest_path: &Path) -> io::Result<()> {
+    let mut cache = match read(cache_file) {
+        Some(cache) if cache.inputs == key.inputs => cache,
+        _ => CacheFile {
+            version: FORMAT_VERSION,
+            inputs: key.inputs.clone(),
+            results: BTreeMap::new(),
+        },
+    };
+    let stored_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    cache.results.insert(
+        key.lookup.clone(),
+        CachedResult {
+            manifest_path: manifest_path.to_owned(),
+            stored_at,
+        },
+    );
+
+    if let Some(dir) = cache_file.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let json = serde_json::to_vec(&cache).expect("cache is always serializable");
+    atomic::write(cache_file, &json)
+}
+
+fn read(cache_file: &Path) -> Option<CacheFile> {
+    let content = fs::read(cache_file).ok()?;
+    let cache: CacheFile = serde_json::from_slice(&content).ok()?;
+    if cache.version == FORMAT_VERSION {
+        Some(cache)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::TempDir;
+    use std::thread;
+
+    #[test]
+    fn concurrent_stores_keep_the_file_valid() {
+        let project = TempDir::new().unwrap();
+        let manifest_path = project
+            .write("Cargo.toml", "[package]\nname = \"kernel\"\n")
+            .unwrap();
+        let bootloader = project.write("bootloader/Cargo.toml", "").unwrap();
+        let cache_file = project.path().join("target/cache.json");
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                let (manifest_path, bootloader, cache_file) = (
+                    manifest_path.clone(),
+                    bootloader.clone(),
+                    cache_file.clone(),
+                );
+                thread::spawn(move || {
+                    let key = Key::new(&manifest_path, &i.to_string()).unwrap();
+                    for _ in 0..20 {
+                        store(&cache_file, &key, &bootloader).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let key = Key::new(&manifest_path, "0").unwrap();
+        store(&cache_file, &key, &bootloader).unwrap();
+        assert_eq!(lookup(&cache_file, &key).unwrap().0, bootloader);
+        // no temporary files are left behind
+        let files = fs::read_dir(project.path().join("target")).unwrap().count();
+        assert_eq!(files, 1);
+    }
+
+    #[test]
+    fn modified_manifest_invalidates_results() {
+        let project = TempDir::new().unwrap();
+        let manifest_path = project
+            .write("Cargo.toml", "[package]\nname = \"kernel\"\n")
+            .unwrap();
+        let bootloader = project.write("bootloader/Cargo.toml", "").unwrap();
+        let cache_file = project.path().join("cache.json");
+        let key = Key::new(&manifest_path, "settings").unwrap();
+        store(&cache_file, &key, &bootloader).unwrap();
+        assert_eq!(lookup(&cache_file, &key).unwrap().0, bootloader);
+        assert_eq!(
+            lookup(&cache_file, &Key::new(&manifest_path, "other").unwrap()),
+            None
+        );
+
+        project
+            .write("Cargo.toml", "[package]\nname = \"renamed\"\n")
+            .unwrap();
+        let key = Key::new(&manifest_path, "settings").unwrap();
+        assert_eq!(lookup(&cache_file, &key), None);
+    }
+}
@@ -0,0 +1,487 @@
+//! Looks up values of the layered cargo configuration, e.g. the default build target.
+//!
+//! Values are queried through `cargo config get`, which is only available on nightly cargo. On
+//! other channels, the configuration files (`.cargo/config.toml`) of the given directory, its
+//! parent directories, and the cargo home directory are read manually. Environment variables
+//! like `CARGO_BUILD_TARGET` take precedence over the files in both cases.
+//!
+//! The manual lookup uses the value of the most specific configuration file that sets the key.
+//! Unlike cargo, it doesn't merge arrays of multiple files and doesn't support `--config`
+//! arguments or `include` directives.
+
+use crate::{process, Context};
+use serde_json::Value;
+use std::{
+    convert, env, fmt, fs, io,
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::Duration,
+};
+
+/// Returns the value of the given dotted configuration key, e.g. `build.target`.
+///
+/// The configuration is looked up as cargo would when run in the given directory. Returns
+/// `None` if the key is not set.
+pub fn get(context: &Context, dir: &Path, key: &str) -> Result<Option<Value>, CargoConfigError> {
+    if let Some(value) = get_from_cargo(context, dir, key)? {
+        return Ok(value);
+    }
+    get_from_files(dir, key)
+}
+
+/// Looks up the key in the environment variables and configuration files, without running
+/// cargo.
+pub(crate) fn get_from_files(dir: &Path, key: &str) -> Result<Option<Value>, CargoConfigError> {
+    if let Some(value) = env::var_os(env_var_name(key)) {
+        return Ok(Some(Value::String(value.to_string_lossy().into_owned())));
+    }
+    Ok(find_in_files(dir, key)?.map(|(value, _)| value))
+}
+
+/// Looks up the key in the configuration files and returns its value together with the path
+/// of the file that sets it.
+fn find_in_files(dir: &Path, key: &str) -> Result<Option<(Value, PathBuf)>, CargoConfigError> {
+    for file in config_files(dir) {
+        let content = match fs::read_to_string(&file) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err.into()),
+        };
+        let table: toml::Table =
+            toml::from_str(&content).map_err(|err| CargoConfigError::InvalidToml {
+                path: file.clone(),
+                message: err.message().to_owned(),
+            })?;
+        if let Some(value) = lookup(&table, key) {
+            let value = serde_json::to_value(value).expect("TOML values are valid JSON values");
+            return Ok(Some((value, file)));
+        }
+    }
+    Ok(None)
+}
+
+/// Returns the default compilation target set through `build.target`.
+pub fn build_target(context: &Context, dir: &Path) -> Result<Option<String>, CargoConfigError> {
+    get_string(context, dir, "build.target")
+}
+
+/// Returns the target directory set through `build.target-dir`.
+///
+/// The path is returned as written in the configuration, so relative paths are not resolved.
+pub fn build_target_dir(
+    context: &Context,
+    dir: &Path,
+) -> Result<Option<PathBuf>, CargoConfigError> {
+    Ok(get_string(context, dir, "build.target-dir")?.map(PathBuf::from))
+}
+
+/// Returns the runner for the given target triple set through `target.<triple>.runner`.
+///
+/// The runner is returned as program followed by its arguments. Runners given as a single
+/// string are split at whitespace, like cargo does.
+pub fn target_runner(
+    context: &Context,
+    dir: &Path,
+    target: &str,
+) -> Result<Option<Vec<String>>, CargoConfigError> {
+    let key = format!("target.{}.runner", target);
+    match get(context, dir, &key)? {
+        None => Ok(None),
+        Some(Value::String(runner)) => {
+            Ok(Some(runner.split_whitespace().map(String::from).collect()))
+        }
+        Some(Value::Array(parts)) => parts
+            .into_iter()
+            .map(|part| match part {
+                Value::String(part) => Ok(part),
+                other => Err(CargoConfigError::UnexpectedType {
+                    key: key.clone(),
+                    value: other,
+                }),
+            })
+            .collect::<Result<_, _>>()
+            .map(Some),
+        Some(other) => Err(CargoConfigError::UnexpectedType { key, value: other }),
+    }
+}
+
+fn get_string(
+    context: &Context,
+    dir: &Path,
+    key: &str,
+) -> Result<Option<String>, CargoConfigError> {
+    match get(context, dir, key)? {
+        None => Ok(None),
+        Some(Value::String(value)) => Ok(Some(value)),
+        Some(other) => Err(CargoConfigError::UnexpectedType {
+            key: key.to_owned(),
+            value: other,
+        }),
+    }
+}
+
+/// Queries the key through `cargo config get`.
+///
+/// Returns `None` if the command is not available, e.g. because cargo is not a nightly version.
+fn get_from_cargo(
+    context: &Context,
+    dir: &Path,
+    key: &str,
+) -> Result<Option<Option<Value>>, CargoConfigError> {
+    let mut cmd = context.cargo_command("config");
+    cmd.arg("get")
+        .arg("--format")
+        .arg("json-value")
+        .arg("-Zunstable-options")
+        .arg(key);
+    cmd.current_dir(dir);
+    cmd.stdin(Stdio::null());
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::piped());
+
+    let output = match process::capture(cmd.spawn()?, context)? {
+        Some(output) => output,
+        None => {
+            return Err(CargoConfigError::TimedOut {
+                timeout: context.timeout.unwrap_or_default(),
+            })
+        }
+    };
+    if output.status.success() {
+        let value = serde_json::from_slice(&output.stdout).map_err(|err| {
+            CargoConfigError::InvalidOutput {
+                message: err.to_string(),
+            }
+        })?;
+        Ok(Some(Some(value)))
+    } else if String::from_utf8_lossy(&output.stderr).contains("is not set") {
+        Ok(Some(None))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Returns the name of the environment variable that overrides the given key.
+fn env_var_name(key: &str) -> String {
+    let mut name = String::from("CARGO_");
+    name.extend(key.chars().map(|c| match c {
+        '.' | '-' => '_',
+        c => c.to_ascii_uppercase(),
+    }));
+    name
+}
+
+/// Returns the configuration files that apply to the given directory, most specific first.
+fn config_files(dir: &Path) -> Vec<PathBuf> {
+    let mut dirs: Vec<_> = dir.ancestors().map(|dir| dir.join(".cargo")).collect();
+    if let Some(cargo_home) = cargo_home() {
+        if !dirs.contains(&cargo_home) {
+            dirs.push(cargo_home);
+        }
+    }
+    dirs.into_iter()
+        .filter_map(|dir| {
+            // the file without extension is only read if `config.toml` doesn't exist
+            ["config.toml", "config"]
+                .iter()
+                .map(|name| dir.join(name))
+                .find(|file| file.is_file())
+        })
+        .collect()
+}
+
+/// Returns the directory of vendored sources that replace crates.io, as set up by
+/// `cargo vendor`.
+///
+/// Follows the `source.crates-io.replace-with` chain of the configuration files to a source
+/// with a `directory` key, e.g. `[source.vendored-sources] directory = "vendor"`. Relative
+/// directories are resolved against the parent of the `.cargo` directory that contains the
+/// configuration file, like cargo does. Returns `None` if crates.io is not replaced by a
+/// directory source.
+///
+/// Only the configuration files are read, since `cargo config get` doesn't report where a
+/// value is set.
+pub fn vendor_dir(dir: &Path) -> Result<Option<PathBuf>, CargoConfigError> {
+    let mut source = String::from("crates-io");
+    let mut visited = Vec::new();
+    loop {
+        let replace_key = format!("source.{}.replace-with", source);
+        match find_in_files(dir, &replace_key)? {
+            Some((Value::String(replacement), _)) => {
+                // guard against replacement cycles
+                if visited.contains(&replacement) {
+                    return Ok(None);
+                }
+                visited.push(source);
+                source = replacement;
+                continue;
+            }
+            Some((value, _)) => {
+                return Err(CargoConfigError::UnexpectedType {
+                    key: replace_key,
+                    value,
+                })
+            }
+            None if visited.is_empty() => return Ok(None),
+            None => {}
+        }
+        let directory_key = format!("source.{}.directory", source);
+        return match find_in_files(dir, &directory_key)? {
+            None => Ok(None),
+            Some((Value::String(directory), file)) => {
+                // the configuration file is located in a `.cargo` directory
+                let base = file
+                    .parent()
+                    .and_then(Path::parent)
+                    .unwrap_or_else(|| Path::new("."));
+                Ok(Some(base.join(directory)))
+            }
+            Some((value, _)) => Err(CargoConfigError::UnexpectedType {
+                key: directory_key,
+                value,
+            }),
+        };
+    }
+}
+
+/// Returns the directories of the `paths` overrides that apply to the given directory.
+///
+/// Cargo uses the packages in these directories instead of registry or git packages with the
+/// same name, without updating the lock file. Unlike other keys, the arrays of all
+/// configuration files are merged, like cargo does. Relative directories are resolved against
+/// the parent of the `.cargo` directory that contains the configuration file.
+pub fn path_overrides(dir: &Path) -> Result<Vec<PathBuf>, CargoConfigError> {
+    let mut overrides = Vec::new();
+    for file in config_files(dir) {
+        let content = fs::read_to_string(&file)?;
+        let table: toml::Table =
+            toml::from_str(&content).map_err(|err| CargoConfigError::InvalidToml {
+                path: file.clone(),
+                message: err.message().to_owned(),
+            })?;
+        let paths = match table.get("paths") {
+            Some(toml::Value::Array(paths)) => paths,
+            Some(value) => {
+                return Err(CargoConfigError::UnexpectedType {
+                    key: String::from("paths"),
+                    value: serde_json::to_value(value).expect("TOML values are valid JSON values"),
+                })
+            }
+            None => continue,
+        };
+        let base = file
+            .parent()
+            .and_then(Path::parent)
+            .unwrap_or_else(|| Path::new("."));
+        for path in paths {
+            match path {
+                toml::Value::String(path) => overrides.push(base.join(path)),
+                other => {
+                    return Err(CargoConfigError::UnexpectedType {
+                        key: String::from("paths"),
+                        value: serde_json::to_value(other)
+                            .expect("TOML values are valid JSON values"),
+                    })
+                }
+            }
+        }
+    }
+    Ok(overrides)
+}
+
+/// Returns the cargo home directory, i.e. `$CARGO_HOME` or `~/.cargo`.
+pub(crate) fn cargo_home() -> Option<PathBuf> {
+    if let Some(cargo_home) = env::var_os("CARGO_HOME") {
+        return Some(PathBuf::from(cargo_home));
+    }
+    let home = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE"))?;
+    Some(PathBuf::from(home).join(".cargo"))
+}
+
+/// Looks up the given dotted key in the given table.
+fn lookup<'t>(table: &'t toml::Table, key: &str) -> Option<&'t toml::Value> {
+    let mut parts = key.split('.');
+    let mut value = table.get(parts.next()?)?;
+    for part in parts {
+        value = value.as_table()?.get(part)?;
+    }
+    Some(value)
+}
+
+/// Failed to look up a cargo configuration value.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum CargoConfigError {
+    /// An I/O error that occurred while running cargo or reading a configuration file.
+    Io(io::Error),
+    /// A configuration file is not valid TOML.
+    InvalidToml {
+        /// The path of the configuration file.
+        path: PathBuf,
+        /// The error message of the TOML parser.
+        message: String,
+    },
+    /// The output of `cargo config get` is not valid JSON.
+    InvalidOutput {
+        /// The error message of the JSON parser.
+        message: String,
+    },
+    /// The command `cargo config get` did not finish within the timeout of the [`Context`].
+    ///
+    /// The process was killed.
+    TimedOut {
+        /// The timeout that expired.
+        timeout: Duration,
+    },
+    /// The value of a key has an unexpected type, e.g. a number instead of a string.
+    UnexpectedType {
+        /// The configuration key.
+        key: String,
+        /// The value of the key.
+        value: Value,
+    },
+}
+
+impl fmt::Display for CargoConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CargoConfigError::Io(err) => {
+                write!(f, "Failed to read the cargo configuration: {}", err)
+            }
+            CargoConfigError::InvalidToml { path, message } => write!(
+                f,
+                "The cargo configuration file `{}` is not valid TOML: {}",
+                path.display(),
+                message
+            ),
+            CargoConfigError::InvalidOutput { message } => write!(
+                f,
+                "Failed to parse `cargo config get` output as JSON: {}",
+                message
+            ),
+            CargoConfigError::TimedOut { timeout } => write!(
+                f,
+                "`cargo config get` did not finish within {:?} and was killed",
+                timeout
+            ),
+            CargoConfigError::UnexpectedType { key, value } => write!(
+                f,
+                "The cargo configuration value `{}` has an unexpected type: {}",
+                key, value
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CargoConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CargoConfigError::Io(err) => Some(err),
+            CargoConfigError::InvalidToml { .. } => None,
+            CargoConfigError::InvalidOutput { .. } => None,
+            CargoConfigError::TimedOut { .. } => None,
+            CargoConfigError::UnexpectedType { .. } => None,
+        }
+    }
+}
+
+impl convert::From<io::Error> for CargoConfigError {
+    fn from(source: io::Error) -> Self {
+        CargoConfigError::Io(source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::TempDir;
+
+    #[test]
+    fn derives_environment_variable_names() {
+        assert_eq!(env_var_name("build.target"), "CARGO_BUILD_TARGET");
+        assert_eq!(env_var_name("build.target-dir"), "CARGO_BUILD_TARGET_DIR");
+    }
+
+    #[test]
+    fn prefers_most_specific_file() {
+        let dir = TempDir::new().unwrap();
+        dir.write(
+            ".cargo/config.toml",
+            "[test-locator]\nouter = \"outer\"\nshared = \"outer\"\n",
+        )
+        .unwrap();
+        dir.write(".cargo/config", "[test-locator]\nignored = true\n")
+            .unwrap();
+        dir.write(
+            "kernel/.cargo/config.toml",
+            "[test-locator]\nshared = \"inner\"\n",
+        )
+        .unwrap();
+        let kernel = dir.path().join("kernel");
+        let get = |key| get_from_files(&kernel, key).unwrap();
+        assert_eq!(get("test-locator.shared"), Some(Value::from("inner")));
+        assert_eq!(get("test-locator.outer"), Some(Value::from("outer")));
+        assert_eq!(get("test-locator.ignored"), None);
+        assert_eq!(get("test-locator.shared.nested"), None);
+    }
+
+    #[test]
+    fn reports_invalid_files() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.write(".cargo/config.toml", "[test-locator\n").unwrap();
+        match get_from_files(dir.path(), "test-locator.key") {
+            Err(CargoConfigError::InvalidToml { path, .. }) => assert_eq!(path, file),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn follows_source_replacements() {
+        let dir = TempDir::new().unwrap();
+        dir.write(
+            ".cargo/config.toml",
+            "[source.crates-io]\nreplace-with = \"mirror\"\n\n\
+             [source.mirror]\nreplace-with = \"vendored-sources\"\n",
+        )
+        .unwrap();
+        dir.write(
+            "kernel/.cargo/config.toml",
+            "[source.vendored-sources]\ndirectory = \"vendor\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            vendor_dir(&dir.path().join("kernel")).unwrap(),
+            Some(dir.path().join("kernel/vendor"))
+        );
+        assert_eq!(vendor_dir(dir.path()).unwrap(), None);
+
+        dir.write(
+            ".cargo/config.toml",
+            "[source.crates-io]\nreplace-with = \"mirror\"\n\n\
+             [source.mirror]\nreplace-with = \"crates-io\"\n",
+        )
+        .unwrap();
+        assert_eq!(vendor_dir(dir.path()).unwrap(), None);
+    }
+
+    #[test]
+    fn merges_path_overrides() {
+        let dir = TempDir::new().unwrap();
+        dir.write(".cargo/config.toml", "paths = [\"outer\"]\n")
+            .unwrap();
+        dir.write("kernel/.cargo/config.toml", "paths = [\"inner\"]\n")
+            .unwrap();
+        let overrides = path_overrides(&dir.path().join("kernel")).unwrap();
+        assert_eq!(
+            overrides[..2],
+            [dir.path().join("kernel/inner"), dir.path().join("outer")]
+        );
+
+        dir.write(".cargo/config.toml", "paths = \"outer\"\n")
+            .unwrap();
+        assert!(matches!(
+            path_overrides(dir.path()),
+            Err(CargoConfigError::UnexpectedType { .. })
+        ));
+    }
+}
@@ -0,0 +1,476 @@
+//! Verifies located packages against the checksums recorded in `Cargo.lock`.
+//!
+//! The lock file records the SHA-256 checksum of the `.crate` archive of every registry package.
+//! Cargo verifies the archive when downloading it, but doesn't check the extracted sources
+//! again before building them. The verification in this module is provided through
+//! [`LocatedPackage::verify_checksum`](crate::LocatedPackage::verify_checksum).
+
+use crate::LocatedPackage;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    convert, fmt, fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+/// The checksum file that `cargo vendor` places in every vendored package.
+const VENDOR_CHECKSUM_FILE: &str = ".cargo-checksum.json";
+
+/// Verifies the given package, see [`LocatedPackage::verify_checksum`].
+pub(crate) fn verify(package: &LocatedPackage, lock_file: &Path) -> Result<(), ChecksumError> {
+    let expected = locked_checksum(package, lock_file)?;
+    let root_dir = package
+        .root_dir()
+        .ok_or_else(|| ChecksumError::CrateFileNotFound {
+            package: package.name.clone(),
+            root_dir: package.manifest_path.clone(),
+        })?;
+    let vendor_checksum_file = root_dir.join(VENDOR_CHECKSUM_FILE);
+    if vendor_checksum_file.is_file() {
+        return verify_vendored(package, root_dir, &vendor_checksum_file, &expected);
+    }
+
+    // extracted registry packages are placed in `registry/src/<index>/<name>-<version>` and
+    // the archives in `registry/cache/<index>/<name>-<version>.crate`
+    let crate_file = root_dir
+        .parent()
+        .and_then(|index_dir| {
+            let index = index_dir.file_name()?;
+            let registry_dir = index_dir.parent()?.parent()?;
+            let file_name = format!("{}-{}.crate", package.name, package.version);
+            Some(registry_dir.join("cache").join(index).join(file_name))
+        })
+        .filter(|crate_file| crate_file.is_file())
+        .ok_or_else(|| ChecksumError::CrateFileNotFound {
+            package: package.name.clone(),
+            root_dir: root_dir.to_owned(),
+        })?;
+    let actual = hash_file(&crate_file)?;
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(ChecksumError::Mismatch {
+            package: package.name.clone(),
+            path: crate_file,
+            expected,
+            actual,
+        })
+    }
+}
+
+/// Returns the checksum of the given package from the lock file.
+fn locked_checksum(package: &LocatedPackage, lock_file: &Path) -> Result<String, ChecksumError> {
+    let no_checksum = || ChecksumError::NoChecksum {
+        package: package.name.clone(),
+        version: package.version.clone(),
+    };
+    let source = package.source.as_deref().ok_or_else(no_checksum)?;
+    if !source.starts_with("registry+") && !source.starts_with("sparse+") {
+        return Err(no_checksum());
+    }
+    let content = fs::read_to_string(lock_file)?;
+    let lock: LockFile =
+        toml::from_str(&content).map_err(|err| ChecksumError::InvalidLockFile {
+            path: lock_file.to_owned(),
+            message: err.message().to_owned(),
+        })?;
+    let entry = lock
+        .package
+        .into_iter()
+        .find(|entry| {
+            entry.name == package.name
+                && entry.version == package.version
+                && entry.source.as_deref() == Some(source)
+        })
+        .ok_or_else(|| ChecksumError::NotLocked {
+            package: package.name.clone(),
+            version: package.version.clone(),
+            lock_file: lock_file.to_owned(),
+        })?;
+    entry.checksum.ok_or_else(no_checksum)
+}
+
+/// Verifies a vendored package against the checksums written by `cargo vendor`.
+///
+/// Unlike extracted registry packages, vendored packages record the checksum of every file, so
+/// modifications of the sources are detected as well.
+fn verify_vendored(
+    package: &LocatedPackage,
+    root_dir: &Path,
+    checksum_file: &Path,
+    expected: &str,
+) -> Result<(), ChecksumError> {
+    let content = fs::read_to_string(checksum_file)?;
+    let checksums: VendorChecksums =
+        serde_json::from_str(&content).map_err(|err| ChecksumError::InvalidChecksumFile {
+            path: checksum_file.to_owned(),
+            message: err.to_string(),
+        })?;
+    let recorded = checksums.package.unwrap_or_default();
+    if recorded != expected {
+        return Err(ChecksumError::Mismatch {
+            package: package.name.clone(),
+            path: checksum_file.to_owned(),
+            expected: expected.to_owned(),
+            actual: recorded,
+        });
+    }
+    for (file, expected) in checksums.files {
+        let path = root_dir.join(&file);
+        let actual = match hash_file(&path) {
+            Ok(actual) => Some(actual),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err.into()),
+        };
+        if actual.as_ref() != Some(&expected) {
+            return Err(ChecksumError::FileMismatch {
+                package: package.name.clone(),
+                path,
+                expected,
+                actual,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Returns the hex-encoded SHA-256 hash of the given file.
+fn hash_file(path: &Path) -> io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+#[derive(Deserialize)]
+struct LockFile {
+    #[serde(default)]
+    package: Vec<LockedPackage>,
+}
+
+#[derive(Deserialize)]
+struct LockedPackage {
+    name: String,
+    version: String,
+    source: Option<String>,
+    checksum: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct VendorChecksums {
+    #[serde(default)]
+    files: BTreeMap<String, String>,
+    package: Option<String>,
+}
+
+/// Failed to verify the checksum of a package.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ChecksumError {
+    /// An I/O error that occurred while reading the lock file or the package sources.
+    Io(io::Error),
+    /// The lock file is not valid TOML or doesn't have the expected format.
+    InvalidLockFile {
+        /// The path of the lock file.
+        path: PathBuf,
+        /// The error message of the TOML parser.
+        message: String,
+    },
+    /// The `.cargo-checksum.json` file of a vendored package could not be parsed.
+    InvalidChecksumFile {
+        /// The path of the checksum file.
+        path: PathBuf,
+        /// The error message of the JSON parser.
+        message: String,
+    },
+    /// The lock file has no entry for the package.
+    NotLocked {
+        /// The name of the package.
+        package: String,
+        /// The version of the package.
+        version: String,
+        /// The path of the lock file.
+        lock_file: PathBuf,
+    },
+    /// The lock file records no checksum for the package, which is the case for path and git
+    /// dependencies.
+    NoChecksum {
+        /// The name of the package.
+        package: String,
+        /// The version of the package.
+        version: String,
+    },
+    /// The `.crate` archive of the package was not found in the registry cache.
+    CrateFileNotFound {
+        /// The name of the package.
+        package: String,
+        /// The root directory of the package.
+        root_dir: PathBuf,
+    },
+    /// The checksum of the package doesn't match the checksum in the lock file.
+    Mismatch {
+        /// The name of the package.
+        package: String,
+        /// The `.crate` archive or the checksum file of the vendored package.
+        path: PathBuf,
+        /// The checksum recorded in the lock file.
+        expected: String,
+        /// The actual checksum.
+        actual: String,
+    },
+    /// A file of a vendored package was modified or removed.
+    FileMismatch {
+        /// The name of the package.
+        package: String,
+        /// The path of the file.
+        path: PathBuf,
+        /// The checksum recorded by `cargo vendor`.
+        expected: String,
+        /// The actual checksum, `None` if the file doesn't exist.
+        actual: Option<String>,
+    },
+}
+
+impl fmt::Display for ChecksumError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChecksumError::Io(err) => write!(f, "Failed to read the package sources: {}", err),
+            ChecksumError::InvalidLockFile { path, message } => write!(
+                f,
+                "The lock file `{}` is not valid: {}",
+                path.display(),
+                message
+            ),
+            ChecksumError::InvalidChecksumFile { path, message } => write!(
+                f,
+                "The checksum file `{}` is not valid: {}",
+                path.display(),
+                message
+            ),
+            ChecksumError::NotLocked {
+                package,
+                version,
+                lock_file,
+            } => write!(
+                f,
+                "The lock file `{}` has no entry for `{}` {}",
+                lock_file.display(),
+                package,
+                version
+            ),
+            ChecksumError::NoChecksum { package, version } => write!(
+                f,
+                "The lock file records no checksum for `{}` {}, only registry packages have \
+                checksums",
+                package, version
+            ),
+            ChecksumError::CrateFileNotFound { package, root_dir } => write!(
+                f,
+                "The `.crate` archive of `{}` was not found in the registry cache for `{}`",
+                package,
+                root_dir.display()
+            ),
+            ChecksumError::Mismatch {
+                package,
+                path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "The checksum of `{}` does not match the lock file (expected {}, found {} in `{}`)",
+                package,
+                expected,
+                actual,
+                path.display()
+            ),
+            ChecksumError::FileMismatch {
+                package,
+                path,
+                expected,
+                actual,
+            } => match actual {
+                Some(actual) => write!(
+                    f,
+                    "The file `{}` of `{}` was modified (expected checksum {}, found {})",
+                    path.display(),
+                    package,
+                    expected,
+                    actual
+                ),
+                None => write!(
+                    f,
+                    "The file `{}` of `{}` was removed",
+                    path.display(),
+                    package
+                ),
+            },
+        }
+    }
+}
+
+impl std::error::Error for ChecksumError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ChecksumError::Io(err) => Some(err),
+            ChecksumError::InvalidLockFile { .. } => None,
+            ChecksumError::InvalidChecksumFile { .. } => None,
+            ChecksumError::NotLocked { .. } => None,
+            ChecksumError::NoChecksum { .. } => None,
+            ChecksumError::CrateFileNotFound { .. } => None,
+            ChecksumError::Mismatch { .. } => None,
+            ChecksumError::FileMismatch { .. } => None,
+        }
+    }
+}
+
+impl convert::From<io::Error> for ChecksumError {
+    fn from(source: io::Error) -> Self {
+        ChecksumError::Io(source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{self, TempDir},
+        Metadata,
+    };
+
+    /// The SHA-256 hash of `crate`.
+    const CRATE_HASH: &str = "f5fe331d2367a7a67ee20bd579c77b929ae49439d8b0d8e9c3b98609797b6b69";
+    /// The SHA-256 hash of `fn main() {}\n`.
+    const MAIN_HASH: &str = "536e506bb90914c243a12b397b9a998f85ae2cbd9ba02dfd03a9e155ca5ca0f4";
+
+    fn bootloader_at(manifest_path: &Path) -> LocatedPackage {
+        let json = fixtures::kernel_with_bootloader(manifest_path.to_str().unwrap()).to_string();
+        Metadata::parse(&json)
+            .unwrap()
+            .locate_package("bootloader")
+            .unwrap()
+    }
+
+    fn write_lock_file(dir: &TempDir, checksum: &str) -> PathBuf {
+        let entry = fixtures::BOOTLOADER_LOCK_ENTRY.replace(&"0".repeat(64), checksum);
+        dir.write("Cargo.lock", &entry).unwrap()
+    }
+
+    #[test]
+    fn hashes_files() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.write("main.rs", "fn main() {}\n").unwrap();
+        assert_eq!(hash_file(&file).unwrap(), MAIN_HASH);
+    }
+
+    #[test]
+    fn verifies_registry_archive() {
+        let dir = TempDir::new().unwrap();
+        let manifest_path = dir
+            .write(
+                "registry/src/index.crates.io-6f17d22bba15001f/bootloader-0.11.3/Cargo.toml",
+                "",
+            )
+            .unwrap();
+        let crate_file = dir
+            .write(
+                "registry/cache/index.crates.io-6f17d22bba15001f/bootloader-0.11.3.crate",
+                "crate",
+            )
+            .unwrap();
+        let package = bootloader_at(&manifest_path);
+        assert!(verify(&package, &write_lock_file(&dir, CRATE_HASH)).is_ok());
+
+        match verify(&package, &write_lock_file(&dir, MAIN_HASH)) {
+            Err(ChecksumError::Mismatch {
+                path,
+                expected,
+                actual,
+                ..
+            }) => {
+                assert_eq!(path, crate_file);
+                assert_eq!(expected, MAIN_HASH);
+                assert_eq!(actual, CRATE_HASH);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        fs::remove_file(&crate_file).unwrap();
+        assert!(matches!(
+            verify(&package, &write_lock_file(&dir, CRATE_HASH)),
+            Err(ChecksumError::CrateFileNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn verifies_vendored_files() {
+        let dir = TempDir::new().unwrap();
+        let manifest_path = dir.write("vendor/bootloader/Cargo.toml", "").unwrap();
+        let main = dir
+            .write("vendor/bootloader/src/main.rs", "fn main() {}\n")
+            .unwrap();
+        dir.write(
+            "vendor/bootloader/.cargo-checksum.json",
+            format!(
+                r#"{{"files": {{"src/main.rs": "{}"}}, "package": "{}"}}"#,
+                MAIN_HASH, CRATE_HASH
+            ),
+        )
+        .unwrap();
+        let package = bootloader_at(&manifest_path);
+        let lock_file = write_lock_file(&dir, CRATE_HASH);
+        assert!(verify(&package, &lock_file).is_ok());
+
+        fs::write(&main, "fn main() { loop {} }\n").unwrap();
+        match verify(&package, &lock_file) {
+            Err(ChecksumError::FileMismatch { path, actual, .. }) => {
+                assert_eq!(path, main);
+                assert!(actual.is_some());
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(matches!(
+            verify(&package, &write_lock_file(&dir, MAIN_HASH)),
+            Err(ChecksumError::Mismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn requires_locked_checksum() {
+        let dir = TempDir::new().unwrap();
+        let package = bootloader_at(&dir.path().join("Cargo.toml"));
+        let lock_file = dir.write("Cargo.lock", "version = 3\n").unwrap();
+        assert!(matches!(
+            verify(&package, &lock_file),
+            Err(ChecksumError::NotLocked { .. })
+        ));
+        let lock_file = dir.write("Cargo.lock", "[[package]\n").unwrap();
+        assert!(matches!(
+            verify(&package, &lock_file),
+            Err(ChecksumError::InvalidLockFile { .. })
+        ));
+
+        let json = fixtures::kernel_with_bootloader("").to_string();
+        let kernel_id = fixtures::KERNEL_ID.parse().unwrap();
+        let kernel = Metadata::parse(&json)
+            .unwrap()
+            .package_by_id(&kernel_id)
+            .unwrap();
+        assert!(matches!(
+            verify(&kernel, &lock_file),
+            Err(ChecksumError::NoChecksum { .. })
+        ));
+    }
+}
@@ -0,0 +1,96 @@
+//! Compares the resolved dependency across multiple projects.
+//!
+//! This is useful for enforcing a uniform bootloader setup across a set of repositories, e.g.
+//! in a monorepo or for a class of student projects.
+
+use crate::{LocateError, LocatedPackage, Metadata};
+use std::path::{Path, PathBuf};
+
+/// Locates the given dependency in each of the given projects and compares the results.
+///
+/// The metadata of the projects is loaded through [`Metadata::load_many`], running at most
+/// `max_concurrency` cargo invocations at the same time.
+pub fn compare<P: AsRef<Path> + Sync>(
+    manifest_paths: &[P],
+    dependency_name: &str,
+    max_concurrency: usize,
+) -> Comparison {
+    let entries = manifest_paths
+        .iter()
+        .zip(Metadata::load_many(manifest_paths, max_concurrency))
+        .map(|(manifest_path, metadata)| Entry {
+            manifest_path: manifest_path.as_ref().to_owned(),
+            result: metadata
+                .map_err(LocateError::from)
+                .and_then(|metadata| metadata.locate_package(dependency_name)),
+        })
+        .collect();
+    Comparison { entries }
+}
+
+/// The result of [`compare`].
+#[derive(Debug)]
+pub struct Comparison {
+    /// The lookup result for each project, in the order of the given manifest paths.
+    pub entries: Vec<Entry>,
+}
+
+/// The lookup result for a single project.
+#[derive(Debug)]
+pub struct Entry {
+    /// The manifest path of the project, as given to [`compare`].
+    pub manifest_path: PathBuf,
+    /// The located dependency package.
+    pub result: Result<LocatedPackage, LocateError>,
+}
+
+impl Comparison {
+    /// Returns the most common configuration of the dependency.
+    ///
+    /// A configuration consists of the version, source, and enabled features of the package.
+    /// If multiple configurations are equally common, the one that occurs first wins. Returns
+    /// `None` if the dependency could not be located in any project.
+    pub fn baseline(&self) -> Option<&LocatedPackage> {
+        let located: Vec<_> = self.located().collect();
+        let mut baseline: Option<(&LocatedPackage, usize)> = None;
+        for package in &located {
+            let count = located
+                .iter()
+                .filter(|other| same_configuration(package, other))
+                .count();
+            if baseline.map(|(_, max)| count > max).unwrap_or(true) {
+                baseline = Some((package, count));
+            }
+        }
+        baseline.map(|(package, _)| package)
+    }
+
+    /// Returns the entries whose configuration differs from the [`baseline`](Self::baseline).
+    ///
+    /// Projects in which the dependency could not be located are reported as well.
+    pub fn outliers(&self) -> impl Iterator<Item = &Entry> {
+        let baseline = self.baseline();
+        self.entries
+            .iter()
+            .filter(move |entry| match (&entry.result, baseline) {
+                (Ok(package), Some(baseline)) => !same_configuration(package, baseline),
+                _ => true,
+            })
+    }
+
+    /// Returns `true` if the dependency was located in every project with the same
+    /// configuration.
+    pub fn is_uniform(&self) -> bool {
+        self.outliers().next().is_none()
+    }
+
+    fn located(&self) -> impl Iterator<Item = &LocatedPackage> {
+        self.entries
+            .iter()
+            .filter_map(|entry| entry.result.as_ref().ok())
+    }
+}
+
+fn same_configuration(a: &LocatedPackage, b: &LocatedPackage) -> bool {
+    a.version == b.version && a.source == b.source && a.features == b.features
+}
@@ -0,0 +1,363 @@
+//! Parses the bootloader configuration from the `[package.metadata.bootloader]` table of the
+//! kernel manifest.
+//!
+//! This table is read by the `bootloader` crate before version 0.11 when it's built together
+//! with the kernel, see the `build` module. Parsing it upfront allows tools to report
+//! configuration errors before starting the build.
+//!
+//! The `[package.metadata.bootloader-locator]` table configures the lookup of the bootloader
+//! itself, see [`Locator::from_manifest_metadata`].
+
+use crate::{Context, LocateError, Locator, Metadata};
+use serde_json::Value;
+use std::{
+    convert, fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+/// The configuration keys understood by the `bootloader` crate.
+const KEYS: &[&str] = &[
+    "physical-memory-offset",
+    "kernel-stack-address",
+    "kernel-stack-size",
+    "boot-info-address",
+];
+
+/// The keys of the `[package.metadata.bootloader-locator]` table.
+const LOCATOR_KEYS: &[&str] = &["dependency-name", "workspace-member", "features", "target"];
+
+/// The page size that addresses must be aligned to.
+const PAGE_SIZE: u64 = 4096;
+
+/// The configuration of the `[package.metadata.bootloader]` table.
+///
+/// All fields are optional. Unset fields are chosen by the bootloader.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BootloaderConfig {
+    /// The virtual address at which the complete physical memory is mapped.
+    ///
+    /// Set through the `physical-memory-offset` key. Only used if the `map_physical_memory`
+    /// feature of the bootloader is enabled.
+    pub physical_memory_offset: Option<u64>,
+    /// The virtual address of the kernel stack, set through the `kernel-stack-address` key.
+    pub kernel_stack_address: Option<u64>,
+    /// The size of the kernel stack in pages, set through the `kernel-stack-size` key.
+    pub kernel_stack_size: Option<u64>,
+    /// The virtual address of the boot information, set through the `boot-info-address` key.
+    pub boot_info_address: Option<u64>,
+}
+
+impl BootloaderConfig {
+    /// Loads the configuration of the root package of the project.
+    ///
+    /// The project is determined as described in
+    /// [`default_manifest_path`](crate::default_manifest_path).
+    pub fn load() -> Result<Self, ConfigError> {
+        let metadata =
+            Metadata::load_with_context(&Context::from_env(), None).map_err(LocateError::from)?;
+        Self::from_metadata(&metadata, None)
+    }
+
+    /// Reads the configuration of the root package or of the given workspace member from the
+    /// given metadata.
+    ///
+    /// For virtual workspaces without a selected member, the single default member of the
+    /// workspace is used, as for [`Locator`] lookups.
+    pub fn from_metadata(
+        metadata: &Metadata,
+        workspace_member: Option<&str>,
+    ) -> Result<Self, ConfigError> {
+        let package_metadata = metadata.package_metadata(workspace_member)?;
+        match package_metadata.get("bootloader") {
+            Some(table) => Self::from_table(table),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Parses the given `[package.metadata.bootloader]` table.
+    ///
+    /// Addresses must be aligned to the 4 KiB page size. Numbers can be given either as
+    /// integers or as strings, which may use a `0x` prefix for hexadecimal values.
+    pub fn from_table(table: &Value) -> Result<Self, ConfigError> {
+        let table = table.as_object().ok_or(ConfigError::NotATable)?;
+        if let Some(key) = table.keys().find(|key| !KEYS.contains(&key.as_str())) {
+            return Err(ConfigError::UnknownKey { key: key.clone() });
+        }
+
+        let address = |key: &'static str| -> Result<Option<u64>, ConfigError> {
+            let address = match table.get(key) {
+                Some(value) => parse_number(key, value)?,
+                None => return Ok(None),
+            };
+            if address % PAGE_SIZE != 0 {
+                return Err(ConfigError::InvalidValue {
+                    key,
+                    reason: format!("address {:#x} is not page aligned", address),
+                });
+            }
+            Ok(Some(address))
+        };
+        let kernel_stack_size = match table.get("kernel-stack-size") {
+            Some(value) => match parse_number("kernel-stack-size", value)? {
+                0 => {
+                    return Err(ConfigError::InvalidValue {
+                        key: "kernel-stack-size",
+                        reason: String::from("the stack must consist of at least one page"),
+                    })
+                }
+                pages => Some(pages),
+            },
+            None => None,
+        };
+
+        Ok(BootloaderConfig {
+            physical_memory_offset: address("physical-memory-offset")?,
+            kernel_stack_address: address("kernel-stack-address")?,
+            kernel_stack_size,
+            boot_info_address: address("boot-info-address")?,
+        })
+    }
+}
+
+/// Creates a locator from the `[package.metadata.bootloader-locator]` table of the given
+/// manifest, see [`Locator::from_manifest_metadata`].
+pub(crate) fn configure_locator(manifest_path: &Path) -> Result<Locator, LocatorConfigError> {
+    let manifest_path = if manifest_path.is_dir() {
+        manifest_path.join("Cargo.toml")
+    } else {
+        manifest_path.to_owned()
+    };
+    let content = fs::read_to_string(&manifest_path)?;
+    let manifest: toml::Table =
+        toml::from_str(&content).map_err(|err| LocatorConfigError::InvalidManifest {
+            path: manifest_path.clone(),
+            message: err.message().to_owned(),
+        })?;
+    let table = manifest
+        .get("package")
+        .and_then(|package| package.get("metadata"))
+        .and_then(|metadata| metadata.get("bootloader-locator"));
+    let table = match table {
+        Some(toml::Value::Table(table)) => table.clone(),
+        Some(_) => return Err(LocatorConfigError::NotATable),
+        None => toml::Table::new(),
+    };
+    if let Some(key) = table
+        .keys()
+        .find(|key| !LOCATOR_KEYS.contains(&key.as_str()))
+    {
+        return Err(LocatorConfigError::UnknownKey { key: key.clone() });
+    }
+
+    let string = |key: &'static str| -> Result<Option<String>, LocatorConfigError> {
+        match table.get(key) {
+            Some(toml::Value::String(value)) if value.is_empty() => {
+                Err(LocatorConfigError::InvalidValue {
+                    key,
+                    reason: String::from("the value must not be empty"),
+                })
+            }
+            Some(toml::Value::String(value)) => Ok(Some(value.clone())),
+            Some(value) => Err(LocatorConfigError::InvalidValue {
+                key,
+                reason: format!("expected a string, found {}", value.type_str()),
+            }),
+            None => Ok(None),
+        }
+    };
+    let features = match table.get("features") {
+        Some(toml::Value::Array(values)) => values
+            .iter()
+            .map(|value| match value {
+                toml::Value::String(feature) => Ok(feature.clone()),
+                other => Err(LocatorConfigError::InvalidValue {
+                    key: "features",
+                    reason: format!("expected a feature name, found {}", other.type_str()),
+                }),
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        Some(value) => {
+            return Err(LocatorConfigError::InvalidValue {
+                key: "features",
+                reason: format!(
+                    "expected an array of feature names, found {}",
+                    value.type_str()
+                ),
+            })
+        }
+        None => Vec::new(),
+    };
+    let target = string("target")?;
+
+    let dependency_name = string("dependency-name")?;
+    let mut locator = Locator::new(dependency_name.as_deref().unwrap_or("bootloader"));
+    locator.manifest_path(manifest_path);
+    if let Some(member) = string("workspace-member")? {
+        locator.workspace_member(member);
+    }
+    if !features.is_empty() || target.is_some() {
+        let mut context = Context::from_env();
+        context.features(features);
+        if let Some(target) = target {
+            context.filter_platform(target);
+        }
+        locator.context(context);
+    }
+    Ok(locator)
+}
+
+/// Parses an integer or a decimal or `0x`-prefixed hexadecimal string.
+fn parse_number(key: &'static str, value: &Value) -> Result<u64, ConfigError> {
+    let parsed = match value {
+        Value::Number(number) => number.as_u64(),
+        Value::String(string) => match string.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16).ok(),
+            None => string.parse().ok(),
+        },
+        _ => None,
+    };
+    parsed.ok_or_else(|| ConfigError::InvalidValue {
+        key,
+        reason: format!("expected a non-negative integer, found `{}`", value),
+    })
+}
+
+/// Failed to load the bootloader configuration.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ConfigError {
+    /// Failed to look up the package in the project metadata.
+    Locate(LocateError),
+    /// The `bootloader` entry of `[package.metadata]` is not a table.
+    NotATable,
+    /// The table contains a key that the bootloader doesn't understand.
+    UnknownKey {
+        /// The unknown key.
+        key: String,
+    },
+    /// The value of a key is not valid.
+    InvalidValue {
+        /// The key with the invalid value.
+        key: &'static str,
+        /// Why the value is not valid.
+        reason: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Locate(source) => {
+                write!(f, "Failed to load the bootloader configuration: {}", source)
+            }
+            ConfigError::NotATable => {
+                write!(f, "`package.metadata.bootloader` must be a table")
+            }
+            ConfigError::UnknownKey { key } => write!(
+                f,
+                "Unknown key `{}` in `package.metadata.bootloader` (supported keys: {})",
+                key,
+                KEYS.join(", ")
+            ),
+            ConfigError::InvalidValue { key, reason } => write!(
+                f,
+                "Invalid value for `{}` in `package.metadata.bootloader`: {}",
+                key, reason
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Locate(source) => Some(source),
+            ConfigError::NotATable => None,
+            ConfigError::UnknownKey { key: _ } => None,
+            ConfigError::InvalidValue { key: _, reason: _ } => None,
+        }
+    }
+}
+
+impl convert::From<LocateError> for ConfigError {
+    fn from(source: LocateError) -> Self {
+        ConfigError::Locate(source)
+    }
+}
+
+/// Failed to read the `[package.metadata.bootloader-locator]` table of a manifest.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum LocatorConfigError {
+    /// Failed to read the manifest.
+    Io(io::Error),
+    /// The manifest is not valid TOML.
+    InvalidManifest {
+        /// The path of the manifest.
+        path: PathBuf,
+        /// The error message of the TOML parser.
+        message: String,
+    },
+    /// The `bootloader-locator` entry of `[package.metadata]` is not a table.
+    NotATable,
+    /// The table contains a key that the locator doesn't understand.
+    UnknownKey {
+        /// The unknown key.
+        key: String,
+    },
+    /// The value of a key is not valid.
+    InvalidValue {
+        /// The key with the invalid value.
+        key: &'static str,
+        /// Why the value is not valid.
+        reason: String,
+    },
+}
+
+impl fmt::Display for LocatorConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LocatorConfigError::Io(source) => {
+                write!(f, "Failed to read the manifest: {}", source)
+            }
+            LocatorConfigError::InvalidManifest { path, message } => write!(
+                f,
+                "The manifest `{}` is not valid: {}",
+                path.display(),
+                message
+            ),
+            LocatorConfigError::NotATable => {
+                write!(f, "`package.metadata.bootloader-locator` must be a table")
+            }
+            LocatorConfigError::UnknownKey { key } => write!(
+                f,
+                "Unknown key `{}` in `package.metadata.bootloader-locator` (supported keys: {})",
+                key,
+                LOCATOR_KEYS.join(", ")
+            ),
+            LocatorConfigError::InvalidValue { key, reason } => write!(
+                f,
+                "Invalid value for `{}` in `package.metadata.bootloader-locator`: {}",
+                key, reason
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LocatorConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LocatorConfigError::Io(source) => Some(source),
+            LocatorConfigError::InvalidManifest { .. } => None,
+            LocatorConfigError::NotATable => None,
+            LocatorConfigError::UnknownKey { key: _ } => None,
+            LocatorConfigError::InvalidValue { key: _, reason: _ } => None,
+        }
+    }
+}
+
+impl convert::From<io::Error> for LocatorConfigError {
+    fn from(source: io::Error) -> Self {
+        LocatorConfigError::Io(source)
+    }
+}
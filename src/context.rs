@@ -0,0 +1,473 @@
+use crate::{
+    cargo_config, default_manifest_path, event::EventHook, invoker::CargoInvoker, process, stats,
+    CancelToken, CargoMetadataError, LocateEvent, RetryPolicy,
+};
+use serde::Serialize;
+use std::{env, ffi::OsString, path::PathBuf, process::Command, sync::Arc, time::Duration};
+
+/// Settings for running `cargo metadata`, independent of the process environment.
+///
+/// The free functions of this crate read their settings from environment variables of the
+/// current process each time they're called. A context captures these settings once, so that
+/// differently configured lookups can run side by side in the same process. Pass it to
+/// [`Locator::context`](crate::Locator::context) or
+/// [`Metadata::load_with_context`](crate::Metadata::load_with_context).
+#[derive(Debug, Clone)]
+pub struct Context {
+    pub(crate) cargo: PathBuf,
+    pub(crate) wrapper: Option<(PathBuf, Vec<String>)>,
+    pub(crate) toolchain: Option<String>,
+    pub(crate) manifest_path: Option<PathBuf>,
+    pub(crate) color: bool,
+    pub(crate) offline: bool,
+    pub(crate) locked: bool,
+    pub(crate) frozen: bool,
+    pub(crate) features: Vec<String>,
+    pub(crate) all_features: bool,
+    pub(crate) no_default_features: bool,
+    pub(crate) filter_platform: Option<String>,
+    pub(crate) no_deps: bool,
+    pub(crate) extra_args: Vec<OsString>,
+    pub(crate) envs: Vec<(OsString, OsString)>,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) stdout_limit: usize,
+    pub(crate) stderr_limit: usize,
+    pub(crate) overflow_dir: Option<PathBuf>,
+    pub(crate) cancel_token: Option<CancelToken>,
+    pub(crate) retry: Option<RetryPolicy>,
+    pub(crate) memoize: bool,
+    pub(crate) path_mappings: Vec<(PathBuf, PathBuf)>,
+    pub(crate) invoker: Option<Arc<dyn CargoInvoker>>,
+    pub(crate) event_hook: Option<EventHook>,
+}
+
+impl Context {
+    /// Creates a context from the environment of the current process.
+    ///
+    /// The cargo executable is taken from the `CARGO` environment variable, falling back to
+    /// `cargo` on the `PATH`. The manifest path is initialized through [`default_manifest_path`].
+    /// Colored output is allowed if the `CARGO_TERM_COLOR` environment variable is set.
+    pub fn from_env() -> Self {
+        Context {
+            cargo: env::var_os("CARGO")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from("cargo")),
+            wrapper: None,
+            toolchain: None,
+            manifest_path: default_manifest_path(),
+            color: env::var_os("CARGO_TERM_COLOR").is_some(),
+            offline: false,
+            locked: false,
+            frozen: false,
+            features: Vec::new(),
+            all_features: false,
+            no_default_features: false,
+            filter_platform: None,
+            no_deps: false,
+            extra_args: Vec::new(),
+            envs: Vec::new(),
+            timeout: None,
+            stdout_limit: process::DEFAULT_STDOUT_LIMIT,
+            stderr_limit: process::DEFAULT_STDERR_LIMIT,
+            overflow_dir: None,
+            cancel_token: None,
+            retry: None,
+            memoize: true,
+            path_mappings: Vec::new(),
+            invoker: None,
+            event_hook: None,
+        }
+    }
+
+    /// Sets the cargo executable that is used for running `cargo metadata`.
+    pub fn cargo(&mut self, cargo: impl Into<PathBuf>) -> &mut Self {
+        self.cargo = cargo.into();
+        self
+    }
+
+    /// Runs cargo through the given wrapper program instead of the [`cargo`](Self::cargo)
+    /// executable.
+    ///
+    /// The program is invoked with the given arguments, followed by the cargo subcommand and its
+    /// arguments, e.g. `cross metadata --format-version 1` or
+    /// `docker run --rm -v ..:/project image cargo metadata --format-version 1`. Lines that the
+    /// wrapper prints to stdout before the JSON output are ignored. Paths that the wrapper
+    /// reports from inside a container can be translated through [`map_path`](Self::map_path).
+    ///
+    /// The wrapper is used for all cargo invocations of the context. The
+    /// [`Backend::Lockfile`](crate::Backend::Lockfile) fast path is skipped, since it reads the
+    /// files of the host instead of the ones the wrapper sees.
+    pub fn wrapper<I, S>(&mut self, program: impl Into<PathBuf>, args: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let args = args.into_iter().map(Into::into).collect();
+        self.wrapper = Some((program.into(), args));
+        self
+    }
+
+    /// Translates paths below `container_path` in the `cargo metadata` output to the
+    /// corresponding paths below `host_path`.
+    ///
+    /// This is required for wrappers that run cargo in a container with the project mounted at
+    /// a different path. Both plain paths and the `path+file://` URLs in package IDs and
+    /// sources are translated. Mappings are matched by whole path components in the order they
+    /// were added, so more specific mappings should be added first.
+    pub fn map_path(
+        &mut self,
+        container_path: impl Into<PathBuf>,
+        host_path: impl Into<PathBuf>,
+    ) -> &mut Self {
+        self.path_mappings
+            .push((container_path.into(), host_path.into()));
+        self
+    }
+
+    /// Runs cargo through [`cross`](https://github.com/cross-rs/cross) for the project in the
+    /// given directory.
+    ///
+    /// Sets `cross` as [`wrapper`](Self::wrapper) and adds path mappings for the mount points
+    /// of older `cross` versions, which mount the project at `/project`, the target directory
+    /// at `/target`, and the cargo home at `/cargo`. Newer versions mount all directories at
+    /// their host paths, so the mappings have no effect for them.
+    pub fn cross(&mut self, project_dir: impl Into<PathBuf>) -> &mut Self {
+        let project_dir = project_dir.into();
+        self.wrapper("cross", Vec::<String>::new());
+        self.map_path("/target", project_dir.join("target"));
+        if let Some(cargo_home) = cargo_config::cargo_home() {
+            self.map_path("/cargo", cargo_home);
+        }
+        self.map_path("/project", project_dir)
+    }
+
+    /// Runs `cargo metadata` through the given invoker instead of a child process.
+    ///
+    /// This allows testing code built on this crate without a cargo project on disk, e.g.
+    /// through the `MockInvoker` of the `test-util` feature.
+    /// Outputs of custom invokers are not memoized and the
+    /// [`Backend::Lockfile`](crate::Backend::Lockfile) fast path is skipped, so all lookups go
+    /// through the invoker. Other cargo commands, e.g. for building the bootloader, still run
+    /// as child processes.
+    pub fn invoker(&mut self, invoker: impl CargoInvoker + 'static) -> &mut Self {
+        self.invoker = Some(Arc::new(invoker));
+        self
+    }
+
+    /// Sets the rustup toolchain that is used for running `cargo metadata`, e.g. `nightly`.
+    ///
+    /// The toolchain is passed as `+<toolchain>` argument, which is only understood by the
+    /// `cargo` proxy of rustup. Note that the `CARGO` environment variable of build scripts
+    /// points to the actual cargo executable instead of the proxy, so you might need to set
+    /// [`cargo`](Self::cargo) to `cargo` as well.
+    pub fn toolchain(&mut self, toolchain: impl Into<String>) -> &mut Self {
+        self.toolchain = Some(toolchain.into());
+        self
+    }
+
+    /// Sets the manifest that is queried when a lookup doesn't specify one.
+    ///
+    /// The path can point either to a `Cargo.toml` file or to the directory containing it.
+    pub fn manifest_path(&mut self, manifest_path: impl Into<PathBuf>) -> &mut Self {
+        self.manifest_path = Some(manifest_path.into());
+        self
+    }
+
+    /// Sets whether cargo may emit colored output.
+    ///
+    /// If disabled, `--color never` is passed to cargo. Otherwise, cargo decides based on its
+    /// own configuration.
+    pub fn color(&mut self, color: bool) -> &mut Self {
+        self.color = color;
+        self
+    }
+
+    /// Sets whether `--offline` is passed to cargo, so that it doesn't access the network.
+    pub fn offline(&mut self, offline: bool) -> &mut Self {
+        self.offline = offline;
+        self
+    }
+
+    /// Sets whether `--locked` is passed to cargo, so that it fails instead of updating the
+    /// `Cargo.lock` file.
+    pub fn locked(&mut self, locked: bool) -> &mut Self {
+        self.locked = locked;
+        self
+    }
+
+    /// Sets whether `--frozen` is passed to cargo, which is equivalent to both `--offline` and
+    /// `--locked`.
+    pub fn frozen(&mut self, frozen: bool) -> &mut Self {
+        self.frozen = frozen;
+        self
+    }
+
+    /// Sets the features that are passed to cargo through `--features`.
+    ///
+    /// Optional dependencies only appear in the resolved dependency graph if they're activated,
+    /// so the features should match the ones of the actual build.
+    pub fn features<I, S>(&mut self, features: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.features = features.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Sets whether `--all-features` is passed to cargo.
+    pub fn all_features(&mut self, all_features: bool) -> &mut Self {
+        self.all_features = all_features;
+        self
+    }
+
+    /// Sets whether `--no-default-features` is passed to cargo.
+    pub fn no_default_features(&mut self, no_default_features: bool) -> &mut Self {
+        self.no_default_features = no_default_features;
+        self
+    }
+
+    /// Restricts the resolved dependency graph to the given target triple.
+    ///
+    /// The triple is passed to cargo through `--filter-platform`, so that dependencies declared
+    /// in `[target.'cfg(..)'.dependencies]` tables are resolved for the actual compilation
+    /// target, e.g. `x86_64-unknown-none`. Without this option, the graph contains the
+    /// dependencies of all platforms.
+    pub fn filter_platform(&mut self, target: impl Into<String>) -> &mut Self {
+        self.filter_platform = Some(target.into());
+        self
+    }
+
+    /// Appends the given arguments to the command line of `cargo metadata`.
+    ///
+    /// This allows passing options that this crate doesn't wrap, e.g. `-Zunstable-options` or
+    /// `--config` overrides. The arguments are passed after all other options, so that they
+    /// can override them. Other cargo commands of the context, e.g. for building the
+    /// bootloader, don't receive them.
+    pub fn extra_args<I, S>(&mut self, args: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<OsString>,
+    {
+        self.extra_args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Sets an environment variable for `cargo metadata`, e.g. `CARGO_HTTP_TIMEOUT`.
+    ///
+    /// Variables that are set multiple times take the last value.
+    pub fn env(&mut self, key: impl Into<OsString>, value: impl Into<OsString>) -> &mut Self {
+        self.envs.push((key.into(), value.into()));
+        self
+    }
+
+    /// Sets the maximum time that a cargo invocation may take.
+    ///
+    /// Processes that don't finish in time are killed and reported through a `TimedOut` error,
+    /// e.g. [`CargoMetadataError::TimedOut`](crate::CargoMetadataError::TimedOut). By default,
+    /// there is no timeout.
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the maximum number of bytes of stdout output of a cargo invocation that are kept in
+    /// memory.
+    ///
+    /// The default is 256 MiB, which is far more than the `cargo metadata` output of large
+    /// workspaces. A `cargo metadata` output that exceeds the limit can't be parsed, so the
+    /// lookup fails with [`CargoMetadataError::OutputTooLarge`]. For other cargo commands, e.g.
+    /// for building the bootloader, the remaining output is ignored.
+    pub fn stdout_limit(&mut self, limit: usize) -> &mut Self {
+        self.stdout_limit = limit;
+        self
+    }
+
+    /// Sets the maximum number of bytes of stderr output of a cargo invocation that are kept in
+    /// memory.
+    ///
+    /// The default is 1 MiB. The remaining output is discarded, or written to a file if an
+    /// [`overflow_dir`](Self::overflow_dir) is set, and replaced by a marker at the end of the
+    /// captured output, e.g. in [`CargoMetadataError::Failed`].
+    pub fn stderr_limit(&mut self, limit: usize) -> &mut Self {
+        self.stderr_limit = limit;
+        self
+    }
+
+    /// Writes the output that exceeds the [`stdout_limit`](Self::stdout_limit) or the
+    /// [`stderr_limit`](Self::stderr_limit) to files in the given directory instead of
+    /// discarding it, e.g. [`std::env::temp_dir`].
+    ///
+    /// A file is only created if the limit of a stream is exceeded. Its path is referenced
+    /// from the truncation marker and from the `overflow_file` fields of
+    /// [`CargoMetadataError::Failed`] and [`CargoMetadataError::OutputTooLarge`]. The files are
+    /// not removed automatically.
+    pub fn overflow_dir(&mut self, dir: impl Into<PathBuf>) -> &mut Self {
+        self.overflow_dir = Some(dir.into());
+        self
+    }
+
+    /// Sets a token for cancelling `cargo metadata` runs of this context.
+    ///
+    /// When the token is cancelled, running `cargo metadata` processes are killed and the
+    /// lookups fail with [`CargoMetadataError::Cancelled`](crate::CargoMetadataError::Cancelled).
+    /// This works for both blocking and async lookups. Async lookups additionally kill the
+    /// process when their future is dropped.
+    pub fn cancel_token(&mut self, token: CancelToken) -> &mut Self {
+        self.cancel_token = Some(token);
+        self
+    }
+
+    /// Calls the given hook with progress events of the `cargo metadata` runs of this context.
+    ///
+    /// This allows front-ends to show progress, e.g. "Resolving workspace metadata…" while
+    /// cargo runs and the `Updating crates.io index` lines of its output, see [`LocateEvent`].
+    /// The hook is called from the thread or task that runs the lookup and, for blocking
+    /// lookups, from a thread that reads the stderr output of cargo, so it should return
+    /// quickly. Custom
+    /// [`invoker`](Self::invoker)s don't emit events.
+    ///
+    /// ```no_run
+    /// use bootloader_locator::{Context, LocateEvent, Locator};
+    ///
+    /// let mut context = Context::from_env();
+    /// context.on_event(|event| match event {
+    ///     LocateEvent::Started { .. } => println!("Resolving workspace metadata…"),
+    ///     LocateEvent::StderrLine(line) => println!("  {}", line.trim()),
+    ///     _ => {}
+    /// });
+    /// let bootloader = Locator::new("bootloader").context(context).locate()?;
+    /// # Ok::<(), bootloader_locator::LocateError>(())
+    /// ```
+    pub fn on_event(&mut self, hook: impl Fn(LocateEvent) + Send + Sync + 'static) -> &mut Self {
+        self.event_hook = Some(EventHook::new(hook));
+        self
+    }
+
+    /// Retries `cargo metadata` runs that fail because of lock contention, see [`RetryPolicy`].
+    ///
+    /// By default, failures are reported immediately.
+    pub fn retry(&mut self, policy: RetryPolicy) -> &mut Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    /// Returns the delay before retrying the given failed attempt, or `None` if the error
+    /// should be reported.
+    pub(crate) fn retry_delay(&self, err: &CargoMetadataError, attempt: u32) -> Option<Duration> {
+        let policy = self.retry.as_ref()?;
+        if attempt >= policy.max_attempts || !err.is_lock_contention() || self.is_cancelled() {
+            return None;
+        }
+        Some(policy.delay(attempt))
+    }
+
+    /// Returns whether the cancel token of the context was cancelled.
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancel_token
+            .as_ref()
+            .is_some_and(CancelToken::is_cancelled)
+    }
+
+    /// Returns the settings that affect the output of `cargo metadata`, serialized as JSON.
+    ///
+    /// These are the cargo executable, the wrapper, the toolchain, the `--offline`, `--locked`,
+    /// and `--frozen` flags, the feature selection, the platform filter, the `--no-deps` flag,
+    /// the extra arguments and environment variables, and the path mappings. Settings that only
+    /// affect how cargo runs, e.g. the timeout, the retry policy, the output limits, the cancel
+    /// token, or the event hook, are left out, so that they don't invalidate memoized or cached
+    /// results. The manifest path is left out too, since it's keyed separately after it was
+    /// resolved.
+    pub(crate) fn resolution_settings(&self) -> String {
+        let settings = ResolutionSettings {
+            cargo: &self.cargo,
+            wrapper: self.wrapper.as_ref(),
+            toolchain: self.toolchain.as_deref(),
+            offline: self.offline,
+            locked: self.locked,
+            frozen: self.frozen,
+            features: &self.features,
+            all_features: self.all_features,
+            no_default_features: self.no_default_features,
+            filter_platform: self.filter_platform.as_deref(),
+            no_deps: self.no_deps,
+            extra_args: &self.extra_args,
+            envs: &self.envs,
+            path_mappings: &self.path_mappings,
+        };
+        serde_json::to_string(&settings).expect("settings are always serializable")
+    }
+
+    /// Sets whether `cargo metadata` outputs are memoized in-process, see the
+    /// [`memo`](crate::memo) module.
+    ///
+    /// Enabled by default. Only has an effect if the `cache` feature is enabled.
+    pub fn memoize(&mut self, memoize: bool) -> &mut Self {
+        self.memoize = memoize;
+        self
+    }
+
+    /// Creates a command for the given cargo subcommand with the general options of this
+    /// context applied.
+    ///
+    /// Every returned command is counted as a cargo invocation in the [`stats`], so it should
+    /// only be created right before it is spawned.
+    ///
+    /// Feature options are not applied, since they refer to the queried project.
+    pub(crate) fn cargo_command(&self, subcommand: &str) -> Command {
+        stats::record_cargo_invocation();
+        self.uncounted_cargo_command(subcommand)
+    }
+
+    /// Like [`cargo_command`](Self::cargo_command), but not counted in the [`stats`], for
+    /// commands that are only displayed and never spawned.
+    pub(crate) fn uncounted_cargo_command(&self, subcommand: &str) -> Command {
+        let mut cmd = match &self.wrapper {
+            Some((program, args)) => {
+                let mut cmd = Command::new(program);
+                cmd.args(args);
+                cmd
+            }
+            None => Command::new(&self.cargo),
+        };
+        if let Some(toolchain) = &self.toolchain {
+            cmd.arg(format!("+{}", toolchain));
+        }
+        cmd.arg(subcommand);
+        // the output is captured, so colors are only useful if the caller explicitly asked for
+        // them
+        if !self.color {
+            cmd.arg("--color").arg("never");
+        }
+        if self.offline {
+            cmd.arg("--offline");
+        }
+        if self.locked {
+            cmd.arg("--locked");
+        }
+        if self.frozen {
+            cmd.arg("--frozen");
+        }
+        cmd
+    }
+}
+
+/// The settings of a [`Context`] that affect the output of `cargo metadata`, see
+/// [`Context::resolution_settings`].
+#[derive(Serialize)]
+struct ResolutionSettings<'a> {
+    cargo: &'a PathBuf,
+    wrapper: Option<&'a (PathBuf, Vec<String>)>,
+    toolchain: Option<&'a str>,
+    offline: bool,
+    locked: bool,
+    frozen: bool,
+    features: &'a [String],
+    all_features: bool,
+    no_default_features: bool,
+    filter_platform: Option<&'a str>,
+    no_deps: bool,
+    extra_args: &'a [OsString],
+    envs: &'a [(OsString, OsString)],
+    path_mappings: &'a [(PathBuf, PathBuf)],
+}
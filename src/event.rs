@@ -0,0 +1,89 @@
+use std::{
+    fmt,
+    process::{Command, ExitStatus},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// A progress event of a `cargo metadata` run, passed to the hook set through
+/// [`Context::on_event`](crate::Context::on_event).
+///
+/// Every run emits a [`Started`](Self::Started) event, a
+/// [`StderrLine`](Self::StderrLine) event for every line that cargo writes to stderr while it
+/// runs, e.g. `Updating crates.io index`, and a [`Finished`](Self::Finished) event. Retried runs
+/// emit all events again. Runs that are skipped, e.g. because of a memoized output, emit no
+/// events.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum LocateEvent {
+    /// A `cargo metadata` process was started.
+    Started {
+        /// The full command line of the process.
+        command: String,
+    },
+    /// The process wrote a line to stderr, without the line terminator.
+    ///
+    /// Invalid UTF-8 is converted lossily.
+    StderrLine(String),
+    /// The process exited or was killed.
+    Finished {
+        /// The time since the process was started.
+        duration: Duration,
+        /// The exit status of the process, `None` if it was killed because of a timeout or a
+        /// cancellation.
+        status: Option<ExitStatus>,
+    },
+}
+
+/// The event hook of a context.
+#[derive(Clone)]
+pub(crate) struct EventHook(Arc<dyn Fn(LocateEvent) + Send + Sync>);
+
+impl EventHook {
+    pub fn new(hook: impl Fn(LocateEvent) + Send + Sync + 'static) -> Self {
+        EventHook(Arc::new(hook))
+    }
+
+    /// Emits a [`LocateEvent::StderrLine`] event for the given line.
+    pub fn stderr_line(&self, line: &[u8]) {
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        (self.0)(LocateEvent::StderrLine(
+            String::from_utf8_lossy(line).into_owned(),
+        ));
+    }
+}
+
+impl fmt::Debug for EventHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EventHook")
+    }
+}
+
+/// Emits the start and the end of a cargo invocation to the event hook of a context, if any.
+pub(crate) struct Progress {
+    hook: Option<(EventHook, Instant)>,
+}
+
+impl Progress {
+    /// Emits a [`LocateEvent::Started`] event for the given command.
+    pub fn start(hook: Option<&EventHook>, command: &Command) -> Self {
+        let hook = hook.map(|hook| {
+            (hook.0)(LocateEvent::Started {
+                command: format!("{:?}", command),
+            });
+            (hook.clone(), Instant::now())
+        });
+        Progress { hook }
+    }
+
+    /// Emits a [`LocateEvent::Finished`] event with the given status.
+    pub fn finish(self, status: Option<ExitStatus>) {
+        if let Some((hook, start)) = self.hook {
+            (hook.0)(LocateEvent::Finished {
+                duration: start.elapsed(),
+                status,
+            });
+        }
+    }
+}
@@ -0,0 +1,213 @@
+//! Hand-written `cargo metadata` outputs and temporary cargo projects for tests.
+//!
+//! Requires the `test-util` feature. These are the fixtures of the tests of this crate, exposed
+//! so that tools built on top of it can test their integration the same way. The metadata
+//! documents can be served through the `MockInvoker` of the [`invoker`](crate::invoker) module,
+//! while the projects on disk allow running lookups end-to-end:
+//!
+//! ```
+//! use bootloader_locator::{fixtures, Backend, Locator};
+//!
+//! let project = fixtures::vendored_kernel()?;
+//! let manifest_path = Locator::new("bootloader")
+//!     .manifest_path(project.path())
+//!     .backend(Backend::Lockfile)
+//!     .locate()?;
+//! assert_eq!(manifest_path, project.path().join("vendor/bootloader/Cargo.toml"));
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+pub use crate::temp::TempDir;
+use serde_json::{json, Value};
+use std::io;
+
+/// The package ID of the kernel in [`kernel_with_bootloader`].
+pub const KERNEL_ID: &str = "path+file:///project#kernel@0.1.0";
+
+/// The package ID of the bootloader in [`kernel_with_bootloader`].
+pub const BOOTLOADER_ID: &str =
+    "registry+https://github.com/rust-lang/crates.io-index#bootloader@0.11.3";
+
+const CRATES_IO: &str = "registry+https://github.com/rust-lang/crates.io-index";
+
+/// Returns the JSON object of a package without dependencies, features, and targets.
+pub fn package(id: &str, name: &str, version: &str, manifest_path: &str) -> Value {
+    let source = if id.starts_with("path+") {
+        Value::Null
+    } else {
+        Value::from(id.split('#').next().unwrap_or(id))
+    };
+    json!({
+        "id": id,
+        "name": name,
+        "version": version,
+        "manifest_path": manifest_path,
+        "source": source,
+        "dependencies": [],
+        "features": {},
+        "targets": [],
+    })
+}
+
+/// Returns the declaration of a crates.io dependency with default features.
+pub fn dependency(name: &str, req: &str) -> Value {
+    json!({
+        "name": name,
+        "rename": null,
+        "optional": false,
+        "target": null,
+        "source": CRATES_IO,
+        "req": req,
+        "kind": null,
+        "features": [],
+        "uses_default_features": true,
+    })
+}
+
+/// Returns a complete `cargo metadata` document for the given packages.
+///
+/// The first package is the root package and the only workspace member. Every edge
+/// `(from, name, to)` adds a normal dependency with the given crate name to the resolve graph.
+pub fn metadata(packages: Vec<Value>, edges: &[(&str, &str, &str)]) -> Value {
+    let nodes: Vec<_> = packages
+        .iter()
+        .map(|package| {
+            let id = package["id"].as_str().unwrap();
+            let deps: Vec<_> = edges
+                .iter()
+                .filter(|(from, _, _)| *from == id)
+                .map(|(_, name, to)| {
+                    json!({
+                        "name": name,
+                        "pkg": to,
+                        "dep_kinds": [{ "kind": null, "target": null }],
+                    })
+                })
+                .collect();
+            json!({ "id": id, "deps": deps, "features": [] })
+        })
+        .collect();
+    let root = packages[0]["id"].clone();
+    json!({
+        "packages": packages,
+        "workspace_members": [root],
+        "workspace_default_members": [root],
+        "resolve": { "nodes": nodes, "root": root },
+        "workspace_root": "/project",
+        "target_directory": "/project/target",
+        "version": 1,
+    })
+}
+
+/// Returns the metadata of a kernel that depends on `bootloader` 0.11.3 with the given
+/// manifest path.
+pub fn kernel_with_bootloader(bootloader_manifest: &str) -> Value {
+    let mut kernel = package(KERNEL_ID, "kernel", "0.1.0", "/project/Cargo.toml");
+    kernel["dependencies"] = json!([dependency("bootloader", "^0.11")]);
+    let bootloader = package(BOOTLOADER_ID, "bootloader", "0.11.3", bootloader_manifest);
+    metadata(
+        vec![kernel, bootloader],
+        &[(KERNEL_ID, "bootloader", BOOTLOADER_ID)],
+    )
+}
+
+/// Returns the metadata of a kernel that depends on a chain of the given number of registry
+/// packages, for measuring lookups in large dependency graphs.
+///
+/// Package `package-<i>` depends on `package-<i + 1>`, and the kernel is `package-0`. Unlike
+/// the other fixtures, the packages have features and a library target like the packages of a
+/// real workspace, so 2000 packages result in about as much JSON as a large monorepo.
+pub fn dependency_chain(packages: usize) -> Value {
+    let id = |i: usize| format!("{}#package-{}@1.0.{}", CRATES_IO, i, i);
+    let packages: Vec<_> = (0..packages)
+        .map(|i| {
+            let dir = format!("/cargo/registry/src/index/package-{}-1.0.{}", i, i);
+            let mut package = package(
+                &id(i),
+                &format!("package-{}", i),
+                &format!("1.0.{}", i),
+                &format!("{}/Cargo.toml", dir),
+            );
+            let mut dependency = dependency(&format!("package-{}", i + 1), "^1.0");
+            dependency["features"] = json!(["std", "alloc"]);
+            package["dependencies"] = json!([dependency]);
+            package["features"] = json!({ "default": ["std"], "std": ["alloc"], "alloc": [] });
+            package["rust_version"] = json!("1.60");
+            package["targets"] = json!([{
+                "name": format!("package_{}", i),
+                "kind": ["lib"],
+                "crate_types": ["lib"],
+                "src_path": format!("{}/src/lib.rs", dir),
+            }]);
+            package
+        })
+        .collect();
+    let names: Vec<_> = (0..packages.len())
+        .map(|i| format!("package_{}", i))
+        .collect();
+    let ids: Vec<_> = (0..packages.len()).map(id).collect();
+    let edges: Vec<_> = (1..packages.len())
+        .map(|i| (ids[i - 1].as_str(), names[i].as_str(), ids[i].as_str()))
+        .collect();
+    let mut metadata = metadata(packages, &edges);
+    for node in metadata["resolve"]["nodes"].as_array_mut().unwrap() {
+        node["features"] = json!(["alloc", "default", "std"]);
+    }
+    metadata
+}
+
+/// The lock file entry of `bootloader` 0.11.3 from crates.io.
+pub const BOOTLOADER_LOCK_ENTRY: &str = r#"[[package]]
+name = "bootloader"
+version = "0.11.3"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+checksum = "0000000000000000000000000000000000000000000000000000000000000000"
+"#;
+
+/// Creates a kernel project that depends on `bootloader` 0.11.3 from vendored sources.
+///
+/// The vendored copy is placed in `vendor/bootloader`, so lookups don't depend on the cargo
+/// home directory. The vendored package only has a manifest, so it can be located through the
+/// lock file, but `cargo metadata` fails for the project.
+pub fn vendored_kernel() -> io::Result<TempDir> {
+    let dir = TempDir::new()?;
+    dir.write(
+        "Cargo.toml",
+        "[package]\nname = \"kernel\"\nversion = \"0.1.0\"\n\n\
+         [dependencies]\nbootloader = \"0.11\"\n",
+    )?;
+    dir.write(
+        "Cargo.lock",
+        format!(
+            "version = 3\n\n{}\n[[package]]\nname = \"kernel\"\nversion = \"0.1.0\"\n\
+             dependencies = [\"bootloader\"]\n",
+            BOOTLOADER_LOCK_ENTRY
+        ),
+    )?;
+    dir.write(
+        ".cargo/config.toml",
+        "[source.crates-io]\nreplace-with = \"vendored-sources\"\n\n\
+         [source.vendored-sources]\ndirectory = \"vendor\"\n",
+    )?;
+    dir.write(
+        "vendor/bootloader/Cargo.toml",
+        "[package]\nname = \"bootloader\"\nversion = \"0.11.3\"\n",
+    )?;
+    Ok(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Metadata;
+
+    #[test]
+    fn dependency_chain_is_valid_metadata() {
+        let metadata = Metadata::parse(&dependency_chain(3).to_string()).unwrap();
+        let package = metadata.locate_package("package-1").unwrap();
+        assert_eq!(
+            package.manifest_path,
+            std::path::Path::new("/cargo/registry/src/index/package-1-1.0.1/Cargo.toml")
+        );
+    }
+}
@@ -0,0 +1,154 @@
+//! Describes the boot-relevant layout of a project, e.g. for IDE extensions.
+//!
+//! IDE extensions typically need to know which workspace members are kernels, where their
+//! bootloader is located, and which files to watch for changes of this information. The
+//! [`describe`] function collects all of this from a single `cargo metadata` run.
+
+use crate::{raw, Context, LocateError, Metadata};
+use serde::Serialize;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Describes the project with the given workspace root.
+///
+/// Every workspace member that depends directly on the dependency with the given name is
+/// reported as a kernel.
+pub fn describe(
+    workspace_root: &Path,
+    dependency_name: &str,
+) -> Result<ProjectLayout, LocateError> {
+    let metadata = Metadata::load_with_context(&Context::from_env(), Some(workspace_root))?;
+    let metadata = &metadata.inner;
+
+    let workspace_root = PathBuf::from(&*metadata.workspace_root);
+    let members: Vec<_> = metadata
+        .workspace_members
+        .iter()
+        .filter_map(|id| metadata.package(id))
+        .collect();
+
+    let mut kernels = Vec::new();
+    for member in &members {
+        let query = raw::Query {
+            workspace_member: Some(&member.id),
+            ..raw::Query::default()
+        };
+        let bootloader = match metadata.dependency_package(dependency_name, &query) {
+            Ok(bootloader) => bootloader,
+            Err(err @ LocateError::MetadataInvalid { .. }) => return Err(err),
+            Err(_) => continue,
+        };
+        kernels.push(Kernel {
+            name: member.name.to_string(),
+            manifest_path: PathBuf::from(&*member.manifest_path),
+            bootloader_manifest: PathBuf::from(&*bootloader.manifest_path),
+            bootloader_version: bootloader.version.to_string(),
+        });
+    }
+
+    // target specifications are usually placed next to the manifest of the kernel or of the
+    // bootloader (e.g. `x86_64-bootloader.json`)
+    let mut spec_dirs = vec![workspace_root.clone()];
+    spec_dirs.extend(
+        members
+            .iter()
+            .map(|member| Path::new(&*member.manifest_path))
+            .chain(
+                kernels
+                    .iter()
+                    .map(|kernel| kernel.bootloader_manifest.as_path()),
+            )
+            .filter_map(Path::parent)
+            .map(Path::to_owned),
+    );
+    spec_dirs.sort();
+    spec_dirs.dedup();
+    let mut target_specs: Vec<_> = spec_dirs
+        .iter()
+        .flat_map(|dir| target_specs_in(dir))
+        .collect();
+    target_specs.sort();
+
+    let mut watch_paths: Vec<_> = members
+        .iter()
+        .map(|member| PathBuf::from(&*member.manifest_path))
+        .collect();
+    watch_paths.push(workspace_root.join("Cargo.toml"));
+    watch_paths.push(workspace_root.join("Cargo.lock"));
+    let vendor_dir = workspace_root.join("vendor");
+    if vendor_dir.is_dir() {
+        watch_paths.push(vendor_dir);
+    }
+    watch_paths.extend(target_specs.iter().cloned());
+    watch_paths.sort();
+    watch_paths.dedup();
+
+    Ok(ProjectLayout {
+        workspace_root,
+        watch_paths,
+        kernels,
+        target_specs,
+    })
+}
+
+/// Returns the target specification files in the given directory.
+///
+/// Target specifications are JSON files with an `llvm-target` field.
+fn target_specs_in(dir: &Path) -> Vec<PathBuf> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().map(|e| e == "json").unwrap_or(false))
+        .filter(|path| {
+            fs::read_to_string(path)
+                .ok()
+                .and_then(|content| serde_json::from_str::<serde_json::Value>(&content).ok())
+                .map(|spec| spec.get("llvm-target").is_some())
+                .unwrap_or(false)
+        })
+        .collect()
+}
+
+/// The boot-relevant layout of a project, as returned by [`describe`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ProjectLayout {
+    /// The root directory of the workspace.
+    pub workspace_root: PathBuf,
+    /// The files and directories whose changes might affect the layout.
+    ///
+    /// This includes the manifests of all workspace members, the `Cargo.lock` file, the
+    /// `vendor` directory (if it exists), and the target specifications. Paths of files that
+    /// don't exist yet (e.g. the `Cargo.lock` before the first build) are included too.
+    pub watch_paths: Vec<PathBuf>,
+    /// The workspace members that depend on the bootloader.
+    pub kernels: Vec<Kernel>,
+    /// The target specification files next to the manifests of the workspace members, the
+    /// workspace root, and the bootloaders.
+    pub target_specs: Vec<PathBuf>,
+}
+
+impl ProjectLayout {
+    /// Serializes the layout as compact JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("layout is always serializable")
+    }
+}
+
+/// A workspace member that depends on the bootloader.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Kernel {
+    /// The package name of the workspace member.
+    pub name: String,
+    /// The manifest path of the workspace member.
+    pub manifest_path: PathBuf,
+    /// The manifest path of the bootloader dependency.
+    pub bootloader_manifest: PathBuf,
+    /// The version of the bootloader dependency.
+    pub bootloader_version: String,
+}
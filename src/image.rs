@@ -0,0 +1,182 @@
+//! Creates bootable disk images from built bootloaders.
+//!
+//! Only BIOS images are supported, created from the bootloader executables of the [`build`]
+//! module. Bootloaders for other architectures are converted the same way through
+//! [`create_disk_image`]. There is no `create_uefi_image` function: the executables of the
+//! [`build`] flow only contain the BIOS stages, and the versions with UEFI support create their
+//! UEFI images themselves, through the `builder` binary in version 0.10 and through the
+//! `DiskImageBuilder` starting with version 0.11.
+//!
+//! [`build`]: crate::build
+
+use crate::{arch::Arch, atomic, toolchain};
+use std::{
+    convert, env,
+    ffi::OsString,
+    fmt,
+    fs::OpenOptions,
+    io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+/// The block size that disk images are padded to.
+const BLOCK_SIZE: u64 = 512;
+
+/// Creates a bootable BIOS disk image from the given bootloader executable.
+///
+/// The executable is typically the [`bootloader_elf`] artifact produced by the [`build`]
+/// module. It is converted into a flat binary through the `llvm-objcopy` of the `llvm-tools`
+/// rustup component, which is looked up in the sysroot of the active `rustc`. The image is
+/// padded to a multiple of the 512 byte block size, as required by virtual machines like QEMU.
+///
+/// The image is written to a temporary file next to `out` first, which is renamed to `out`
+/// once it's complete, so that an existing image is never replaced by a partial one.
+///
+/// [`bootloader_elf`]: crate::build::Artifacts::bootloader_elf
+/// [`build`]: crate::build
+pub fn create_bios_image(bootloader_elf: &Path, out: &Path) -> Result<(), ImageError> {
+    create_disk_image(Arch::X86_64, bootloader_elf, out)
+}
+
+/// Creates a bootable disk image from the given bootloader executable for the given
+/// architecture.
+///
+/// This works like [`create_bios_image`], which is the same as this function for
+/// [`Arch::X86_64`], but converts the executable with the object format and `objcopy`
+/// architecture of the given [`Arch`].
+pub fn create_disk_image(arch: Arch, bootloader_elf: &Path, out: &Path) -> Result<(), ImageError> {
+    if !bootloader_elf.is_file() {
+        return Err(ImageError::BootloaderNotFound {
+            path: bootloader_elf.to_owned(),
+        });
+    }
+    let objcopy = llvm_objcopy()?;
+
+    atomic::write_with(out, |temp_path| {
+        let output = Command::new(&objcopy)
+            .arg("-I")
+            .arg(arch.objcopy_input_format())
+            .arg("-O")
+            .arg("binary")
+            .arg(format!(
+                "--binary-architecture={}",
+                arch.objcopy_binary_architecture()
+            ))
+            .arg(bootloader_elf)
+            .arg(temp_path)
+            .stdin(Stdio::null())
+            .output()?;
+        if !output.status.success() {
+            return Err(ImageError::ObjcopyFailed {
+                stderr: output.stderr,
+            });
+        }
+
+        let file = OpenOptions::new().write(true).open(temp_path)?;
+        let len = file.metadata()?.len();
+        let padded_len = len.div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+        file.set_len(padded_len)?;
+        Ok(())
+    })
+}
+
+/// Looks up the `llvm-objcopy` executable of the `llvm-tools` rustup component.
+fn llvm_objcopy() -> Result<PathBuf, ImageError> {
+    let rustc = env::var_os("RUSTC").unwrap_or_else(|| OsString::from("rustc"));
+    let host = toolchain::rustc_info_for(rustc.clone())?.host;
+    let output = Command::new(&rustc)
+        .arg("--print")
+        .arg("sysroot")
+        .stdin(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        return Err(ImageError::LlvmToolsNotFound);
+    }
+
+    let sysroot = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
+    let objcopy = sysroot
+        .join("lib")
+        .join("rustlib")
+        .join(host)
+        .join("bin")
+        .join(format!("llvm-objcopy{}", env::consts::EXE_SUFFIX));
+    if objcopy.is_file() {
+        Ok(objcopy)
+    } else {
+        Err(ImageError::LlvmToolsNotFound)
+    }
+}
+
+/// Failed to create a disk image.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ImageError {
+    /// An I/O error that occurred while creating the image.
+    Io(io::Error),
+    /// The given bootloader executable does not exist.
+    BootloaderNotFound {
+        /// The path of the bootloader executable as given by the caller.
+        path: PathBuf,
+    },
+    /// The `llvm-objcopy` executable was not found in the sysroot of the active toolchain.
+    ///
+    /// It is part of the `llvm-tools` component, which can be installed through
+    /// `rustup component add llvm-tools-preview`.
+    LlvmToolsNotFound,
+    /// Failed to determine the host target of the active Rust toolchain.
+    Toolchain(toolchain::ProbeError),
+    /// The command `llvm-objcopy` did not exit successfully.
+    ObjcopyFailed {
+        /// The standard error output of `llvm-objcopy`.
+        stderr: Vec<u8>,
+    },
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageError::Io(err) => write!(f, "Failed to create the disk image: {}", err),
+            ImageError::BootloaderNotFound { path } => {
+                write!(f, "No bootloader executable found at `{}`", path.display())
+            }
+            ImageError::LlvmToolsNotFound => write!(
+                f,
+                "Could not find `llvm-objcopy` in the sysroot of the active toolchain, try \
+                installing it through `rustup component add llvm-tools-preview`"
+            ),
+            ImageError::Toolchain(source) => {
+                write!(f, "Failed to probe the Rust toolchain: {}", source)
+            }
+            ImageError::ObjcopyFailed { stderr } => write!(
+                f,
+                "`llvm-objcopy` was not successful: {}",
+                String::from_utf8_lossy(stderr)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ImageError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ImageError::Io(err) => Some(err),
+            ImageError::BootloaderNotFound { path: _ } => None,
+            ImageError::LlvmToolsNotFound => None,
+            ImageError::Toolchain(source) => Some(source),
+            ImageError::ObjcopyFailed { stderr: _ } => None,
+        }
+    }
+}
+
+impl convert::From<io::Error> for ImageError {
+    fn from(source: io::Error) -> Self {
+        ImageError::Io(source)
+    }
+}
+
+impl convert::From<toolchain::ProbeError> for ImageError {
+    fn from(source: toolchain::ProbeError) -> Self {
+        ImageError::Toolchain(source)
+    }
+}
@@ -0,0 +1,172 @@
+//! Pluggable execution of `cargo metadata`.
+//!
+//! By default, all lookups run `cargo metadata` as a child process, which requires a real cargo
+//! project on disk. Through [`Context::invoker`], the command can be replaced by a custom
+//! [`CargoInvoker`], e.g. by the `MockInvoker` of the `test-util` feature that serves canned
+//! JSON output in tests.
+
+use crate::{CargoMetadataError, Context};
+use std::{fmt, path::Path};
+#[cfg(any(test, feature = "test-util"))]
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+/// Runs `cargo metadata` on behalf of the lookups of a [`Context`].
+pub trait CargoInvoker: fmt::Debug + Send + Sync {
+    /// Returns the JSON output of `cargo metadata` for the given manifest.
+    ///
+    /// If no manifest path is given, the manifest path of the context is used. If the context
+    /// has none either, the manifest of the current working directory is queried. The output
+    /// is parsed by the caller, so invokers must not strip or modify it.
+    ///
+    /// Invokers are also used by async lookups, where they block the executor until they
+    /// return.
+    fn metadata(
+        &self,
+        context: &Context,
+        manifest_path: Option<&Path>,
+    ) -> Result<String, CargoMetadataError>;
+}
+
+/// The default invoker that runs `cargo metadata` as a child process.
+///
+/// It respects all settings of the context, including the cargo executable, the toolchain,
+/// the wrapper, the timeout, and the path mappings.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProcessInvoker;
+
+impl CargoInvoker for ProcessInvoker {
+    fn metadata(
+        &self,
+        context: &Context,
+        manifest_path: Option<&Path>,
+    ) -> Result<String, CargoMetadataError> {
+        crate::run_metadata(context, manifest_path)
+    }
+}
+
+/// An invoker that serves canned `cargo metadata` outputs instead of running cargo.
+///
+/// Requires the `test-util` feature. Clones of a mock share their responses and the record of
+/// invocations, so a clone can be passed to [`Context::invoker`] while the original is kept
+/// for inspecting the invocations afterwards.
+///
+/// ```
+/// use bootloader_locator::{invoker::MockInvoker, Context, Metadata};
+///
+/// let mock = MockInvoker::new();
+/// mock.output(r#"{"version": 1, "packages": [], "resolve": null, "workspace_root": "/project"}"#);
+/// let mut context = Context::from_env();
+/// context.invoker(mock.clone());
+/// let _ = Metadata::load_with_context(&context, None);
+/// assert_eq!(mock.invocations().len(), 1);
+/// ```
+#[cfg(any(test, feature = "test-util"))]
+#[derive(Debug, Clone, Default)]
+pub struct MockInvoker {
+    state: Arc<Mutex<MockState>>,
+}
+
+#[cfg(any(test, feature = "test-util"))]
+#[derive(Debug, Default)]
+struct MockState {
+    default: Option<MockResponse>,
+    responses: Vec<(PathBuf, MockResponse)>,
+    invocations: Vec<Option<PathBuf>>,
+}
+
+#[cfg(any(test, feature = "test-util"))]
+#[derive(Debug, Clone)]
+enum MockResponse {
+    Output(String),
+    Failure {
+        exit_code: Option<i32>,
+        stderr: String,
+    },
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl MockInvoker {
+    /// Creates a mock without responses, which fails all invocations with
+    /// [`CargoMetadataError::Failed`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serves the given output for all manifests without a specific response.
+    pub fn output(&self, json: impl Into<String>) -> &Self {
+        self.state.lock().unwrap().default = Some(MockResponse::Output(json.into()));
+        self
+    }
+
+    /// Serves the given output for the given manifest path.
+    ///
+    /// The path is compared with the manifest path after resolving it against the context, so
+    /// it must be given in the same form as passed to the lookup.
+    pub fn output_for(&self, manifest_path: impl Into<PathBuf>, json: impl Into<String>) -> &Self {
+        self.state
+            .lock()
+            .unwrap()
+            .responses
+            .push((manifest_path.into(), MockResponse::Output(json.into())));
+        self
+    }
+
+    /// Fails all invocations without a specific response as a failed `cargo metadata` run with
+    /// the given exit code and stderr output.
+    pub fn failure(&self, exit_code: Option<i32>, stderr: impl Into<String>) -> &Self {
+        self.state.lock().unwrap().default = Some(MockResponse::Failure {
+            exit_code,
+            stderr: stderr.into(),
+        });
+        self
+    }
+
+    /// Returns the manifest paths of all invocations so far, in order.
+    ///
+    /// Invocations without a manifest path, i.e. for the current working directory, are
+    /// recorded as `None`.
+    pub fn invocations(&self) -> Vec<Option<PathBuf>> {
+        self.state.lock().unwrap().invocations.clone()
+    }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl CargoInvoker for MockInvoker {
+    fn metadata(
+        &self,
+        context: &Context,
+        manifest_path: Option<&Path>,
+    ) -> Result<String, CargoMetadataError> {
+        let manifest_path = manifest_path
+            .or(context.manifest_path.as_deref())
+            .map(Path::to_owned);
+        let mut state = self.state.lock().unwrap();
+        state.invocations.push(manifest_path.clone());
+        let response = manifest_path
+            .and_then(|manifest_path| {
+                state
+                    .responses
+                    .iter()
+                    .rev()
+                    .find(|(path, _)| *path == manifest_path)
+                    .map(|(_, response)| response.clone())
+            })
+            .or_else(|| state.default.clone());
+        match response {
+            Some(MockResponse::Output(json)) => Ok(json),
+            Some(MockResponse::Failure { exit_code, stderr }) => Err(CargoMetadataError::Failed {
+                exit_code,
+                stderr,
+                overflow_file: None,
+            }),
+            None => Err(CargoMetadataError::Failed {
+                exit_code: Some(101),
+                stderr: "error: the mock invoker has no response for this manifest".into(),
+                overflow_file: None,
+            }),
+        }
+    }
+}
@@ -0,0 +1,85 @@
+//! Determines where build artifacts are placed by default.
+//!
+//! The [`build`](crate::build) and [`test_kernels`](crate::test_kernels) modules write their
+//! artifacts to a directory of the kernel project unless an explicit directory is given. The
+//! [`LayoutStrategy`] decides which directory that is, so that all artifacts of a project
+//! follow the same layout.
+
+use crate::ParseValueError;
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str,
+};
+
+/// The directory layout for build artifacts.
+///
+/// Each kind of artifact is placed in its own component directory, e.g. `bootloader` for the
+/// bootloader build or `test-kernels` for the disk images of the integration tests.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum LayoutStrategy {
+    /// Places artifacts in `target/<component>`. This is the default.
+    #[default]
+    Target,
+    /// Places artifacts in `target/boot/<component>`.
+    TargetBoot,
+    /// Places artifacts in a top-level `dist/<component>` directory.
+    Dist,
+    /// Places artifacts in `target/<profile>/<component>`, next to the artifacts of cargo for
+    /// the given profile directory, e.g. `debug`.
+    PerProfile {
+        /// The profile directory, e.g. `debug` or `release`.
+        profile: String,
+    },
+}
+
+impl LayoutStrategy {
+    /// Returns the directory for the given component of the project in the given directory.
+    ///
+    /// The `project_dir` is typically the directory of the kernel manifest.
+    pub fn dir(&self, project_dir: &Path, component: &str) -> PathBuf {
+        let base = match self {
+            LayoutStrategy::Target => project_dir.join("target"),
+            LayoutStrategy::TargetBoot => project_dir.join("target").join("boot"),
+            LayoutStrategy::Dist => project_dir.join("dist"),
+            LayoutStrategy::PerProfile { profile } => project_dir.join("target").join(profile),
+        };
+        base.join(component)
+    }
+}
+
+impl fmt::Display for LayoutStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LayoutStrategy::Target => write!(f, "target"),
+            LayoutStrategy::TargetBoot => write!(f, "target-boot"),
+            LayoutStrategy::Dist => write!(f, "dist"),
+            LayoutStrategy::PerProfile { profile } => write!(f, "per-profile:{}", profile),
+        }
+    }
+}
+
+/// Parses the spellings of the `Display` implementation, i.e. `target`, `target-boot`, `dist`,
+/// or `per-profile:<profile>`.
+///
+/// This allows reading the layout from a configuration file or a command line argument.
+impl str::FromStr for LayoutStrategy {
+    type Err = ParseValueError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "target" => Ok(LayoutStrategy::Target),
+            "target-boot" => Ok(LayoutStrategy::TargetBoot),
+            "dist" => Ok(LayoutStrategy::Dist),
+            _ => match s.strip_prefix("per-profile:") {
+                Some(profile) if !profile.is_empty() => Ok(LayoutStrategy::PerProfile {
+                    profile: profile.to_owned(),
+                }),
+                _ => Err(ParseValueError::new(
+                    s,
+                    &["target", "target-boot", "dist", "per-profile:<profile>"],
+                )),
+            },
+        }
+    }
+}
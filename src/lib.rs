@@ -2,71 +2,2319 @@
 
 #![warn(missing_docs)]
 
-use std::{convert, fmt, io, path::PathBuf, process::Command, string};
+use messages::Message;
+
+#[macro_use]
+mod trace;
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    convert, env, fmt, fs, io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    str, string,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        OnceLock,
+    },
+    thread,
+    time::{Duration, SystemTime},
+};
+
+pub use cancel::{CancelToken, Cancelled};
+pub use context::Context;
+pub use event::LocateEvent;
+pub use locator::Locator;
+pub use package_id::PackageId;
+pub use quick::quick_locate;
+#[cfg(feature = "run")]
+pub use quick::{quick_build_and_run, QuickError};
+pub use retry::RetryPolicy;
+pub use semver::VersionReq;
+
+#[cfg(feature = "build")]
+pub mod arch;
+#[cfg(feature = "build")]
+pub mod artifacts;
+#[cfg(feature = "build")]
+pub mod build;
+#[cfg(feature = "cache")]
+pub mod cache;
+pub mod cargo_config;
+#[cfg(feature = "checksum")]
+pub mod checksum;
+pub mod compare;
+pub mod config;
+#[cfg(any(test, feature = "test-util"))]
+pub mod fixtures;
+pub mod ide;
+#[cfg(feature = "image")]
+pub mod image;
+pub mod invoker;
+#[cfg(feature = "build")]
+pub mod layout;
+pub mod memo;
+pub mod messages;
+pub mod prelude;
+pub mod report;
+#[cfg(feature = "run")]
+pub mod runner;
+pub mod stats;
+#[cfg(feature = "run")]
+pub mod test_kernels;
+pub mod toolchain;
+#[cfg(feature = "notify")]
+pub mod watch;
+
+mod atomic;
+mod cancel;
+mod context;
+mod event;
+mod locator;
+mod lockfile;
+mod nonblocking;
+mod package_id;
+mod process;
+mod quick;
+mod raw;
+mod retry;
+#[cfg(any(test, feature = "test-util"))]
+mod temp;
+mod wrapper;
 
 /// Locates the dependency with the given name on the file system.
 ///
-/// Returns the manifest path of the bootloader, i.e. the path to the Cargo.toml on the file
-/// system.
-pub fn locate_bootloader(dependency_name: &str) -> Result<PathBuf, LocateError> {
-    let metadata = metadata()?;
+/// Returns the manifest path of the bootloader, i.e. the path to the Cargo.toml on the file
+/// system.
+///
+/// The dependency is searched in the dependencies of the root package. For virtual workspaces,
+/// which have no root package, the single default member of the workspace (as reported in
+/// `workspace_default_members`) is used instead. If there is no single default member, a
+/// [`LocateError::VirtualWorkspace`] error lists the workspace members. To search the
+/// dependencies of some other workspace member, use [`Locator::workspace_member`].
+///
+/// The project is determined as described in [`default_manifest_path`]. Use a [`Locator`] to
+/// query a specific project or to configure the lookup further.
+pub fn locate_bootloader(dependency_name: &str) -> Result<PathBuf, LocateError> {
+    Locator::new(dependency_name).locate()
+}
+
+/// Async version of [`locate_bootloader`] that doesn't block the async runtime while
+/// `cargo metadata` runs.
+///
+/// Requires the `tokio` feature. Without it, this fails with
+/// [`CargoMetadataError::FeatureDisabled`].
+pub async fn locate_bootloader_async(dependency_name: &str) -> Result<PathBuf, LocateError> {
+    Locator::new(dependency_name).locate_async().await
+}
+
+/// Locates the dependency with the given name and returns details about the resolved package.
+///
+/// Like [`locate_bootloader`], but also returns the version, source, and enabled features of
+/// the dependency, so that callers don't need to parse its manifest themselves.
+pub fn locate_bootloader_package(dependency_name: &str) -> Result<LocatedPackage, LocateError> {
+    Locator::new(dependency_name).locate_package()
+}
+
+/// Locates the dependency with the given name and checks that its version satisfies the given
+/// requirement.
+///
+/// Like [`locate_bootloader`], but fails with [`LocateError::VersionMismatch`] if the resolved
+/// version of the dependency doesn't match the requirement, so that tools can reject
+/// unsupported versions before starting a build:
+///
+/// ```no_run
+/// use bootloader_locator::{locate_bootloader_with_req, VersionReq};
+///
+/// let requirement = VersionReq::parse(">=0.10.12").unwrap();
+/// let bootloader_manifest = locate_bootloader_with_req("bootloader", &requirement)?;
+/// # Ok::<(), bootloader_locator::LocateError>(())
+/// ```
+pub fn locate_bootloader_with_req(
+    dependency_name: &str,
+    version_req: &VersionReq,
+) -> Result<PathBuf, LocateError> {
+    Locator::new(dependency_name)
+        .version_req(version_req.clone())
+        .locate()
+}
+
+/// Locates all of the given dependencies using a single `cargo metadata` run.
+///
+/// Returns the lookup result for each dependency name, keyed by the name. Failing to look up
+/// one dependency doesn't affect the others, so the only error returned directly is a failure
+/// to query the project metadata.
+///
+/// The dependencies are searched as described in [`locate_bootloader`].
+pub fn locate_dependencies(
+    dependency_names: &[&str],
+) -> Result<HashMap<String, Result<PathBuf, LocateError>>, CargoMetadataError> {
+    let output = metadata(&Context::from_env(), None)?;
+    let metadata = raw::Metadata::parse(&output)?;
+    Ok(dependency_names
+        .iter()
+        .map(|&name| {
+            let result = metadata.locate(name, &raw::Query::default());
+            (name.to_owned(), result)
+        })
+        .collect())
+}
+
+/// Locates the given dependency of each of the given workspace members using a single
+/// `cargo metadata` run.
+///
+/// This is useful for workspaces with multiple kernels, e.g. one per architecture, that each
+/// depend on their own bootloader version. Returns the lookup result for each member, keyed by
+/// the member as given. The members are selected as for [`Locator::workspace_member`], i.e. by
+/// package name, package ID, or path. Failing to look up the dependency of one member doesn't
+/// affect the others, so the only error returned directly is a failure to query the project
+/// metadata.
+///
+/// ```no_run
+/// use bootloader_locator::locate_for_members;
+///
+/// let members = ["kernel-x86_64", "kernel-aarch64", "kernel-riscv64"];
+/// for (member, result) in locate_for_members(&members, "bootloader")? {
+///     match result {
+///         Ok(bootloader) => println!("{}: bootloader {}", member, bootloader.version),
+///         Err(err) => println!("{}: {}", member, err),
+///     }
+/// }
+/// # Ok::<(), bootloader_locator::CargoMetadataError>(())
+/// ```
+///
+/// See [`Locator::locate_for_members`] for customizing the lookups.
+pub fn locate_for_members(
+    members: &[&str],
+    dependency_name: &str,
+) -> Result<HashMap<String, Result<LocatedPackage, LocateError>>, CargoMetadataError> {
+    let output = metadata(&Context::from_env(), None)?;
+    let metadata = raw::Metadata::parse(&output)?.with_overrides();
+    Ok(members
+        .iter()
+        .map(|&member| {
+            let query = raw::Query {
+                workspace_member: Some(member),
+                ..raw::Query::default()
+            };
+            let result = metadata.locate_package(dependency_name, &query);
+            (member.to_owned(), result)
+        })
+        .collect())
+}
+
+/// Runs the given locators concurrently and returns details about the located packages.
+///
+/// Each locator runs [`Locator::locate_package`] with its own settings, typically for a
+/// separate manifest, e.g. for a number of independent example kernels. At most
+/// `max_concurrency` lookups run at the same time; zero is treated as one. The results are
+/// returned in the order of the given locators, with a separate result for each locator, so
+/// that one failing lookup doesn't prevent the others.
+///
+/// ```no_run
+/// use bootloader_locator::{locate_many, Locator};
+///
+/// let kernels = ["examples/basic", "examples/framebuffer", "examples/heap"];
+/// let locators = kernels.iter().map(|kernel| {
+///     let mut locator = Locator::new("bootloader");
+///     locator.manifest_path(kernel);
+///     locator
+/// });
+/// for (kernel, result) in kernels.iter().zip(locate_many(locators, 4)) {
+///     match result {
+///         Ok(bootloader) => println!("{}: bootloader {}", kernel, bootloader.version),
+///         Err(err) => println!("{}: {}", kernel, err),
+///     }
+/// }
+/// ```
+pub fn locate_many(
+    locators: impl IntoIterator<Item = Locator>,
+    max_concurrency: usize,
+) -> Vec<Result<LocatedPackage, LocateError>> {
+    let locators: Vec<_> = locators.into_iter().collect();
+    run_concurrently(&locators, max_concurrency, Locator::locate_package)
+}
+
+/// Calls the given function for every item on a pool of at most `max_concurrency` threads.
+///
+/// The results are returned in the order of the items.
+fn run_concurrently<T, R, F>(items: &[T], max_concurrency: usize, f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<_> = items.iter().map(|_| None).collect();
+
+    let finished = thread::scope(|scope| {
+        let workers: Vec<_> = (0..max_concurrency.clamp(1, items.len().max(1)))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut finished = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let item = match items.get(index) {
+                            Some(item) => item,
+                            None => break finished,
+                        };
+                        finished.push((index, f(item)));
+                    }
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("worker thread panicked"))
+            .collect::<Vec<_>>()
+    });
+    for (index, result) in finished {
+        results[index] = Some(result);
+    }
+
+    results
+        .into_iter()
+        .map(|result| result.expect("every item was processed"))
+        .collect()
+}
+
+/// Looks up the package that the given manifest file belongs to.
+///
+/// The `manifest_path` can point either to a `Cargo.toml` file or to the directory containing
+/// it. The given path is canonicalized before comparing it with the paths reported by
+/// `cargo metadata`, so relative paths and symlinks are supported.
+///
+/// The project is determined as described in [`default_manifest_path`].
+pub fn package_for_manifest(manifest_path: &Path) -> Result<Package, LocateError> {
+    let output = metadata(&Context::from_env(), None)?;
+    raw::Metadata::parse(&output)?.package_for_manifest(manifest_path)
+}
+
+/// Returns the directory where cargo places the build output of the given project.
+///
+/// The `path` can point either to a `Cargo.toml` file or to the directory containing it. The
+/// directory is taken from the `target_directory` field of the `cargo metadata` output, so it
+/// respects the `CARGO_TARGET_DIR` environment variable, the `build.target-dir` setting of
+/// cargo configuration files, and the workspace that the package belongs to. Compilation
+/// targets and profiles use subdirectories of it.
+pub fn locate_target_dir(path: &Path) -> Result<PathBuf, CargoMetadataError> {
+    Ok(Metadata::load(path)?.target_directory().to_owned())
+}
+
+/// Returns the default members of the workspace.
+///
+/// These are the packages that cargo commands operate on when they are run in the workspace
+/// root without a `--package` argument. The list is taken from the `workspace_default_members`
+/// field of the `cargo metadata` output, which requires cargo 1.71 or newer.
+///
+/// The project is determined as described in [`default_manifest_path`].
+pub fn workspace_default_members() -> Result<Vec<Package>, LocateError> {
+    let output = metadata(&Context::from_env(), None)?;
+    raw::Metadata::parse(&output)?.workspace_default_members()
+}
+
+/// The optional functionality that is available in this build of the crate.
+///
+/// Returned by [`capabilities`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Capabilities {
+    /// Whether async lookups like [`locate_bootloader_async`] are supported.
+    ///
+    /// Requires the `tokio` feature. Otherwise, async lookups fail with
+    /// [`CargoMetadataError::FeatureDisabled`].
+    pub async_lookup: bool,
+    /// Whether the `watch` module is available.
+    ///
+    /// Requires the `notify` feature.
+    pub watch: bool,
+    /// Whether `cargo metadata` outputs are memoized in-process, see the [`memo`] module.
+    ///
+    /// Requires the `cache` feature.
+    pub memoization: bool,
+    /// Whether results can be cached on disk through `Locator::cache_file`.
+    ///
+    /// Requires the `cache` feature.
+    pub disk_cache: bool,
+    /// Whether packages can be verified through `LocatedPackage::verify_checksum`.
+    ///
+    /// Requires the `checksum` feature.
+    pub checksum: bool,
+    /// Whether the `build` and `artifacts` modules are available.
+    ///
+    /// Requires the `build` feature.
+    pub build: bool,
+    /// Whether the `image` module is available.
+    ///
+    /// Requires the `image` feature.
+    pub image: bool,
+    /// Whether the `runner` and `test_kernels` modules are available.
+    ///
+    /// Requires the `run` feature.
+    pub run: bool,
+}
+
+/// Returns the optional functionality that is available in this build of the crate.
+///
+/// This allows tools to check at runtime which cargo features of this crate are enabled, e.g.
+/// for reporting unsupported operations instead of failing.
+pub fn capabilities() -> Capabilities {
+    Capabilities {
+        async_lookup: cfg!(feature = "tokio"),
+        watch: cfg!(feature = "notify"),
+        memoization: cfg!(feature = "cache"),
+        disk_cache: cfg!(feature = "cache"),
+        checksum: cfg!(feature = "checksum"),
+        build: cfg!(feature = "build"),
+        image: cfg!(feature = "image"),
+        run: cfg!(feature = "run"),
+    }
+}
+
+/// The `cargo metadata` format versions that this crate can parse.
+const SUPPORTED_FORMAT_VERSIONS: &[u32] = &[1];
+
+/// Returns the `cargo metadata` format versions that this crate can parse.
+///
+/// Metadata in other formats is rejected with [`CargoMetadataError::UnsupportedFormatVersion`].
+/// The version of parsed metadata is available through [`Metadata::format_version`].
+pub fn supported_metadata_versions() -> &'static [u32] {
+    SUPPORTED_FORMAT_VERSIONS
+}
+
+/// Returns the manifest that is queried when no manifest path is given explicitly.
+///
+/// The manifest is determined in the following order:
+///
+/// 1. If the `CARGO_MANIFEST_DIR` environment variable is set, the `Cargo.toml` in that
+///    directory is used. Cargo sets this variable for build scripts and for `cargo run`, so the
+///    lookup operates on the package that is being built independent of the working directory.
+/// 2. Otherwise, `None` is returned and `cargo metadata` is run without a `--manifest-path`
+///    argument, so cargo searches the current working directory and its parents.
+///
+/// To override this, pass the desired manifest path to [`Metadata::load`] or
+/// [`Locator::manifest_path`], or set up a [`Context`] with a different manifest path.
+pub fn default_manifest_path() -> Option<PathBuf> {
+    env::var_os("CARGO_MANIFEST_DIR").map(|dir| PathBuf::from(dir).join("Cargo.toml"))
+}
+
+/// Searches the given directory and its parents for a `Cargo.toml` file, like
+/// `cargo locate-project` does.
+///
+/// Returns the nearest manifest, which for workspace members is the manifest of the member
+/// rather than of the workspace root. This is sufficient for all lookups of this crate, since
+/// cargo determines the workspace of a member itself. Relative paths are interpreted relative
+/// to the current working directory. Returns `None` if no manifest is found.
+///
+/// [`Locator`] uses this function to resolve manifest paths that don't exist, see
+/// [`Locator::discover_manifest`].
+pub fn discover_manifest(start_dir: &Path) -> Option<PathBuf> {
+    let start_dir = if start_dir.is_relative() {
+        env::current_dir().ok()?.join(start_dir)
+    } else {
+        start_dir.to_owned()
+    };
+    start_dir
+        .ancestors()
+        .map(|dir| dir.join("Cargo.toml"))
+        .find(|manifest| manifest.is_file())
+}
+
+/// Project metadata in the format of `cargo metadata --format-version 1`.
+///
+/// Loading the metadata once and running all lookups on it avoids running `cargo metadata`
+/// again for every query, which can take a while for large workspaces. Use
+/// [`Locator::locate_in`] to run configured lookups on it.
+///
+/// This type also makes it possible to run the dependency lookups on metadata that was obtained
+/// in some other way, e.g. from a build farm. All methods validate the metadata while walking it
+/// and report malformed input through errors, they never panic.
+#[derive(Debug, Clone)]
+pub struct Metadata {
+    inner: raw::Metadata<'static>,
+    json: OnceLock<serde_json::Value>,
+}
+
+impl Metadata {
+    /// Parses the given `cargo metadata` JSON output.
+    ///
+    /// A leading UTF-8 byte order mark is ignored. Lines before the JSON document that don't
+    /// look like JSON, e.g. log output of wrapper scripts, are skipped as well and are available
+    /// through [`Metadata::ignored_output`].
+    ///
+    /// Fails with [`CargoMetadataError::ParseJson`] if the input is not valid JSON or if it has
+    /// an unexpected structure. If leading output was skipped, the failure is reported as
+    /// [`CargoMetadataError::ParseJsonAfterIgnoredOutput`] instead, so that the unexpected
+    /// output is visible in the error.
+    pub fn parse(json: &str) -> Result<Self, CargoMetadataError> {
+        let inner = raw::Metadata::parse(json)?.into_owned();
+        Ok(Metadata {
+            inner,
+            json: OnceLock::new(),
+        })
+    }
+
+    /// Parses the output of a `cargo metadata` run, keeping the output without copying it.
+    ///
+    /// The overrides of the project are read as well, see [`Metadata::read_overrides`].
+    fn from_output(output: String) -> Result<Self, CargoMetadataError> {
+        let inner = raw::Metadata::parse_owned(output)?.with_overrides();
+        Ok(Metadata {
+            inner,
+            json: OnceLock::new(),
+        })
+    }
+
+    /// Reads the overrides of the project that are not part of the `cargo metadata` output.
+    ///
+    /// These are the `paths` overrides of the cargo configuration files, which are reported
+    /// through [`LocatedPackage::override_applied`], and the `[replace]` section of the
+    /// workspace manifest, which determines the [`OverrideKind`] of
+    /// [`LocatedPackage::source_overridden`]. Metadata loaded through cargo, e.g. by
+    /// [`Metadata::load`], reads them automatically. Metadata from [`Metadata::parse`] doesn't
+    /// access the file system unless this method is called, which reads the workspace manifest
+    /// and the configuration files that apply to the workspace root.
+    pub fn read_overrides(&mut self) -> &mut Self {
+        self.inner.read_overrides();
+        self
+    }
+
+    /// Returns the format version of the metadata, i.e. the `version` field of the JSON output.
+    pub fn format_version(&self) -> u32 {
+        self.inner.version
+    }
+
+    /// Returns the output that preceded the JSON document and was skipped during parsing.
+    ///
+    /// Returns `None` if the input started with the JSON document (optionally preceded by a byte
+    /// order mark).
+    pub fn ignored_output(&self) -> Option<&str> {
+        self.inner.ignored_output.as_deref()
+    }
+
+    /// Returns the complete JSON document, for fields that are not covered by this crate.
+    ///
+    /// The document is parsed into a [`serde_json::Value`] on the first successful call. Fails
+    /// with [`CargoMetadataError::ParseJson`] if the document can't be represented as a value,
+    /// e.g. because it's nested too deeply.
+    pub fn raw_json(&self) -> Result<&serde_json::Value, CargoMetadataError> {
+        if let Some(json) = self.json.get() {
+            return Ok(json);
+        }
+        let json = serde_json::from_str(self.inner.json())?;
+        Ok(self.json.get_or_init(|| json))
+    }
+
+    /// Returns the directory where cargo places the build output of the workspace.
+    ///
+    /// See [`locate_target_dir`] for details.
+    pub fn target_directory(&self) -> &Path {
+        Path::new(&*self.inner.target_directory)
+    }
+
+    /// Runs `cargo metadata` for the given manifest and parses its output.
+    ///
+    /// The `manifest_path` can point either to a `Cargo.toml` file or to the directory
+    /// containing it.
+    pub fn load(manifest_path: &Path) -> Result<Self, CargoMetadataError> {
+        Self::load_with_context(&Context::from_env(), Some(manifest_path))
+    }
+
+    /// Runs `cargo metadata` with the settings of the given context and parses its output.
+    ///
+    /// If no `manifest_path` is given, the manifest path of the context is used.
+    pub fn load_with_context(
+        context: &Context,
+        manifest_path: Option<&Path>,
+    ) -> Result<Self, CargoMetadataError> {
+        let output = metadata(context, manifest_path)?;
+        Self::from_output(output)
+    }
+
+    /// Runs `cargo metadata` for each of the given manifests and parses the outputs.
+    ///
+    /// At most `max_concurrency` cargo invocations run at the same time; zero is treated as one.
+    /// The results are returned in the order of the given manifest paths, with a separate result
+    /// for each path, so that one failing project doesn't prevent loading the others.
+    pub fn load_many<P: AsRef<Path> + Sync>(
+        manifest_paths: &[P],
+        max_concurrency: usize,
+    ) -> Vec<Result<Self, CargoMetadataError>> {
+        let context = Context::from_env();
+        run_concurrently(manifest_paths, max_concurrency, |manifest_path| {
+            Self::load_with_context(&context, Some(manifest_path.as_ref()))
+        })
+    }
+
+    /// Async version of [`Metadata::load`] that doesn't block the async runtime while
+    /// `cargo metadata` runs.
+    ///
+    /// Requires the `tokio` feature. Without it, this fails with
+    /// [`CargoMetadataError::FeatureDisabled`].
+    pub async fn load_async(manifest_path: &Path) -> Result<Self, CargoMetadataError> {
+        let output = nonblocking::metadata(&Context::from_env(), Some(manifest_path)).await?;
+        Self::from_output(output)
+    }
+
+    /// Locates the dependency with the given name.
+    ///
+    /// See [`locate_bootloader`] for details.
+    pub fn locate(&self, dependency_name: &str) -> Result<PathBuf, LocateError> {
+        self.inner.locate(dependency_name, &raw::Query::default())
+    }
+
+    /// Locates the dependency with the given name and returns details about the resolved package.
+    ///
+    /// See [`locate_bootloader_package`] for details.
+    pub fn locate_package(&self, dependency_name: &str) -> Result<LocatedPackage, LocateError> {
+        self.inner
+            .locate_package(dependency_name, &raw::Query::default())
+    }
+
+    /// Returns all packages in the resolved dependency graph that match the given dependency
+    /// name.
+    ///
+    /// See [`Locator::locate_candidates`] for details.
+    pub fn locate_candidates(
+        &self,
+        dependency_name: &str,
+    ) -> Result<impl Iterator<Item = LocatedPackage>, LocateError> {
+        Ok(self
+            .inner
+            .candidates(dependency_name, &raw::Query::default())?
+            .into_iter())
+    }
+
+    /// Returns all packages with the given name in the resolved dependency graph.
+    ///
+    /// See [`Locator::locate_all_versions`] for details.
+    pub fn locate_all_versions(
+        &self,
+        package_name: &str,
+    ) -> Result<Vec<LocatedPackage>, LocateError> {
+        self.inner
+            .all_versions(package_name, &raw::Query::default())
+    }
+
+    /// Explains why the packages with the given name are part of the dependency graph.
+    ///
+    /// Returns one chain for every version of the package that is reachable from the root
+    /// package, ordered by version. Each chain is the shortest path from the root package to
+    /// the package, which answers questions like "why is `bootloader` 0.9 used when the
+    /// manifest requires 0.11?" in error messages:
+    ///
+    /// ```no_run
+    /// use bootloader_locator::Metadata;
+    /// use std::path::Path;
+    ///
+    /// let metadata = Metadata::load(Path::new("kernel"))?;
+    /// for chain in metadata.explain("bootloader")? {
+    ///     println!("{}", chain);
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    ///
+    /// The root package is determined as for [`Locator`] lookups. Versions that are only
+    /// reachable from other workspace members are not included, see [`Locator::explain`] for
+    /// selecting the member.
+    pub fn explain(&self, package_name: &str) -> Result<Vec<DependencyChain>, LocateError> {
+        self.inner.explain(package_name, &raw::Query::default())
+    }
+
+    /// Reports which dependents requested each enabled feature of the given dependency.
+    ///
+    /// The dependency is located as for [`locate_package`](Metadata::locate_package). Returns
+    /// one entry for every feature in its [`features`](LocatedPackage::features), in the same
+    /// order. All packages in the dependency graph that depend on the same package are taken
+    /// into account, since cargo unifies their features. This allows tools to print actionable
+    /// warnings:
+    ///
+    /// ```no_run
+    /// use bootloader_locator::Metadata;
+    /// use std::path::Path;
+    ///
+    /// let metadata = Metadata::load(Path::new("kernel"))?;
+    /// for origin in metadata.feature_origin("bootloader")? {
+    ///     for request in &origin.requested_by {
+    ///         println!("`{}` is enabled by `{}`", origin.feature, request.package.name);
+    ///     }
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn feature_origin(&self, dependency_name: &str) -> Result<Vec<FeatureOrigin>, LocateError> {
+        self.inner
+            .feature_origin(dependency_name, &raw::Query::default())
+    }
+
+    /// Returns the kinds of the root package's dependency on the given dependency.
+    ///
+    /// A dependency can be declared in multiple dependency tables at once, e.g. as a normal
+    /// dependency for one target and as a build dependency. Each declaration results in one entry,
+    /// including the target platform it is restricted to:
+    ///
+    /// ```toml
+    /// [target.'cfg(target_os = "none")'.dependencies]
+    /// bootloader = "0.9"
+    /// ```
+    ///
+    /// results in a [`DepKind::Normal`] entry with a `target` of `cfg(target_os = "none")`.
+    ///
+    /// The information is only available with cargo 1.41 or newer. For older versions, an empty
+    /// list is returned.
+    pub fn dependency_kinds(&self, dependency_name: &str) -> Result<Vec<DepKindInfo>, LocateError> {
+        self.inner
+            .dependency_kinds(dependency_name, &raw::Query::default())
+    }
+
+    /// Checks whether the active Rust toolchain is recent enough for the given dependency.
+    ///
+    /// Compares the `rust-version` field of the dependency's manifest against the version of the
+    /// `rustc` executable returned by [`toolchain::rustc_info`]. Returns an advisory if the
+    /// toolchain is older than required, so that tools can report the problem before starting a
+    /// build that is bound to fail. Returns `None` if the toolchain is recent enough or if the
+    /// dependency doesn't specify a `rust-version`.
+    pub fn check_rust_version(
+        &self,
+        dependency_name: &str,
+    ) -> Result<Option<RustVersionAdvisory>, LocateError> {
+        let package = self
+            .inner
+            .dependency_package(dependency_name, &raw::Query::default())?;
+        let required = match &package.rust_version {
+            Some(required) => required,
+            None => return Ok(None),
+        };
+        let required_parsed =
+            toolchain::parse_version(required).ok_or_else(|| LocateError::MetadataInvalid {
+                field: format!("packages[{}].rust_version", package.id),
+            })?;
+
+        let rustc = toolchain::rustc_info()?;
+        let active_parsed = rustc
+            .version_components()
+            .ok_or_else(|| toolchain::ProbeError::InvalidOutput(rustc.version.clone()))?;
+
+        if active_parsed >= required_parsed {
+            Ok(None)
+        } else {
+            Ok(Some(RustVersionAdvisory {
+                required: required.to_string(),
+                active: rustc.version,
+            }))
+        }
+    }
+
+    /// Looks up the package that the given manifest file belongs to.
+    ///
+    /// See [`package_for_manifest`] for details.
+    pub fn package_for_manifest(&self, manifest_path: &Path) -> Result<Package, LocateError> {
+        self.inner.package_for_manifest(manifest_path)
+    }
+
+    /// Returns details about the package with the given ID.
+    ///
+    /// Unlike lookups by name, this is exact and unambiguous, so an ID recorded from a
+    /// previous lookup (e.g. [`LocatedPackage::id`]) can be used to find the same package
+    /// again without name matching. IDs are compared by their string representation, which
+    /// changed in cargo 1.77, so IDs recorded with an older cargo version are not found.
+    pub fn package_by_id(&self, id: &PackageId) -> Result<LocatedPackage, LocateError> {
+        self.inner.package_by_id(id)
+    }
+
+    /// Returns the default members of the workspace.
+    ///
+    /// See [`workspace_default_members`] for details.
+    pub fn workspace_default_members(&self) -> Result<Vec<Package>, LocateError> {
+        self.inner.workspace_default_members()
+    }
+
+    /// Returns details about the root package or the given workspace member, e.g. the kernel.
+    ///
+    /// The package is determined as for [`Locator`] lookups: the root package of the manifest,
+    /// the single default member of a virtual workspace, or the member selected as for
+    /// [`Locator::workspace_member`]. Its [`features`](LocatedPackage::features) are the
+    /// features that are enabled for the package in the resolved dependency graph.
+    pub fn root_package(
+        &self,
+        workspace_member: Option<&str>,
+    ) -> Result<LocatedPackage, LocateError> {
+        let query = raw::Query {
+            workspace_member,
+            ..raw::Query::default()
+        };
+        self.inner.located_root(&query)
+    }
+
+    /// Returns the names of the `[[bin]]` targets of the root package or of the given workspace
+    /// member.
+    ///
+    /// Kernels often have multiple binary targets, e.g. a normal kernel and a stress-test kernel.
+    /// The root package is determined as for [`Locator`] lookups.
+    pub fn bin_targets(&self, workspace_member: Option<&str>) -> Result<Vec<String>, LocateError> {
+        let query = raw::Query {
+            workspace_member,
+            ..raw::Query::default()
+        };
+        self.inner.targets_of_kind(&query, "bin")
+    }
+
+    /// Returns the names of the integration test targets of the root package or of the given
+    /// workspace member, i.e. the files in its `tests` directory.
+    ///
+    /// Kernels following the pattern of the _Writing an OS in Rust_ blog build each integration
+    /// test as a separate bootable executable, see the `test_kernels` module.
+    pub fn test_targets(&self, workspace_member: Option<&str>) -> Result<Vec<String>, LocateError> {
+        let query = raw::Query {
+            workspace_member,
+            ..raw::Query::default()
+        };
+        self.inner.targets_of_kind(&query, "test")
+    }
+
+    /// Returns the path of the kernel executable that `cargo build` produces for a binary
+    /// target of the root package or of the given workspace member.
+    ///
+    /// The `target` is either a target triple or the path of a target specification file,
+    /// like the `--target` argument of cargo. The `profile` is the name of a cargo profile,
+    /// e.g. `dev` or `release`. If no `bin_name` is given, the `default-run` binary of the
+    /// package is used, or its only binary target. Fails with [`LocateError::BinaryNotFound`]
+    /// if the package has no binary target with the given name and with
+    /// [`LocateError::AmbiguousBinary`] if no name is given and the package has multiple
+    /// binary targets.
+    ///
+    /// The returned path can be passed to `BootloaderBuilder::new` of the `build` module for
+    /// building the disk image of the selected kernel. The path is computed from the
+    /// target directory of the metadata, so it doesn't need to exist yet.
+    pub fn kernel_binary(
+        &self,
+        workspace_member: Option<&str>,
+        target: &str,
+        profile: &str,
+        bin_name: Option<&str>,
+    ) -> Result<PathBuf, LocateError> {
+        let query = raw::Query {
+            workspace_member,
+            ..raw::Query::default()
+        };
+        self.inner.kernel_binary(&query, target, profile, bin_name)
+    }
+
+    /// Returns the `[package.metadata]` table of the root package or of the given workspace
+    /// member.
+    pub(crate) fn package_metadata(
+        &self,
+        workspace_member: Option<&str>,
+    ) -> Result<&serde_json::Value, LocateError> {
+        let query = raw::Query {
+            workspace_member,
+            ..raw::Query::default()
+        };
+        Ok(&self.inner.root_package(&query)?.metadata)
+    }
+}
+
+/// The active Rust toolchain is older than the `rust-version` required by a dependency.
+///
+/// Returned by [`Metadata::check_rust_version`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RustVersionAdvisory {
+    /// The `rust-version` of the dependency.
+    pub required: String,
+    /// The version of the active `rustc`.
+    pub active: String,
+}
+
+/// Canonicalizes the given path, falling back to the path itself if this fails (e.g. because
+/// the file no longer exists).
+///
+/// On Windows, the `\\?\` prefix that `fs::canonicalize` adds is removed again through
+/// [`simplify_path`], so that canonicalized paths can be compared with the paths reported by
+/// cargo and passed to other programs.
+fn canonicalize(path: &Path) -> PathBuf {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+    let simplified = match path.to_str().map(simplify_path) {
+        Some(Cow::Owned(simplified)) => Some(simplified),
+        _ => None,
+    };
+    simplified.map(PathBuf::from).unwrap_or(path)
+}
+
+/// Turns verbatim Windows paths into regular paths and uses `\` as the only separator.
+///
+/// Cargo sometimes reports paths with the `\\?\` prefix of verbatim paths and with mixed
+/// separators, which breaks string comparisons and confuses other programs, e.g. QEMU.
+/// Following the `dunce` crate, the prefix is only removed if the remaining path is valid
+/// without it, i.e. if it's not longer than `MAX_PATH` and has no reserved components.
+/// `\\?\UNC\server\share` becomes `\\server\share`. On other platforms, paths are
+/// returned unchanged.
+fn simplify_path(path: &str) -> Cow<'_, str> {
+    const MAX_PATH: usize = 260;
+    const RESERVED: &[&str] = &[
+        "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+        "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+    ];
+    if !cfg!(windows) {
+        return Cow::Borrowed(path);
+    }
+    let (prefix, rest) = if let Some(rest) = path.strip_prefix(r"\\?\UNC\") {
+        (r"\\", rest)
+    } else if let Some(rest) = path.strip_prefix(r"\\?\") {
+        let is_drive =
+            rest.as_bytes().get(1) == Some(&b':') && rest.as_bytes()[0].is_ascii_alphabetic();
+        if is_drive {
+            ("", rest)
+        } else {
+            return Cow::Borrowed(path);
+        }
+    } else if path.contains('/') {
+        return Cow::Owned(path.replace('/', "\\"));
+    } else {
+        return Cow::Borrowed(path);
+    };
+    let is_valid = prefix.len() + rest.len() <= MAX_PATH
+        && rest.split(['\\', '/']).all(|component| {
+            let stem = component.split('.').next().unwrap_or_default();
+            !RESERVED.iter().any(|name| stem.eq_ignore_ascii_case(name))
+                && !component.ends_with('.')
+                && !component.ends_with(' ')
+        });
+    if !is_valid {
+        return Cow::Borrowed(path);
+    }
+    Cow::Owned(format!("{}{}", prefix, rest.replace('/', "\\")))
+}
+
+/// A package in the project metadata.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Package {
+    /// The package ID used by cargo, e.g. in the `resolve` table.
+    pub id: PackageId,
+    /// The name of the package.
+    pub name: String,
+    /// The version of the package.
+    pub version: String,
+}
+
+impl Package {
+    pub(crate) fn from_raw(package: &raw::Package) -> Self {
+        Package {
+            id: PackageId::from_metadata(&package.id, &package.name, &package.version),
+            name: package.name.clone().into_owned(),
+            version: package.version.clone().into_owned(),
+        }
+    }
+}
+
+/// A chain of dependencies from the root package to a package in the dependency graph.
+///
+/// Returned by [`Metadata::explain`]. The [`Display`](fmt::Display) implementation prints the
+/// chain on one line, e.g. `kernel 0.1.0 -> x86_64 0.14.10 -> bootloader 0.9.23`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DependencyChain {
+    /// The packages of the chain, starting with the root package and ending with the package
+    /// that was asked for.
+    ///
+    /// Every package is a dependency of its predecessor. The chain consists of only the root
+    /// package if the root package itself was asked for.
+    pub packages: Vec<Package>,
+}
+
+impl fmt::Display for DependencyChain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, package) in self.packages.iter().enumerate() {
+            if i > 0 {
+                write!(f, " -> ")?;
+            }
+            write!(f, "{} {}", package.name, package.version)?;
+        }
+        Ok(())
+    }
+}
+
+/// Explains why a feature of a dependency is enabled.
+///
+/// Returned by [`Metadata::feature_origin`]. Cargo unifies the features that all dependents
+/// request, so a feature might be enabled although the package that is being built doesn't
+/// request it, e.g. the `bios` feature of the bootloader when only one of several workspace
+/// members needs it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeatureOrigin {
+    /// The name of the enabled feature.
+    pub feature: String,
+    /// The dependents that requested the feature.
+    ///
+    /// Features that are enabled through default features, e.g. `bios` through `default`, are
+    /// only requested indirectly, see [`implied_by`](Self::implied_by).
+    pub requested_by: Vec<FeatureRequest>,
+    /// The enabled features of the package itself that enable the feature, e.g. `default`.
+    pub implied_by: Vec<String>,
+}
+
+/// A dependent that requested a feature, see [`FeatureOrigin::requested_by`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeatureRequest {
+    /// The dependent package.
+    pub package: Package,
+    /// The enabled feature of the dependent through which the feature was requested, e.g.
+    /// `uefi` for a `uefi = ["bootloader/uefi"]` entry.
+    ///
+    /// `None` if the feature is requested in the dependency declaration, including the
+    /// `default` feature of declarations without `default-features = false`.
+    pub through_feature: Option<String>,
+}
+
+/// A dependency package as resolved by cargo.
+///
+/// Returned by [`locate_bootloader_package`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocatedPackage {
+    /// The package ID used by cargo.
+    pub id: PackageId,
+    /// The name of the package.
+    pub name: String,
+    /// The version of the package.
+    pub version: String,
+    /// The path to the `Cargo.toml` of the package.
+    pub manifest_path: PathBuf,
+    /// The source of the package as reported by cargo, e.g.
+    /// `registry+https://github.com/rust-lang/crates.io-index` or
+    /// `git+https://github.com/rust-osdev/bootloader?branch=main#<commit>`.
+    ///
+    /// `None` for path dependencies.
+    pub source: Option<String>,
+    /// The features that are enabled for the package in the resolved dependency graph.
+    pub features: Vec<String>,
+    /// The declared source of the dependency, if the package was resolved from a different
+    /// source through a `[patch]` or `[replace]` section.
+    ///
+    /// This explains surprising paths, e.g. a git checkout of a fork instead of the crates.io
+    /// release. Only set by lookups of a dependency like [`locate_bootloader_package`], since
+    /// the declaration is needed for the comparison. For metadata from [`Metadata::parse`],
+    /// `[replace]` entries are reported as [`OverrideKind::Patch`] unless
+    /// [`Metadata::read_overrides`] was called.
+    pub source_overridden: Option<OriginalSource>,
+    /// The canonicalized directory of the `paths` override in a cargo configuration file (e.g.
+    /// `.cargo/config.toml`) that the package was loaded from.
+    ///
+    /// Cargo uses such overrides instead of the registry or git package without updating
+    /// `Cargo.lock`, so tools should warn that the build uses a local copy. The configuration
+    /// files are looked up from the workspace root when the metadata is loaded. For metadata
+    /// from [`Metadata::parse`], this is only set after [`Metadata::read_overrides`].
+    pub override_applied: Option<PathBuf>,
+    /// Whether the dependency is declared as optional, so that it is only part of the
+    /// dependency graph if one of the [`activating_features`](Self::activating_features) is
+    /// enabled.
+    ///
+    /// Only set by lookups of a dependency like [`locate_bootloader_package`]. If the
+    /// dependency is declared multiple times, e.g. for different targets, it is only optional
+    /// if all declarations are.
+    pub optional: bool,
+    /// The features of the declaring package that activate the optional dependency, either
+    /// directly or by enabling another activating feature.
+    ///
+    /// Empty if the dependency is not [`optional`](Self::optional).
+    pub activating_features: Vec<String>,
+    /// The build targets of the package, e.g. its library and its `[[bin]]` targets.
+    pub targets: Vec<Target>,
+    raw: RawJson,
+    target_directory: PathBuf,
+    default_run: Option<String>,
+}
+
+/// The JSON text of a package in the `cargo metadata` output, parsed on first access.
+///
+/// Lookups return many packages whose raw JSON is never inspected, so only the text is copied
+/// from the output. Packages are compared by their text, regardless of whether it was parsed.
+#[derive(Debug, Clone)]
+struct RawJson {
+    json: Box<str>,
+    value: OnceLock<serde_json::Value>,
+}
+
+impl RawJson {
+    fn new(json: &str) -> Self {
+        RawJson {
+            json: json.into(),
+            value: OnceLock::new(),
+        }
+    }
+
+    fn value(&self) -> Result<&serde_json::Value, CargoMetadataError> {
+        if let Some(value) = self.value.get() {
+            return Ok(value);
+        }
+        let value = serde_json::from_str(&self.json)?;
+        Ok(self.value.get_or_init(|| value))
+    }
+}
+
+impl PartialEq for RawJson {
+    fn eq(&self, other: &Self) -> bool {
+        self.json == other.json
+    }
+}
+
+impl Eq for RawJson {}
+
+/// A build target of a package, as listed in the `targets` array of `cargo metadata`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    /// The name of the target, e.g. the name of the executable for binary targets.
+    pub name: String,
+    /// The kinds of the target, e.g. `bin`, `lib`, `proc-macro`, `test`, or `custom-build`.
+    ///
+    /// Library targets can have multiple kinds if they set several `crate-type`s, e.g. `rlib`
+    /// and `staticlib`.
+    pub kind: Vec<String>,
+    /// The path of the root source file of the target, e.g. `src/main.rs`.
+    pub src_path: PathBuf,
+}
+
+impl Target {
+    /// Returns whether this is a `[[bin]]` target.
+    pub fn is_bin(&self) -> bool {
+        self.kind.iter().any(|kind| kind == "bin")
+    }
+
+    /// Returns whether this is the library target of the package, for any crate type.
+    pub fn is_lib(&self) -> bool {
+        self.kind.iter().any(|kind| {
+            matches!(
+                &**kind,
+                "lib" | "rlib" | "dylib" | "cdylib" | "staticlib" | "proc-macro"
+            )
+        })
+    }
+}
+
+/// The declared source of a dependency that was overridden.
+///
+/// Stored in [`LocatedPackage::source_overridden`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OriginalSource {
+    /// The source in the dependency declaration, e.g.
+    /// `registry+https://github.com/rust-lang/crates.io-index`.
+    ///
+    /// `None` for path dependencies.
+    pub source: Option<String>,
+    /// The version requirement in the dependency declaration, e.g. `^0.9.23`.
+    pub version_req: String,
+    /// Whether the dependency was overridden through `[patch]` or `[replace]`.
+    pub kind: OverrideKind,
+}
+
+/// The manifest section through which a dependency was overridden.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverrideKind {
+    /// A `[patch]` section, either in the workspace manifest or in a cargo configuration file.
+    Patch,
+    /// The deprecated `[replace]` section of the workspace manifest.
+    Replace,
+    /// A `paths` override of a cargo configuration file, see
+    /// [`LocatedPackage::override_applied`].
+    Paths,
+}
+
+impl LocatedPackage {
+    /// Detects which generation of the `bootloader` crate this package is.
+    ///
+    /// Versions before 0.11 are reported as [`BootloaderKind::Legacy09`], newer versions as
+    /// [`BootloaderKind::Modern011`] together with the enabled firmware features. Returns `None`
+    /// if the version is not a valid semver version.
+    ///
+    /// Only the version and features are inspected, so the result is meaningless for packages
+    /// other than `bootloader`.
+    pub fn detect_kind(&self) -> Option<BootloaderKind> {
+        let version = semver::Version::parse(&self.version).ok()?;
+        if (version.major, version.minor) < (0, 11) {
+            Some(BootloaderKind::Legacy09)
+        } else {
+            Some(BootloaderKind::Modern011 {
+                bios: self.has_feature("bios"),
+                uefi: self.has_feature("uefi"),
+            })
+        }
+    }
+
+    /// Probes which boot paths the bootloader can produce.
+    ///
+    /// Combines the [kind](LocatedPackage::detect_kind), the enabled features, the targets,
+    /// and the [prebuilt artifacts](LocatedPackage::artifacts) of the package. Versions before
+    /// 0.11 only support BIOS, through the `bootloader` binary target or a prebuilt BIOS image.
+    /// Newer versions support the firmware types whose feature is enabled or for which prebuilt
+    /// binaries are shipped. Returns `None` if the version is not a valid semver version.
+    ///
+    /// Like [`detect_kind`](LocatedPackage::detect_kind), the result is meaningless for
+    /// packages other than `bootloader`.
+    pub fn capabilities(&self) -> Option<BootCapabilities> {
+        let kind = self.detect_kind()?;
+        let artifacts = self.artifacts();
+        let prebuilt = |kinds: &[PrebuiltKind]| {
+            artifacts
+                .iter()
+                .any(|artifact| kinds.contains(&artifact.kind))
+        };
+        let capabilities = match kind {
+            BootloaderKind::Legacy09 => BootCapabilities {
+                kind,
+                bios: self.bins().any(|bin| bin.name == "bootloader")
+                    || prebuilt(&[PrebuiltKind::BiosImage]),
+                uefi: false,
+                kernel_input: KernelInput::Prelinked,
+            },
+            BootloaderKind::Modern011 { bios, uefi } => BootCapabilities {
+                kind,
+                bios: bios || prebuilt(&[PrebuiltKind::BiosImage, PrebuiltKind::BiosBootSector]),
+                uefi: uefi || prebuilt(&[PrebuiltKind::UefiImage, PrebuiltKind::UefiBootloader]),
+                kernel_input: KernelInput::Elf,
+            },
+        };
+        Some(capabilities)
+    }
+
+    /// Returns whether the given feature is enabled for the package in the resolved dependency
+    /// graph, see [`features`](LocatedPackage::features).
+    ///
+    /// For `bootloader` 0.11 and later, the `bios` and `uefi` features determine which disk
+    /// images can be built.
+    pub fn has_feature(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+
+    /// Returns the JSON object of the package in the `cargo metadata` output.
+    ///
+    /// This gives access to fields that are not covered by this type, e.g. `links` or
+    /// `rust_version`, without running `cargo metadata` again. Like [`Metadata::raw_json`], the
+    /// object is parsed into a [`serde_json::Value`] on the first successful call.
+    pub fn raw(&self) -> Result<&serde_json::Value, CargoMetadataError> {
+        self.raw.value()
+    }
+
+    /// Returns the root directory of the package, i.e. the directory containing its
+    /// `Cargo.toml`.
+    ///
+    /// Returns `None` if the manifest path has no parent directory, which only happens for
+    /// malformed metadata, e.g. an empty manifest path.
+    pub fn root_dir(&self) -> Option<&Path> {
+        self.manifest_path.parent()
+    }
+
+    /// Parses the [`source`](Self::source) of the package.
+    ///
+    /// For git dependencies, this gives the repository URL, the branch, tag, or revision
+    /// requested in the manifest, and the commit that cargo resolved it to, e.g. for recording
+    /// the provenance of a boot image.
+    pub fn source_details(&self) -> Source {
+        Source::from_metadata(self.source.as_deref())
+    }
+
+    /// Returns the `[[bin]]` targets of the package.
+    pub fn bins(&self) -> impl Iterator<Item = &Target> {
+        self.targets.iter().filter(|target| target.is_bin())
+    }
+
+    /// Returns the library target of the package, if it has one.
+    pub fn lib(&self) -> Option<&Target> {
+        self.targets.iter().find(|target| target.is_lib())
+    }
+
+    /// Returns the path of the executable that `cargo build` produces for the package with the
+    /// given profile and compilation target.
+    ///
+    /// The `profile` is the name of a cargo profile, e.g. `dev`, `release`, or a custom profile.
+    /// The `target` is either a target triple or the path of a target specification file, like
+    /// the `--target` argument of cargo. The executable is the `default-run` binary of the
+    /// package, or its only binary target. For packages with multiple binary targets and no
+    /// `default-run`, the binary named after the package is assumed, like for `cargo run`.
+    ///
+    /// The path is computed from the target directory of the `cargo metadata` output that the
+    /// package was located in, so it doesn't need to exist yet. Note that the target directory
+    /// is the one of the project that depends on the package, so the path is only correct if
+    /// the package is built as part of that project.
+    pub fn artifact_path(&self, profile: &str, target: &str) -> PathBuf {
+        let mut bins = self.bins();
+        let bin_name = match (&self.default_run, bins.next(), bins.next()) {
+            (Some(default_run), _, _) => default_run,
+            (None, Some(bin), None) => &bin.name,
+            (None, _, _) => &self.name,
+        };
+        raw::artifact_path(&self.target_directory, target, profile, bin_name)
+    }
+
+    /// Verifies the package sources against the checksum recorded in the given `Cargo.lock`
+    /// file.
+    ///
+    /// For packages extracted from a registry, the `.crate` archive in the registry cache of
+    /// cargo is hashed, since the lock file records the checksum of the archive. Modifications
+    /// of the extracted sources are not detected this way. For packages vendored through
+    /// `cargo vendor`, the checksum in the `.cargo-checksum.json` file is compared and every
+    /// file of the package is hashed, so modified sources are detected too.
+    ///
+    /// Path and git dependencies have no checksum and fail with
+    /// [`ChecksumError::NoChecksum`](checksum::ChecksumError::NoChecksum). Requires the
+    /// `checksum` feature.
+    #[cfg(feature = "checksum")]
+    pub fn verify_checksum(&self, lock_file: &Path) -> Result<(), checksum::ChecksumError> {
+        checksum::verify(self, lock_file)
+    }
+
+    /// Enumerates the prebuilt boot binaries that are shipped in the package directory.
+    ///
+    /// Modern `bootloader` releases can include precompiled BIOS stages and UEFI binaries, so
+    /// that they don't need to be built from source. The root directory of the package and its
+    /// direct subdirectories are searched for the known file names listed in
+    /// [`PrebuiltKind::file_names`]. Only existing files are returned, sorted by kind and path.
+    ///
+    /// Returns an empty list for packages that don't ship any prebuilt binaries, e.g. all
+    /// `bootloader` versions before 0.11.
+    pub fn artifacts(&self) -> Vec<PrebuiltArtifact> {
+        let root = match self.root_dir() {
+            Some(root) => root,
+            None => return Vec::new(),
+        };
+        let mut dirs = vec![root.to_owned()];
+        if let Ok(entries) = fs::read_dir(root) {
+            dirs.extend(
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .map(|entry| entry.path())
+                    .filter(|path| path.is_dir() && !path.ends_with("target")),
+            );
+        }
+
+        let mut artifacts = Vec::new();
+        for dir in &dirs {
+            for &kind in PrebuiltKind::ALL {
+                for name in kind.file_names() {
+                    let path = dir.join(name);
+                    if path.is_file() {
+                        artifacts.push(PrebuiltArtifact { kind, path });
+                    }
+                }
+            }
+        }
+        artifacts.sort();
+        artifacts
+    }
+}
+
+/// A prebuilt boot binary shipped in a package directory.
+///
+/// Returned by [`LocatedPackage::artifacts`].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PrebuiltArtifact {
+    /// What the binary is used for.
+    pub kind: PrebuiltKind,
+    /// The absolute path to the binary.
+    pub path: PathBuf,
+}
+
+/// The kind of a [`PrebuiltArtifact`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[non_exhaustive]
+pub enum PrebuiltKind {
+    /// A complete BIOS disk image.
+    BiosImage,
+    /// The boot sector of the BIOS bootloader, i.e. its first stage.
+    BiosBootSector,
+    /// The second stage of the BIOS bootloader.
+    BiosStage2,
+    /// The third stage of the BIOS bootloader.
+    BiosStage3,
+    /// The fourth stage of the BIOS bootloader.
+    BiosStage4,
+    /// A complete UEFI disk image.
+    UefiImage,
+    /// The UEFI bootloader executable.
+    UefiBootloader,
+}
+
+impl PrebuiltKind {
+    const ALL: &'static [PrebuiltKind] = &[
+        PrebuiltKind::BiosImage,
+        PrebuiltKind::BiosBootSector,
+        PrebuiltKind::BiosStage2,
+        PrebuiltKind::BiosStage3,
+        PrebuiltKind::BiosStage4,
+        PrebuiltKind::UefiImage,
+        PrebuiltKind::UefiBootloader,
+    ];
+
+    /// Returns the file names under which binaries of this kind are recognized.
+    pub fn file_names(self) -> &'static [&'static str] {
+        match self {
+            PrebuiltKind::BiosImage => &["bios.img", "boot-bios.img"],
+            PrebuiltKind::BiosBootSector => &[
+                "bootloader-x86_64-bios-boot-sector",
+                "boot-sector.bin",
+                "bios-boot-sector.bin",
+            ],
+            PrebuiltKind::BiosStage2 => &["bootloader-x86_64-bios-stage-2", "bios-stage-2.bin"],
+            PrebuiltKind::BiosStage3 => &["bootloader-x86_64-bios-stage-3", "bios-stage-3.bin"],
+            PrebuiltKind::BiosStage4 => &["bootloader-x86_64-bios-stage-4", "bios-stage-4.bin"],
+            PrebuiltKind::UefiImage => &["uefi.img", "boot-uefi.img"],
+            PrebuiltKind::UefiBootloader => {
+                &["bootloader-x86_64-uefi.efi", "boot-uefi.efi", "BOOTX64.EFI"]
+            }
+        }
+    }
+}
+
+impl fmt::Display for PrebuiltKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            PrebuiltKind::BiosImage => "bios-image",
+            PrebuiltKind::BiosBootSector => "bios-boot-sector",
+            PrebuiltKind::BiosStage2 => "bios-stage-2",
+            PrebuiltKind::BiosStage3 => "bios-stage-3",
+            PrebuiltKind::BiosStage4 => "bios-stage-4",
+            PrebuiltKind::UefiImage => "uefi-image",
+            PrebuiltKind::UefiBootloader => "uefi-bootloader",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The generation of the `bootloader` crate, which determines how boot images are created.
+///
+/// Returned by [`LocatedPackage::detect_kind`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootloaderKind {
+    /// Versions before 0.11, which are built together with the kernel through `bootimage` or
+    /// the builder of the bootloader crate.
+    Legacy09,
+    /// Version 0.11 and later, where the kernel uses the `bootloader_api` crate and images are
+    /// created through the disk image builder of the `bootloader` crate.
+    Modern011 {
+        /// Whether the `bios` feature is enabled.
+        bios: bool,
+        /// Whether the `uefi` feature is enabled.
+        uefi: bool,
+    },
+}
+
+/// The boot paths that a located bootloader can produce.
+///
+/// Returned by [`LocatedPackage::capabilities`], so that image-building tools can branch on
+/// what the bootloader supports instead of on its version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BootCapabilities {
+    /// The generation of the bootloader, see [`LocatedPackage::detect_kind`].
+    pub kind: BootloaderKind,
+    /// Whether BIOS disk images can be created.
+    pub bios: bool,
+    /// Whether UEFI disk images can be created.
+    pub uefi: bool,
+    /// How the kernel is passed to the bootloader.
+    pub kernel_input: KernelInput,
+}
+
+/// How the kernel is passed to the bootloader, see [`BootCapabilities::kernel_input`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KernelInput {
+    /// The kernel is linked into the bootloader executable when the bootloader is built, with
+    /// its ELF file given through the `KERNEL` environment variable. This is how versions
+    /// before 0.11 work, see the `build` module.
+    Prelinked,
+    /// The kernel ELF file is placed on the boot partition by the disk image builder and
+    /// loaded by the bootloader at boot time. This is how version 0.11 and later work.
+    Elf,
+}
+
+/// Describes one declaration of a dependency, as reported in the `dep_kinds` field of the
+/// resolve graph.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DepKindInfo {
+    /// The dependency table the dependency is declared in.
+    pub kind: DepKind,
+    /// The target platform the dependency is restricted to, either as a target triple or as a
+    /// `cfg(..)` expression.
+    ///
+    /// `None` if the dependency applies to all targets.
+    pub target: Option<String>,
+}
+
+/// Determines how a dependency name is matched against the dependencies of a package.
+///
+/// Set through [`Locator::name_matching`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NameMatching {
+    /// Matches the key of the dependency in the manifest.
+    ///
+    /// For renamed dependencies like `my-bootloader = { package = "bootloader", .. }`, this is
+    /// the new name `my-bootloader`. Dashes and underscores are treated as equivalent, since
+    /// cargo reports the key as a crate name. This is the default.
+    #[default]
+    Key,
+    /// Matches the name of the dependency package, e.g. `bootloader` for renamed dependencies.
+    PackageName,
+    /// Matches either the key or the package name.
+    ///
+    /// If multiple dependencies match, the first one in the resolve graph is used.
+    Any,
+}
+
+/// Determines how [`Locator::locate`] resolves the dependency.
+///
+/// Set through [`Locator::backend`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    /// Runs `cargo metadata`. This is the default.
+    #[default]
+    CargoMetadata,
+    /// Reads the `Cargo.lock` file and the cargo registry cache without spawning cargo.
+    ///
+    /// This only works for direct dependencies whose resolution is unambiguous from the lock
+    /// file, i.e. non-optional dependencies of a package that don't depend on the target
+    /// platform, overrides through `[patch]` or `[replace]`, or source replacement, and whose
+    /// sources are already downloaded. In all other cases, e.g. if the locked version doesn't
+    /// match the requirement of the manifest, `cargo metadata` is run instead.
+    Lockfile,
+    /// Runs `cargo metadata --no-deps` and reads the dependency declarations of the root package.
+    ///
+    /// This skips the dependency resolution, which needs network access if the dependencies
+    /// are not downloaded yet, but only works for non-optional path dependencies, e.g. a local
+    /// fork of the `bootloader` crate. In all other cases, and for queries that need the
+    /// resolved versions or sources, `cargo metadata` is run again with full resolution.
+    NoDeps,
+}
+
+/// Restricts the source that a located dependency must come from.
+///
+/// Set through [`Locator::source`].
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum SourceFilter {
+    /// A package registry, e.g. crates.io.
+    Registry,
+    /// A git repository.
+    Git {
+        /// The revision that the dependency must be checked out at.
+        ///
+        /// Matches a prefix of the resolved commit hash or the `rev`, `tag`, or `branch` given in
+        /// the manifest. `None` accepts any revision.
+        rev: Option<String>,
+    },
+    /// A local path, e.g. a path dependency or a `[patch]` override with a path.
+    Path,
+    /// Any source. This is the default.
+    #[default]
+    Any,
+}
+
+impl SourceFilter {
+    /// Checks whether the given `source` field of a package matches the filter.
+    ///
+    /// The `source` field is `None` for path dependencies.
+    fn matches(&self, source: Option<&str>) -> bool {
+        match (self, source) {
+            (SourceFilter::Any, _) => true,
+            (SourceFilter::Path, source) => source.is_none(),
+            (SourceFilter::Registry, Some(source)) => {
+                source.starts_with("registry+") || source.starts_with("sparse+")
+            }
+            (SourceFilter::Git { rev: filter }, Some(source)) => {
+                let (reference, rev) = match Source::from_metadata(Some(source)) {
+                    Source::Git { reference, rev, .. } => (reference, rev),
+                    _ => return false,
+                };
+                let filter = match filter {
+                    Some(filter) => filter,
+                    None => return true,
+                };
+                rev.is_some_and(|rev| rev.starts_with(filter.as_str()))
+                    || reference.is_some_and(|reference| reference.name() == filter)
+            }
+            (_, None) => false,
+        }
+    }
+}
+
+impl fmt::Display for SourceFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SourceFilter::Registry => write!(f, "registry"),
+            SourceFilter::Git { rev: Some(rev) } => write!(f, "git (revision `{}`)", rev),
+            SourceFilter::Git { rev: None } => write!(f, "git"),
+            SourceFilter::Path => write!(f, "path"),
+            SourceFilter::Any => write!(f, "any"),
+        }
+    }
+}
+
+/// The parsed source of a package.
+///
+/// Returned by [`LocatedPackage::source_details`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// A package registry, e.g. crates.io.
+    Registry {
+        /// The URL of the registry index, e.g. `https://github.com/rust-lang/crates.io-index`.
+        ///
+        /// Sparse registries keep their `sparse+` prefix, e.g. `sparse+https://index.crates.io/`.
+        url: String,
+    },
+    /// A git repository.
+    Git {
+        /// The URL of the repository, e.g. `https://github.com/rust-osdev/bootloader`.
+        url: String,
+        /// The branch, tag, or revision given in the manifest, `None` for the default branch.
+        reference: Option<GitReference>,
+        /// The full hash of the commit that the dependency is checked out at.
+        ///
+        /// `None` if cargo didn't report it, e.g. in the package IDs of cargo 1.77 and newer.
+        rev: Option<String>,
+    },
+    /// A local path, e.g. a path dependency or a `[patch]` override with a path.
+    Path,
+    /// A source kind that is not known to this crate, given in the format reported by cargo.
+    Unknown(String),
+}
+
+impl Source {
+    /// Parses the `source` field of a package in the `cargo metadata` output.
+    ///
+    /// The field is `None` for path dependencies.
+    pub(crate) fn from_metadata(source: Option<&str>) -> Self {
+        let source = match source {
+            Some(source) => source,
+            None => return Source::Path,
+        };
+        if let Some(url) = source.strip_prefix("registry+") {
+            return Source::Registry {
+                url: url.to_owned(),
+            };
+        }
+        if source.starts_with("sparse+") {
+            return Source::Registry {
+                url: source.to_owned(),
+            };
+        }
+        if source.starts_with("path+") {
+            return Source::Path;
+        }
+        let url = match source.strip_prefix("git+") {
+            Some(url) => url,
+            None => return Source::Unknown(source.to_owned()),
+        };
+        let (url, rev) = match url.split_once('#') {
+            Some((url, rev)) if !rev.is_empty() => (url, Some(rev.to_owned())),
+            Some((url, _)) => (url, None),
+            None => (url, None),
+        };
+        let (url, query) = url.split_once('?').unwrap_or((url, ""));
+        let reference = query
+            .split('&')
+            .find_map(|param| match param.split_once('=')? {
+                ("branch", name) => Some(GitReference::Branch(name.to_owned())),
+                ("tag", name) => Some(GitReference::Tag(name.to_owned())),
+                ("rev", name) => Some(GitReference::Rev(name.to_owned())),
+                _ => None,
+            });
+        Source::Git {
+            url: url.to_owned(),
+            reference,
+            rev,
+        }
+    }
+}
+
+/// The reference of a git dependency, as given in the manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GitReference {
+    /// A `branch` key.
+    Branch(String),
+    /// A `tag` key.
+    Tag(String),
+    /// A `rev` key, which can be a commit hash or any other reference.
+    Rev(String),
+}
+
+impl GitReference {
+    /// Returns the name of the branch, tag, or revision.
+    pub fn name(&self) -> &str {
+        match self {
+            GitReference::Branch(name) | GitReference::Tag(name) | GitReference::Rev(name) => name,
+        }
+    }
+}
+
+/// The kind of a dependency.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DepKind {
+    /// A normal dependency declared in `[dependencies]`.
+    Normal,
+    /// A development dependency declared in `[dev-dependencies]`.
+    Development,
+    /// A build dependency declared in `[build-dependencies]`.
+    Build,
+    /// A dependency kind that is not known to this crate.
+    Unknown(String),
+}
+
+impl fmt::Display for DepKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DepKind::Normal => write!(f, "normal"),
+            DepKind::Development => write!(f, "dev"),
+            DepKind::Build => write!(f, "build"),
+            DepKind::Unknown(kind) => write!(f, "{}", kind),
+        }
+    }
+}
+
+/// Parses the spellings of the `Display` implementation, i.e. `normal`, `dev`, or `build`.
+///
+/// Unknown kinds are rejected, since they can't be used for filtering.
+impl str::FromStr for DepKind {
+    type Err = ParseValueError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "normal" => Ok(DepKind::Normal),
+            "dev" => Ok(DepKind::Development),
+            "build" => Ok(DepKind::Build),
+            _ => Err(ParseValueError::new(s, &["normal", "dev", "build"])),
+        }
+    }
+}
+
+impl fmt::Display for NameMatching {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NameMatching::Key => write!(f, "key"),
+            NameMatching::PackageName => write!(f, "package-name"),
+            NameMatching::Any => write!(f, "any"),
+        }
+    }
+}
+
+/// Parses the spellings of the `Display` implementation, i.e. `key`, `package-name`, or `any`.
+impl str::FromStr for NameMatching {
+    type Err = ParseValueError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "key" => Ok(NameMatching::Key),
+            "package-name" => Ok(NameMatching::PackageName),
+            "any" => Ok(NameMatching::Any),
+            _ => Err(ParseValueError::new(s, &["key", "package-name", "any"])),
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Backend::CargoMetadata => write!(f, "cargo-metadata"),
+            Backend::Lockfile => write!(f, "lockfile"),
+            Backend::NoDeps => write!(f, "no-deps"),
+        }
+    }
+}
+
+/// Parses the spellings of the `Display` implementation, i.e. `cargo-metadata`, `lockfile`, or
+/// `no-deps`.
+impl str::FromStr for Backend {
+    type Err = ParseValueError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cargo-metadata" => Ok(Backend::CargoMetadata),
+            "lockfile" => Ok(Backend::Lockfile),
+            "no-deps" => Ok(Backend::NoDeps),
+            _ => Err(ParseValueError::new(
+                s,
+                &["cargo-metadata", "lockfile", "no-deps"],
+            )),
+        }
+    }
+}
+
+/// Describes how the result of [`Locator::locate_with_provenance`] was produced.
+///
+/// Lookups can take different paths on different machines, e.g. a cached result on a developer
+/// machine and a `cargo metadata` run in CI, which explains results that differ between them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Provenance {
+    /// The strategy that produced the result.
+    pub strategy: Strategy,
+    /// When the result was produced.
+    ///
+    /// For [`Strategy::DiskCache`] and [`Strategy::MemoizedMetadata`], this is the time of the
+    /// earlier lookup whose result was reused, otherwise the time of this lookup. Results of the
+    /// disk cache are only stored with a precision of seconds.
+    pub produced_at: SystemTime,
+    /// Whether the lookup ran in [`strict`](Locator::strict) mode, which only uses
+    /// [`Strategy::CargoMetadata`].
+    pub strict: bool,
+}
+
+/// A strategy through which [`Locator::locate`] finds the dependency, see [`Provenance`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Strategy {
+    /// The manifest path was read from the given environment variable, see
+    /// [`Locator::allow_env_override`].
+    EnvOverride {
+        /// The name of the environment variable.
+        variable: String,
+    },
+    /// The result was read from the cache file that is set through `Locator::cache_file`.
+    DiskCache,
+    /// The dependency was found through the [`Backend::Lockfile`] fast path.
+    Lockfile,
+    /// The dependency was found through the [`Backend::NoDeps`] fast path.
+    NoDeps,
+    /// The dependency was found in the output of a `cargo metadata` run.
+    CargoMetadata,
+    /// The dependency was found in a `cargo metadata` output of an earlier lookup, see the
+    /// [`memo`] module.
+    MemoizedMetadata,
+}
 
-    let root = metadata["resolve"]["root"]
-        .as_str()
-        .ok_or(LocateError::MetadataInvalid)?;
+impl fmt::Display for Strategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Strategy::EnvOverride { variable } => write!(f, "env-override:{}", variable),
+            Strategy::DiskCache => write!(f, "disk-cache"),
+            Strategy::Lockfile => write!(f, "lockfile"),
+            Strategy::NoDeps => write!(f, "no-deps"),
+            Strategy::CargoMetadata => write!(f, "cargo-metadata"),
+            Strategy::MemoizedMetadata => write!(f, "memoized-metadata"),
+        }
+    }
+}
 
-    let root_resolve = metadata["resolve"]["nodes"]
-        .as_array()
-        .unwrap()
-        .iter()
-        .find(|r| r["id"] == root)
-        .ok_or(LocateError::MetadataInvalid)?;
+/// Failed to parse a setting from a string, e.g. a [`DepKind`] from a command line argument.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseValueError {
+    value: String,
+    expected: &'static [&'static str],
+}
 
-    let dependency = root_resolve["deps"]
-        .as_array()
-        .unwrap()
-        .iter()
-        .find(|d| d["name"] == dependency_name)
-        .ok_or(LocateError::DependencyNotFound)?;
-    let dependency_id = dependency["pkg"]
-        .as_str()
-        .ok_or(LocateError::MetadataInvalid)?;
-
-    let dependency_package = metadata["packages"]
-        .as_array()
-        .unwrap()
-        .iter()
-        .find(|p| p["id"] == dependency_id)
-        .ok_or(LocateError::MetadataInvalid)?;
-    let dependency_manifest = dependency_package["manifest_path"]
-        .as_str()
-        .ok_or(LocateError::MetadataInvalid)?;
+impl ParseValueError {
+    pub(crate) fn new(value: &str, expected: &'static [&'static str]) -> Self {
+        ParseValueError {
+            value: value.to_owned(),
+            expected,
+        }
+    }
+
+    /// Returns the string that failed to parse.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// Returns the accepted spellings.
+    pub fn expected(&self) -> &[&'static str] {
+        self.expected
+    }
+}
 
-    Ok(dependency_manifest.into())
+impl fmt::Display for ParseValueError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid value `{}` (expected one of: {})",
+            self.value,
+            self.expected.join(", ")
+        )
+    }
 }
 
+impl std::error::Error for ParseValueError {}
+
 /// Failed to locate the bootloader dependency with the given name.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum LocateError {
     /// The project metadata returned from `cargo metadata` was not valid.
-    MetadataInvalid,
+    MetadataInvalid {
+        /// The field of the metadata that is missing or invalid, e.g. `resolve`.
+        ///
+        /// Entries of the `packages` and `resolve.nodes` arrays are identified through their
+        /// package ID, e.g. `packages[<id>].version`.
+        field: String,
+    },
     /// No dependency with the given name found in the project metadata.
-    DependencyNotFound,
+    ///
+    /// The root package does not declare a dependency with this name in any of its dependency
+    /// tables.
+    DependencyNotFound {
+        /// The name of the dependency.
+        dependency_name: String,
+        /// The manifest path of the package whose dependencies were searched.
+        manifest_path: PathBuf,
+        /// Declared dependencies with similar names, most similar first.
+        ///
+        /// Names are compared through their edit distance, treating `-` and `_` as equal.
+        candidates: Vec<String>,
+    },
+    /// The root package declares a dependency with the given name, but it is not part of the
+    /// resolved dependency graph.
+    ///
+    /// This happens for optional dependencies whose activating features are disabled.
+    DependencyNotActivated {
+        /// The name of the dependency.
+        dependency_name: String,
+        /// The manifest path of the package whose dependencies were searched.
+        manifest_path: PathBuf,
+        /// The features of the root package that would activate the dependency, either directly
+        /// or by enabling another activating feature.
+        activating_features: Vec<String>,
+        /// The target platforms the dependency declarations are restricted to.
+        ///
+        /// Empty if at least one declaration applies to all targets.
+        targets: Vec<String>,
+    },
+    /// The given dependency name is the name of the root package itself.
+    ///
+    /// This happens when the locator is run inside the bootloader project instead of inside
+    /// the project that depends on it.
+    DependencyIsRoot {
+        /// The name of the dependency.
+        dependency_name: String,
+        /// The manifest path of the root package.
+        manifest_path: PathBuf,
+    },
+    /// No package with the given manifest path found in the project metadata.
+    PackageNotFound {
+        /// The manifest path that was looked up.
+        manifest_path: PathBuf,
+    },
+    /// No package with the given ID found in the project metadata.
+    PackageIdNotFound {
+        /// The package ID that was looked up.
+        id: PackageId,
+    },
+    /// The dependency was found, but its version doesn't match the requirement given to
+    /// [`Locator::version_req`].
+    VersionMismatch {
+        /// The name of the dependency.
+        dependency_name: String,
+        /// The manifest path of the package whose dependencies were searched.
+        manifest_path: PathBuf,
+        /// The version of the dependency.
+        found: String,
+        /// The version requirement.
+        required: String,
+    },
+    /// The dependency was found, but it is not declared with the kind given to
+    /// [`Locator::dep_kind`].
+    DepKindMismatch {
+        /// The name of the dependency.
+        dependency_name: String,
+        /// The manifest path of the package whose dependencies were searched.
+        manifest_path: PathBuf,
+        /// The kinds that the dependency is declared with.
+        ///
+        /// Empty for cargo versions before 1.41, which don't report dependency kinds.
+        found: Vec<DepKind>,
+        /// The required dependency kind.
+        required: DepKind,
+    },
+    /// The dependency was found, but it doesn't come from the source given to
+    /// [`Locator::source`].
+    SourceMismatch {
+        /// The name of the dependency.
+        dependency_name: String,
+        /// The manifest path of the package whose dependencies were searched.
+        manifest_path: PathBuf,
+        /// The `source` field of the dependency package, `None` for path dependencies.
+        found: Option<String>,
+        /// The required source.
+        required: SourceFilter,
+    },
+    /// The environment variable given to [`Locator::allow_env_override`] doesn't point to a
+    /// manifest.
+    EnvOverrideInvalid {
+        /// The name of the environment variable.
+        variable: String,
+        /// The value of the environment variable.
+        path: PathBuf,
+    },
+    /// The workspace has no member with the name given to [`Locator::workspace_member`].
+    WorkspaceMemberNotFound {
+        /// The package name, path, or package ID that selects the workspace member.
+        member: String,
+    },
+    /// The workspace member selector of [`Locator::workspace_member`] matches several members.
+    ///
+    /// This happens for package names that are used by multiple members. Select the member by
+    /// its path or its package ID instead.
+    AmbiguousMember {
+        /// The package name, path, or package ID that selects the workspace member.
+        member: String,
+        /// The manifest paths of all matching members.
+        matches: Vec<PathBuf>,
+    },
+    /// The manifest belongs to a virtual workspace without a root package, which doesn't have
+    /// exactly one default member.
+    ///
+    /// Select the member whose dependencies should be searched through
+    /// [`Locator::workspace_member`].
+    VirtualWorkspace {
+        /// The path to the manifest of the workspace.
+        manifest_path: PathBuf,
+        /// The names of the workspace members, sorted alphabetically.
+        members: Vec<String>,
+    },
+    /// The project uses vendored sources, but the vendor directory doesn't contain the locked
+    /// version of the dependency.
+    ///
+    /// Running `cargo vendor` again updates the vendor directory.
+    VendoredCopyMissing {
+        /// The name of the dependency.
+        dependency_name: String,
+        /// The locked version of the dependency.
+        version: String,
+        /// The vendor directory from the cargo configuration.
+        vendor_dir: PathBuf,
+        /// The error of the failed `cargo metadata` run.
+        source: Box<CargoMetadataError>,
+    },
+    /// The package has no binary target with the name given to [`Metadata::kernel_binary`].
+    BinaryNotFound {
+        /// The name of the package.
+        package: String,
+        /// The requested binary name.
+        bin_name: String,
+        /// The names of the binary targets of the package.
+        available: Vec<String>,
+    },
+    /// No binary name was given to [`Metadata::kernel_binary`] and the package doesn't have
+    /// exactly one binary target.
+    AmbiguousBinary {
+        /// The name of the package.
+        package: String,
+        /// The names of the binary targets of the package, empty if there are none.
+        available: Vec<String>,
+    },
+    /// The manifest of the located package doesn't exist or isn't readable.
+    ///
+    /// Returned if [`Locator::verify_exists`] is enabled. This happens when the package
+    /// sources referenced by the metadata were removed, e.g. by a cleanup of the cargo cache.
+    /// Running `cargo fetch` downloads them again.
+    ManifestMissing {
+        /// The path of the manifest.
+        path: PathBuf,
+    },
     /// Failed to query project metadata.
     Metadata(CargoMetadataError),
+    /// Failed to determine the version of the active Rust toolchain.
+    Toolchain(toolchain::ProbeError),
 }
 
 impl fmt::Display for LocateError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            LocateError::MetadataInvalid => write!(f, "The `cargo metadata` output was not valid"),
-            LocateError::DependencyNotFound => write!(
+            LocateError::MetadataInvalid { field } => write!(
+                f,
+                "The `cargo metadata` output was not valid (missing or invalid field `{}`)",
+                field
+            ),
+            LocateError::DependencyNotFound {
+                dependency_name,
+                manifest_path,
+                candidates,
+            } => {
+                write!(
+                    f,
+                    "Could not find a dependency named `{}` in `{}`",
+                    dependency_name,
+                    manifest_path.display()
+                )?;
+                if let Some(candidate) = candidates.first() {
+                    write!(f, " (did you mean `{}`?)", candidate)?;
+                }
+                Ok(())
+            }
+            LocateError::DependencyNotActivated {
+                dependency_name,
+                manifest_path,
+                activating_features,
+                targets,
+            } => {
+                write!(
+                    f,
+                    "The dependency `{}` is declared in `{}`, but not activated in the current \
+                    configuration",
+                    dependency_name,
+                    manifest_path.display()
+                )?;
+                if !activating_features.is_empty() {
+                    write!(
+                        f,
+                        " (activated by features: {})",
+                        activating_features.join(", ")
+                    )?;
+                }
+                if !targets.is_empty() {
+                    write!(f, " (restricted to targets: {})", targets.join(", "))?;
+                }
+                Ok(())
+            }
+            LocateError::DependencyIsRoot {
+                dependency_name,
+                manifest_path,
+            } => write!(
+                f,
+                "The dependency name `{}` refers to the root package `{}` itself instead of one \
+                of its dependencies",
+                dependency_name,
+                manifest_path.display()
+            ),
+            LocateError::PackageNotFound { manifest_path } => write!(
+                f,
+                "Could not find a package with the manifest path `{}` in the `cargo metadata` \
+                output",
+                manifest_path.display()
+            ),
+            LocateError::PackageIdNotFound { id } => write!(
+                f,
+                "Could not find a package with the ID `{}` in the `cargo metadata` output",
+                id
+            ),
+            LocateError::VersionMismatch {
+                dependency_name,
+                manifest_path,
+                found,
+                required,
+            } => write!(
+                f,
+                "The dependency `{}` of `{}` has version {}, which does not match the \
+                requirement `{}`",
+                dependency_name,
+                manifest_path.display(),
+                found,
+                required
+            ),
+            LocateError::DepKindMismatch {
+                dependency_name,
+                manifest_path,
+                found,
+                required,
+            } => write!(
+                f,
+                "The dependency `{}` of `{}` is not a {} dependency (declared as: {})",
+                dependency_name,
+                manifest_path.display(),
+                required,
+                join_kinds(found)
+            ),
+            LocateError::SourceMismatch {
+                dependency_name,
+                manifest_path,
+                found,
+                required,
+            } => write!(
+                f,
+                "The dependency `{}` of `{}` comes from {} instead of the required {} source",
+                dependency_name,
+                manifest_path.display(),
+                describe_source(found.as_deref()),
+                required
+            ),
+            LocateError::EnvOverrideInvalid { variable, path } => write!(
+                f,
+                "The environment variable `{}` is set to `{}`, which is not a `Cargo.toml` file \
+                or a directory containing one",
+                variable,
+                path.display()
+            ),
+            LocateError::WorkspaceMemberNotFound { member } => write!(
+                f,
+                "Could not find a workspace member matching `{}` in the `cargo metadata` output",
+                member
+            ),
+            LocateError::AmbiguousMember { member, matches } => write!(
+                f,
+                "The workspace member selector `{}` matches multiple members ({}), select one by \
+                path or package ID",
+                member,
+                join_paths(matches)
+            ),
+            LocateError::VirtualWorkspace {
+                manifest_path,
+                members,
+            } => write!(
+                f,
+                "`{}` is a virtual workspace without a single default member, select one of the \
+                workspace members: {}",
+                manifest_path.display(),
+                members.join(", ")
+            ),
+            LocateError::VendoredCopyMissing {
+                dependency_name,
+                version,
+                vendor_dir,
+                source: _,
+            } => write!(
+                f,
+                "The vendored copy of `{}` {} is missing in `{}`, run `cargo vendor` to update \
+                the vendor directory",
+                dependency_name,
+                version,
+                vendor_dir.display()
+            ),
+            LocateError::BinaryNotFound {
+                package,
+                bin_name,
+                available,
+            } => write!(
                 f,
-                "Could not find a dependency with the given name in the `cargo metadata` output"
+                "The package `{}` has no binary target named `{}` (binary targets: {})",
+                package,
+                bin_name,
+                available.join(", ")
+            ),
+            LocateError::AmbiguousBinary { package, available } if available.is_empty() => {
+                write!(f, "The package `{}` has no binary targets", package)
+            }
+            LocateError::AmbiguousBinary { package, available } => write!(
+                f,
+                "The package `{}` has multiple binary targets ({}), select one by name",
+                package,
+                available.join(", ")
+            ),
+            LocateError::ManifestMissing { path } => write!(
+                f,
+                "The manifest `{}` of the located package doesn't exist or isn't readable, run \
+                `cargo fetch` to download the package again",
+                path.display()
             ),
             LocateError::Metadata(source) => {
                 write!(f, "Failed to retrieve project metadata: {}", source)
             }
+            LocateError::Toolchain(source) => {
+                write!(f, "Failed to probe the Rust toolchain: {}", source)
+            }
+        }
+    }
+}
+
+impl LocateError {
+    /// Returns the error as a [`Message`] that can be translated.
+    ///
+    /// See the [`messages`] module for details.
+    pub fn message(&self) -> Message {
+        match self {
+            LocateError::MetadataInvalid { field } => {
+                Message::new("locate.metadata-invalid").with_arg("field", field.as_str())
+            }
+            LocateError::DependencyNotFound {
+                dependency_name,
+                manifest_path,
+                candidates,
+            } => Message::new("locate.dependency-not-found")
+                .with_arg("dependency_name", dependency_name.as_str())
+                .with_arg("manifest_path", manifest_path.display().to_string())
+                .with_arg("candidates", candidates.join(", ")),
+            LocateError::DependencyNotActivated {
+                dependency_name,
+                manifest_path,
+                activating_features,
+                targets,
+            } => Message::new("locate.dependency-not-activated")
+                .with_arg("dependency_name", dependency_name.as_str())
+                .with_arg("manifest_path", manifest_path.display().to_string())
+                .with_arg("activating_features", activating_features.join(", "))
+                .with_arg("targets", targets.join(", ")),
+            LocateError::DependencyIsRoot {
+                dependency_name,
+                manifest_path,
+            } => Message::new("locate.dependency-is-root")
+                .with_arg("dependency_name", dependency_name.as_str())
+                .with_arg("manifest_path", manifest_path.display().to_string()),
+            LocateError::PackageNotFound { manifest_path } => {
+                Message::new("locate.package-not-found")
+                    .with_arg("manifest_path", manifest_path.display().to_string())
+            }
+            LocateError::PackageIdNotFound { id } => {
+                Message::new("locate.package-id-not-found").with_arg("id", id.as_str())
+            }
+            LocateError::VersionMismatch {
+                dependency_name,
+                manifest_path,
+                found,
+                required,
+            } => Message::new("locate.version-mismatch")
+                .with_arg("dependency_name", dependency_name.as_str())
+                .with_arg("manifest_path", manifest_path.display().to_string())
+                .with_arg("found", found.as_str())
+                .with_arg("required", required.as_str()),
+            LocateError::DepKindMismatch {
+                dependency_name,
+                manifest_path,
+                found,
+                required,
+            } => Message::new("locate.dep-kind-mismatch")
+                .with_arg("dependency_name", dependency_name.as_str())
+                .with_arg("manifest_path", manifest_path.display().to_string())
+                .with_arg("found", join_kinds(found))
+                .with_arg("required", required.to_string()),
+            LocateError::SourceMismatch {
+                dependency_name,
+                manifest_path,
+                found,
+                required,
+            } => Message::new("locate.source-mismatch")
+                .with_arg("dependency_name", dependency_name.as_str())
+                .with_arg("manifest_path", manifest_path.display().to_string())
+                .with_arg("found", describe_source(found.as_deref()))
+                .with_arg("required", required.to_string()),
+            LocateError::EnvOverrideInvalid { variable, path } => {
+                Message::new("locate.env-override-invalid")
+                    .with_arg("variable", variable.as_str())
+                    .with_arg("path", path.display().to_string())
+            }
+            LocateError::WorkspaceMemberNotFound { member } => {
+                Message::new("locate.workspace-member-not-found")
+                    .with_arg("member", member.as_str())
+            }
+            LocateError::AmbiguousMember { member, matches } => {
+                Message::new("locate.ambiguous-member")
+                    .with_arg("member", member.as_str())
+                    .with_arg("matches", join_paths(matches))
+            }
+            LocateError::VirtualWorkspace {
+                manifest_path,
+                members,
+            } => Message::new("locate.virtual-workspace")
+                .with_arg("manifest_path", manifest_path.display().to_string())
+                .with_arg("members", members.join(", ")),
+            LocateError::VendoredCopyMissing {
+                dependency_name,
+                version,
+                vendor_dir,
+                source,
+            } => Message::new("locate.vendored-copy-missing")
+                .with_arg("dependency_name", dependency_name.as_str())
+                .with_arg("version", version.as_str())
+                .with_arg("vendor_dir", vendor_dir.display().to_string())
+                .with_source(source.message()),
+            LocateError::BinaryNotFound {
+                package,
+                bin_name,
+                available,
+            } => Message::new("locate.binary-not-found")
+                .with_arg("package", package.as_str())
+                .with_arg("bin_name", bin_name.as_str())
+                .with_arg("available", available.join(", ")),
+            LocateError::AmbiguousBinary { package, available } => {
+                Message::new("locate.ambiguous-binary")
+                    .with_arg("package", package.as_str())
+                    .with_arg("available", available.join(", "))
+            }
+            LocateError::ManifestMissing { path } => {
+                Message::new("locate.manifest-missing").with_arg("path", path.display().to_string())
+            }
+            LocateError::Metadata(source) => {
+                Message::new("locate.metadata").with_source(source.message())
+            }
+            LocateError::Toolchain(source) => {
+                Message::new("locate.toolchain").with_source(source.message())
+            }
         }
     }
 }
@@ -74,65 +2322,419 @@ impl fmt::Display for LocateError {
 impl std::error::Error for LocateError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
-            LocateError::MetadataInvalid => None,
-            LocateError::DependencyNotFound => None,
+            LocateError::MetadataInvalid { .. } => None,
+            LocateError::DependencyNotFound { .. } => None,
+            LocateError::DependencyNotActivated { .. } => None,
+            LocateError::DependencyIsRoot { .. } => None,
+            LocateError::PackageNotFound { .. } => None,
+            LocateError::PackageIdNotFound { .. } => None,
+            LocateError::VersionMismatch { .. } => None,
+            LocateError::DepKindMismatch { .. } => None,
+            LocateError::SourceMismatch { .. } => None,
+            LocateError::EnvOverrideInvalid { .. } => None,
+            LocateError::WorkspaceMemberNotFound { .. } => None,
+            LocateError::AmbiguousMember { .. } => None,
+            LocateError::VirtualWorkspace { .. } => None,
+            LocateError::VendoredCopyMissing { source, .. } => Some(source.as_ref()),
+            LocateError::BinaryNotFound { .. } => None,
+            LocateError::AmbiguousBinary { .. } => None,
+            LocateError::ManifestMissing { .. } => None,
             LocateError::Metadata(source) => Some(source),
+            LocateError::Toolchain(source) => Some(source),
         }
     }
 }
 
+/// Describes the given `source` field of a package for an error message.
+fn describe_source(source: Option<&str>) -> String {
+    match source {
+        Some(source) => format!("`{}`", source),
+        None => String::from("a local path"),
+    }
+}
+
+/// Joins the given dependency kinds for an error message.
+fn join_kinds(kinds: &[DepKind]) -> String {
+    if kinds.is_empty() {
+        return String::from("unknown");
+    }
+    kinds
+        .iter()
+        .map(|kind| kind.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Joins the given paths for an error message.
+fn join_paths(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|path| format!("`{}`", path.display()))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 impl convert::From<CargoMetadataError> for LocateError {
     fn from(source: CargoMetadataError) -> Self {
         LocateError::Metadata(source)
     }
 }
 
-fn metadata() -> Result<serde_json::Value, CargoMetadataError> {
-    let mut cmd = Command::new(env!("CARGO"));
-    cmd.arg("metadata");
-    cmd.arg("--format-version").arg("1");
-    let output = cmd.output()?;
+impl convert::From<toolchain::ProbeError> for LocateError {
+    fn from(source: toolchain::ProbeError) -> Self {
+        LocateError::Toolchain(source)
+    }
+}
+
+/// Runs `cargo metadata` and returns its JSON output.
+///
+/// If no manifest path is given, the manifest path of the context is used. If the context has
+/// none either, cargo uses the manifest of the current working directory.
+fn metadata(context: &Context, manifest_path: Option<&Path>) -> Result<String, CargoMetadataError> {
+    memoized_metadata(context, manifest_path).map(|(output, _)| output)
+}
+
+/// Like [`metadata`], but also returns when the output was memoized if a memoized output was
+/// reused.
+fn memoized_metadata(
+    context: &Context,
+    manifest_path: Option<&Path>,
+) -> Result<(String, Option<SystemTime>), CargoMetadataError> {
+    let memo_key = memo::Key::new(context, manifest_path);
+    if let Some((output, memoized_at)) = memo_key.as_ref().and_then(memo::get) {
+        return Ok((output, Some(memoized_at)));
+    }
+    let mut attempt = 1;
+    let output = loop {
+        let result = match &context.invoker {
+            Some(invoker) => invoker.metadata(context, manifest_path),
+            None => run_metadata(context, manifest_path),
+        };
+        match result {
+            Err(err) => match context.retry_delay(&err, attempt) {
+                Some(delay) => {
+                    thread::sleep(delay);
+                    attempt += 1;
+                }
+                None => return Err(err),
+            },
+            Ok(output) => break output,
+        }
+    };
+    if let Some(memo_key) = memo_key {
+        memo::insert(memo_key, &output);
+    }
+    Ok((output, None))
+}
+
+/// Runs `cargo metadata` as a child process, see [`invoker::ProcessInvoker`].
+fn run_metadata(
+    context: &Context,
+    manifest_path: Option<&Path>,
+) -> Result<String, CargoMetadataError> {
+    if context.is_cancelled() {
+        return Err(CargoMetadataError::Cancelled);
+    }
+    let mut command = metadata_command(context, manifest_path)?;
+    let invocation = trace::Invocation::start(&command);
+    let progress = event::Progress::start(context.event_hook.as_ref(), &command);
+    let child = command.spawn()?;
+    let output = process::capture_cancellable(
+        child,
+        context.timeout,
+        &process::CaptureLimits::of(context),
+        context.cancel_token.as_ref(),
+        context.event_hook.as_ref(),
+    )?;
+    match output {
+        Some(output) => {
+            invocation.finish(Some(output.status), &output.stderr);
+            progress.finish(Some(output.status));
+            metadata_output(context, output)
+        }
+        None => {
+            invocation.finish(None, &[]);
+            progress.finish(None);
+            if context.is_cancelled() {
+                Err(CargoMetadataError::Cancelled)
+            } else {
+                Err(CargoMetadataError::TimedOut {
+                    timeout: context.timeout.unwrap_or_default(),
+                })
+            }
+        }
+    }
+}
+
+/// Creates the `cargo metadata` command with piped stdout and stderr.
+fn metadata_command(
+    context: &Context,
+    manifest_path: Option<&Path>,
+) -> Result<Command, CargoMetadataError> {
+    metadata_args(context.cargo_command("metadata"), context, manifest_path)
+}
+
+/// Like [`metadata_command`], but not counted in the [`stats`], for displaying the command.
+fn displayed_metadata_command(
+    context: &Context,
+    manifest_path: Option<&Path>,
+) -> Result<Command, CargoMetadataError> {
+    metadata_args(
+        context.uncounted_cargo_command("metadata"),
+        context,
+        manifest_path,
+    )
+}
+
+/// Adds the `cargo metadata` arguments of the given context to the given command.
+fn metadata_args(
+    mut cmd: Command,
+    context: &Context,
+    manifest_path: Option<&Path>,
+) -> Result<Command, CargoMetadataError> {
+    cmd.arg("--format-version")
+        .arg(SUPPORTED_FORMAT_VERSIONS[0].to_string());
+    if let Some(manifest_path) = manifest_path.or(context.manifest_path.as_deref()) {
+        let manifest_path = normalize_manifest_path(manifest_path)?;
+        // cargo looks up its configuration (e.g. `.cargo/config.toml`) starting from the working
+        // directory, so run it in the package directory to get results that don't depend on
+        // the working directory of the current process
+        if let Some(package_dir) = manifest_path.parent() {
+            cmd.current_dir(package_dir);
+        }
+        cmd.arg("--manifest-path").arg(manifest_path);
+    }
+    if !context.features.is_empty() {
+        cmd.arg("--features").arg(context.features.join(","));
+    }
+    if context.all_features {
+        cmd.arg("--all-features");
+    }
+    if context.no_default_features {
+        cmd.arg("--no-default-features");
+    }
+    if let Some(target) = &context.filter_platform {
+        cmd.arg("--filter-platform").arg(target);
+    }
+    if context.no_deps {
+        cmd.arg("--no-deps");
+    }
+    cmd.args(&context.extra_args);
+    cmd.envs(context.envs.iter().map(|(key, value)| (key, value)));
+    cmd.stdin(Stdio::null());
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::piped());
+    Ok(cmd)
+}
 
-    if !output.status.success() {
+/// Turns the results of a finished `cargo metadata` run into its JSON output.
+///
+/// The path mappings of the context are applied to the output.
+fn metadata_output(
+    context: &Context,
+    captured: process::Captured,
+) -> Result<String, CargoMetadataError> {
+    if !captured.status.success() {
         return Err(CargoMetadataError::Failed {
-            stderr: output.stderr,
+            exit_code: captured.status.code(),
+            stderr: String::from_utf8_lossy(&captured.stderr).into_owned(),
+            overflow_file: captured.stderr_overflow.and_then(|overflow| overflow.file),
+        });
+    }
+    if let Some(overflow) = captured.stdout_overflow {
+        return Err(CargoMetadataError::OutputTooLarge {
+            limit: context.stdout_limit,
+            overflow_file: overflow.file,
         });
     }
 
-    let output = String::from_utf8(output.stdout)?;
-    let parsed = serde_json::from_str(&output)?;
+    let output = match String::from_utf8(captured.stdout) {
+        Ok(output) => output,
+        Err(err) => lenient_output(err.into_bytes())?,
+    };
+
+    Ok(wrapper::map_output(context, output))
+}
+
+/// Converts stdout output that is not valid UTF-8.
+///
+/// Only the JSON document is required to be valid UTF-8. Lines before it, e.g. warnings of
+/// wrapper scripts in the encoding of the system locale, are converted lossily, since they're
+/// only used for diagnostics.
+fn lenient_output(stdout: Vec<u8>) -> Result<String, string::FromUtf8Error> {
+    let mut offset = 0;
+    for line in stdout.split_inclusive(|&byte| byte == b'\n') {
+        if line.trim_ascii_start().starts_with(b"{") {
+            break;
+        }
+        offset += line.len();
+    }
+    if offset == 0 || offset == stdout.len() {
+        return String::from_utf8(stdout);
+    }
+    let json = String::from_utf8(stdout[offset..].to_vec())?;
+    Ok(String::from_utf8_lossy(&stdout[..offset]).into_owned() + &json)
+}
+
+/// Returns the manifest that cargo would operate on for the given manifest path argument,
+/// without running cargo.
+///
+/// If no manifest path is given, the manifest is searched in the current working directory and
+/// its parents, like cargo does. Returns `None` if no manifest is found.
+fn find_manifest(manifest_path: Option<&Path>) -> Option<PathBuf> {
+    match manifest_path {
+        Some(manifest_path) => normalize_manifest_path(manifest_path).ok(),
+        None => discover_manifest(&env::current_dir().ok()?),
+    }
+}
 
-    Ok(parsed)
+/// Turns the given user-supplied manifest path into a path to an existing `Cargo.toml` file.
+///
+/// Paths to directories are interpreted as the directory containing the manifest. Relative paths
+/// are made absolute by joining them to the current working directory.
+fn normalize_manifest_path(manifest_path: &Path) -> Result<PathBuf, CargoMetadataError> {
+    let mut normalized = if manifest_path.is_dir() {
+        manifest_path.join("Cargo.toml")
+    } else {
+        manifest_path.to_owned()
+    };
+    if normalized.is_relative() {
+        normalized = env::current_dir()?.join(normalized);
+    }
+    if normalized.is_file() {
+        let simplified = match normalized.to_str().map(simplify_path) {
+            Some(Cow::Owned(simplified)) => Some(simplified),
+            _ => None,
+        };
+        Ok(simplified.map(PathBuf::from).unwrap_or(normalized))
+    } else {
+        Err(CargoMetadataError::ManifestNotFound {
+            path: manifest_path.to_owned(),
+        })
+    }
 }
 
 /// Failed to query project metadata.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum CargoMetadataError {
     /// An I/O error that occurred while trying to execute `cargo metadata`.
     Io(io::Error),
+    /// The given manifest path points neither to a file nor to a directory containing a
+    /// `Cargo.toml` file.
+    ManifestNotFound {
+        /// The manifest path as given by the caller.
+        path: PathBuf,
+    },
     /// The command `cargo metadata` did not exit successfully.
     Failed {
+        /// The exit code of `cargo metadata`, `None` if it was terminated by a signal.
+        exit_code: Option<i32>,
         /// The standard error output of `cargo metadata`.
-        stderr: Vec<u8>,
+        ///
+        /// Invalid UTF-8 sequences are replaced. Only the output up to the
+        /// [`stderr_limit`](Context::stderr_limit) is kept. Longer output is truncated and ends
+        /// with a marker that states the number of discarded bytes.
+        stderr: String,
+        /// The file that the truncated part of the stderr output was written to, if an
+        /// [`overflow_dir`](Context::overflow_dir) is set.
+        overflow_file: Option<PathBuf>,
+    },
+    /// The stdout output of `cargo metadata` exceeded the
+    /// [`stdout_limit`](Context::stdout_limit) of the [`Context`].
+    OutputTooLarge {
+        /// The limit in bytes.
+        limit: usize,
+        /// The file that the output beyond the limit was written to, if an
+        /// [`overflow_dir`](Context::overflow_dir) is set.
+        overflow_file: Option<PathBuf>,
     },
-    /// The output of `cargo metadata` was not valid UTF-8.
+    /// The JSON output of `cargo metadata` on stdout was not valid UTF-8.
+    ///
+    /// Only the JSON document is required to be valid UTF-8. Lines on stdout before the
+    /// document and the stderr output are converted lossily, so they never cause this error.
     StringConversion(string::FromUtf8Error),
     /// An error occurred while parsing the output of `cargo metadata` as JSON.
     ParseJson(serde_json::Error),
+    /// The output of `cargo metadata` started with lines that are not part of the JSON
+    /// document and the remaining output could not be parsed either.
+    ///
+    /// This is usually caused by wrapper scripts that print log messages to stdout.
+    ParseJsonAfterIgnoredOutput {
+        /// The lines that were skipped before parsing.
+        ignored_output: String,
+        /// The error that occurred while parsing the remaining output.
+        source: serde_json::Error,
+    },
+    /// The command `cargo metadata` did not finish within the timeout of the [`Context`].
+    ///
+    /// The process was killed.
+    TimedOut {
+        /// The timeout that expired.
+        timeout: Duration,
+    },
+    /// The [`CancelToken`] of the [`Context`] was cancelled.
+    ///
+    /// A running `cargo metadata` process was killed.
+    Cancelled,
+    /// The requested functionality requires a cargo feature of this crate that is disabled.
+    ///
+    /// See [`capabilities`].
+    FeatureDisabled {
+        /// The name of the disabled feature, e.g. `tokio`.
+        feature: &'static str,
+    },
+    /// The metadata uses a format version that this crate doesn't understand.
+    ///
+    /// See [`supported_metadata_versions`].
+    UnsupportedFormatVersion {
+        /// The `version` field of the metadata.
+        version: u32,
+    },
 }
 
 impl fmt::Display for CargoMetadataError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             CargoMetadataError::Io(err) => write!(f, "Failed to execute `cargo metadata`: {}", err),
-            CargoMetadataError::Failed { stderr } => write!(
+            CargoMetadataError::ManifestNotFound { path } => write!(
                 f,
-                "`cargo metadata` was not successful: {}",
-                String::from_utf8_lossy(stderr)
+                "No `Cargo.toml` file found at `{}` (or `{}`)",
+                path.display(),
+                path.join("Cargo.toml").display()
             ),
+            CargoMetadataError::Failed {
+                exit_code, stderr, ..
+            } => {
+                write!(f, "`cargo metadata` was not successful")?;
+                if let Some(exit_code) = exit_code {
+                    write!(f, " (exit code {})", exit_code)?;
+                }
+                write!(f, ": {}", strip_ansi_escapes(stderr))
+            }
+            CargoMetadataError::OutputTooLarge {
+                limit,
+                overflow_file,
+            } => {
+                write!(
+                    f,
+                    "The output of `cargo metadata` exceeded the limit of {} bytes",
+                    limit
+                )?;
+                if let Some(overflow_file) = overflow_file {
+                    write!(
+                        f,
+                        ", the remaining output was written to `{}`",
+                        overflow_file.display()
+                    )?;
+                }
+                Ok(())
+            }
             CargoMetadataError::StringConversion(err) => write!(
                 f,
-                "Failed to convert the `cargo metadata` output to a string: {}",
+                "The JSON output of `cargo metadata` is not valid UTF-8 (invalid byte sequence \
+                after {} valid bytes): {}",
+                err.utf8_error().valid_up_to(),
                 err
             ),
             CargoMetadataError::ParseJson(err) => write!(
@@ -140,17 +2742,247 @@ impl fmt::Display for CargoMetadataError {
                 "Failed to parse `cargo metadata` output as JSON: {}",
                 err
             ),
+            CargoMetadataError::ParseJsonAfterIgnoredOutput {
+                ignored_output,
+                source,
+            } => write!(
+                f,
+                "Failed to parse `cargo metadata` output as JSON after skipping unexpected \
+                leading output `{}`: {}",
+                ignored_output.trim_end(),
+                source
+            ),
+            CargoMetadataError::TimedOut { timeout } => write!(
+                f,
+                "`cargo metadata` did not finish within {:?} and was killed",
+                timeout
+            ),
+            CargoMetadataError::Cancelled => write!(f, "`cargo metadata` was cancelled"),
+            CargoMetadataError::FeatureDisabled { feature } => write!(
+                f,
+                "This functionality requires the `{}` feature of `bootloader-locator`, which is \
+                disabled",
+                feature
+            ),
+            CargoMetadataError::UnsupportedFormatVersion { version } => write!(
+                f,
+                "Unsupported `cargo metadata` format version {} (supported versions: {:?})",
+                version, SUPPORTED_FORMAT_VERSIONS
+            ),
         }
     }
 }
 
+impl CargoMetadataError {
+    /// Returns the error as a [`Message`] that can be translated.
+    ///
+    /// See the [`messages`] module for details.
+    pub fn message(&self) -> Message {
+        match self {
+            CargoMetadataError::Io(err) => {
+                Message::new("metadata.io").with_arg("error", err.to_string())
+            }
+            CargoMetadataError::ManifestNotFound { path } => {
+                Message::new("metadata.manifest-not-found")
+                    .with_arg("path", path.display().to_string())
+            }
+            CargoMetadataError::Failed {
+                exit_code, stderr, ..
+            } => Message::new("metadata.failed")
+                .with_arg(
+                    "exit_code",
+                    exit_code.map(|code| code.to_string()).unwrap_or_default(),
+                )
+                .with_arg("stderr", strip_ansi_escapes(stderr)),
+            CargoMetadataError::OutputTooLarge {
+                limit,
+                overflow_file,
+            } => Message::new("metadata.output-too-large")
+                .with_arg("limit", limit.to_string())
+                .with_arg(
+                    "overflow_file",
+                    overflow_file
+                        .as_ref()
+                        .map(|file| file.display().to_string())
+                        .unwrap_or_default(),
+                ),
+            CargoMetadataError::StringConversion(err) => {
+                Message::new("metadata.string-conversion").with_arg("error", err.to_string())
+            }
+            CargoMetadataError::ParseJson(err) => {
+                Message::new("metadata.parse-json").with_arg("error", err.to_string())
+            }
+            CargoMetadataError::ParseJsonAfterIgnoredOutput {
+                ignored_output,
+                source,
+            } => Message::new("metadata.parse-json-after-ignored-output")
+                .with_arg("ignored_output", ignored_output.trim_end())
+                .with_arg("error", source.to_string()),
+            CargoMetadataError::TimedOut { timeout } => {
+                Message::new("metadata.timed-out").with_arg("timeout", format!("{:?}", timeout))
+            }
+            CargoMetadataError::Cancelled => Message::new("metadata.cancelled"),
+            CargoMetadataError::FeatureDisabled { feature } => {
+                Message::new("metadata.feature-disabled").with_arg("feature", *feature)
+            }
+            CargoMetadataError::UnsupportedFormatVersion { version } => {
+                Message::new("metadata.unsupported-format-version")
+                    .with_arg("version", version.to_string())
+            }
+        }
+    }
+
+    /// Returns the standard error output of a failed `cargo metadata` invocation as text.
+    ///
+    /// ANSI escape sequences (e.g. for colors) are removed, so the text is suitable for
+    /// structured logs. Returns `None` for errors that didn't originate from a failed
+    /// `cargo metadata` run.
+    pub fn stderr_text(&self) -> Option<String> {
+        match self {
+            CargoMetadataError::Failed { stderr, .. } => Some(strip_ansi_escapes(stderr)),
+            _ => None,
+        }
+    }
+
+    /// Returns whether `cargo metadata` failed because it couldn't acquire a file lock, e.g. the
+    /// lock of the package cache that is shared by all cargo processes of the user.
+    ///
+    /// Such failures are transient and are retried if a [`RetryPolicy`] is set.
+    pub fn is_lock_contention(&self) -> bool {
+        const PATTERNS: &[&str] = &[
+            "waiting for file lock",
+            "package cache lock",
+            "failed to lock file",
+        ];
+        match self.stderr_text() {
+            Some(stderr) => PATTERNS.iter().any(|pattern| stderr.contains(pattern)),
+            None => false,
+        }
+    }
+
+    /// Classifies a failed `cargo metadata` invocation by its stderr output.
+    ///
+    /// Returns `None` for errors that didn't originate from a failed `cargo metadata` run. The
+    /// classification is based on the messages of current cargo and rustup versions, so
+    /// failures with unknown messages are reported as [`CargoFailureKind::Other`].
+    pub fn failure(&self) -> Option<CargoFailure> {
+        let (exit_code, stderr) = match self {
+            CargoMetadataError::Failed { exit_code, .. } => (*exit_code, self.stderr_text()?),
+            _ => return None,
+        };
+        let contains_any = |patterns: &[&str]| patterns.iter().any(|p| stderr.contains(p));
+        let kind = if (stderr.contains("toolchain '") && stderr.contains("is not installed"))
+            || contains_any(&["no such command: `+", "invalid toolchain name"])
+        {
+            CargoFailureKind::ToolchainMissing
+        } else if stderr.contains("could not find `Cargo.toml`")
+            || (stderr.contains("manifest path `") && stderr.contains("does not exist"))
+            || (stderr.contains("failed to read `") && stderr.contains("Cargo.toml`"))
+        {
+            CargoFailureKind::ManifestNotFound
+        } else if contains_any(&[
+            "failed to download",
+            "failed to fetch",
+            "failed to query replaced source registry",
+            "failed to get successful HTTP response",
+            "spurious network error",
+            "network failure",
+            "Couldn't resolve host",
+            "--offline was specified",
+        ]) {
+            CargoFailureKind::NetworkError
+        } else {
+            CargoFailureKind::Other
+        };
+        Some(CargoFailure {
+            kind,
+            exit_code,
+            stderr,
+        })
+    }
+}
+
+/// A classified failure of a `cargo metadata` invocation.
+///
+/// Returned by [`CargoMetadataError::failure`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CargoFailure {
+    /// The cause of the failure.
+    pub kind: CargoFailureKind,
+    /// The exit code of `cargo metadata`, `None` if it was terminated by a signal.
+    pub exit_code: Option<i32>,
+    /// The standard error output of `cargo metadata` without ANSI escape sequences.
+    pub stderr: String,
+}
+
+/// The cause of a [`CargoFailure`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CargoFailureKind {
+    /// A manifest of the project doesn't exist or couldn't be read, e.g. the manifest of a
+    /// workspace member or of a path dependency.
+    ManifestNotFound,
+    /// Cargo failed to access the network, e.g. for updating the registry index or for
+    /// fetching a git dependency, or needed to access it in offline mode.
+    NetworkError,
+    /// The rustup toolchain given to [`Context::toolchain`] is not installed, or the cargo
+    /// executable is not the rustup proxy and doesn't understand the `+<toolchain>` argument.
+    ToolchainMissing,
+    /// Any other failure.
+    Other,
+}
+
+/// Removes ANSI escape sequences from the given text.
+fn strip_ansi_escapes(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\u{1b}' {
+            stripped.push(c);
+            continue;
+        }
+        match chars.next() {
+            // control sequence: parameter and intermediate bytes followed by a final byte
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if ('\u{40}'..='\u{7e}').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            // operating system command: terminated by BEL or ESC \
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\u{7}' {
+                        break;
+                    }
+                    if c == '\u{1b}' && chars.peek() == Some(&'\\') {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            // other escape sequences consist of a single character
+            _ => {}
+        }
+    }
+    stripped
+}
+
 impl std::error::Error for CargoMetadataError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
             CargoMetadataError::Io(err) => Some(err),
-            CargoMetadataError::Failed { stderr: _ } => None,
+            CargoMetadataError::ManifestNotFound { path: _ } => None,
+            CargoMetadataError::Failed { .. } => None,
+            CargoMetadataError::OutputTooLarge { .. } => None,
             CargoMetadataError::StringConversion(err) => Some(err),
             CargoMetadataError::ParseJson(err) => Some(err),
+            CargoMetadataError::ParseJsonAfterIgnoredOutput { source, .. } => Some(source),
+            CargoMetadataError::TimedOut { timeout: _ } => None,
+            CargoMetadataError::Cancelled => None,
+            CargoMetadataError::FeatureDisabled { feature: _ } => None,
+            CargoMetadataError::UnsupportedFormatVersion { version: _ } => None,
         }
     }
 }
@@ -172,3 +3004,196 @@ impl convert::From<serde_json::Error> for CargoMetadataError {
         CargoMetadataError::ParseJson(source)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    #[test]
+    fn empty_manifest_path_does_not_panic() {
+        let json = fixtures::kernel_with_bootloader("").to_string();
+        let metadata = Metadata::parse(&json).unwrap();
+        let package = metadata.locate_package("bootloader").unwrap();
+        assert_eq!(package.root_dir(), None);
+        assert!(package.artifacts().is_empty());
+        assert!(package.capabilities().is_some());
+    }
+
+    #[test]
+    fn raw_json_contains_uncovered_fields() {
+        let mut json = fixtures::kernel_with_bootloader("/bootloader/Cargo.toml");
+        json["metadata"] = serde_json::json!({ "custom": true });
+        let metadata = Metadata::parse(&json.to_string()).unwrap();
+        let raw = metadata.raw_json().unwrap();
+        assert_eq!(raw["metadata"]["custom"], true);
+        assert!(std::ptr::eq(raw, metadata.raw_json().unwrap()));
+    }
+
+    #[test]
+    fn raw_package_json_is_parsed_on_access() {
+        let mut json = fixtures::kernel_with_bootloader("/bootloader/Cargo.toml");
+        json["packages"][1]["links"] = "bootloader".into();
+        let metadata = Metadata::parse(&json.to_string()).unwrap();
+        let package = metadata.locate_package("bootloader").unwrap();
+        let unparsed = package.clone();
+        assert_eq!(package.raw().unwrap()["links"], "bootloader");
+        // parsing doesn't affect the comparison
+        assert_eq!(package, unparsed);
+    }
+
+    #[test]
+    #[cfg(feature = "checksum")]
+    fn empty_manifest_path_fails_checksum_verification() {
+        let json = fixtures::kernel_with_bootloader("").to_string();
+        let metadata = Metadata::parse(&json).unwrap();
+        let package = metadata.locate_package("bootloader").unwrap();
+        let project = fixtures::TempDir::new().unwrap();
+        let lock_file = project
+            .write(
+                "Cargo.lock",
+                "[[package]]\nname = \"bootloader\"\nversion = \"0.11.3\"\nsource = \
+                \"registry+https://github.com/rust-lang/crates.io-index\"\nchecksum = \"00\"\n",
+            )
+            .unwrap();
+        assert!(matches!(
+            package.verify_checksum(&lock_file),
+            Err(checksum::ChecksumError::CrateFileNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn reads_path_overrides_on_request() {
+        let project = fixtures::TempDir::new().unwrap();
+        project
+            .write(".cargo/config.toml", "paths = [\"bootloader\"]\n")
+            .unwrap();
+        let manifest_path = project.write("bootloader/Cargo.toml", "").unwrap();
+        let mut json = fixtures::kernel_with_bootloader(manifest_path.to_str().unwrap());
+        json["packages"][1]["source"] = serde_json::Value::Null;
+        json["workspace_root"] = project.path().to_str().unwrap().into();
+
+        let mut metadata = Metadata::parse(&json.to_string()).unwrap();
+        let package = metadata.locate_package("bootloader").unwrap();
+        assert_eq!(package.override_applied, None);
+        metadata.read_overrides();
+        let package = metadata.locate_package("bootloader").unwrap();
+        assert_eq!(
+            package.override_applied,
+            Some(canonicalize(&project.path().join("bootloader")))
+        );
+    }
+
+    #[test]
+    fn reads_replace_section_on_request() {
+        let project = fixtures::TempDir::new().unwrap();
+        project
+            .write(
+                "Cargo.toml",
+                "[replace]\n\"bootloader:0.11.3\" = { path = \"bootloader\" }\n",
+            )
+            .unwrap();
+        let mut json = fixtures::kernel_with_bootloader("/project/bootloader/Cargo.toml");
+        json["packages"][1]["source"] = serde_json::Value::Null;
+        json["workspace_root"] = project.path().to_str().unwrap().into();
+
+        let mut metadata = Metadata::parse(&json.to_string()).unwrap();
+        let kind = |metadata: &Metadata| {
+            let package = metadata.locate_package("bootloader").unwrap();
+            package.source_overridden.unwrap().kind
+        };
+        assert_eq!(kind(&metadata), OverrideKind::Patch);
+        metadata.read_overrides();
+        assert_eq!(kind(&metadata), OverrideKind::Replace);
+    }
+
+    fn failed(stderr: &str) -> CargoMetadataError {
+        CargoMetadataError::Failed {
+            exit_code: Some(101),
+            stderr: stderr.into(),
+            overflow_file: None,
+        }
+    }
+
+    #[test]
+    fn classifies_cargo_failures() {
+        let kind = |stderr| failed(stderr).failure().unwrap().kind;
+        assert_eq!(
+            kind("error: toolchain 'nightly-2020-01-01' is not installed"),
+            CargoFailureKind::ToolchainMissing
+        );
+        assert_eq!(
+            kind("error: no such command: `+nightly`"),
+            CargoFailureKind::ToolchainMissing
+        );
+        assert_eq!(
+            kind("error: manifest path `/project/Cargo.toml` does not exist"),
+            CargoFailureKind::ManifestNotFound
+        );
+        assert_eq!(
+            kind(
+                "error: failed to get `bootloader` as a dependency\n\nCaused by:\n  \
+                  failed to query replaced source registry `crates-io`"
+            ),
+            CargoFailureKind::NetworkError
+        );
+        assert_eq!(
+            kind("error: failed to parse manifest"),
+            CargoFailureKind::Other
+        );
+        assert!(CargoMetadataError::TimedOut {
+            timeout: Duration::from_secs(1)
+        }
+        .failure()
+        .is_none());
+    }
+
+    #[test]
+    fn detects_lock_contention() {
+        assert!(failed("Blocking waiting for file lock on package cache").is_lock_contention());
+        assert!(!failed("error: failed to parse manifest").is_lock_contention());
+    }
+
+    #[test]
+    fn strips_ansi_escapes() {
+        assert_eq!(
+            strip_ansi_escapes("\u{1b}[1m\u{1b}[31merror\u{1b}[0m: failed"),
+            "error: failed"
+        );
+        assert_eq!(
+            strip_ansi_escapes("\u{1b}]8;;https://doc.rust-lang.org\u{7}docs\u{1b}]8;;\u{1b}\\"),
+            "docs"
+        );
+        assert_eq!(strip_ansi_escapes("\u{1b}7saved\u{1b}8"), "saved");
+        assert_eq!(strip_ansi_escapes("plain text"), "plain text");
+    }
+
+    #[test]
+    #[cfg(not(windows))]
+    fn keeps_paths_on_other_platforms() {
+        assert_eq!(simplify_path(r"\\?\C:\project"), r"\\?\C:\project");
+        assert_eq!(simplify_path("/project/Cargo.toml"), "/project/Cargo.toml");
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn simplifies_verbatim_paths() {
+        assert_eq!(
+            simplify_path(r"\\?\C:\project/Cargo.toml"),
+            r"C:\project\Cargo.toml"
+        );
+        assert_eq!(simplify_path(r"\\?\UNC\server\share"), r"\\server\share");
+        assert_eq!(
+            simplify_path(r"\\?\C:\project\nul.txt"),
+            r"\\?\C:\project\nul.txt"
+        );
+        assert_eq!(
+            simplify_path(r"\\?\Volume{1234}\project"),
+            r"\\?\Volume{1234}\project"
+        );
+        assert_eq!(
+            simplify_path(r"C:\project\Cargo.toml"),
+            r"C:\project\Cargo.toml"
+        );
+    }
+}
@@ -0,0 +1,1158 @@
+#[cfg(feature = "cache")]
+use crate::cache;
+use crate::{
+    config::{self, LocatorConfigError},
+    discover_manifest, displayed_metadata_command, find_manifest, lockfile, memoized_metadata,
+    metadata, normalize_manifest_path, raw,
+    report::Report,
+    Backend, CargoMetadataError, Context, DepKind, DependencyChain, FeatureOrigin, LocateError,
+    LocatedPackage, Metadata, NameMatching, Provenance, SourceFilter, Strategy,
+};
+use semver::VersionReq;
+#[cfg(feature = "cache")]
+use serde::Serialize;
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    env,
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+    time::{Instant, SystemTime},
+};
+
+#[cfg(feature = "cache")]
+type CacheKey = cache::Key;
+/// Without the `cache` feature, lookups are never cached.
+#[cfg(not(feature = "cache"))]
+type CacheKey = ();
+
+/// Configurable lookup of a dependency on the file system.
+///
+/// Created through [`Locator::new`] and configured through its builder methods:
+///
+/// ```no_run
+/// use bootloader_locator::Locator;
+///
+/// let manifest = Locator::new("bootloader")
+///     .manifest_path("kernel/Cargo.toml")
+///     .locate()?;
+/// # Ok::<(), bootloader_locator::LocateError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Locator {
+    dependency_name: String,
+    manifest_path: Option<PathBuf>,
+    workspace_member: Option<String>,
+    transitive: bool,
+    version_req: Option<VersionReq>,
+    dep_kind: Option<DepKind>,
+    name_matching: NameMatching,
+    source: SourceFilter,
+    env_override: Option<String>,
+    backend: Backend,
+    #[cfg(feature = "cache")]
+    cache_file: Option<PathBuf>,
+    build_script: bool,
+    discover_manifest: bool,
+    verify_exists: bool,
+    strict: bool,
+    extra_args: Vec<OsString>,
+    envs: Vec<(OsString, OsString)>,
+    context: Option<Context>,
+}
+
+impl Locator {
+    /// Creates a locator for the dependency with the given name.
+    pub fn new(dependency_name: impl Into<String>) -> Self {
+        Locator {
+            dependency_name: dependency_name.into(),
+            manifest_path: None,
+            workspace_member: None,
+            transitive: false,
+            version_req: None,
+            dep_kind: None,
+            name_matching: NameMatching::default(),
+            source: SourceFilter::default(),
+            env_override: None,
+            backend: Backend::default(),
+            #[cfg(feature = "cache")]
+            cache_file: None,
+            build_script: false,
+            discover_manifest: true,
+            verify_exists: false,
+            strict: false,
+            extra_args: Vec::new(),
+            envs: Vec::new(),
+            context: None,
+        }
+    }
+
+    /// Creates a locator from the `[package.metadata.bootloader-locator]` table of the given
+    /// manifest.
+    ///
+    /// This keeps the lookup settings of a kernel in its manifest instead of in every tool that
+    /// locates its bootloader:
+    ///
+    /// ```toml
+    /// [package.metadata.bootloader-locator]
+    /// dependency-name = "bootloader"  # the default
+    /// workspace-member = "kernel"
+    /// features = ["uefi"]
+    /// target = "x86_64-unknown-none"
+    /// ```
+    ///
+    /// All keys are optional and unknown keys are rejected. The `dependency-name` and
+    /// `workspace-member` keys correspond to [`Locator::new`] and
+    /// [`Locator::workspace_member`]. The `features` and `target` keys are set on a
+    /// [`Context::from_env`] through [`Context::features`] and [`Context::filter_platform`],
+    /// so calling [`context`](Locator::context) afterwards replaces them. The manifest is
+    /// used as [`manifest_path`](Locator::manifest_path), and the path can point to the
+    /// directory containing it as well.
+    pub fn from_manifest_metadata(
+        manifest_path: impl AsRef<Path>,
+    ) -> Result<Self, LocatorConfigError> {
+        config::configure_locator(manifest_path.as_ref())
+    }
+
+    /// Sets the manifest of the project that depends on the dependency.
+    ///
+    /// The path can point either to a `Cargo.toml` file or to the directory containing it. If
+    /// not set, the manifest path of the [`context`](Locator::context) is used. Paths that
+    /// don't contain a manifest are resolved through
+    /// [`discover_manifest`](Locator::discover_manifest).
+    pub fn manifest_path(&mut self, manifest_path: impl Into<PathBuf>) -> &mut Self {
+        self.manifest_path = Some(manifest_path.into());
+        self
+    }
+
+    /// Searches the dependencies of the given workspace member.
+    ///
+    /// The member is selected by its package ID, by the path of its directory or manifest
+    /// relative to the workspace root (e.g. `kernels/a` or `./kernels/a/Cargo.toml`), or by its
+    /// package name. Fails with [`LocateError::AmbiguousMember`] if a package name is used by
+    /// several members and with [`LocateError::WorkspaceMemberNotFound`] if no member matches.
+    ///
+    /// By default, the dependencies of the root package are searched, or of the single default
+    /// member if the workspace is virtual. Virtual workspaces with several default members fail
+    /// with [`LocateError::VirtualWorkspace`], which lists the members to choose from.
+    pub fn workspace_member(&mut self, member: impl Into<String>) -> &mut Self {
+        self.workspace_member = Some(member.into());
+        self
+    }
+
+    /// Sets whether indirect dependencies are searched too.
+    ///
+    /// By default, only the direct dependencies of the starting package are considered. If
+    /// enabled and no direct dependency matches, the full dependency graph is searched
+    /// breadth-first and the first matching package is returned. This is useful when the
+    /// dependency is pulled in through some intermediate crate, e.g. a hardware abstraction
+    /// layer.
+    pub fn transitive(&mut self, transitive: bool) -> &mut Self {
+        self.transitive = transitive;
+        self
+    }
+
+    /// Only considers dependency packages whose version matches the given requirement.
+    ///
+    /// This is useful if the dependency graph contains multiple versions of the dependency,
+    /// e.g. because different workspace members depend on different major versions. Combine it
+    /// with [`transitive`](Locator::transitive) to search the full dependency graph for a
+    /// matching version.
+    pub fn version_req(&mut self, version_req: VersionReq) -> &mut Self {
+        self.version_req = Some(version_req);
+        self
+    }
+
+    /// Only considers dependencies that are declared with the given kind, e.g. only
+    /// `[build-dependencies]` entries for [`DepKind::Build`].
+    ///
+    /// A dependency that is declared in multiple tables matches each of their kinds. Requires
+    /// cargo 1.41 or newer, since older versions don't report dependency kinds. Fails with
+    /// [`LocateError::DepKindMismatch`] if the dependency is only declared with other kinds.
+    pub fn dep_kind(&mut self, dep_kind: DepKind) -> &mut Self {
+        self.dep_kind = Some(dep_kind);
+        self
+    }
+
+    /// Sets how the dependency name is matched, see [`NameMatching`].
+    ///
+    /// By default, the name is matched against the dependency keys of the manifest, so renamed
+    /// dependencies are only found through their new name.
+    pub fn name_matching(&mut self, name_matching: NameMatching) -> &mut Self {
+        self.name_matching = name_matching;
+        self
+    }
+
+    /// Only considers dependency packages that come from the given source.
+    ///
+    /// This makes it possible to assert that the dependency comes from a local path override
+    /// instead of crates.io, or vice versa. Fails with [`LocateError::SourceMismatch`] if the
+    /// dependency only comes from other sources.
+    pub fn source(&mut self, source: SourceFilter) -> &mut Self {
+        self.source = source;
+        self
+    }
+
+    /// Allows overriding the result of [`locate`](Locator::locate) through the given
+    /// environment variable, e.g. `BOOTLOADER_MANIFEST`.
+    ///
+    /// If the variable is set, its value is returned directly without running
+    /// `cargo metadata`, which is useful for hermetic CI builds. The value can point either to
+    /// a `Cargo.toml` file or to the directory containing it. Fails with
+    /// [`LocateError::EnvOverrideInvalid`] if no manifest exists at the given path. The other
+    /// lookup methods ignore the override, since they need the metadata of the dependency.
+    pub fn allow_env_override(&mut self, variable: impl Into<String>) -> &mut Self {
+        self.env_override = Some(variable.into());
+        self
+    }
+
+    /// Sets how [`locate`](Locator::locate) resolves the dependency, see [`Backend`].
+    ///
+    /// The [`Backend::Lockfile`] fast path avoids running `cargo metadata`, which can take
+    /// several seconds for large workspaces, and the [`Backend::NoDeps`] fast path avoids the
+    /// dependency resolution. The other lookup methods always run `cargo metadata` with full
+    /// resolution, since they need the full metadata of the dependency.
+    pub fn backend(&mut self, backend: Backend) -> &mut Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Caches the result of [`locate`](Locator::locate) in the given file, see the
+    /// [`cache`] module.
+    ///
+    /// A cached result is returned without running `cargo metadata` as long as the manifests
+    /// and the `Cargo.lock` file of the project are unchanged. The file is typically placed in
+    /// the target directory, as returned by [`cache::default_cache_file`]. Failures to read or
+    /// write the cache are ignored, so the lookup falls back to running `cargo metadata`.
+    /// Requires the `cache` feature.
+    #[cfg(feature = "cache")]
+    pub fn cache_file(&mut self, cache_file: impl Into<PathBuf>) -> &mut Self {
+        self.cache_file = Some(cache_file.into());
+        self
+    }
+
+    /// Enables the build script mode, in which [`locate`](Locator::locate) prints
+    /// `cargo:rerun-if-changed` directives for the inputs of the lookup.
+    ///
+    /// Build scripts that locate a dependency need to rerun when the result might change. In
+    /// this mode, a successful lookup prints directives for the manifest of the package, the
+    /// `Cargo.toml` and `Cargo.lock` files of its workspace, and the located manifest. If an
+    /// [environment override](Locator::allow_env_override) is allowed, a
+    /// `cargo:rerun-if-env-changed` directive is printed for its variable too.
+    ///
+    /// Cargo sets the `CARGO_MANIFEST_DIR` environment variable for build scripts, so the
+    /// lookup starts at the manifest of the package that is being built by default, see
+    /// [`default_manifest_path`](crate::default_manifest_path).
+    pub fn build_script(&mut self, build_script: bool) -> &mut Self {
+        self.build_script = build_script;
+        self
+    }
+
+    /// Sets whether manifest paths that don't exist are resolved by searching the parent
+    /// directories.
+    ///
+    /// If the manifest path points to a directory without a `Cargo.toml`, or to a `Cargo.toml`
+    /// file that doesn't exist, the nearest manifest in the directory or its parents is used
+    /// instead, see [`discover_manifest`](crate::discover_manifest). This way, passing
+    /// `./Cargo.toml` works when a tool is run from a subdirectory of the project. Enabled by
+    /// default. If disabled or if no manifest is found, such paths fail with
+    /// [`CargoMetadataError::ManifestNotFound`].
+    pub fn discover_manifest(&mut self, discover_manifest: bool) -> &mut Self {
+        self.discover_manifest = discover_manifest;
+        self
+    }
+
+    /// Sets whether lookups verify that the located manifest exists on disk.
+    ///
+    /// The metadata might reference package sources that were removed since, e.g. by a cleanup
+    /// of the cargo cache, so that builds fail much later with a confusing error. If enabled,
+    /// [`locate`](Locator::locate), [`locate_package`](Locator::locate_package), and their
+    /// variants check that the manifest is readable and that the package directory exists,
+    /// failing with [`LocateError::ManifestMissing`] otherwise. Disabled by default.
+    pub fn verify_exists(&mut self, verify_exists: bool) -> &mut Self {
+        self.verify_exists = verify_exists;
+        self
+    }
+
+    /// Enables the strict mode, in which the dependency is always resolved through a fresh
+    /// `cargo metadata` run, without any fallbacks or heuristics.
+    ///
+    /// This is useful in CI, where a surprising result is worse than a slower lookup. In strict
+    /// mode:
+    ///
+    /// - the [`allow_env_override`](Locator::allow_env_override) variable is ignored,
+    /// - the `cache_file` and the in-process memoization of [`Context::memoize`] are skipped,
+    /// - the [`Backend::Lockfile`] and [`Backend::NoDeps`] fast paths are skipped,
+    /// - manifest paths that don't exist are not resolved through
+    ///   [`discover_manifest`](Locator::discover_manifest), and
+    /// - package names are compared exactly instead of treating `-` and `_` as equivalent.
+    ///   Dependency keys are still compared as crate names, since cargo reports them that way.
+    ///
+    /// The mode is reported in [`Provenance::strict`]. Disabled by default.
+    pub fn strict(&mut self, strict: bool) -> &mut Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Appends the given arguments to the command line of `cargo metadata`, see
+    /// [`Context::extra_args`].
+    ///
+    /// The arguments are added to the ones of the [`context`](Locator::context). Since they
+    /// might change the result in ways that the [`Backend::Lockfile`] fast path doesn't know
+    /// about, the fast path is skipped if extra arguments or environment variables are set.
+    pub fn extra_args<I, S>(&mut self, args: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<OsString>,
+    {
+        self.extra_args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Sets an environment variable for `cargo metadata`, see [`Context::env`].
+    pub fn env(&mut self, key: impl Into<OsString>, value: impl Into<OsString>) -> &mut Self {
+        self.envs.push((key.into(), value.into()));
+        self
+    }
+
+    /// Locates the dependency and returns the path to its `Cargo.toml`.
+    pub fn locate(&self) -> Result<PathBuf, LocateError> {
+        self.locate_with_provenance()
+            .map(|(manifest_path, _)| manifest_path)
+    }
+
+    /// Like [`locate`](Locator::locate), but also reports through which strategy the
+    /// dependency was found and when.
+    pub fn locate_with_provenance(&self) -> Result<(PathBuf, Provenance), LocateError> {
+        let (manifest_path, provenance) = self.locate_inner()?;
+        self.verify_manifest(&manifest_path)?;
+        self.print_rerun_directives(&manifest_path);
+        debug_event!(provenance = ?provenance, "located dependency");
+        Ok((manifest_path, provenance))
+    }
+
+    fn locate_inner(&self) -> Result<(PathBuf, Provenance), LocateError> {
+        let now = |strategy| Provenance {
+            strategy,
+            produced_at: SystemTime::now(),
+            strict: self.strict,
+        };
+        if let Some((variable, manifest_path)) = self.env_override()? {
+            let variable = variable.to_owned();
+            return Ok((manifest_path, now(Strategy::EnvOverride { variable })));
+        }
+        let cache_key = self.cache_key();
+        if let Some((manifest_path, stored_at)) = self.cached(cache_key.as_ref()) {
+            let provenance = Provenance {
+                strategy: Strategy::DiskCache,
+                produced_at: stored_at,
+                strict: self.strict,
+            };
+            return Ok((manifest_path, provenance));
+        }
+        let fast_path = match self.locate_from_lockfile() {
+            Some(manifest_path) => Some((manifest_path, now(Strategy::Lockfile))),
+            None => self
+                .locate_without_deps()?
+                .map(|manifest_path| (manifest_path, now(Strategy::NoDeps))),
+        };
+        let (manifest_path, provenance) = match fast_path {
+            Some(located) => located,
+            None => {
+                let context = self.effective_context();
+                let manifest_path = self.effective_manifest_path(&context);
+                let (output, memoized_at) = memoized_metadata(&context, manifest_path.as_deref())
+                    .map_err(|err| {
+                    self.explain_metadata_error(err, manifest_path.as_deref())
+                })?;
+                let located =
+                    raw::Metadata::parse(&output)?.locate(&self.dependency_name, &self.query())?;
+                let provenance = match memoized_at {
+                    Some(memoized_at) => Provenance {
+                        strategy: Strategy::MemoizedMetadata,
+                        produced_at: memoized_at,
+                        strict: self.strict,
+                    },
+                    None => now(Strategy::CargoMetadata),
+                };
+                (located, provenance)
+            }
+        };
+        self.store_in_cache(cache_key.as_ref(), &manifest_path);
+        Ok((manifest_path, provenance))
+    }
+
+    /// Async version of [`Locator::locate`] that doesn't block the async runtime while
+    /// `cargo metadata` runs.
+    ///
+    /// Requires the `tokio` feature. Without it, this fails with
+    /// [`CargoMetadataError::FeatureDisabled`].
+    pub async fn locate_async(&self) -> Result<PathBuf, LocateError> {
+        let manifest_path = self.locate_inner_async().await?;
+        self.verify_manifest(&manifest_path)?;
+        self.print_rerun_directives(&manifest_path);
+        Ok(manifest_path)
+    }
+
+    async fn locate_inner_async(&self) -> Result<PathBuf, LocateError> {
+        if let Some((_, manifest_path)) = self.env_override()? {
+            return Ok(manifest_path);
+        }
+        let cache_key = self.cache_key();
+        if let Some((manifest_path, _)) = self.cached(cache_key.as_ref()) {
+            return Ok(manifest_path);
+        }
+        let fast_path = match self.locate_from_lockfile() {
+            Some(manifest_path) => Some(manifest_path),
+            None => self.locate_without_deps_async().await?,
+        };
+        let manifest_path = match fast_path {
+            Some(manifest_path) => manifest_path,
+            None => {
+                let context = self.effective_context();
+                let manifest_path = self.effective_manifest_path(&context);
+                let output = crate::nonblocking::metadata(&context, manifest_path.as_deref())
+                    .await
+                    .map_err(|err| self.explain_metadata_error(err, manifest_path.as_deref()))?;
+                raw::Metadata::parse(&output)?.locate(&self.dependency_name, &self.query())?
+            }
+        };
+        self.store_in_cache(cache_key.as_ref(), &manifest_path);
+        Ok(manifest_path)
+    }
+
+    /// Locates the dependency and returns details about the resolved package.
+    pub fn locate_package(&self) -> Result<LocatedPackage, LocateError> {
+        let output = self.metadata()?;
+        let package = raw::Metadata::parse(&output)?
+            .with_overrides()
+            .locate_package(&self.dependency_name, &self.query())?;
+        self.verify_manifest(&package.manifest_path)?;
+        Ok(package)
+    }
+
+    /// Locates the dependency of each of the given workspace members using a single
+    /// `cargo metadata` run.
+    ///
+    /// The members replace the [`workspace_member`](Locator::workspace_member) setting, all
+    /// other settings apply to every lookup. Returns the lookup result for each member, keyed
+    /// by the member as given, see [`locate_for_members`](crate::locate_for_members).
+    pub fn locate_for_members(
+        &self,
+        members: &[&str],
+    ) -> Result<HashMap<String, Result<LocatedPackage, LocateError>>, LocateError> {
+        let output = self.metadata()?;
+        let metadata = raw::Metadata::parse(&output)?.with_overrides();
+        Ok(members
+            .iter()
+            .map(|&member| {
+                let query = raw::Query {
+                    workspace_member: Some(member),
+                    ..self.query()
+                };
+                let result = metadata
+                    .locate_package(&self.dependency_name, &query)
+                    .and_then(|package| {
+                        self.verify_manifest(&package.manifest_path)?;
+                        Ok(package)
+                    });
+                (member.to_owned(), result)
+            })
+            .collect())
+    }
+
+    /// Returns details about the package whose dependencies are searched, e.g. the kernel.
+    ///
+    /// This is the root package of the manifest or the selected
+    /// [`workspace_member`](Locator::workspace_member), see [`Metadata::root_package`]. The
+    /// dependency name is not used.
+    pub fn locate_root(&self) -> Result<LocatedPackage, LocateError> {
+        let output = self.metadata()?;
+        raw::Metadata::parse(&output)?
+            .with_overrides()
+            .located_root(&self.query())
+    }
+
+    /// Returns all packages with the dependency name in the resolved dependency graph.
+    ///
+    /// The packages are ordered by version. Only the [`version_req`](Locator::version_req)
+    /// setting is taken into account, since the packages are not looked up through the
+    /// dependencies of a specific package.
+    pub fn locate_all_versions(&self) -> Result<Vec<LocatedPackage>, LocateError> {
+        let output = self.metadata()?;
+        raw::Metadata::parse(&output)?
+            .with_overrides()
+            .all_versions(&self.dependency_name, &self.query())
+    }
+
+    /// Returns the shortest chain of dependencies from the root package to every version of the
+    /// dependency in the resolved dependency graph.
+    ///
+    /// See [`Metadata::explain`] for details. The [`workspace_member`](Locator::workspace_member)
+    /// and [`version_req`](Locator::version_req) settings are taken into account.
+    pub fn explain(&self) -> Result<Vec<DependencyChain>, LocateError> {
+        let output = self.metadata()?;
+        raw::Metadata::parse(&output)?.explain(&self.dependency_name, &self.query())
+    }
+
+    /// Reports which dependents requested each enabled feature of the dependency.
+    ///
+    /// See [`Metadata::feature_origin`] for details.
+    pub fn feature_origin(&self) -> Result<Vec<FeatureOrigin>, LocateError> {
+        let output = self.metadata()?;
+        raw::Metadata::parse(&output)?.feature_origin(&self.dependency_name, &self.query())
+    }
+
+    /// Returns all packages in the resolved dependency graph that match the dependency name.
+    ///
+    /// This includes packages that are reached through other packages than the root package,
+    /// e.g. a local path copy of the dependency next to a crates.io release, so that callers
+    /// can apply their own selection policy. The packages are returned in the order of the
+    /// resolve graph. The [`name_matching`](Locator::name_matching) setting is applied to
+    /// the dependency keys of all packages in the graph, and the
+    /// [`version_req`](Locator::version_req) setting is taken into account as well.
+    pub fn locate_candidates(&self) -> Result<impl Iterator<Item = LocatedPackage>, LocateError> {
+        let output = self.metadata()?;
+        let candidates = raw::Metadata::parse(&output)?
+            .with_overrides()
+            .candidates(&self.dependency_name, &self.query())?;
+        Ok(candidates.into_iter())
+    }
+
+    /// Locates the dependency in the given, already loaded metadata.
+    ///
+    /// The [`manifest_path`](Locator::manifest_path) setting is ignored, since the metadata
+    /// already describes a specific project. This makes it possible to answer many queries
+    /// from a single `cargo metadata` run:
+    ///
+    /// ```no_run
+    /// use bootloader_locator::{Locator, Metadata};
+    /// use std::path::Path;
+    ///
+    /// let metadata = Metadata::load(Path::new("kernel"))?;
+    /// let bootloader = Locator::new("bootloader").locate_in(&metadata)?;
+    /// let uefi = Locator::new("uefi").transitive(true).locate_in(&metadata)?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn locate_in(&self, metadata: &Metadata) -> Result<PathBuf, LocateError> {
+        let manifest_path = metadata
+            .inner
+            .locate(&self.dependency_name, &self.query())?;
+        self.verify_manifest(&manifest_path)?;
+        Ok(manifest_path)
+    }
+
+    /// Locates the dependency in the given metadata and returns details about the resolved
+    /// package.
+    ///
+    /// See [`Locator::locate_in`] for details.
+    pub fn locate_package_in(&self, metadata: &Metadata) -> Result<LocatedPackage, LocateError> {
+        let package = metadata
+            .inner
+            .locate_package(&self.dependency_name, &self.query())?;
+        self.verify_manifest(&package.manifest_path)?;
+        Ok(package)
+    }
+
+    /// Locates the dependency like [`locate_package`](Locator::locate_package) and records the
+    /// outcome in a machine-readable [`Report`].
+    ///
+    /// Failures are recorded in the report too, so this method never fails:
+    ///
+    /// ```no_run
+    /// use bootloader_locator::Locator;
+    ///
+    /// println!("{}", Locator::new("bootloader").report().to_json());
+    /// ```
+    pub fn report(&self) -> Report {
+        let start = Instant::now();
+        let result = self.locate_package();
+        let duration = start.elapsed();
+        let context = self.effective_context();
+        let manifest_path = self.effective_manifest_path(&context);
+        let command = displayed_metadata_command(&context, manifest_path.as_deref()).ok();
+        Report::new(&self.dependency_name, result, duration, command.as_ref())
+    }
+
+    /// Sets the context that is used for running `cargo metadata`.
+    ///
+    /// Defaults to [`Context::from_env`], evaluated when the lookup runs.
+    pub fn context(&mut self, context: Context) -> &mut Self {
+        self.context = Some(context);
+        self
+    }
+
+    /// Checks that the given manifest is readable if the [`verify_exists`](Locator::verify_exists)
+    /// mode is enabled.
+    fn verify_manifest(&self, manifest_path: &Path) -> Result<(), LocateError> {
+        if !self.verify_exists {
+            return Ok(());
+        }
+        let package_dir_exists = manifest_path.parent().is_some_and(Path::is_dir);
+        if !package_dir_exists || fs::File::open(manifest_path).is_err() {
+            return Err(LocateError::ManifestMissing {
+                path: manifest_path.to_owned(),
+            });
+        }
+        Ok(())
+    }
+
+    fn effective_context(&self) -> Cow<'_, Context> {
+        let mut context = match &self.context {
+            Some(context) => Cow::Borrowed(context),
+            None => Cow::Owned(Context::from_env()),
+        };
+        if !self.extra_args.is_empty() || !self.envs.is_empty() {
+            let context = context.to_mut();
+            context.extra_args.extend(self.extra_args.iter().cloned());
+            context.envs.extend(self.envs.iter().cloned());
+        }
+        if self.strict && context.memoize {
+            context.to_mut().memoize = false;
+        }
+        context
+    }
+
+    /// Returns the manifest path that is passed to `cargo metadata`.
+    ///
+    /// Falls back to the manifest path of the context and applies the
+    /// [`discover_manifest`](Locator::discover_manifest) setting.
+    fn effective_manifest_path(&self, context: &Context) -> Option<PathBuf> {
+        let manifest_path = self
+            .manifest_path
+            .as_deref()
+            .or(context.manifest_path.as_deref())?;
+        if !self.discover_manifest || self.strict || normalize_manifest_path(manifest_path).is_ok()
+        {
+            return Some(manifest_path.to_owned());
+        }
+        let start_dir = if manifest_path.is_dir() {
+            Some(manifest_path)
+        } else if manifest_path.file_name() == Some("Cargo.toml".as_ref()) {
+            manifest_path.parent().map(|dir| {
+                if dir.as_os_str().is_empty() {
+                    Path::new(".")
+                } else {
+                    dir
+                }
+            })
+        } else {
+            None
+        };
+        // keep the original path if nothing is found, so that the error refers to it
+        start_dir
+            .filter(|dir| dir.is_dir())
+            .and_then(discover_manifest)
+            .or_else(|| Some(manifest_path.to_owned()))
+    }
+
+    fn metadata(&self) -> Result<String, LocateError> {
+        let context = self.effective_context();
+        let manifest_path = self.effective_manifest_path(&context);
+        metadata(&context, manifest_path.as_deref())
+            .map_err(|err| self.explain_metadata_error(err, manifest_path.as_deref()))
+    }
+
+    /// Turns a failed `cargo metadata` run into a more specific error if the cause is known.
+    fn explain_metadata_error(
+        &self,
+        err: CargoMetadataError,
+        manifest_path: Option<&Path>,
+    ) -> LocateError {
+        if let CargoMetadataError::Failed { .. } = err {
+            let missing = lockfile::missing_vendored_copy(&self.dependency_name, manifest_path);
+            if let Some((vendor_dir, version)) = missing {
+                return LocateError::VendoredCopyMissing {
+                    dependency_name: self.dependency_name.clone(),
+                    version,
+                    vendor_dir,
+                    source: Box::new(err),
+                };
+            }
+        }
+        err.into()
+    }
+
+    /// Returns the environment variable of [`allow_env_override`](Locator::allow_env_override)
+    /// and the manifest path given through it, if it is set.
+    ///
+    /// The variable is ignored in [`strict`](Locator::strict) mode.
+    fn env_override(&self) -> Result<Option<(&str, PathBuf)>, LocateError> {
+        let variable = match &self.env_override {
+            Some(variable) if !self.strict => variable,
+            _ => return Ok(None),
+        };
+        let path = match env::var_os(variable) {
+            Some(path) => PathBuf::from(path),
+            None => return Ok(None),
+        };
+        match normalize_manifest_path(&path) {
+            Ok(manifest_path) => {
+                debug_event!(variable = %variable, manifest_path = ?manifest_path, "using override");
+                Ok(Some((variable, manifest_path)))
+            }
+            Err(_) => Err(LocateError::EnvOverrideInvalid {
+                variable: variable.clone(),
+                path,
+            }),
+        }
+    }
+
+    /// Prints the `cargo:rerun-if-changed` directives of the
+    /// [`build_script`](Locator::build_script) mode, if it's enabled.
+    fn print_rerun_directives(&self, located_manifest: &Path) {
+        if !self.build_script {
+            return;
+        }
+        for directive in self.rerun_directives(located_manifest) {
+            println!("{}", directive);
+        }
+    }
+
+    /// Returns the directives printed by [`print_rerun_directives`](Self::print_rerun_directives).
+    fn rerun_directives(&self, located_manifest: &Path) -> Vec<String> {
+        let mut directives = Vec::new();
+        if let Some(variable) = &self.env_override {
+            directives.push(format!("cargo:rerun-if-env-changed={}", variable));
+        }
+        let mut rerun_if_changed = |path: &Path| {
+            directives.push(format!("cargo:rerun-if-changed={}", path.display()));
+        };
+        let context = self.effective_context();
+        let manifest_path = self.effective_manifest_path(&context);
+        if let Some(manifest_path) = find_manifest(manifest_path.as_deref()) {
+            let manifest_dir = manifest_path.parent().unwrap_or_else(|| Path::new("."));
+            // the lock file is placed in the workspace root
+            let workspace_root = manifest_dir
+                .ancestors()
+                .find(|dir| dir.join("Cargo.lock").is_file())
+                .unwrap_or(manifest_dir);
+            rerun_if_changed(&manifest_path);
+            if workspace_root != manifest_dir {
+                rerun_if_changed(&workspace_root.join("Cargo.toml"));
+            }
+            rerun_if_changed(&workspace_root.join("Cargo.lock"));
+        }
+        rerun_if_changed(located_manifest);
+        directives
+    }
+
+    /// Returns the key of the lookup in the [`cache_file`](Locator::cache_file), if one is set.
+    ///
+    /// The key covers the settings that select the located package, i.e. the dependency name,
+    /// the workspace member, the transitive search, the version requirement, the dependency
+    /// kind, the name matching, the source filter, and the backend, plus the
+    /// [`Context::resolution_settings`] of the effective context, which include the extra
+    /// arguments and environment variables of the locator. Settings like the environment
+    /// override or the build script mode are left out, since they don't change the result.
+    ///
+    /// Returns `None` in [`strict`](Locator::strict) mode, which skips the cache.
+    #[cfg(feature = "cache")]
+    fn cache_key(&self) -> Option<CacheKey> {
+        if self.strict {
+            return None;
+        }
+        self.cache_file.as_ref()?;
+        let context = self.effective_context();
+        let manifest_path = self.effective_manifest_path(&context);
+        let settings = LookupSettings {
+            dependency_name: &self.dependency_name,
+            workspace_member: self.workspace_member.as_deref(),
+            transitive: self.transitive,
+            version_req: self.version_req.as_ref().map(ToString::to_string),
+            dep_kind: self.dep_kind.as_ref().map(ToString::to_string),
+            name_matching: self.name_matching.to_string(),
+            source: self.source.to_string(),
+            backend: self.backend.to_string(),
+            context: context.resolution_settings(),
+        };
+        cache::Key::new(
+            &find_manifest(manifest_path.as_deref())?,
+            &serde_json::to_string(&settings).expect("settings are always serializable"),
+        )
+    }
+
+    #[cfg(not(feature = "cache"))]
+    fn cache_key(&self) -> Option<CacheKey> {
+        None
+    }
+
+    /// Returns the cached result and the time it was stored.
+    #[cfg(feature = "cache")]
+    fn cached(&self, key: Option<&CacheKey>) -> Option<(PathBuf, SystemTime)> {
+        let (manifest_path, stored_at) = cache::lookup(self.cache_file.as_deref()?, key?)?;
+        debug_event!(manifest_path = ?manifest_path, "using cached result");
+        Some((manifest_path, stored_at))
+    }
+
+    #[cfg(not(feature = "cache"))]
+    fn cached(&self, _key: Option<&CacheKey>) -> Option<(PathBuf, SystemTime)> {
+        None
+    }
+
+    #[cfg(feature = "cache")]
+    fn store_in_cache(&self, key: Option<&CacheKey>, manifest_path: &Path) {
+        if let (Some(cache_file), Some(key)) = (&self.cache_file, key) {
+            let _ = cache::store(cache_file, key, manifest_path);
+        }
+    }
+
+    #[cfg(not(feature = "cache"))]
+    fn store_in_cache(&self, _key: Option<&CacheKey>, _manifest_path: &Path) {}
+
+    /// Runs the [`Backend::Lockfile`] fast path if it's enabled.
+    fn locate_from_lockfile(&self) -> Option<PathBuf> {
+        if self.backend != Backend::Lockfile || self.strict {
+            return None;
+        }
+        let context = self.effective_context();
+        if context.wrapper.is_some()
+            || !context.path_mappings.is_empty()
+            || context.invoker.is_some()
+            || !context.extra_args.is_empty()
+            || !context.envs.is_empty()
+        {
+            return None;
+        }
+        let manifest_path = self.effective_manifest_path(&context);
+        let located = lockfile::locate(
+            &self.dependency_name,
+            manifest_path.as_deref(),
+            &self.query(),
+        );
+        debug_event!(located = ?located, "ran lockfile fast path");
+        located
+    }
+
+    /// Runs the [`Backend::NoDeps`] fast path if it's enabled.
+    fn locate_without_deps(&self) -> Result<Option<PathBuf>, LocateError> {
+        let context = match self.no_deps_context() {
+            Some(context) => context,
+            None => return Ok(None),
+        };
+        let manifest_path = self.effective_manifest_path(&context);
+        let output = metadata(&context, manifest_path.as_deref())
+            .map_err(|err| self.explain_metadata_error(err, manifest_path.as_deref()))?;
+        self.declared_path_dependency(&output, manifest_path.as_deref())
+    }
+
+    /// Async version of [`Locator::locate_without_deps`].
+    async fn locate_without_deps_async(&self) -> Result<Option<PathBuf>, LocateError> {
+        let context = match self.no_deps_context() {
+            Some(context) => context,
+            None => return Ok(None),
+        };
+        let manifest_path = self.effective_manifest_path(&context);
+        let output = crate::nonblocking::metadata(&context, manifest_path.as_deref())
+            .await
+            .map_err(|err| self.explain_metadata_error(err, manifest_path.as_deref()))?;
+        self.declared_path_dependency(&output, manifest_path.as_deref())
+    }
+
+    /// Returns the context for running `cargo metadata --no-deps`, or `None` if the
+    /// [`Backend::NoDeps`] fast path is disabled.
+    fn no_deps_context(&self) -> Option<Context> {
+        if self.backend != Backend::NoDeps || self.strict {
+            return None;
+        }
+        let mut context = self.effective_context().into_owned();
+        context.no_deps = true;
+        Some(context)
+    }
+
+    fn declared_path_dependency(
+        &self,
+        output: &str,
+        manifest_path: Option<&Path>,
+    ) -> Result<Option<PathBuf>, LocateError> {
+        let root_manifest = find_manifest(manifest_path);
+        let located = raw::Metadata::parse(output)?.declared_path_dependency(
+            &self.dependency_name,
+            &self.query(),
+            root_manifest.as_deref(),
+        )?;
+        debug_event!(located = ?located, "ran no-deps fast path");
+        Ok(located)
+    }
+
+    fn query(&self) -> raw::Query<'_> {
+        raw::Query {
+            workspace_member: self.workspace_member.as_deref(),
+            transitive: self.transitive,
+            version_req: self.version_req.as_ref(),
+            dep_kind: self.dep_kind.as_ref(),
+            name_matching: self.name_matching,
+            source: Some(&self.source),
+            exact_names: self.strict,
+        }
+    }
+}
+
+/// The settings of a [`Locator`] that are part of its cache key, see `Locator::cache_key`.
+#[cfg(feature = "cache")]
+#[derive(Serialize)]
+struct LookupSettings<'a> {
+    dependency_name: &'a str,
+    workspace_member: Option<&'a str>,
+    transitive: bool,
+    version_req: Option<String>,
+    dep_kind: Option<String>,
+    name_matching: String,
+    source: String,
+    backend: String,
+    context: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixtures, invoker::MockInvoker, CargoFailureKind};
+
+    const BOOTLOADER_MANIFEST: &str = "/registry/bootloader-0.11.3/Cargo.toml";
+
+    fn mock_context(mock: &MockInvoker) -> Context {
+        let mut context = Context::from_env();
+        context.invoker(mock.clone());
+        context
+    }
+
+    #[test]
+    fn locates_dependency_in_mock_metadata() {
+        let mock = MockInvoker::new();
+        mock.output(fixtures::kernel_with_bootloader(BOOTLOADER_MANIFEST).to_string());
+        let manifest_path = Locator::new("bootloader")
+            .manifest_path("/project/Cargo.toml")
+            .context(mock_context(&mock))
+            .locate()
+            .unwrap();
+        assert_eq!(manifest_path, Path::new(BOOTLOADER_MANIFEST));
+        assert_eq!(
+            mock.invocations(),
+            [Some(PathBuf::from("/project/Cargo.toml"))]
+        );
+    }
+
+    #[test]
+    fn serves_output_for_manifest() {
+        let mock = MockInvoker::new();
+        mock.output(fixtures::kernel_with_bootloader("/default/Cargo.toml").to_string());
+        mock.output_for(
+            "/other/Cargo.toml",
+            fixtures::kernel_with_bootloader(BOOTLOADER_MANIFEST).to_string(),
+        );
+        let package = Locator::new("bootloader")
+            .manifest_path("/other/Cargo.toml")
+            .context(mock_context(&mock))
+            .locate_package()
+            .unwrap();
+        assert_eq!(package.manifest_path, Path::new(BOOTLOADER_MANIFEST));
+        assert_eq!(package.version.to_string(), "0.11.3");
+    }
+
+    #[test]
+    fn reports_similar_dependency_names() {
+        let mock = MockInvoker::new();
+        mock.output(fixtures::kernel_with_bootloader(BOOTLOADER_MANIFEST).to_string());
+        let err = Locator::new("bootlaoder")
+            .context(mock_context(&mock))
+            .locate()
+            .unwrap_err();
+        match err {
+            LocateError::DependencyNotFound {
+                dependency_name,
+                candidates,
+                ..
+            } => {
+                assert_eq!(dependency_name, "bootlaoder");
+                assert_eq!(candidates, ["bootloader"]);
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn classifies_failed_invocation() {
+        let mock = MockInvoker::new();
+        mock.failure(
+            Some(101),
+            "\u{1b}[1m\u{1b}[31merror\u{1b}[0m: could not find `Cargo.toml` in `/project`",
+        );
+        let err = Locator::new("bootloader")
+            .context(mock_context(&mock))
+            .locate()
+            .unwrap_err();
+        let failure = match &err {
+            LocateError::Metadata(err) => err.failure().unwrap(),
+            other => panic!("unexpected error: {:?}", other),
+        };
+        assert_eq!(failure.kind, CargoFailureKind::ManifestNotFound);
+        assert_eq!(failure.exit_code, Some(101));
+        assert_eq!(
+            failure.stderr,
+            "error: could not find `Cargo.toml` in `/project`"
+        );
+    }
+
+    #[test]
+    fn rerun_directives_cover_workspace() {
+        let workspace = fixtures::TempDir::new().unwrap();
+        workspace
+            .write("Cargo.toml", "[workspace]\nmembers = [\"kernel\"]\n")
+            .unwrap();
+        workspace.write("Cargo.lock", "version = 3\n").unwrap();
+        let manifest_path = workspace
+            .write("kernel/Cargo.toml", "[package]\nname = \"kernel\"\n")
+            .unwrap();
+        let directives = Locator::new("bootloader")
+            .manifest_path(&manifest_path)
+            .allow_env_override("BOOTLOADER_MANIFEST")
+            .rerun_directives(Path::new(BOOTLOADER_MANIFEST));
+        let changed = |path: &Path| format!("cargo:rerun-if-changed={}", path.display());
+        assert_eq!(
+            directives,
+            [
+                String::from("cargo:rerun-if-env-changed=BOOTLOADER_MANIFEST"),
+                changed(&manifest_path),
+                changed(&workspace.path().join("Cargo.toml")),
+                changed(&workspace.path().join("Cargo.lock")),
+                changed(Path::new(BOOTLOADER_MANIFEST)),
+            ]
+        );
+    }
+
+    #[test]
+    fn lockfile_backend_skips_cargo() {
+        let project = fixtures::vendored_kernel().unwrap();
+        // an invoker disables the fast path, so a plain context is used and a fallback to
+        // `cargo metadata` would fail because the vendored package is not a complete crate
+        let (manifest_path, provenance) = Locator::new("bootloader")
+            .manifest_path(project.path())
+            .backend(Backend::Lockfile)
+            .locate_with_provenance()
+            .unwrap();
+        assert_eq!(
+            manifest_path,
+            project.path().join("vendor/bootloader/Cargo.toml")
+        );
+        assert_eq!(provenance.strategy, Strategy::Lockfile);
+    }
+
+    #[test]
+    fn provenance_reports_strategy() {
+        let project = fixtures::TempDir::new().unwrap();
+        let manifest_path = project
+            .write("Cargo.toml", "[package]\nname = \"kernel\"\n")
+            .unwrap();
+        let bootloader = project.write("bootloader/Cargo.toml", "").unwrap();
+        let mock = MockInvoker::new();
+        mock.output(fixtures::kernel_with_bootloader(bootloader.to_str().unwrap()).to_string());
+        let mut locator = Locator::new("bootloader");
+        locator
+            .manifest_path(&manifest_path)
+            .context(mock_context(&mock));
+
+        let before = SystemTime::now();
+        let (_, provenance) = locator.locate_with_provenance().unwrap();
+        assert_eq!(provenance.strategy, Strategy::CargoMetadata);
+        assert!(provenance.produced_at >= before);
+
+        let variable = "BOOTLOADER_LOCATOR_TEST_PROVENANCE";
+        env::set_var(variable, &bootloader);
+        let (_, provenance) = locator
+            .clone()
+            .allow_env_override(variable)
+            .locate_with_provenance()
+            .unwrap();
+        env::remove_var(variable);
+        assert_eq!(
+            provenance.strategy,
+            Strategy::EnvOverride {
+                variable: variable.to_owned()
+            }
+        );
+
+        #[cfg(feature = "cache")]
+        {
+            locator.cache_file(project.path().join("cache.json"));
+            let (_, stored) = locator.locate_with_provenance().unwrap();
+            let (manifest_path, cached) = locator.locate_with_provenance().unwrap();
+            assert_eq!(manifest_path, bootloader);
+            assert_eq!(stored.strategy, Strategy::CargoMetadata);
+            assert_eq!(cached.strategy, Strategy::DiskCache);
+            assert!(cached.produced_at <= SystemTime::now());
+        }
+    }
+
+    #[test]
+    fn strict_mode_skips_fallbacks() {
+        let project = fixtures::TempDir::new().unwrap();
+        let manifest_path = project
+            .write("Cargo.toml", "[package]\nname = \"kernel\"\n")
+            .unwrap();
+        let bootloader = project.write("bootloader/Cargo.toml", "").unwrap();
+        let elsewhere = project.write("elsewhere/Cargo.toml", "").unwrap();
+        let mock = MockInvoker::new();
+        mock.output(fixtures::kernel_with_bootloader(bootloader.to_str().unwrap()).to_string());
+        let variable = "BOOTLOADER_LOCATOR_TEST_STRICT";
+        env::set_var(variable, &elsewhere);
+        let mut locator = Locator::new("bootloader");
+        locator
+            .manifest_path(&manifest_path)
+            .allow_env_override(variable)
+            .strict(true)
+            .context(mock_context(&mock));
+        #[cfg(feature = "cache")]
+        locator.cache_file(project.path().join("cache.json"));
+        for _ in 0..2 {
+            let (located, provenance) = locator.locate_with_provenance().unwrap();
+            assert_eq!(located, bootloader);
+            assert_eq!(provenance.strategy, Strategy::CargoMetadata);
+            assert!(provenance.strict);
+        }
+        env::remove_var(variable);
+        assert_eq!(mock.invocations().len(), 2);
+
+        // without the fast path, `cargo metadata` fails on the incomplete vendored package
+        let project = fixtures::vendored_kernel().unwrap();
+        let result = Locator::new("bootloader")
+            .manifest_path(project.path())
+            .backend(Backend::Lockfile)
+            .strict(true)
+            .locate();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "cache")]
+    fn cache_key_only_covers_resolution_settings() {
+        let project = fixtures::TempDir::new().unwrap();
+        let manifest_path = project
+            .write("Cargo.toml", "[package]\nname = \"kernel\"\n")
+            .unwrap();
+        let key = |configure: &dyn Fn(&mut Context)| {
+            let mut context = Context::from_env();
+            configure(&mut context);
+            Locator::new("bootloader")
+                .manifest_path(&manifest_path)
+                .cache_file(project.path().join("cache.json"))
+                .context(context)
+                .cache_key()
+                .unwrap()
+        };
+        let default = key(&|_| {});
+        assert_eq!(
+            key(&|context| {
+                context.timeout(std::time::Duration::from_secs(1));
+                context.memoize(false);
+            }),
+            default
+        );
+        assert_ne!(
+            key(&|context| {
+                context.features(["uefi"]);
+            }),
+            default
+        );
+    }
+}
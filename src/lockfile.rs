@@ -0,0 +1,542 @@
+//! Resolves a direct dependency from the `Cargo.lock` file without running `cargo metadata`.
+//!
+//! This is the [`Backend::Lockfile`](crate::Backend::Lockfile) fast path. It reads the manifest
+//! of the package, the lock file of its workspace, and the extracted sources in the cargo home
+//! directory. Whenever the result could differ from the one of `cargo metadata`, e.g. because
+//! the dependency is optional, the lock file is outdated, or sources are replaced, `None` is
+//! returned so that the caller falls back to `cargo metadata`. The only supported source
+//! replacement is a vendor directory created by `cargo vendor`.
+
+use crate::{cargo_config, find_manifest, raw, DepKind, NameMatching};
+use semver::{Version, VersionReq};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// The source of crates.io packages in the lock file.
+const CRATES_IO_SOURCE: &str = "registry+https://github.com/rust-lang/crates.io-index";
+
+/// The maximum directory depth that is searched for a package inside a git checkout.
+const GIT_CHECKOUT_SEARCH_DEPTH: usize = 3;
+
+/// Returns the manifest path of the given direct dependency of the package with the given
+/// manifest, or `None` if the lock file doesn't determine it unambiguously.
+///
+/// The manifest is determined through [`find_manifest`].
+pub(crate) fn locate(
+    dependency_name: &str,
+    manifest_path: Option<&Path>,
+    query: &raw::Query,
+) -> Option<PathBuf> {
+    if query.workspace_member.is_some()
+        || query.transitive
+        || query.name_matching != NameMatching::Key
+        || !matches!(query.dep_kind, None | Some(DepKind::Normal))
+    {
+        return None;
+    }
+
+    let manifest_path = find_manifest(manifest_path)?;
+    let manifest_dir = manifest_path.parent()?;
+    let manifest = read_toml(&manifest_path)?;
+    let package = manifest.get("package")?.as_table()?;
+    let package_name = package.get("name")?.as_str()?;
+    if package.contains_key("workspace") || has_overrides(&manifest) {
+        return None;
+    }
+    // `paths` overrides of the cargo configuration are not recorded in the lock file
+    if !cargo_config::path_overrides(manifest_dir).ok()?.is_empty() {
+        return None;
+    }
+    let workspace_root = workspace_root(manifest_dir)?;
+    let workspace_manifest_path = workspace_root.join("Cargo.toml");
+    if workspace_root != manifest_dir && has_overrides(&read_toml(&workspace_manifest_path)?) {
+        return None;
+    }
+
+    // the dependency must be declared exactly once, in `[dependencies]`, and not be optional
+    let declared_in_target = manifest
+        .get("target")
+        .and_then(|target| target.as_table())
+        .map(|targets| {
+            targets.values().any(|target| {
+                target
+                    .get("dependencies")
+                    .and_then(|dependencies| find_key(dependencies, dependency_name))
+                    .is_some()
+            })
+        })
+        .unwrap_or(false);
+    if declared_in_target {
+        return None;
+    }
+    let declaration = find_key(manifest.get("dependencies")?, dependency_name)?;
+    let (dependency_package, requirement) = match declaration {
+        toml::Value::String(requirement) => (dependency_name, Some(requirement.as_str())),
+        toml::Value::Table(table) => {
+            let optional = table
+                .get("optional")
+                .and_then(|optional| optional.as_bool());
+            if optional == Some(true) || table.contains_key("workspace") {
+                return None;
+            }
+            if let Some(path) = table.get("path") {
+                let dependency_manifest = manifest_dir.join(path.as_str()?).join("Cargo.toml");
+                return check_path_dependency(dependency_manifest, query);
+            }
+            let package = match table.get("package") {
+                Some(package) => package.as_str()?,
+                None => dependency_name,
+            };
+            let requirement = match table.get("version") {
+                Some(requirement) => Some(requirement.as_str()?),
+                None => None,
+            };
+            (package, requirement)
+        }
+        _ => return None,
+    };
+
+    let lock_path = workspace_root.join("Cargo.lock");
+    let vendor_dir = cargo_config::vendor_dir(manifest_dir).ok()?;
+    if vendor_dir.is_none()
+        && cargo_config::get_from_files(manifest_dir, "source")
+            .ok()?
+            .is_some()
+    {
+        return None;
+    }
+
+    let lock = read_toml(&lock_path)?;
+    let packages = lock.get("package")?.as_array()?;
+    let mut roots = packages.iter().filter(|package| {
+        package.get("name").and_then(|name| name.as_str()) == Some(package_name)
+            && package.get("source").is_none()
+    });
+    let root = roots.next()?;
+    if roots.next().is_some() {
+        return None;
+    }
+
+    // lock file entries are either `name`, `name version`, or `name version (source)`
+    let mut entries = root
+        .get("dependencies")?
+        .as_array()?
+        .iter()
+        .filter_map(|entry| entry.as_str())
+        .filter(|entry| entry.split(' ').next() == Some(dependency_package));
+    let entry = entries.next()?;
+    if entries.next().is_some() {
+        return None;
+    }
+    let mut parts = entry.splitn(3, ' ').skip(1);
+    let version = parts.next();
+    let source = parts
+        .next()
+        .map(|source| source.trim_start_matches('(').trim_end_matches(')'));
+
+    let mut locked = packages.iter().filter(|package| {
+        let field = |name| {
+            package
+                .get(name)
+                .and_then(|value: &toml::Value| value.as_str())
+        };
+        field("name") == Some(dependency_package)
+            && version
+                .map(|version| field("version") == Some(version))
+                .unwrap_or(true)
+            && source
+                .map(|source| field("source") == Some(source))
+                .unwrap_or(true)
+    });
+    let locked_package = locked.next()?;
+    if locked.next().is_some() {
+        return None;
+    }
+    let version = locked_package.get("version")?.as_str()?;
+    let source = locked_package.get("source")?.as_str()?;
+    if !satisfies(query, version, Some(source)) {
+        return None;
+    }
+    // cargo doesn't touch the lock file if nothing changed, so its modification time doesn't
+    // tell whether it's up to date; a manifest requirement that the locked version doesn't
+    // match means that the manifest was changed since the lock file was written
+    if let Some(requirement) = requirement {
+        if !VersionReq::parse(requirement)
+            .ok()?
+            .matches(&Version::parse(version).ok()?)
+        {
+            return None;
+        }
+    }
+
+    if let Some(vendor_dir) = vendor_dir {
+        return if source == CRATES_IO_SOURCE {
+            vendored_manifest(&vendor_dir, dependency_package, version)
+        } else {
+            None
+        };
+    }
+    let cargo_home = cargo_config::cargo_home()?;
+    if source.starts_with("registry+") || source.starts_with("sparse+") {
+        registry_manifest(&cargo_home, dependency_package, version)
+    } else if let Some(url) = source.strip_prefix("git+") {
+        let (_, commit) = url.split_once('#')?;
+        git_manifest(&cargo_home, dependency_package, commit)
+    } else {
+        None
+    }
+}
+
+/// Returns the vendor directory and the locked version of the given dependency if the
+/// project uses vendored sources and the vendored copy of the dependency is missing.
+///
+/// This is used for explaining failed `cargo metadata` runs. All locked crates.io packages with
+/// the given name are checked, since the lock file doesn't tell which of them is the direct
+/// dependency without resolving the manifest.
+pub(crate) fn missing_vendored_copy(
+    dependency_name: &str,
+    manifest_path: Option<&Path>,
+) -> Option<(PathBuf, String)> {
+    let manifest_path = find_manifest(manifest_path)?;
+    let manifest_dir = manifest_path.parent()?;
+    let vendor_dir = cargo_config::vendor_dir(manifest_dir).ok()??;
+    let lock_path = manifest_dir
+        .ancestors()
+        .map(|dir| dir.join("Cargo.lock"))
+        .find(|lock_path| lock_path.is_file())?;
+    let lock = read_toml(&lock_path)?;
+    let crate_name = dependency_name.replace('-', "_");
+    lock.get("package")?
+        .as_array()?
+        .iter()
+        .filter(|package| {
+            let name = package.get("name").and_then(|name| name.as_str());
+            let source = package.get("source").and_then(|source| source.as_str());
+            name.map(|name| name.replace('-', "_")) == Some(crate_name.clone())
+                && source == Some(CRATES_IO_SOURCE)
+        })
+        .find_map(|package| {
+            let name = package.get("name")?.as_str()?;
+            let version = package.get("version")?.as_str()?;
+            match vendored_manifest(&vendor_dir, name, version) {
+                Some(_) => None,
+                None => Some((vendor_dir.clone(), version.to_owned())),
+            }
+        })
+}
+
+/// Returns the manifest of the vendored copy of the package with the given name and version.
+///
+/// `cargo vendor` places packages in `<name>` directories, or in `<name>-<version>` directories
+/// if multiple versions are vendored or `--versioned-dirs` is passed.
+fn vendored_manifest(vendor_dir: &Path, name: &str, version: &str) -> Option<PathBuf> {
+    [format!("{}-{}", name, version), name.to_owned()]
+        .iter()
+        .map(|dir| vendor_dir.join(dir).join("Cargo.toml"))
+        .find(|manifest_path| {
+            let manifest = read_toml(manifest_path);
+            let vendored_version = manifest
+                .as_ref()
+                .and_then(|manifest| manifest.get("package")?.get("version")?.as_str());
+            vendored_version == Some(version)
+        })
+}
+
+/// Checks the manifest of a path dependency against the version and source filters.
+fn check_path_dependency(manifest_path: PathBuf, query: &raw::Query) -> Option<PathBuf> {
+    let manifest = read_toml(&manifest_path)?;
+    let version = manifest
+        .get("package")?
+        .get("version")
+        .and_then(|version| version.as_str());
+    let version = match (version, query.version_req) {
+        (Some(version), _) => version,
+        // the version might be inherited from the workspace
+        (None, Some(_)) => return None,
+        (None, None) => "0.0.0",
+    };
+    if satisfies(query, version, None) {
+        Some(manifest_path)
+    } else {
+        None
+    }
+}
+
+/// Checks whether a package with the given version and `source` field matches the query.
+fn satisfies(query: &raw::Query, version: &str, source: Option<&str>) -> bool {
+    let version_matches = match query.version_req {
+        Some(version_req) => Version::parse(version)
+            .map(|version| version_req.matches(&version))
+            .unwrap_or(false),
+        None => true,
+    };
+    let source_matches = query
+        .source
+        .map(|filter| filter.matches(source))
+        .unwrap_or(true);
+    version_matches && source_matches
+}
+
+/// Returns the dependency entry with the given key, treating `-` and `_` as equivalent.
+fn find_key<'t>(dependencies: &'t toml::Value, key: &str) -> Option<&'t toml::Value> {
+    let crate_name = key.replace('-', "_");
+    let mut matching = dependencies
+        .as_table()?
+        .iter()
+        .filter(|(candidate, _)| candidate.replace('-', "_") == crate_name);
+    let (_, found) = matching.next()?;
+    if matching.next().is_some() {
+        return None;
+    }
+    Some(found)
+}
+
+/// Checks whether the manifest overrides dependencies through `[patch]` or `[replace]`.
+fn has_overrides(manifest: &toml::Table) -> bool {
+    manifest.contains_key("patch") || manifest.contains_key("replace")
+}
+
+/// Returns the directory of the workspace root manifest for the package in the given
+/// directory.
+///
+/// This is the nearest directory with a manifest that has a `[workspace]` table, or the
+/// package directory itself if there is none. Returns `None` if a workspace root is found that
+/// doesn't obviously include the package, since `members` and `exclude` patterns are not
+/// evaluated.
+fn workspace_root(manifest_dir: &Path) -> Option<PathBuf> {
+    for dir in manifest_dir.ancestors() {
+        let manifest_path = dir.join("Cargo.toml");
+        if !manifest_path.is_file() {
+            continue;
+        }
+        let manifest = read_toml(&manifest_path)?;
+        let workspace = match manifest.get("workspace") {
+            Some(workspace) => workspace.as_table()?,
+            None => continue,
+        };
+        if dir == manifest_dir {
+            return Some(dir.to_owned());
+        }
+        let relative = manifest_dir
+            .strip_prefix(dir)
+            .ok()?
+            .to_str()?
+            .replace('\\', "/");
+        let members = workspace
+            .get("members")
+            .and_then(|members| members.as_array());
+        let is_member = members
+            .map(|members| {
+                members
+                    .iter()
+                    .any(|member| member.as_str() == Some(&relative))
+            })
+            .unwrap_or(false);
+        return if is_member {
+            Some(dir.to_owned())
+        } else {
+            None
+        };
+    }
+    Some(manifest_dir.to_owned())
+}
+
+/// Returns the manifest of the extracted registry package with the given name and version.
+///
+/// Returns `None` if the package was not extracted completely or if it's extracted for
+/// multiple registries, e.g. for both the git and the sparse index of crates.io.
+fn registry_manifest(cargo_home: &Path, name: &str, version: &str) -> Option<PathBuf> {
+    let package_dir = format!("{}-{}", name, version);
+    let mut extracted = fs::read_dir(cargo_home.join("registry").join("src"))
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path().join(&package_dir))
+        // cargo creates the `.cargo-ok` file after extracting the package
+        .filter(|dir| dir.join(".cargo-ok").is_file());
+    let dir = extracted.next()?;
+    if extracted.next().is_some() {
+        return None;
+    }
+    Some(dir.join("Cargo.toml"))
+}
+
+/// Returns the manifest of the package with the given name in the git checkouts of the given
+/// commit.
+///
+/// Cargo names the checkout directories after the abbreviated commit hash.
+fn git_manifest(cargo_home: &Path, name: &str, commit: &str) -> Option<PathBuf> {
+    let short_commit = commit.get(..7)?;
+    let mut found = Vec::new();
+    for entry in fs::read_dir(cargo_home.join("git").join("checkouts")).ok()? {
+        let checkout = entry.ok()?.path().join(short_commit);
+        if checkout.is_dir() {
+            find_package(&checkout, name, GIT_CHECKOUT_SEARCH_DEPTH, &mut found);
+        }
+    }
+    if found.len() == 1 {
+        found.pop()
+    } else {
+        None
+    }
+}
+
+/// Collects the manifests of packages with the given name in the given directory tree.
+fn find_package(dir: &Path, name: &str, depth: usize, found: &mut Vec<PathBuf>) {
+    let manifest_path = dir.join("Cargo.toml");
+    let package_name = read_toml(&manifest_path).and_then(|manifest| {
+        let name = manifest.get("package")?.get("name")?.as_str()?;
+        Some(name.to_owned())
+    });
+    if package_name.as_deref() == Some(name) {
+        found.push(manifest_path);
+    }
+    if depth == 0 {
+        return;
+    }
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        if path.is_dir() && !hidden && entry.file_name() != "target" {
+            find_package(&path, name, depth - 1, found);
+        }
+    }
+}
+
+fn read_toml(path: &Path) -> Option<toml::Table> {
+    toml::from_str(&fs::read_to_string(path).ok()?).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{self, TempDir};
+
+    fn locate_bootloader(project: &TempDir) -> Option<PathBuf> {
+        locate("bootloader", Some(project.path()), &raw::Query::default())
+    }
+
+    #[test]
+    fn locates_vendored_dependency() {
+        let project = fixtures::vendored_kernel().unwrap();
+        assert_eq!(
+            locate_bootloader(&project),
+            Some(project.path().join("vendor/bootloader/Cargo.toml"))
+        );
+        assert_eq!(
+            missing_vendored_copy("bootloader", Some(project.path())),
+            None
+        );
+    }
+
+    #[test]
+    fn reports_missing_vendored_copy() {
+        let project = fixtures::vendored_kernel().unwrap();
+        fs::remove_dir_all(project.path().join("vendor/bootloader")).unwrap();
+        assert_eq!(locate_bootloader(&project), None);
+        assert_eq!(
+            missing_vendored_copy("bootloader", Some(project.path())),
+            Some((project.path().join("vendor"), String::from("0.11.3")))
+        );
+    }
+
+    #[test]
+    fn falls_back_for_outdated_lock_file() {
+        let project = fixtures::vendored_kernel().unwrap();
+        project
+            .write(
+                "Cargo.toml",
+                "[package]\nname = \"kernel\"\nversion = \"0.1.0\"\n\n\
+             [dependencies]\nbootloader = \"0.9\"\n",
+            )
+            .unwrap();
+        assert_eq!(locate_bootloader(&project), None);
+    }
+
+    #[test]
+    fn falls_back_for_optional_and_patched_dependencies() {
+        let project = fixtures::vendored_kernel().unwrap();
+        project
+            .write(
+                "Cargo.toml",
+                "[package]\nname = \"kernel\"\nversion = \"0.1.0\"\n\n\
+             [dependencies]\nbootloader = { version = \"0.11\", optional = true }\n",
+            )
+            .unwrap();
+        assert_eq!(locate_bootloader(&project), None);
+
+        project
+            .write(
+                "Cargo.toml",
+                "[package]\nname = \"kernel\"\nversion = \"0.1.0\"\n\n\
+             [dependencies]\nbootloader = \"0.11\"\n\n\
+             [patch.crates-io]\nbootloader = { path = \"../bootloader\" }\n",
+            )
+            .unwrap();
+        assert_eq!(locate_bootloader(&project), None);
+    }
+
+    #[test]
+    fn checks_path_dependency_against_query() {
+        let project = TempDir::new().unwrap();
+        project
+            .write(
+                "Cargo.toml",
+                "[package]\nname = \"kernel\"\nversion = \"0.1.0\"\n\n\
+             [dependencies]\nbootloader = { path = \"bootloader\" }\n",
+            )
+            .unwrap();
+        let bootloader = project
+            .write(
+                "bootloader/Cargo.toml",
+                "[package]\nname = \"bootloader\"\nversion = \"0.11.3\"\n",
+            )
+            .unwrap();
+        assert_eq!(locate_bootloader(&project), Some(bootloader));
+
+        let version_req = VersionReq::parse("^0.9").unwrap();
+        let query = raw::Query {
+            version_req: Some(&version_req),
+            ..raw::Query::default()
+        };
+        assert_eq!(locate("bootloader", Some(project.path()), &query), None);
+    }
+
+    #[test]
+    fn finds_workspace_root() {
+        let workspace = TempDir::new().unwrap();
+        workspace
+            .write("Cargo.toml", "[workspace]\nmembers = [\"kernel\"]\n")
+            .unwrap();
+        workspace
+            .write("kernel/Cargo.toml", "[package]\nname = \"kernel\"\n")
+            .unwrap();
+        workspace
+            .write("other/Cargo.toml", "[package]\nname = \"other\"\n")
+            .unwrap();
+        assert_eq!(
+            workspace_root(&workspace.path().join("kernel")),
+            Some(workspace.path().to_owned())
+        );
+        // glob patterns and `exclude` are not evaluated
+        assert_eq!(workspace_root(&workspace.path().join("other")), None);
+        assert_eq!(
+            workspace_root(workspace.path()),
+            Some(workspace.path().to_owned())
+        );
+
+        let package = TempDir::new().unwrap();
+        package
+            .write("Cargo.toml", "[package]\nname = \"kernel\"\n")
+            .unwrap();
+        assert_eq!(
+            workspace_root(package.path()),
+            Some(package.path().to_owned())
+        );
+    }
+}
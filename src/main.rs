@@ -0,0 +1,141 @@
+//! Command line interface for locating a dependency on the file system.
+//!
+//! Prints the manifest path of the given dependency, e.g. for use in Makefiles:
+//!
+//! ```text
+//! bootloader-locator <dependency-name> [--manifest-path <path>] [--json] [--output <format>]
+//! ```
+//!
+//! With `--json`, the manifest path or the error is printed as a JSON object. With
+//! `--output json`, the full [`Report`](bootloader_locator::report::Report) of the lookup is
+//! printed instead, including the located package, the timing, and the cargo command.
+//!
+//! Requires the `cli` feature, which is enabled by default.
+
+use bootloader_locator::Locator;
+use std::{env, path::PathBuf, process};
+
+const USAGE: &str =
+    "Usage: bootloader-locator <dependency-name> [--manifest-path <path>] [--json] \
+                     [--output <text|json>]";
+
+fn main() {
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("Error: {}\n\n{}", message, USAGE);
+            process::exit(2);
+        }
+    };
+    if args.help {
+        println!("{}", USAGE);
+        return;
+    }
+
+    let mut locator = Locator::new(args.dependency_name);
+    if let Some(manifest_path) = args.manifest_path {
+        locator.manifest_path(manifest_path);
+    }
+
+    if args.output == Output::Json {
+        let report = locator.report();
+        println!("{}", report.to_json());
+        if report.error.is_some() {
+            process::exit(1);
+        }
+        return;
+    }
+
+    match locator.locate() {
+        Ok(manifest_path) if args.json => {
+            println!("{}", serde_json::json!({ "manifest_path": manifest_path }));
+        }
+        Ok(manifest_path) => println!("{}", manifest_path.display()),
+        Err(err) if args.json => {
+            println!("{}", serde_json::json!({ "error": err.to_string() }));
+            process::exit(1);
+        }
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            process::exit(1);
+        }
+    }
+}
+
+struct Args {
+    dependency_name: String,
+    manifest_path: Option<PathBuf>,
+    json: bool,
+    output: Output,
+    help: bool,
+}
+
+#[derive(PartialEq, Eq)]
+enum Output {
+    Text,
+    Json,
+}
+
+fn parse_output(format: &str) -> Result<Output, String> {
+    match format {
+        "text" => Ok(Output::Text),
+        "json" => Ok(Output::Json),
+        other => Err(format!(
+            "Unknown output format `{}`, expected `text` or `json`",
+            other
+        )),
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut dependency_name = None;
+    let mut manifest_path = None;
+    let mut json = false;
+    let mut output = Output::Text;
+    let mut help = false;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--manifest-path" => {
+                let path = args
+                    .next()
+                    .ok_or("The `--manifest-path` argument requires a value")?;
+                manifest_path = Some(PathBuf::from(path));
+            }
+            "--json" => json = true,
+            "--output" => {
+                let format = args
+                    .next()
+                    .ok_or("The `--output` argument requires a value")?;
+                output = parse_output(&format)?;
+            }
+            "-h" | "--help" => help = true,
+            other if other.starts_with("--manifest-path=") => {
+                manifest_path = Some(PathBuf::from(&other["--manifest-path=".len()..]));
+            }
+            other if other.starts_with("--output=") => {
+                output = parse_output(&other["--output=".len()..])?;
+            }
+            other if other.starts_with('-') => {
+                return Err(format!("Unknown argument `{}`", other));
+            }
+            _ if dependency_name.is_some() => {
+                return Err(format!("Unexpected argument `{}`", arg));
+            }
+            _ => dependency_name = Some(arg),
+        }
+    }
+
+    let dependency_name = match dependency_name {
+        Some(name) => name,
+        None if help => String::new(),
+        None => return Err("No dependency name given".into()),
+    };
+    Ok(Args {
+        dependency_name,
+        manifest_path,
+        json,
+        output,
+        help,
+    })
+}
@@ -0,0 +1,151 @@
+//! Process-wide memoization of `cargo metadata` outputs.
+//!
+//! Tools that look up dependencies from several subsystems would otherwise run
+//! `cargo metadata` again for every lookup. With the `cache` feature, the output is kept in
+//! memory and reused as long as the manifests and the `Cargo.lock` file of the project are not
+//! modified. Outputs are keyed by the canonicalized manifest path, the modification times of
+//! all `Cargo.toml` and `Cargo.lock` files in its directory and its parents, and the settings
+//! of the [`Context`] that affect the output, e.g. the enabled features but not the timeout.
+//! The parent directories are included since they might contain the root manifest of the
+//! workspace, whose `[patch]` and `[workspace.dependencies]` tables affect the resolution.
+//!
+//! Memoization can be disabled for a single lookup through [`Context::memoize`]. Without the
+//! `cache` feature, nothing is memoized.
+
+use crate::{canonicalize, find_manifest, Context};
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+static MEMO: Mutex<Option<HashMap<Key, (String, SystemTime)>>> = Mutex::new(None);
+
+/// Removes all memoized `cargo metadata` outputs, so that the next lookups run cargo again.
+pub fn clear() {
+    *MEMO.lock().unwrap() = None;
+}
+
+/// The memoization key of a `cargo metadata` run.
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub(crate) struct Key {
+    manifest_path: PathBuf,
+    /// The modification times of the existing `Cargo.toml` and `Cargo.lock` files.
+    inputs: Vec<(PathBuf, SystemTime)>,
+    /// The [`Context::resolution_settings`].
+    context: String,
+}
+
+impl Key {
+    /// Creates the key for running `cargo metadata` with the given settings.
+    ///
+    /// Returns `None` if memoization is disabled, if the manifest can't be determined, or if
+    /// the modification time of one of the input files can't be read.
+    pub fn new(context: &Context, manifest_path: Option<&Path>) -> Option<Self> {
+        if !cfg!(feature = "cache") || !context.memoize || context.invoker.is_some() {
+            return None;
+        }
+        let manifest_path = find_manifest(manifest_path.or(context.manifest_path.as_deref()))?;
+        let manifest_path = canonicalize(&manifest_path);
+        let mut inputs = Vec::new();
+        for dir in manifest_path.parent()?.ancestors() {
+            for name in ["Cargo.toml", "Cargo.lock"] {
+                let path = dir.join(name);
+                match fs::metadata(&path).and_then(|metadata| metadata.modified()) {
+                    Ok(modified) => inputs.push((path, modified)),
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                    Err(_) => return None,
+                }
+            }
+        }
+        Some(Key {
+            manifest_path,
+            inputs,
+            context: context.resolution_settings(),
+        })
+    }
+}
+
+/// Returns the memoized output for the given key, together with the time it was memoized.
+pub(crate) fn get(key: &Key) -> Option<(String, SystemTime)> {
+    MEMO.lock().unwrap().as_ref()?.get(key).cloned()
+}
+
+/// Memoizes the given output.
+pub(crate) fn insert(key: Key, output: &str) {
+    MEMO.lock()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .insert(key, (output.to_owned(), SystemTime::now()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::TempDir;
+
+    #[test]
+    #[cfg(feature = "cache")]
+    fn key_covers_workspace_files() {
+        use std::time::Duration;
+
+        let workspace = TempDir::new().unwrap();
+        let root_manifest = workspace
+            .write("Cargo.toml", "[workspace]\nmembers = [\"kernel\"]\n")
+            .unwrap();
+        let lock_file = workspace.write("Cargo.lock", "version = 3\n").unwrap();
+        let manifest_path = workspace
+            .write("kernel/Cargo.toml", "[package]\nname = \"kernel\"\n")
+            .unwrap();
+        let context = Context::from_env();
+        let key = || Key::new(&context, Some(&manifest_path)).unwrap();
+
+        assert_eq!(key(), key());
+        for (i, path) in [&root_manifest, &lock_file, &manifest_path]
+            .iter()
+            .enumerate()
+        {
+            let before = key();
+            // set the modification time explicitly, since it might have a coarse resolution
+            let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000 + i as u64);
+            let file = fs::File::options().write(true).open(path).unwrap();
+            file.set_modified(modified).unwrap();
+            assert_ne!(key(), before, "{} is not covered", path.display());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "cache")]
+    fn key_only_covers_resolution_settings() {
+        use std::time::Duration;
+
+        let project = TempDir::new().unwrap();
+        let manifest_path = project
+            .write("Cargo.toml", "[package]\nname = \"kernel\"\n")
+            .unwrap();
+        let key = |context: &Context| Key::new(context, Some(&manifest_path)).unwrap();
+        let default = key(&Context::from_env());
+
+        let mut context = Context::from_env();
+        context
+            .timeout(Duration::from_secs(1))
+            .stdout_limit(1024)
+            .overflow_dir(project.path());
+        assert_eq!(key(&context), default);
+        context.features(["uefi"]);
+        assert_ne!(key(&context), default);
+    }
+
+    #[test]
+    fn disabled_without_memoization() {
+        let project = TempDir::new().unwrap();
+        let manifest_path = project
+            .write("Cargo.toml", "[package]\nname = \"kernel\"\n")
+            .unwrap();
+        let mut context = Context::from_env();
+        context.memoize(false);
+        assert!(Key::new(&context, Some(&manifest_path)).is_none());
+    }
+}
@@ -0,0 +1,263 @@
+//! Stable identifiers for the user-facing messages of this crate, for translating them.
+//!
+//! The `Display` output of the error types is always English. Tools that present errors in
+//! other languages can instead turn an error into a [`Message`] through its `message` method,
+//! e.g. [`LocateError::message`](crate::LocateError::message), and render it with their own
+//! templates:
+//!
+//! ```no_run
+//! use bootloader_locator::locate_bootloader;
+//!
+//! if let Err(err) = locate_bootloader("bootloader") {
+//!     let message = err.message();
+//!     let text = message.render_with(|id| match id {
+//!         "locate.dependency-not-found" => {
+//!             Some("Keine Abhängigkeit mit dem angegebenen Namen gefunden")
+//!         }
+//!         _ => None,
+//!     });
+//!     eprintln!("{}", text);
+//! }
+//! ```
+//!
+//! The identifiers of the [`CATALOG`] are stable. New identifiers might be added in future
+//! versions, so translations should always fall back to the default templates.
+
+/// The identifiers of all messages together with their default English templates.
+///
+/// Templates reference the arguments of a message through `{name}` placeholders. The special
+/// `{source}` placeholder is replaced with the rendered [`source`](Message::source) message.
+pub const CATALOG: &[(&str, &str)] = &[
+    (
+        "locate.metadata-invalid",
+        "The `cargo metadata` output was not valid (missing or invalid field `{field}`)",
+    ),
+    (
+        "locate.dependency-not-found",
+        "Could not find a dependency named `{dependency_name}` in `{manifest_path}`",
+    ),
+    (
+        "locate.dependency-not-activated",
+        "The dependency `{dependency_name}` is declared in `{manifest_path}`, but not activated in \
+        the current configuration",
+    ),
+    (
+        "locate.dependency-is-root",
+        "The dependency name `{dependency_name}` refers to the root package `{manifest_path}` \
+        itself instead of one of its dependencies",
+    ),
+    (
+        "locate.package-not-found",
+        "Could not find a package with the manifest path `{manifest_path}` in the `cargo metadata` \
+        output",
+    ),
+    (
+        "locate.package-id-not-found",
+        "Could not find a package with the ID `{id}` in the `cargo metadata` output",
+    ),
+    (
+        "locate.version-mismatch",
+        "The dependency `{dependency_name}` of `{manifest_path}` has version {found}, which does \
+        not match the requirement `{required}`",
+    ),
+    (
+        "locate.dep-kind-mismatch",
+        "The dependency `{dependency_name}` of `{manifest_path}` is not a {required} dependency \
+        (declared as: {found})",
+    ),
+    (
+        "locate.source-mismatch",
+        "The dependency `{dependency_name}` of `{manifest_path}` comes from {found} instead of \
+        the required {required} source",
+    ),
+    (
+        "locate.env-override-invalid",
+        "The environment variable `{variable}` is set to `{path}`, which is not a `Cargo.toml` \
+        file or a directory containing one",
+    ),
+    (
+        "locate.workspace-member-not-found",
+        "Could not find a workspace member matching `{member}` in the `cargo metadata` output",
+    ),
+    (
+        "locate.ambiguous-member",
+        "The workspace member selector `{member}` matches multiple members ({matches}), select \
+        one by path or package ID",
+    ),
+    (
+        "locate.virtual-workspace",
+        "`{manifest_path}` is a virtual workspace without a single default member, select one of \
+        the workspace members: {members}",
+    ),
+    (
+        "locate.vendored-copy-missing",
+        "The vendored copy of `{dependency_name}` {version} is missing in `{vendor_dir}`, run \
+        `cargo vendor` to update the vendor directory",
+    ),
+    (
+        "locate.binary-not-found",
+        "The package `{package}` has no binary target named `{bin_name}` (binary targets: \
+        {available})",
+    ),
+    (
+        "locate.ambiguous-binary",
+        "The package `{package}` doesn't have exactly one binary target (binary targets: \
+        {available}), select one by name",
+    ),
+    (
+        "locate.manifest-missing",
+        "The manifest `{path}` of the located package doesn't exist or isn't readable, run \
+        `cargo fetch` to download the package again",
+    ),
+    (
+        "locate.metadata",
+        "Failed to retrieve project metadata: {source}",
+    ),
+    (
+        "locate.toolchain",
+        "Failed to probe the Rust toolchain: {source}",
+    ),
+    ("metadata.io", "Failed to execute `cargo metadata`: {error}"),
+    (
+        "metadata.manifest-not-found",
+        "No `Cargo.toml` file found at `{path}`",
+    ),
+    (
+        "metadata.failed",
+        "`cargo metadata` was not successful: {stderr}",
+    ),
+    (
+        "metadata.output-too-large",
+        "The output of `cargo metadata` exceeded the limit of {limit} bytes",
+    ),
+    (
+        "metadata.string-conversion",
+        "The JSON output of `cargo metadata` is not valid UTF-8: {error}",
+    ),
+    (
+        "metadata.parse-json",
+        "Failed to parse `cargo metadata` output as JSON: {error}",
+    ),
+    (
+        "metadata.parse-json-after-ignored-output",
+        "Failed to parse `cargo metadata` output as JSON after skipping unexpected leading \
+        output `{ignored_output}`: {error}",
+    ),
+    (
+        "metadata.timed-out",
+        "`cargo metadata` did not finish within {timeout} and was killed",
+    ),
+    ("metadata.cancelled", "`cargo metadata` was cancelled"),
+    (
+        "metadata.feature-disabled",
+        "This functionality requires the `{feature}` feature of `bootloader-locator`, which is \
+        disabled",
+    ),
+    (
+        "metadata.unsupported-format-version",
+        "Unsupported `cargo metadata` format version {version}",
+    ),
+    ("toolchain.io", "Failed to execute `rustc`: {error}"),
+    (
+        "toolchain.failed",
+        "`rustc --version --verbose` was not successful: {stderr}",
+    ),
+    (
+        "toolchain.invalid-output",
+        "Unexpected output of `rustc --version --verbose`: {output}",
+    ),
+];
+
+/// Returns the default English template for the message with the given identifier.
+pub fn default_template(id: &str) -> Option<&'static str> {
+    CATALOG
+        .iter()
+        .find(|(catalog_id, _)| *catalog_id == id)
+        .map(|(_, template)| *template)
+}
+
+/// A user-facing message, identified by a stable identifier of the [`CATALOG`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    id: &'static str,
+    args: Vec<(&'static str, String)>,
+    source: Option<Box<Message>>,
+}
+
+impl Message {
+    pub(crate) fn new(id: &'static str) -> Self {
+        debug_assert!(default_template(id).is_some(), "unknown message id {}", id);
+        Message {
+            id,
+            args: Vec::new(),
+            source: None,
+        }
+    }
+
+    pub(crate) fn with_arg(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.args.push((name, value.into()));
+        self
+    }
+
+    pub(crate) fn with_source(mut self, source: Message) -> Self {
+        self.source = Some(Box::new(source));
+        self
+    }
+
+    /// Returns the identifier of the message.
+    pub fn id(&self) -> &'static str {
+        self.id
+    }
+
+    /// Returns the arguments of the message as `(name, value)` pairs.
+    ///
+    /// Some arguments carry details that are not referenced by the default template, e.g. the
+    /// activating features of a dependency that is not activated.
+    pub fn args(&self) -> &[(&'static str, String)] {
+        &self.args
+    }
+
+    /// Returns the value of the argument with the given name.
+    pub fn arg(&self, name: &str) -> Option<&str> {
+        self.args
+            .iter()
+            .find(|(arg_name, _)| *arg_name == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the message that caused this message, e.g. the message of a metadata error that
+    /// caused a lookup to fail.
+    pub fn source(&self) -> Option<&Message> {
+        self.source.as_deref()
+    }
+
+    /// Renders the message with the default English templates.
+    pub fn render(&self) -> String {
+        self.render_with(|_| None)
+    }
+
+    /// Renders the message with the templates returned by the given function.
+    ///
+    /// The function is called with the identifier of this message and of all of its source
+    /// messages. If it returns `None`, the default template of the [`CATALOG`] is used.
+    pub fn render_with<'a, F>(&self, templates: F) -> String
+    where
+        F: Fn(&str) -> Option<&'a str>,
+    {
+        self.render_inner(&templates)
+    }
+
+    fn render_inner<'a>(&self, templates: &dyn Fn(&str) -> Option<&'a str>) -> String {
+        let template = templates(self.id)
+            .or_else(|| default_template(self.id))
+            .unwrap_or(self.id);
+        let mut rendered = template.to_owned();
+        for (name, value) in &self.args {
+            rendered = rendered.replace(&format!("{{{}}}", name), value);
+        }
+        if let Some(source) = &self.source {
+            rendered = rendered.replace("{source}", &source.render_inner(templates));
+        }
+        rendered
+    }
+}
@@ -0,0 +1,207 @@
+//! Runs `cargo metadata` without blocking the async runtime.
+//!
+//! Requires the `tokio` feature. Without it, all lookups fail with
+//! [`CargoMetadataError::FeatureDisabled`].
+
+#[cfg(feature = "tokio")]
+use crate::{
+    event::{EventHook, Progress},
+    memo, metadata_command, metadata_output,
+    process::{CaptureLimits, Captured, Overflow, OverflowSink},
+    trace,
+};
+use crate::{CargoMetadataError, Context};
+use std::path::Path;
+#[cfg(feature = "tokio")]
+use tokio::{
+    io::{self, AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader},
+    process::Command,
+};
+
+/// Async version of [`crate::metadata`].
+#[cfg(not(feature = "tokio"))]
+pub(crate) async fn metadata(
+    _context: &Context,
+    _manifest_path: Option<&Path>,
+) -> Result<String, CargoMetadataError> {
+    Err(CargoMetadataError::FeatureDisabled { feature: "tokio" })
+}
+
+/// Async version of [`crate::metadata`].
+#[cfg(feature = "tokio")]
+pub(crate) async fn metadata(
+    context: &Context,
+    manifest_path: Option<&Path>,
+) -> Result<String, CargoMetadataError> {
+    let memo_key = memo::Key::new(context, manifest_path);
+    if let Some((output, _)) = memo_key.as_ref().and_then(memo::get) {
+        return Ok(output);
+    }
+    let mut attempt = 1;
+    let output = loop {
+        let result = match &context.invoker {
+            Some(invoker) => invoker.metadata(context, manifest_path),
+            None => run_metadata(context, manifest_path).await,
+        };
+        match result {
+            Err(err) => match context.retry_delay(&err, attempt) {
+                Some(delay) => {
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                None => return Err(err),
+            },
+            Ok(output) => break output,
+        }
+    };
+    if let Some(memo_key) = memo_key {
+        memo::insert(memo_key, &output);
+    }
+    Ok(output)
+}
+
+/// Runs `cargo metadata` as a child process.
+#[cfg(feature = "tokio")]
+async fn run_metadata(
+    context: &Context,
+    manifest_path: Option<&Path>,
+) -> Result<String, CargoMetadataError> {
+    if context.is_cancelled() {
+        return Err(CargoMetadataError::Cancelled);
+    }
+    let command = metadata_command(context, manifest_path)?;
+    let invocation = trace::Invocation::start(&command);
+    let progress = Progress::start(context.event_hook.as_ref(), &command);
+    let mut command = Command::from(command);
+    // the process is killed if the future of the lookup is dropped
+    command.kill_on_drop(true);
+    let mut child = command.spawn()?;
+
+    let stdout_pipe = child.stdout.take().expect("stdout is piped");
+    let stderr_pipe = child.stderr.take().expect("stderr is piped");
+    let limits = CaptureLimits::of(context);
+    let run = async {
+        // read both streams concurrently to avoid a deadlock when the pipe buffer of one stream
+        // fills up while we're waiting for the other one
+        let (stdout, stderr) = tokio::join!(
+            read_bounded(
+                stdout_pipe,
+                limits.stdout,
+                OverflowSink::new(limits.overflow_dir.clone(), "stdout"),
+            ),
+            read_stderr(stderr_pipe, &limits, context.event_hook.as_ref())
+        );
+        let status = child.wait().await?;
+        io::Result::Ok((status, stdout?, stderr?))
+    };
+    let run = async {
+        match context.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, run).await.ok(),
+            None => Some(run.await),
+        }
+    };
+    let cancelled = async {
+        match &context.cancel_token {
+            Some(token) => token.cancelled().await,
+            None => std::future::pending().await,
+        }
+    };
+    let result = tokio::select! {
+        result = run => result,
+        _ = cancelled => None,
+    };
+    let (status, (stdout, stdout_overflow), (mut stderr, stderr_overflow)) = match result {
+        Some(result) => result?,
+        None => {
+            child.kill().await?;
+            invocation.finish(None, &[]);
+            progress.finish(None);
+            if context.is_cancelled() {
+                return Err(CargoMetadataError::Cancelled);
+            }
+            return Err(CargoMetadataError::TimedOut {
+                timeout: context.timeout.unwrap_or_default(),
+            });
+        }
+    };
+    if let Some(overflow) = &stderr_overflow {
+        stderr.extend_from_slice(overflow.marker().as_bytes());
+    }
+    invocation.finish(Some(status), &stderr);
+    progress.finish(Some(status));
+
+    let captured = Captured {
+        status,
+        stdout,
+        stdout_overflow,
+        stderr,
+        stderr_overflow,
+    };
+    metadata_output(context, captured)
+}
+
+/// Reads the stderr output of cargo, passing every line to the given event hook.
+#[cfg(feature = "tokio")]
+async fn read_stderr(
+    stream: impl AsyncRead + Unpin,
+    limits: &CaptureLimits,
+    events: Option<&EventHook>,
+) -> io::Result<(Vec<u8>, Option<Overflow>)> {
+    let overflow = OverflowSink::new(limits.overflow_dir.clone(), "stderr");
+    match events {
+        Some(events) => read_bounded_lines(stream, limits.stderr, overflow, events).await,
+        None => read_bounded(stream, limits.stderr, overflow).await,
+    }
+}
+
+/// Async version of [`crate::process::read_bounded_lines`].
+///
+/// Overflow files are written synchronously, which only blocks the executor briefly since they
+/// are only written once the limit is exceeded.
+#[cfg(feature = "tokio")]
+async fn read_bounded_lines(
+    stream: impl AsyncRead + Unpin,
+    limit: usize,
+    mut overflow: OverflowSink,
+    events: &EventHook,
+) -> io::Result<(Vec<u8>, Option<Overflow>)> {
+    let mut stream = BufReader::new(stream);
+    let mut data = Vec::new();
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if stream.read_until(b'\n', &mut line).await? == 0 {
+            break;
+        }
+        events.stderr_line(&line);
+        let kept = line.len().min(limit - data.len());
+        data.extend_from_slice(&line[..kept]);
+        overflow.write(&line[kept..])?;
+    }
+    Ok((data, overflow.finish()))
+}
+
+/// Async version of [`crate::process::read_bounded`].
+///
+/// Overflow files are written synchronously, see [`read_bounded_lines`].
+#[cfg(feature = "tokio")]
+async fn read_bounded(
+    stream: impl AsyncRead + Unpin,
+    limit: usize,
+    mut overflow: OverflowSink,
+) -> io::Result<(Vec<u8>, Option<Overflow>)> {
+    let mut stream = stream.take(limit as u64);
+    let mut data = Vec::new();
+    stream.read_to_end(&mut data).await?;
+
+    let mut stream = stream.into_inner();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        overflow.write(&buffer[..read])?;
+    }
+    Ok((data, overflow.finish()))
+}
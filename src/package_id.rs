@@ -0,0 +1,118 @@
+use crate::ParseValueError;
+use std::{fmt, str};
+
+/// The accepted package ID formats, for parse errors.
+const FORMATS: &[&str] = &["<name> <version> (<source>)", "<source>#<name>@<version>"];
+
+/// A package ID as used by cargo, e.g. in the `resolve` table of `cargo metadata`.
+///
+/// Cargo 1.77 changed the format of package IDs from `bootloader 0.9.23 (registry+https://..)`
+/// to the package ID specification format `registry+https://..#bootloader@0.9.23`. This type
+/// parses both formats, so that callers don't need to care which cargo version produced the
+/// ID. Package IDs compare equal if their string representations are equal, so the same package
+/// has different IDs when reported by different cargo versions.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PackageId {
+    repr: String,
+    name: String,
+    version: String,
+    source: Option<String>,
+}
+
+impl PackageId {
+    /// Creates the ID of a package from `cargo metadata`, whose name and version are known.
+    ///
+    /// Only the source is parsed from the ID, so that IDs in unknown formats are still usable.
+    pub(crate) fn from_metadata(repr: &str, name: &str, version: &str) -> Self {
+        let source = repr
+            .parse::<PackageId>()
+            .ok()
+            .and_then(|parsed| parsed.source);
+        PackageId {
+            repr: repr.to_owned(),
+            name: name.to_owned(),
+            version: version.to_owned(),
+            source,
+        }
+    }
+
+    /// Returns the ID in the format reported by cargo.
+    pub fn as_str(&self) -> &str {
+        &self.repr
+    }
+
+    /// Returns the name of the package.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the version of the package.
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// Returns the source of the package, e.g.
+    /// `registry+https://github.com/rust-lang/crates.io-index`.
+    ///
+    /// Unlike [`LocatedPackage::source`](crate::LocatedPackage::source), path packages have a
+    /// `path+file://` source too. The new ID format doesn't include the resolved commit of git
+    /// sources.
+    pub fn source(&self) -> Option<&str> {
+        self.source.as_deref()
+    }
+}
+
+impl fmt::Display for PackageId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.repr)
+    }
+}
+
+/// Parses both the old `<name> <version> (<source>)` format and the package ID specification
+/// format `<source>#<name>@<version>` of cargo 1.77 and newer.
+///
+/// In the specification format, the name is omitted if it's equal to the last path segment of
+/// the source URL, e.g. `path+file:///home/user/kernel#0.1.0`.
+impl str::FromStr for PackageId {
+    type Err = ParseValueError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ParseValueError::new(s, FORMATS);
+        let (name, version, source) = match s.split_once(' ') {
+            Some((name, rest)) => {
+                let (version, source) = match rest.split_once(' ') {
+                    Some((version, source)) => {
+                        let source = source
+                            .strip_prefix('(')
+                            .and_then(|source| source.strip_suffix(')'))
+                            .ok_or_else(error)?;
+                        (version, Some(source))
+                    }
+                    None => (rest, None),
+                };
+                (name.to_owned(), version, source)
+            }
+            None => {
+                let (source, fragment) = s.rsplit_once('#').ok_or_else(error)?;
+                let (name, version) = match fragment.split_once('@') {
+                    Some((name, version)) => (name.to_owned(), version),
+                    None => {
+                        let path = source.split('?').next().unwrap_or(source);
+                        let name = path.trim_end_matches('/').rsplit('/').next();
+                        (name.ok_or_else(error)?.to_owned(), fragment)
+                    }
+                };
+                (name, version, Some(source))
+            }
+        };
+        if name.is_empty() || version.is_empty() {
+            return Err(error());
+        }
+        Ok(PackageId {
+            repr: s.to_owned(),
+            name,
+            version: version.to_owned(),
+            source: source.map(String::from),
+        })
+    }
+}
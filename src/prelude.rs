@@ -0,0 +1,72 @@
+//! Re-exports the most commonly used items of this crate.
+//!
+//! The builders and runners are only re-exported if their cargo features are enabled, which
+//! they are by default.
+//!
+//! The common case of locating the `bootloader` dependency of a kernel and booting the kernel
+//! in QEMU, e.g. in a `runner` binary, fits into a few lines:
+//!
+//! ```no_run
+//! # #[cfg(feature = "run")]
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use bootloader_locator::prelude::*;
+//!
+//! let kernel = std::env::args().nth(1).expect("no kernel executable given");
+//! let outcome = quick_build_and_run(kernel)?;
+//! assert_eq!(outcome, TestOutcome::Success);
+//! # Ok(())
+//! # }
+//! # #[cfg(not(feature = "run"))]
+//! # fn main() {}
+//! ```
+//!
+//! Build scripts that only need the location of the bootloader use [`quick_locate`]:
+//!
+//! ```no_run
+//! use bootloader_locator::prelude::*;
+//!
+//! let bootloader_manifest = quick_locate()?;
+//! println!("cargo:rustc-env=BOOTLOADER_MANIFEST={}", bootloader_manifest.display());
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! For more control over the individual steps, the builders are re-exported as well:
+//!
+//! ```no_run
+//! # #[cfg(feature = "run")]
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use bootloader_locator::prelude::*;
+//!
+//! let bootloader = Locator::new("bootloader").transitive(true).locate_package()?;
+//! let artifacts =
+//!     BootloaderBuilder::new(&bootloader.manifest_path, "target/x86_64-os/debug/kernel")
+//!         .kernel_manifest("Cargo.toml")
+//!         .build()?;
+//! create_bios_image(&artifacts.bootloader_elf, "target/bios.img".as_ref())?;
+//! let outcome = QemuRunner::new("target/bios.img").arg("-nographic").run()?;
+//! println!("{:?}", outcome);
+//! # Ok(())
+//! # }
+//! # #[cfg(not(feature = "run"))]
+//! # fn main() {}
+//! ```
+
+pub use crate::{
+    locate_bootloader, locate_bootloader_package, quick::quick_locate, BootloaderKind, Context,
+    LocateError, LocatedPackage, Locator, Metadata,
+};
+
+#[cfg(feature = "build")]
+pub use crate::{
+    arch::Arch, artifacts::ArtifactLocator, build::BootloaderBuilder, layout::LayoutStrategy,
+};
+
+#[cfg(feature = "image")]
+pub use crate::image::create_bios_image;
+
+#[cfg(feature = "run")]
+pub use crate::{
+    quick::{quick_build_and_run, QuickError},
+    runner::{QemuRunner, TestOutcome},
+    test_kernels::TestKernels,
+};
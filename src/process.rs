@@ -0,0 +1,364 @@
+//! Helpers for running child processes with bounded output and optional timeouts.
+
+use crate::{atomic::AtomicFile, event::EventHook, CancelToken, Context};
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    path::PathBuf,
+    process::{Child, ExitStatus},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// The default maximum number of bytes of stdout output that are kept in memory.
+pub(crate) const DEFAULT_STDOUT_LIMIT: usize = 256 * 1024 * 1024;
+
+/// The default maximum number of bytes of stderr output that are kept in memory.
+pub(crate) const DEFAULT_STDERR_LIMIT: usize = 1024 * 1024;
+
+/// Limits for the output of a child process that is kept in memory.
+///
+/// Output beyond the limit of a stream is discarded, or written to a file in the overflow
+/// directory if one is set.
+#[derive(Debug, Clone)]
+pub(crate) struct CaptureLimits {
+    pub stdout: usize,
+    pub stderr: usize,
+    pub overflow_dir: Option<PathBuf>,
+}
+
+impl CaptureLimits {
+    /// Returns the limits of the given context.
+    pub fn of(context: &Context) -> Self {
+        CaptureLimits {
+            stdout: context.stdout_limit,
+            stderr: context.stderr_limit,
+            overflow_dir: context.overflow_dir.clone(),
+        }
+    }
+}
+
+/// The output of a child process that exited before its timeout.
+pub(crate) struct Captured {
+    pub status: ExitStatus,
+    /// The stdout output up to the limit.
+    pub stdout: Vec<u8>,
+    /// The stdout output that exceeded the limit, if any.
+    pub stdout_overflow: Option<Overflow>,
+    /// The stderr output up to the limit, followed by a truncation marker if the limit was
+    /// exceeded.
+    pub stderr: Vec<u8>,
+    /// The stderr output that exceeded the limit, if any.
+    pub stderr_overflow: Option<Overflow>,
+}
+
+/// Output of a stream that exceeded its capture limit.
+#[derive(Debug)]
+pub(crate) struct Overflow {
+    /// The number of bytes that exceeded the limit.
+    pub bytes: u64,
+    /// The file that the bytes were written to, `None` if they were discarded.
+    pub file: Option<PathBuf>,
+}
+
+impl Overflow {
+    /// Returns the marker that is appended to truncated output.
+    pub fn marker(&self) -> String {
+        match &self.file {
+            Some(file) => format!(
+                "\n[... {} more bytes written to `{}`]\n",
+                self.bytes,
+                file.display()
+            ),
+            None => format!("\n[... {} more bytes truncated]\n", self.bytes),
+        }
+    }
+}
+
+/// Collects the output of a stream that exceeds its capture limit.
+///
+/// If an overflow directory is given, the output is written to a new file in it, which is only
+/// created once the limit is exceeded. The file only appears once the stream is finished, so
+/// that tools watching the directory never read a partial file.
+pub(crate) struct OverflowSink {
+    dir: Option<PathBuf>,
+    stream: &'static str,
+    file: Option<AtomicFile>,
+    bytes: u64,
+}
+
+impl OverflowSink {
+    pub fn new(dir: Option<PathBuf>, stream: &'static str) -> Self {
+        OverflowSink {
+            dir,
+            stream,
+            file: None,
+            bytes: 0,
+        }
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        self.bytes += data.len() as u64;
+        if let Some(dir) = &self.dir {
+            if self.file.is_none() {
+                let path = dir.join(unique_file_name(&format!("{}.log", self.stream)));
+                self.file = Some(AtomicFile::create(&path)?);
+            }
+            if let Some(file) = &mut self.file {
+                file.write_all(data)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the collected overflow, `None` if the limit was not exceeded.
+    ///
+    /// The overflow file is moved to its final path. If that fails, the output is reported as
+    /// discarded.
+    pub fn finish(self) -> Option<Overflow> {
+        if self.bytes == 0 {
+            return None;
+        }
+        let file = self.file.and_then(|file| {
+            let path = file.path().to_owned();
+            file.commit().ok().map(|()| path)
+        });
+        Some(Overflow {
+            bytes: self.bytes,
+            file,
+        })
+    }
+}
+
+/// Returns a file name that is unique among all processes, e.g. for temporary files.
+///
+/// The name consists of the crate name, the process ID, a counter, and the given suffix.
+pub(crate) fn unique_file_name(suffix: &str) -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    format!(
+        "bootloader-locator-{}-{}-{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed),
+        suffix
+    )
+}
+
+/// Reads the piped stdout and stderr of the given child and waits for it to exit.
+///
+/// The output is bounded by the capture limits of the context. If the child doesn't exit
+/// within the timeout of the context, it is killed and `None` is returned.
+pub(crate) fn capture(child: Child, context: &Context) -> io::Result<Option<Captured>> {
+    capture_cancellable(
+        child,
+        context.timeout,
+        &CaptureLimits::of(context),
+        None,
+        None,
+    )
+}
+
+/// Like [`capture`], but also kills the child and returns `None` when the given token is
+/// cancelled.
+///
+/// If an event hook is given, every line of stderr is passed to it while the child runs.
+pub(crate) fn capture_cancellable(
+    mut child: Child,
+    timeout: Option<Duration>,
+    limits: &CaptureLimits,
+    cancel: Option<&CancelToken>,
+    events: Option<&EventHook>,
+) -> io::Result<Option<Captured>> {
+    // read both streams on separate threads to avoid a deadlock when the pipe buffer of one
+    // stream fills up while we're blocked reading the other one
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
+    let (stdout_sender, stdout_receiver) = mpsc::channel();
+    let (stderr_sender, stderr_receiver) = mpsc::channel();
+    let (stdout_limit, stderr_limit) = (limits.stdout, limits.stderr);
+    let stdout_overflow = OverflowSink::new(limits.overflow_dir.clone(), "stdout");
+    let stderr_overflow = OverflowSink::new(limits.overflow_dir.clone(), "stderr");
+    thread::spawn(move || {
+        let result = read_bounded(stdout, stdout_limit, stdout_overflow);
+        let _ = stdout_sender.send(result);
+    });
+    let events = events.cloned();
+    thread::spawn(move || {
+        let result = match events {
+            Some(events) => read_bounded_lines(stderr, stderr_limit, stderr_overflow, |line| {
+                events.stderr_line(line)
+            }),
+            None => read_bounded(stderr, stderr_limit, stderr_overflow),
+        };
+        let _ = stderr_sender.send(result);
+    });
+
+    let status = match wait_cancellable(&mut child, timeout, cancel)? {
+        Some(status) => status,
+        // don't wait for the reader threads, since grandchildren of the killed process
+        // might keep the pipes open
+        None => return Ok(None),
+    };
+    let (stdout, stdout_overflow) = stdout_receiver
+        .recv()
+        .expect("stdout reader thread panicked")?;
+    let (mut stderr, stderr_overflow) = stderr_receiver
+        .recv()
+        .expect("stderr reader thread panicked")?;
+    if let Some(overflow) = &stderr_overflow {
+        stderr.extend_from_slice(overflow.marker().as_bytes());
+    }
+    Ok(Some(Captured {
+        status,
+        stdout,
+        stdout_overflow,
+        stderr,
+        stderr_overflow,
+    }))
+}
+
+/// Waits for the given child to exit.
+///
+/// If the child doesn't exit within the given timeout, it is killed and `None` is returned.
+#[cfg(feature = "run")]
+pub(crate) fn wait_timeout(
+    child: &mut Child,
+    timeout: Option<Duration>,
+) -> io::Result<Option<ExitStatus>> {
+    wait_cancellable(child, timeout, None)
+}
+
+/// Like [`wait_timeout`], but also kills the child and returns `None` when the given token is
+/// cancelled.
+fn wait_cancellable(
+    child: &mut Child,
+    timeout: Option<Duration>,
+    cancel: Option<&CancelToken>,
+) -> io::Result<Option<ExitStatus>> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    if deadline.is_none() && cancel.is_none() {
+        return child.wait().map(Some);
+    }
+    let poll_interval = Duration::from_millis(10);
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        let now = Instant::now();
+        let expired = deadline.is_some_and(|deadline| now >= deadline);
+        if expired || cancel.is_some_and(CancelToken::is_cancelled) {
+            child.kill()?;
+            child.wait()?;
+            return Ok(None);
+        }
+        let remaining = deadline.map_or(poll_interval, |deadline| deadline - now);
+        thread::sleep(remaining.min(poll_interval));
+    }
+}
+
+/// Reads the given stream to the end, keeping at most `limit` bytes.
+///
+/// If the stream contains more data, the remaining bytes are passed to the given overflow sink.
+pub(crate) fn read_bounded(
+    stream: impl Read,
+    limit: usize,
+    mut overflow: OverflowSink,
+) -> io::Result<(Vec<u8>, Option<Overflow>)> {
+    let mut stream = stream.take(limit as u64);
+    let mut data = Vec::new();
+    stream.read_to_end(&mut data)?;
+
+    let mut stream = stream.into_inner();
+    let mut buffer = [0; 64 * 1024];
+    loop {
+        let read = match stream.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        overflow.write(&buffer[..read])?;
+    }
+    Ok((data, overflow.finish()))
+}
+
+/// Like [`read_bounded`], but passes every line of the stream to the given callback as soon as
+/// it was read, including the lines that exceed the limit.
+pub(crate) fn read_bounded_lines(
+    stream: impl Read,
+    limit: usize,
+    mut overflow: OverflowSink,
+    mut on_line: impl FnMut(&[u8]),
+) -> io::Result<(Vec<u8>, Option<Overflow>)> {
+    let mut stream = BufReader::new(stream);
+    let mut data = Vec::new();
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if stream.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        on_line(&line);
+        let kept = line.len().min(limit - data.len());
+        data.extend_from_slice(&line[..kept]);
+        overflow.write(&line[kept..])?;
+    }
+    Ok((data, overflow.finish()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp::TempDir;
+    use std::fs;
+
+    #[test]
+    fn read_bounded_discards_overflow() {
+        let (data, overflow) =
+            read_bounded(&b"0123456789"[..], 4, OverflowSink::new(None, "stdout")).unwrap();
+        assert_eq!(data, b"0123");
+        let overflow = overflow.unwrap();
+        assert_eq!(overflow.bytes, 6);
+        assert_eq!(overflow.file, None);
+        assert_eq!(overflow.marker(), "\n[... 6 more bytes truncated]\n");
+    }
+
+    #[test]
+    fn read_bounded_within_limit() {
+        let (data, overflow) =
+            read_bounded(&b"0123"[..], 4, OverflowSink::new(None, "stdout")).unwrap();
+        assert_eq!(data, b"0123");
+        assert!(overflow.is_none());
+    }
+
+    #[test]
+    fn read_bounded_writes_overflow_file() {
+        let dir = TempDir::new().unwrap();
+        let sink = OverflowSink::new(Some(dir.path().to_owned()), "stderr");
+        let (data, overflow) = read_bounded(&b"0123456789"[..], 4, sink).unwrap();
+        assert_eq!(data, b"0123");
+        let file = overflow.unwrap().file.unwrap();
+        assert_eq!(fs::read(&file).unwrap(), b"456789");
+    }
+
+    #[test]
+    fn read_bounded_lines_reports_all_lines() {
+        let dir = TempDir::new().unwrap();
+        let sink = OverflowSink::new(Some(dir.path().to_owned()), "stderr");
+        let mut lines = Vec::new();
+        let (data, overflow) = read_bounded_lines(&b"abc\ndef\nghi\n"[..], 6, sink, |line| {
+            lines.push(line.to_owned())
+        })
+        .unwrap();
+        assert_eq!(lines, [&b"abc\n"[..], b"def\n", b"ghi\n"]);
+        assert_eq!(data, b"abc\nde");
+        let overflow = overflow.unwrap();
+        assert_eq!(overflow.bytes, 6);
+        assert_eq!(fs::read(overflow.file.unwrap()).unwrap(), b"f\nghi\n");
+    }
+}
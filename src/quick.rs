@@ -0,0 +1,137 @@
+//! One-call functions for the common case of a single kernel with a `bootloader` dependency.
+//!
+//! Everything except [`quick_locate`] requires the `run` feature.
+
+use crate::LocateError;
+#[cfg(feature = "run")]
+use crate::{
+    build::{BootloaderBuilder, BuildError},
+    image::{self, ImageError},
+    locate_bootloader_package,
+    runner::{QemuRunner, RunError, TestOutcome},
+    BootloaderKind,
+};
+use std::path::PathBuf;
+#[cfg(feature = "run")]
+use std::{convert, fmt, path::Path};
+
+/// Locates the `bootloader` dependency of the current project.
+///
+/// This is a shorthand for `locate_bootloader("bootloader")`, see
+/// [`locate_bootloader`](crate::locate_bootloader) for how the project is determined.
+pub fn quick_locate() -> Result<PathBuf, LocateError> {
+    crate::locate_bootloader("bootloader")
+}
+
+/// Builds a bootable disk image for the given kernel executable and runs it in QEMU.
+///
+/// The `bootloader` dependency is located through [`quick_locate`] and built with the default
+/// settings of [`BootloaderBuilder`]. The BIOS disk image is written next to the kernel as
+/// `bootimage-<kernel>.bin`, like `bootimage` does, and run through [`QemuRunner`] with the
+/// `isa-debug-exit` device.
+///
+/// Only `bootloader` versions before 0.11 are supported. Newer versions create disk images
+/// through their own `DiskImageBuilder`. Requires the `run` feature.
+#[cfg(feature = "run")]
+pub fn quick_build_and_run(kernel_binary: impl AsRef<Path>) -> Result<TestOutcome, QuickError> {
+    let kernel_binary = kernel_binary.as_ref();
+    let bootloader = locate_bootloader_package("bootloader")?;
+    if let Some(BootloaderKind::Modern011 { .. }) = bootloader.detect_kind() {
+        return Err(QuickError::UnsupportedVersion {
+            version: bootloader.version,
+        });
+    }
+    let artifacts = BootloaderBuilder::new(&bootloader.manifest_path, kernel_binary).build()?;
+
+    let kernel_name = kernel_binary
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| String::from("kernel"));
+    let image = kernel_binary.with_file_name(format!("bootimage-{}.bin", kernel_name));
+    image::create_bios_image(&artifacts.bootloader_elf, &image)?;
+
+    Ok(QemuRunner::new(image).run()?)
+}
+
+/// Failed to build or run a disk image through [`quick_build_and_run`].
+#[cfg(feature = "run")]
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum QuickError {
+    /// Failed to locate the `bootloader` dependency.
+    Locate(LocateError),
+    /// The `bootloader` dependency is version 0.11 or later, which is not supported.
+    UnsupportedVersion {
+        /// The version of the `bootloader` dependency.
+        version: String,
+    },
+    /// Failed to build the bootloader with the embedded kernel.
+    Build(BuildError),
+    /// Failed to create the disk image.
+    Image(ImageError),
+    /// Failed to run the disk image in QEMU.
+    Run(RunError),
+}
+
+#[cfg(feature = "run")]
+impl fmt::Display for QuickError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QuickError::Locate(source) => {
+                write!(f, "Failed to locate the bootloader: {}", source)
+            }
+            QuickError::UnsupportedVersion { version } => write!(
+                f,
+                "Version {} of the bootloader is not supported, only versions before 0.11 can \
+                be built with the embedded kernel",
+                version
+            ),
+            QuickError::Build(source) => write!(f, "Failed to build the bootloader: {}", source),
+            QuickError::Image(source) => {
+                write!(f, "Failed to create the disk image: {}", source)
+            }
+            QuickError::Run(source) => write!(f, "Failed to run the disk image: {}", source),
+        }
+    }
+}
+
+#[cfg(feature = "run")]
+impl std::error::Error for QuickError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            QuickError::Locate(source) => Some(source),
+            QuickError::UnsupportedVersion { version: _ } => None,
+            QuickError::Build(source) => Some(source),
+            QuickError::Image(source) => Some(source),
+            QuickError::Run(source) => Some(source),
+        }
+    }
+}
+
+#[cfg(feature = "run")]
+impl convert::From<LocateError> for QuickError {
+    fn from(source: LocateError) -> Self {
+        QuickError::Locate(source)
+    }
+}
+
+#[cfg(feature = "run")]
+impl convert::From<BuildError> for QuickError {
+    fn from(source: BuildError) -> Self {
+        QuickError::Build(source)
+    }
+}
+
+#[cfg(feature = "run")]
+impl convert::From<ImageError> for QuickError {
+    fn from(source: ImageError) -> Self {
+        QuickError::Image(source)
+    }
+}
+
+#[cfg(feature = "run")]
+impl convert::From<RunError> for QuickError {
+    fn from(source: RunError) -> Self {
+        QuickError::Run(source)
+    }
+}
@@ -0,0 +1,1807 @@
+//! Typed view of the `cargo metadata` output.
+//!
+//! Only the fields that are needed for locating dependencies are deserialized, all other fields
+//! are skipped by the parser. String values, including the ones nested in options, lists, and
+//! maps, borrow from the JSON output unless they contain escape sequences. The lists and maps
+//! themselves are still allocated.
+
+use crate::{
+    canonicalize, cargo_config, simplify_path, stats, CargoMetadataError, DepKind, DepKindInfo,
+    DependencyChain, FeatureOrigin, FeatureRequest, LocateError, LocatedPackage, NameMatching,
+    OriginalSource, OverrideKind, Package as PackageInfo, PackageId, RawJson, SourceFilter,
+    Target as TargetInfo, SUPPORTED_FORMAT_VERSIONS,
+};
+use semver::{Version, VersionReq};
+use serde::{de, Deserialize, Deserializer};
+use serde_json::value::RawValue;
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    fmt, fs,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct Metadata<'a> {
+    #[serde(borrow, deserialize_with = "deserialize_packages")]
+    pub packages: Vec<Package<'a>>,
+    #[serde(borrow, deserialize_with = "borrowed_vec")]
+    pub workspace_members: Vec<Cow<'a, str>>,
+    /// Only available since cargo 1.71.
+    #[serde(borrow, default, deserialize_with = "borrowed_option_vec")]
+    pub workspace_default_members: Option<Vec<Cow<'a, str>>>,
+    #[serde(borrow)]
+    pub resolve: Option<Resolve<'a>>,
+    #[serde(borrow)]
+    pub workspace_root: Cow<'a, str>,
+    #[serde(borrow)]
+    pub target_directory: Cow<'a, str>,
+    /// The format version of the metadata, as requested through `--format-version`.
+    pub version: u32,
+    /// Non-JSON output that preceded the JSON document.
+    #[serde(skip)]
+    pub ignored_output: Option<Cow<'a, str>>,
+    /// The complete output that was parsed.
+    ///
+    /// The raw JSON of the document and of the packages is sliced from it on demand, so that it
+    /// is kept in memory only once.
+    #[serde(skip)]
+    pub output: Cow<'a, str>,
+    /// The byte offset of the JSON document in the output.
+    #[serde(skip)]
+    pub json_start: usize,
+    /// The canonicalized directories of the `paths` overrides of the cargo configuration.
+    ///
+    /// Only set by [`Metadata::read_overrides`], since `cargo metadata` doesn't report them.
+    #[serde(skip)]
+    pub path_overrides: Vec<PathBuf>,
+    /// The names of the packages in the `[replace]` section of the workspace manifest.
+    ///
+    /// Only set by [`Metadata::read_overrides`], since `cargo metadata` doesn't report them.
+    #[serde(skip)]
+    pub replaced_packages: Vec<String>,
+}
+
+impl<'a> Metadata<'a> {
+    pub fn parse(input: &'a str) -> Result<Self, CargoMetadataError> {
+        stats::record_metadata_parse(input.len());
+        let output = input.strip_prefix('\u{feff}').unwrap_or(input);
+        let (ignored_output, json) = split_leading_noise(output);
+        match serde_json::from_str::<Self>(json) {
+            Ok(metadata) if !SUPPORTED_FORMAT_VERSIONS.contains(&metadata.version) => {
+                Err(CargoMetadataError::UnsupportedFormatVersion {
+                    version: metadata.version,
+                })
+            }
+            Ok(mut metadata) => {
+                // turn the addresses of the package objects into offsets in the output
+                let base = input.as_ptr() as usize;
+                for package in &mut metadata.packages {
+                    let span = &package.json_span;
+                    package.json_span = (span.start - base)..(span.end - base);
+                }
+                metadata.simplify_paths();
+                metadata.ignored_output = ignored_output.map(Cow::Borrowed);
+                metadata.json_start = json.as_ptr() as usize - base;
+                metadata.output = Cow::Borrowed(input);
+                Ok(metadata)
+            }
+            Err(source) => Err(match ignored_output {
+                Some(ignored_output) => CargoMetadataError::ParseJsonAfterIgnoredOutput {
+                    ignored_output: ignored_output.to_owned(),
+                    source,
+                },
+                None => CargoMetadataError::ParseJson(source),
+            }),
+        }
+    }
+
+    /// Parses the given output and keeps it without copying it.
+    pub fn parse_owned(output: String) -> Result<Metadata<'static>, CargoMetadataError> {
+        let mut metadata = Metadata::parse(&output)?.into_owned_without_output();
+        metadata.output = Cow::Owned(output);
+        Ok(metadata)
+    }
+
+    pub fn into_owned(self) -> Metadata<'static> {
+        let output = self.output.clone().into_owned();
+        let mut metadata = self.into_owned_without_output();
+        metadata.output = Cow::Owned(output);
+        metadata
+    }
+
+    /// Reads the overrides of the project that `cargo metadata` doesn't report.
+    ///
+    /// This is done once when loading the metadata through cargo, so that lookups on the
+    /// parsed metadata don't access the file system. The configuration files are looked up
+    /// from the workspace root. Invalid configuration files and manifests are ignored, since
+    /// cargo would have failed to run with them.
+    pub fn read_overrides(&mut self) {
+        let workspace_root = Path::new(&*self.workspace_root);
+        self.path_overrides = cargo_config::path_overrides(workspace_root)
+            .unwrap_or_default()
+            .iter()
+            .map(|dir| canonicalize(dir))
+            .collect();
+        self.replaced_packages = replaced_packages(workspace_root);
+    }
+
+    /// Like [`Metadata::read_overrides`], for chaining it after parsing.
+    pub fn with_overrides(mut self) -> Self {
+        self.read_overrides();
+        self
+    }
+
+    /// Returns the JSON document.
+    pub fn json(&self) -> &str {
+        &self.output[self.json_start..]
+    }
+
+    /// Returns the JSON object of the given package.
+    fn package_json(&self, package: &Package) -> &str {
+        self.output
+            .get(package.json_span.clone())
+            .unwrap_or_default()
+    }
+
+    fn into_owned_without_output(self) -> Metadata<'static> {
+        Metadata {
+            packages: self.packages.into_iter().map(Package::into_owned).collect(),
+            workspace_members: self.workspace_members.into_iter().map(owned).collect(),
+            workspace_default_members: self
+                .workspace_default_members
+                .map(|members| members.into_iter().map(owned).collect()),
+            resolve: self.resolve.map(Resolve::into_owned),
+            workspace_root: owned(self.workspace_root),
+            target_directory: owned(self.target_directory),
+            version: self.version,
+            ignored_output: self.ignored_output.map(owned),
+            output: Cow::Borrowed(""),
+            json_start: self.json_start,
+            path_overrides: self.path_overrides,
+            replaced_packages: self.replaced_packages,
+        }
+    }
+
+    /// Normalizes the reported paths on Windows, see [`simplify_path`].
+    fn simplify_paths(&mut self) {
+        if !cfg!(windows) {
+            return;
+        }
+        simplify(&mut self.workspace_root);
+        simplify(&mut self.target_directory);
+        for package in &mut self.packages {
+            simplify(&mut package.manifest_path);
+            for target in &mut package.targets {
+                simplify(&mut target.src_path);
+            }
+            for dependency in &mut package.dependencies {
+                if let Some(path) = &mut dependency.path {
+                    simplify(path);
+                }
+            }
+        }
+    }
+
+    pub fn package(&self, id: &str) -> Option<&Package<'a>> {
+        self.packages.iter().find(|p| p.id == id)
+    }
+
+    /// Looks up the package with the given ID, which must exist in valid metadata.
+    fn expect_package(&self, id: &str) -> Result<&Package<'a>, LocateError> {
+        self.package(id)
+            .ok_or_else(|| invalid(format!("packages[{}]", id)))
+    }
+
+    fn resolve(&self) -> Result<&Resolve<'a>, LocateError> {
+        self.resolve.as_ref().ok_or_else(|| invalid("resolve"))
+    }
+
+    pub fn locate(&self, dependency_name: &str, query: &Query) -> Result<PathBuf, LocateError> {
+        let dependency_package = self.dependency_package(dependency_name, query)?;
+        Ok(PathBuf::from(&*dependency_package.manifest_path))
+    }
+
+    pub fn locate_package(
+        &self,
+        dependency_name: &str,
+        query: &Query,
+    ) -> Result<LocatedPackage, LocateError> {
+        let dependency = self.root_dependency(dependency_name, query)?;
+        let mut package = self.located_package(&dependency.pkg)?;
+        if let Some((declaring_package, declaration)) = self.declaration(dependency)? {
+            package.source_overridden = self.original_source(dependency, declaration)?;
+            if let (Some(original), Some(_)) =
+                (&mut package.source_overridden, &package.override_applied)
+            {
+                original.kind = OverrideKind::Paths;
+            }
+            let key = declaration.key();
+            // the dependency is always active if any of its declarations is not optional
+            package.optional = declaring_package
+                .dependencies
+                .iter()
+                .filter(|d| d.key() == key)
+                .all(|d| d.optional);
+            if package.optional {
+                package.activating_features = declaring_package.activating_features(key);
+            }
+        }
+        Ok(package)
+    }
+
+    /// Returns the `paths` override of the cargo configuration that the given path package was
+    /// loaded from, if any.
+    ///
+    /// Only overrides read through [`Metadata::read_overrides`] are considered.
+    fn path_override(&self, package: &Package) -> Option<PathBuf> {
+        if self.path_overrides.is_empty() {
+            return None;
+        }
+        let package_dir = canonicalize(Path::new(&*package.manifest_path).parent()?);
+        self.path_overrides
+            .iter()
+            .find(|dir| **dir == package_dir)
+            .cloned()
+    }
+
+    /// Returns the package that declares the given dependency and its declaration in the
+    /// manifest.
+    fn declaration(
+        &self,
+        dependency: &Dep,
+    ) -> Result<Option<(&Package<'a>, &PackageDependency<'a>)>, LocateError> {
+        let resolve = self.resolve()?;
+        let declaring_node = resolve
+            .nodes
+            .iter()
+            .find(|node| node.deps.iter().any(|d| std::ptr::eq(d, dependency)));
+        let declaring_package = match declaring_node {
+            Some(node) => self.expect_package(&node.id)?,
+            None => return Ok(None),
+        };
+        let package = self.expect_package(&dependency.pkg)?;
+        let declaration = declaring_package
+            .dependencies
+            .iter()
+            .find(|d| crate_name(d.key()) == dependency.name)
+            .or_else(|| {
+                let mut by_name = declaring_package.dependencies.iter();
+                by_name.find(|d| d.name == package.name)
+            });
+        Ok(declaration.map(|declaration| (declaring_package, declaration)))
+    }
+
+    /// Compares the declaration of the given dependency with the package it resolved to.
+    ///
+    /// Returns the declared source if the package comes from a different source, which happens
+    /// if it was overridden through a `[patch]` or `[replace]` section.
+    fn original_source(
+        &self,
+        dependency: &Dep,
+        declaration: &PackageDependency,
+    ) -> Result<Option<OriginalSource>, LocateError> {
+        let package = self.expect_package(&dependency.pkg)?;
+
+        // the resolved source of git dependencies includes the commit as URL fragment
+        let resolved_source = package
+            .source
+            .as_deref()
+            .map(|source| source.split('#').next().unwrap_or(source));
+        if declaration.source.as_deref() == resolved_source {
+            return Ok(None);
+        }
+        let kind = if self
+            .replaced_packages
+            .iter()
+            .any(|name| *name == package.name)
+        {
+            OverrideKind::Replace
+        } else {
+            OverrideKind::Patch
+        };
+        Ok(Some(OriginalSource {
+            source: declaration.source.as_deref().map(String::from),
+            version_req: declaration.req.to_string(),
+            kind,
+        }))
+    }
+
+    /// Returns all packages in the resolved dependency graph that match the given name, in the
+    /// order of the resolve graph.
+    ///
+    /// Unlike for lookups of a dependency, the name is compared against all dependency keys in
+    /// the graph, since the candidates are not reached through a specific package.
+    pub fn candidates(
+        &self,
+        name: &str,
+        query: &Query,
+    ) -> Result<Vec<LocatedPackage>, LocateError> {
+        let resolve = self.resolve()?;
+        let mut candidates = Vec::new();
+        for node in &resolve.nodes {
+            let package = self.expect_package(&node.id)?;
+            let package_matches = || query.package_name_matches(&package.name, name);
+            let key_matches = || {
+                resolve
+                    .nodes
+                    .iter()
+                    .flat_map(|n| &n.deps)
+                    .any(|d| d.pkg == node.id && d.name == crate_name(name))
+            };
+            let matches = match query.name_matching {
+                NameMatching::Key => key_matches(),
+                NameMatching::PackageName => package_matches(),
+                NameMatching::Any => key_matches() || package_matches(),
+            };
+            if matches && self.satisfies_version_req(&node.id, query) {
+                candidates.push(self.located_package(&node.id)?);
+            }
+        }
+        Ok(candidates)
+    }
+
+    /// Returns all packages with the given name in the resolved dependency graph, ordered by
+    /// version.
+    pub fn all_versions(
+        &self,
+        package_name: &str,
+        query: &Query,
+    ) -> Result<Vec<LocatedPackage>, LocateError> {
+        let resolve = self.resolve()?;
+        let mut packages = Vec::new();
+        for node in &resolve.nodes {
+            let package = self.expect_package(&node.id)?;
+            if query.package_name_matches(&package.name, package_name)
+                && self.satisfies_version_req(&node.id, query)
+            {
+                let version = Version::parse(&package.version)
+                    .map_err(|_| invalid(format!("packages[{}].version", package.id)))?;
+                packages.push((version, self.located_package(&node.id)?));
+            }
+        }
+        packages.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(packages.into_iter().map(|(_, package)| package).collect())
+    }
+
+    /// Returns the shortest chain of dependencies from the root package to every package with
+    /// the given name, ordered by version.
+    pub fn explain(
+        &self,
+        package_name: &str,
+        query: &Query,
+    ) -> Result<Vec<DependencyChain>, LocateError> {
+        let resolve = self.resolve()?;
+        let root = &*self.root_package(query)?.id;
+
+        // breadth-first, so that the recorded parents form the shortest chains
+        let mut parents: HashMap<&str, &str> = HashMap::new();
+        let mut reached = vec![root];
+        let mut queue = VecDeque::new();
+        queue.push_back(root);
+        while let Some(id) = queue.pop_front() {
+            for dependency in &resolve.expect_node(id)?.deps {
+                let pkg = &*dependency.pkg;
+                if pkg != root && !parents.contains_key(pkg) {
+                    parents.insert(pkg, id);
+                    reached.push(pkg);
+                    queue.push_back(pkg);
+                }
+            }
+        }
+
+        let mut chains = Vec::new();
+        for id in reached {
+            let package = self.expect_package(id)?;
+            if !query.package_name_matches(&package.name, package_name)
+                || !self.satisfies_version_req(id, query)
+            {
+                continue;
+            }
+            let version = Version::parse(&package.version)
+                .map_err(|_| invalid(format!("packages[{}].version", package.id)))?;
+            let mut packages = vec![PackageInfo::from_raw(package)];
+            let mut current = id;
+            while let Some(parent) = parents.get(current) {
+                packages.push(PackageInfo::from_raw(self.expect_package(parent)?));
+                current = parent;
+            }
+            packages.reverse();
+            chains.push((version, DependencyChain { packages }));
+        }
+        chains.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(chains.into_iter().map(|(_, chain)| chain).collect())
+    }
+
+    pub fn feature_origin(
+        &self,
+        dependency_name: &str,
+        query: &Query,
+    ) -> Result<Vec<FeatureOrigin>, LocateError> {
+        let resolve = self.resolve()?;
+        let id = &*self.root_dependency(dependency_name, query)?.pkg;
+        let package = self.expect_package(id)?;
+        let enabled = &resolve.expect_node(id)?.features;
+        let mut origins: Vec<_> = enabled
+            .iter()
+            .map(|feature| FeatureOrigin {
+                feature: feature.to_string(),
+                requested_by: Vec::new(),
+                implied_by: Vec::new(),
+            })
+            .collect();
+
+        // the declarations of all dependents, both directly and through their own features
+        for node in &resolve.nodes {
+            for dependency in node.deps.iter().filter(|d| d.pkg == id) {
+                let dependent = self.expect_package(&node.id)?;
+                let declarations = dependent
+                    .dependencies
+                    .iter()
+                    .filter(|d| crate_name(d.key()) == dependency.name);
+                for declaration in declarations {
+                    let key = declaration.key();
+                    let mut requested: Vec<(&str, Option<&str>)> = declaration
+                        .features
+                        .iter()
+                        .map(|feature| (&**feature, None))
+                        .collect();
+                    if declaration.uses_default_features {
+                        requested.push(("default", None));
+                    }
+                    for feature in &node.features {
+                        let values = dependent.features.get(feature).into_iter().flatten();
+                        for value in values {
+                            match value.split_once('/') {
+                                Some((dep, dep_feature)) if dep.trim_end_matches('?') == key => {
+                                    requested.push((dep_feature, Some(feature)))
+                                }
+                                _ => {}
+                            }
+                        }
+                    }
+                    for (feature, through_feature) in requested {
+                        let origin = match origins.iter_mut().find(|o| o.feature == feature) {
+                            Some(origin) => origin,
+                            None => continue,
+                        };
+                        let request = FeatureRequest {
+                            package: PackageInfo::from_raw(dependent),
+                            through_feature: through_feature.map(String::from),
+                        };
+                        if !origin.requested_by.contains(&request) {
+                            origin.requested_by.push(request);
+                        }
+                    }
+                }
+            }
+        }
+
+        // features that are enabled by other enabled features of the package
+        for origin in &mut origins {
+            for (feature, values) in &package.features {
+                if enabled.contains(feature) && values.iter().any(|v| *v == origin.feature) {
+                    origin.implied_by.push(feature.to_string());
+                }
+            }
+        }
+        Ok(origins)
+    }
+
+    fn located_package(&self, id: &str) -> Result<LocatedPackage, LocateError> {
+        let package = self.expect_package(id)?;
+        let node = self.resolve()?.expect_node(id)?;
+
+        Ok(LocatedPackage {
+            id: PackageId::from_metadata(&package.id, &package.name, &package.version),
+            name: package.name.to_string(),
+            version: package.version.to_string(),
+            manifest_path: PathBuf::from(&*package.manifest_path),
+            source: package.source.as_deref().map(String::from),
+            features: node.features.iter().map(|f| f.to_string()).collect(),
+            source_overridden: None,
+            override_applied: match package.source {
+                Some(_) => None,
+                None => self.path_override(package),
+            },
+            optional: false,
+            activating_features: Vec::new(),
+            raw: RawJson::new(self.package_json(package)),
+            target_directory: PathBuf::from(&*self.target_directory),
+            targets: package
+                .targets
+                .iter()
+                .map(|target| TargetInfo {
+                    name: target.name.to_string(),
+                    kind: target.kind.iter().map(|kind| kind.to_string()).collect(),
+                    src_path: PathBuf::from(&*target.src_path),
+                })
+                .collect(),
+            default_run: package.default_run.as_deref().map(String::from),
+        })
+    }
+
+    /// Looks up the package of the root package's dependency with the given name.
+    pub fn dependency_package(
+        &self,
+        dependency_name: &str,
+        query: &Query,
+    ) -> Result<&Package<'a>, LocateError> {
+        let dependency = self.root_dependency(dependency_name, query)?;
+        self.expect_package(&dependency.pkg)
+    }
+
+    pub fn dependency_kinds(
+        &self,
+        dependency_name: &str,
+        query: &Query,
+    ) -> Result<Vec<DepKindInfo>, LocateError> {
+        let dependency = self.root_dependency(dependency_name, query)?;
+        Ok(dependency
+            .dep_kinds
+            .iter()
+            .map(|info| DepKindInfo {
+                kind: info.dep_kind(),
+                target: info.target.as_deref().map(String::from),
+            })
+            .collect())
+    }
+
+    /// Returns the package whose dependencies are searched.
+    pub fn root_package(&self, query: &Query) -> Result<&Package<'a>, LocateError> {
+        let root = self.root_id(query)?;
+        self.expect_package(root)
+    }
+
+    /// Returns details about the package whose dependencies are searched.
+    pub fn located_root(&self, query: &Query) -> Result<LocatedPackage, LocateError> {
+        let root = self.root_id(query)?;
+        self.located_package(root)
+    }
+
+    /// Returns the ID of the package whose dependencies are searched.
+    fn root_id(&self, query: &Query) -> Result<&str, LocateError> {
+        if let Some(member) = query.workspace_member {
+            return self.workspace_member_id(member);
+        }
+
+        let resolve = self.resolve()?;
+        match (&resolve.root, &self.workspace_default_members) {
+            (Some(root), _) => Ok(root),
+            (None, Some(members)) if members.len() == 1 => Ok(&members[0]),
+            (None, _) => {
+                let mut members = self
+                    .workspace_members
+                    .iter()
+                    .map(|id| Ok(self.expect_package(id)?.name.to_string()))
+                    .collect::<Result<Vec<_>, LocateError>>()?;
+                members.sort();
+                Err(LocateError::VirtualWorkspace {
+                    manifest_path: Path::new(&*self.workspace_root).join("Cargo.toml"),
+                    members,
+                })
+            }
+        }
+    }
+
+    /// Looks up a path dependency through the dependency declarations of the root package,
+    /// without using the resolve graph.
+    ///
+    /// This works with the output of `cargo metadata --no-deps`, which only contains the
+    /// workspace members. Returns `None` if the declarations don't determine the package, e.g.
+    /// for registry dependencies, optional dependencies, or queries that need the resolved
+    /// versions.
+    pub fn declared_path_dependency(
+        &self,
+        dependency_name: &str,
+        query: &Query,
+        manifest_path: Option<&Path>,
+    ) -> Result<Option<PathBuf>, LocateError> {
+        let needs_resolve = query.transitive
+            || query.version_req.is_some()
+            || matches!(
+                query.source,
+                Some(SourceFilter::Registry | SourceFilter::Git { .. })
+            );
+        if needs_resolve {
+            return Ok(None);
+        }
+        let root = match query.workspace_member {
+            Some(member) => self.expect_package(self.workspace_member_id(member)?)?,
+            None => {
+                let by_manifest = match manifest_path.map(PathQuery::new) {
+                    Some(query) => self
+                        .workspace_member_packages()?
+                        .into_iter()
+                        .find(|p| query.matches(Path::new(&*p.manifest_path))),
+                    None => None,
+                };
+                let default_member = match self.workspace_default_members.as_deref() {
+                    Some([member]) => Some(self.expect_package(member)?),
+                    _ => None,
+                };
+                match by_manifest.or(default_member) {
+                    Some(root) => root,
+                    None => return Ok(None),
+                }
+            }
+        };
+        let mut declarations = root.dependencies.iter().filter(|d| {
+            let key_matches = || crate_name(d.key()) == crate_name(dependency_name);
+            let package_matches = || query.package_name_matches(&d.name, dependency_name);
+            let name_matches = match query.name_matching {
+                NameMatching::Key => key_matches(),
+                NameMatching::PackageName => package_matches(),
+                NameMatching::Any => key_matches() || package_matches(),
+            };
+            let kind_matches = query
+                .dep_kind
+                .is_none_or(|kind| dep_kind(d.kind.as_deref()) == *kind);
+            name_matches && kind_matches
+        });
+        let declaration = match (declarations.next(), declarations.next()) {
+            (Some(declaration), None) => declaration,
+            _ => return Ok(None),
+        };
+        match &declaration.path {
+            Some(path) if !declaration.optional => Ok(Some(Path::new(&**path).join("Cargo.toml"))),
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns the ID of the workspace member that is selected by the given package ID, path, or
+    /// package name.
+    fn workspace_member_id(&self, selector: &str) -> Result<&str, LocateError> {
+        if let Some(id) = self.workspace_members.iter().find(|id| **id == selector) {
+            return Ok(id);
+        }
+        let is_path = selector == "."
+            || selector.contains('/')
+            || selector.contains(std::path::MAIN_SEPARATOR)
+            || selector.ends_with("Cargo.toml");
+        let matches: Vec<&Package> = if is_path {
+            let mut dir = Path::new(&*self.workspace_root).join(selector);
+            if dir.file_name().is_some_and(|name| name == "Cargo.toml") {
+                dir.pop();
+            }
+            let dir = PathQuery::new(&dir);
+            self.workspace_member_packages()?
+                .into_iter()
+                .filter(|p| {
+                    Path::new(&*p.manifest_path)
+                        .parent()
+                        .is_some_and(|d| dir.matches(d))
+                })
+                .collect()
+        } else {
+            self.workspace_member_packages()?
+                .into_iter()
+                .filter(|p| p.name == selector)
+                .collect()
+        };
+        match matches.as_slice() {
+            [package] => Ok(&package.id),
+            [] => Err(LocateError::WorkspaceMemberNotFound {
+                member: selector.to_owned(),
+            }),
+            _ => {
+                let mut matches: Vec<_> = matches
+                    .iter()
+                    .map(|p| PathBuf::from(&*p.manifest_path))
+                    .collect();
+                matches.sort();
+                Err(LocateError::AmbiguousMember {
+                    member: selector.to_owned(),
+                    matches,
+                })
+            }
+        }
+    }
+
+    /// Returns the packages of all workspace members.
+    fn workspace_member_packages(&self) -> Result<Vec<&Package<'a>>, LocateError> {
+        self.workspace_members
+            .iter()
+            .map(|id| self.expect_package(id))
+            .collect()
+    }
+
+    /// Looks up the dependency with the given name in the resolve node of the root package.
+    fn root_dependency(
+        &self,
+        dependency_name: &str,
+        query: &Query,
+    ) -> Result<&Dep<'a>, LocateError> {
+        let resolve = self.resolve()?;
+        let root = self.root_id(query)?;
+        debug_event!(
+            root = root,
+            dependency_name,
+            "searching dependencies of root package"
+        );
+
+        let root_resolve = resolve.expect_node(root)?;
+
+        let mut dependency = root_resolve
+            .deps
+            .iter()
+            .find(|d| self.has_name(d, dependency_name, query) && self.matches_query(d, query));
+        if dependency.is_none() && query.transitive {
+            dependency = self.transitive_dependency(resolve, root, dependency_name, query)?;
+        }
+        let dependency = match dependency {
+            Some(dependency) => dependency,
+            None => {
+                let root_package = self.expect_package(root)?;
+                if query.package_name_matches(&root_package.name, dependency_name) {
+                    return Err(LocateError::DependencyIsRoot {
+                        dependency_name: dependency_name.to_owned(),
+                        manifest_path: PathBuf::from(&*root_package.manifest_path),
+                    });
+                }
+                // report the first filter that rules out all dependencies with the given name
+                let manifest_path = || PathBuf::from(&*root_package.manifest_path);
+                let named: Vec<_> = root_resolve
+                    .deps
+                    .iter()
+                    .filter(|d| self.has_name(d, dependency_name, query))
+                    .collect();
+                let by_version: Vec<_> = named
+                    .iter()
+                    .filter(|d| self.satisfies_version_req(&d.pkg, query))
+                    .collect();
+                if let (Some(version_req), true) = (query.version_req, by_version.is_empty()) {
+                    if let Some(package) = named.first().and_then(|d| self.package(&d.pkg)) {
+                        return Err(LocateError::VersionMismatch {
+                            dependency_name: dependency_name.to_owned(),
+                            manifest_path: manifest_path(),
+                            found: package.version.to_string(),
+                            required: version_req.to_string(),
+                        });
+                    }
+                }
+                let by_kind: Vec<_> = by_version
+                    .iter()
+                    .filter(|d| self.satisfies_dep_kind(d, query))
+                    .collect();
+                if let (Some(dep_kind), true) = (query.dep_kind, by_kind.is_empty()) {
+                    if let Some(dependency) = by_version.first() {
+                        return Err(LocateError::DepKindMismatch {
+                            dependency_name: dependency_name.to_owned(),
+                            manifest_path: manifest_path(),
+                            found: dependency.dep_kinds.iter().map(|k| k.dep_kind()).collect(),
+                            required: dep_kind.clone(),
+                        });
+                    }
+                }
+                if let Some(source) = query.source {
+                    if let Some(package) = by_kind.first().and_then(|d| self.package(&d.pkg)) {
+                        return Err(LocateError::SourceMismatch {
+                            dependency_name: dependency_name.to_owned(),
+                            manifest_path: manifest_path(),
+                            found: package.source.as_deref().map(String::from),
+                            required: source.clone(),
+                        });
+                    }
+                }
+                return Err(root_package.missing_dependency_error(dependency_name, query));
+            }
+        };
+        debug_event!(
+            dependency_name,
+            node = &*dependency.name,
+            package = &*dependency.pkg,
+            "matched dependency"
+        );
+
+        Ok(dependency)
+    }
+
+    /// Walks the resolve graph breadth-first, starting at the given node, and returns the first
+    /// dependency with the given name.
+    fn transitive_dependency<'r>(
+        &self,
+        resolve: &'r Resolve<'a>,
+        start: &'r str,
+        dependency_name: &str,
+        query: &Query,
+    ) -> Result<Option<&'r Dep<'a>>, LocateError> {
+        let mut visited = HashSet::new();
+        let mut queue = VecDeque::new();
+        visited.insert(start);
+        queue.push_back(start);
+
+        while let Some(id) = queue.pop_front() {
+            let node = resolve.expect_node(id)?;
+            for dependency in &node.deps {
+                if self.has_name(dependency, dependency_name, query)
+                    && self.matches_query(dependency, query)
+                {
+                    debug_event!(parent = id, "found transitive dependency");
+                    return Ok(Some(dependency));
+                }
+                if visited.insert(&dependency.pkg) {
+                    queue.push_back(&dependency.pkg);
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Checks whether the given dependency has the given name, according to the name matching
+    /// mode of the query.
+    fn has_name(&self, dependency: &Dep, dependency_name: &str, query: &Query) -> bool {
+        // the resolve graph uses the crate name of the dependency key, with `-` replaced by `_`
+        let key_matches = || dependency.name == crate_name(dependency_name);
+        let package_matches = || {
+            self.package(&dependency.pkg)
+                .map(|package| query.package_name_matches(&package.name, dependency_name))
+                .unwrap_or(false)
+        };
+        match query.name_matching {
+            NameMatching::Key => key_matches(),
+            NameMatching::PackageName => package_matches(),
+            NameMatching::Any => key_matches() || package_matches(),
+        }
+    }
+
+    /// Checks whether the given dependency satisfies the dependency kind, version requirement,
+    /// and source filter of the query.
+    fn matches_query(&self, dependency: &Dep, query: &Query) -> bool {
+        self.satisfies_dep_kind(dependency, query)
+            && self.satisfies_version_req(&dependency.pkg, query)
+            && self.satisfies_source(&dependency.pkg, query)
+    }
+
+    /// Checks whether the given dependency is declared with the dependency kind of the query.
+    fn satisfies_dep_kind(&self, dependency: &Dep, query: &Query) -> bool {
+        match query.dep_kind {
+            Some(dep_kind) => dependency
+                .dep_kinds
+                .iter()
+                .any(|entry| entry.dep_kind() == *dep_kind),
+            None => true,
+        }
+    }
+
+    /// Checks whether the given package comes from the source of the query.
+    fn satisfies_source(&self, id: &str, query: &Query) -> bool {
+        match query.source {
+            Some(source) => self
+                .package(id)
+                .map(|package| source.matches(package.source.as_deref()))
+                .unwrap_or(false),
+            None => true,
+        }
+    }
+
+    /// Checks whether the version of the given package satisfies the version requirement of the
+    /// query.
+    ///
+    /// Packages with invalid versions never satisfy a requirement.
+    fn satisfies_version_req(&self, id: &str, query: &Query) -> bool {
+        let version_req = match query.version_req {
+            Some(version_req) => version_req,
+            None => return true,
+        };
+        self.package(id)
+            .and_then(|package| Version::parse(&package.version).ok())
+            .map(|version| version_req.matches(&version))
+            .unwrap_or(false)
+    }
+
+    /// Returns the names of the targets of the root package with the given kind, e.g. `bin`.
+    pub fn targets_of_kind(&self, query: &Query, kind: &str) -> Result<Vec<String>, LocateError> {
+        let package = self.root_package(query)?;
+        Ok(package
+            .targets_of_kind(kind)
+            .map(|name| name.to_string())
+            .collect())
+    }
+
+    /// Returns the path of the executable that `cargo build` produces for the given binary
+    /// target of the root package.
+    ///
+    /// If no binary name is given, the `default-run` binary or the single binary target of the
+    /// package is used.
+    pub fn kernel_binary(
+        &self,
+        query: &Query,
+        target: &str,
+        profile: &str,
+        bin_name: Option<&str>,
+    ) -> Result<PathBuf, LocateError> {
+        let package = self.root_package(query)?;
+        let available = self.targets_of_kind(query, "bin")?;
+        let bin_name = match bin_name.or(package.default_run.as_deref()) {
+            Some(bin_name) if available.iter().any(|name| name == bin_name) => bin_name,
+            Some(bin_name) => {
+                return Err(LocateError::BinaryNotFound {
+                    package: package.name.to_string(),
+                    bin_name: bin_name.to_owned(),
+                    available,
+                })
+            }
+            None if available.len() == 1 => &available[0],
+            None => {
+                return Err(LocateError::AmbiguousBinary {
+                    package: package.name.to_string(),
+                    available,
+                })
+            }
+        };
+        Ok(artifact_path(
+            Path::new(&*self.target_directory),
+            target,
+            profile,
+            bin_name,
+        ))
+    }
+
+    pub fn package_for_manifest(&self, manifest_path: &Path) -> Result<PackageInfo, LocateError> {
+        let mut manifest_path = manifest_path.to_owned();
+        if manifest_path.is_dir() {
+            manifest_path.push("Cargo.toml");
+        }
+        let query = PathQuery::new(&manifest_path);
+
+        let package = self
+            .packages
+            .iter()
+            .find(|p| query.matches(Path::new(&*p.manifest_path)))
+            .ok_or(LocateError::PackageNotFound {
+                manifest_path: query.canonical,
+            })?;
+
+        Ok(PackageInfo::from_raw(package))
+    }
+
+    pub fn package_by_id(&self, id: &PackageId) -> Result<LocatedPackage, LocateError> {
+        if self.package(id.as_str()).is_none() {
+            return Err(LocateError::PackageIdNotFound { id: id.clone() });
+        }
+        self.located_package(id.as_str())
+    }
+
+    pub fn workspace_default_members(&self) -> Result<Vec<PackageInfo>, LocateError> {
+        self.workspace_default_members
+            .as_ref()
+            .ok_or_else(|| invalid("workspace_default_members"))?
+            .iter()
+            .map(|id| self.expect_package(id).map(PackageInfo::from_raw))
+            .collect()
+    }
+}
+
+/// Options that control which package a lookup starts from.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Query<'q> {
+    /// The name of the workspace member whose dependencies are searched.
+    ///
+    /// If `None`, the root package (or the single default member of a virtual workspace) is used.
+    pub workspace_member: Option<&'q str>,
+    /// Whether the dependencies of dependencies are searched too.
+    pub transitive: bool,
+    /// Only packages whose version matches this requirement are considered.
+    pub version_req: Option<&'q VersionReq>,
+    /// Only dependencies declared with this kind are considered.
+    pub dep_kind: Option<&'q DepKind>,
+    /// How the dependency name is compared against the dependencies.
+    pub name_matching: NameMatching,
+    /// Only packages from this source are considered.
+    pub source: Option<&'q SourceFilter>,
+    /// Whether package names are compared exactly instead of as crate names, see
+    /// [`Locator::strict`](crate::Locator::strict).
+    pub exact_names: bool,
+}
+
+impl Query<'_> {
+    /// Checks whether the given package name matches the given name.
+    ///
+    /// Unless [`exact_names`](Self::exact_names) is set, `-` and `_` are treated as equivalent,
+    /// like for dependency keys.
+    fn package_name_matches(&self, package_name: &str, name: &str) -> bool {
+        if self.exact_names {
+            package_name == name
+        } else {
+            crate_name(package_name) == crate_name(name)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct Package<'a> {
+    /// The byte range of the JSON object of the package in [`Metadata::output`], for
+    /// [`LocatedPackage::raw`].
+    #[serde(skip)]
+    pub json_span: Range<usize>,
+    #[serde(borrow)]
+    pub id: Cow<'a, str>,
+    #[serde(borrow)]
+    pub name: Cow<'a, str>,
+    #[serde(borrow)]
+    pub version: Cow<'a, str>,
+    #[serde(borrow)]
+    pub manifest_path: Cow<'a, str>,
+    /// `None` for path dependencies and workspace members.
+    #[serde(borrow, default, deserialize_with = "borrowed_option")]
+    pub source: Option<Cow<'a, str>>,
+    #[serde(borrow)]
+    pub dependencies: Vec<PackageDependency<'a>>,
+    #[serde(borrow, deserialize_with = "borrowed_features")]
+    pub features: Features<'a>,
+    /// Only available since cargo 1.58.
+    #[serde(borrow, default, deserialize_with = "borrowed_option")]
+    pub rust_version: Option<Cow<'a, str>>,
+    /// The `[package.metadata]` table of the manifest, `null` if there is none.
+    #[serde(default)]
+    pub metadata: serde_json::Value,
+    #[serde(borrow)]
+    pub targets: Vec<Target<'a>>,
+    /// The `package.default-run` field of the manifest.
+    #[serde(borrow, default, deserialize_with = "borrowed_option")]
+    pub default_run: Option<Cow<'a, str>>,
+}
+
+impl Package<'_> {
+    fn into_owned(self) -> Package<'static> {
+        Package {
+            json_span: self.json_span,
+            id: owned(self.id),
+            name: owned(self.name),
+            version: owned(self.version),
+            manifest_path: owned(self.manifest_path),
+            source: self.source.map(owned),
+            dependencies: self
+                .dependencies
+                .into_iter()
+                .map(PackageDependency::into_owned)
+                .collect(),
+            features: self
+                .features
+                .into_iter()
+                .map(|(name, values)| (owned(name), values.into_iter().map(owned).collect()))
+                .collect(),
+            rust_version: self.rust_version.map(owned),
+            metadata: self.metadata,
+            targets: self.targets.into_iter().map(Target::into_owned).collect(),
+            default_run: self.default_run.map(owned),
+        }
+    }
+
+    /// Returns the names of the targets with the given kind, e.g. `bin`.
+    fn targets_of_kind<'s>(&'s self, kind: &'s str) -> impl Iterator<Item = &'s str> + 's {
+        self.targets
+            .iter()
+            .filter(move |target| target.kind.iter().any(|k| k == kind))
+            .map(|target| &*target.name)
+    }
+
+    /// Returns the declared dependencies whose names are similar to the given name, most
+    /// similar first.
+    fn similar_dependencies(&self, dependency_name: &str) -> Vec<String> {
+        let wanted = crate_name(dependency_name).to_lowercase();
+        // allow one typo for short names and about one per three characters for longer ones
+        let max_distance = (wanted.chars().count() / 3).max(1);
+        let mut candidates: Vec<(usize, String)> = self
+            .dependencies
+            .iter()
+            .map(|d| d.key().to_owned())
+            .filter_map(|name| {
+                let distance = edit_distance(&wanted, &crate_name(&name).to_lowercase());
+                (distance <= max_distance).then_some((distance, name))
+            })
+            .collect();
+        candidates.sort();
+        candidates.dedup_by(|(_, a), (_, b)| a == b);
+        candidates.into_iter().map(|(_, name)| name).collect()
+    }
+
+    /// Creates the error for a dependency that is not part of the resolve node of this package.
+    ///
+    /// Distinguishes between dependencies that are not declared at all and dependencies that are
+    /// declared, but not activated (e.g. because they are optional).
+    fn missing_dependency_error(&self, dependency_name: &str, query: &Query) -> LocateError {
+        let key_matches =
+            |d: &PackageDependency| crate_name(d.key()) == crate_name(dependency_name);
+        let package_matches =
+            |d: &PackageDependency| query.package_name_matches(&d.name, dependency_name);
+        let declarations: Vec<_> = self
+            .dependencies
+            .iter()
+            .filter(|d| match query.name_matching {
+                NameMatching::Key => key_matches(d),
+                NameMatching::PackageName => package_matches(d),
+                NameMatching::Any => key_matches(d) || package_matches(d),
+            })
+            .collect();
+        if declarations.is_empty() {
+            return LocateError::DependencyNotFound {
+                dependency_name: dependency_name.to_owned(),
+                manifest_path: PathBuf::from(&*self.manifest_path),
+                candidates: self.similar_dependencies(dependency_name),
+            };
+        }
+
+        let activating_features = if declarations.iter().any(|d| d.optional) {
+            self.activating_features(declarations[0].key())
+        } else {
+            Vec::new()
+        };
+
+        let mut targets = Vec::new();
+        if declarations.iter().all(|d| d.target.is_some()) {
+            targets.extend(
+                declarations
+                    .iter()
+                    .filter_map(|d| d.target.as_deref().map(String::from)),
+            );
+            targets.sort();
+            targets.dedup();
+        }
+
+        LocateError::DependencyNotActivated {
+            dependency_name: dependency_name.to_owned(),
+            manifest_path: PathBuf::from(&*self.manifest_path),
+            activating_features,
+            targets,
+        }
+    }
+
+    /// Returns the features of the package that activate the optional dependency with the given
+    /// key, either directly or by enabling another activating feature, sorted by name.
+    fn activating_features(&self, key: &str) -> Vec<String> {
+        let mut activating_features = Vec::new();
+        let uses_dep_syntax = self
+            .features
+            .values()
+            .flatten()
+            .any(|value| value.strip_prefix("dep:") == Some(key));
+        if !uses_dep_syntax && !self.features.contains_key(key) {
+            // implicit feature of optional dependency, which older cargo versions don't list
+            activating_features.push(key.to_owned());
+        }
+        // features that directly enable the dependency
+        for (feature, values) in &self.features {
+            let activates = values.iter().any(|value| {
+                value.strip_prefix("dep:") == Some(key)
+                    || (!uses_dep_syntax && value == key)
+                    || value
+                        .split_once('/')
+                        .map(|(dep, _)| dep == key)
+                        .unwrap_or(false)
+            });
+            if activates {
+                activating_features.push(feature.to_string());
+            }
+        }
+        // features that enable one of the activating features
+        let mut i = 0;
+        while i < activating_features.len() {
+            for (feature, values) in &self.features {
+                if values.iter().any(|v| *v == activating_features[i])
+                    && !activating_features.iter().any(|f| f == feature)
+                {
+                    activating_features.push(feature.to_string());
+                }
+            }
+            i += 1;
+        }
+        activating_features.sort();
+        activating_features
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct Target<'a> {
+    #[serde(borrow)]
+    pub name: Cow<'a, str>,
+    /// The target kinds, e.g. `bin`, `lib`, or `test`.
+    #[serde(borrow, deserialize_with = "borrowed_vec")]
+    pub kind: Vec<Cow<'a, str>>,
+    #[serde(borrow)]
+    pub src_path: Cow<'a, str>,
+}
+
+impl Target<'_> {
+    fn into_owned(self) -> Target<'static> {
+        Target {
+            name: owned(self.name),
+            kind: self.kind.into_iter().map(owned).collect(),
+            src_path: owned(self.src_path),
+        }
+    }
+}
+
+/// A dependency as declared in the manifest of a package.
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct PackageDependency<'a> {
+    /// The name of the dependency package.
+    #[serde(borrow)]
+    pub name: Cow<'a, str>,
+    /// The dependency key in the manifest, if it differs from the package name.
+    #[serde(borrow, default, deserialize_with = "borrowed_option")]
+    pub rename: Option<Cow<'a, str>>,
+    pub optional: bool,
+    #[serde(borrow, default, deserialize_with = "borrowed_option")]
+    pub target: Option<Cow<'a, str>>,
+    /// The declared source, `None` for path dependencies.
+    #[serde(borrow, default, deserialize_with = "borrowed_option")]
+    pub source: Option<Cow<'a, str>>,
+    /// The declared version requirement.
+    #[serde(borrow, default)]
+    pub req: Cow<'a, str>,
+    /// `null` for normal dependencies, `"dev"` or `"build"` otherwise.
+    #[serde(borrow, default, deserialize_with = "borrowed_option")]
+    pub kind: Option<Cow<'a, str>>,
+    /// The directory of path dependencies.
+    #[serde(borrow, default, deserialize_with = "borrowed_option")]
+    pub path: Option<Cow<'a, str>>,
+    /// The features that the declaration enables.
+    #[serde(borrow, default, deserialize_with = "borrowed_vec")]
+    pub features: Vec<Cow<'a, str>>,
+    #[serde(default = "default_features")]
+    pub uses_default_features: bool,
+}
+
+fn default_features() -> bool {
+    true
+}
+
+impl PackageDependency<'_> {
+    fn into_owned(self) -> PackageDependency<'static> {
+        PackageDependency {
+            name: owned(self.name),
+            rename: self.rename.map(owned),
+            optional: self.optional,
+            target: self.target.map(owned),
+            source: self.source.map(owned),
+            req: owned(self.req),
+            kind: self.kind.map(owned),
+            path: self.path.map(owned),
+            features: self.features.into_iter().map(owned).collect(),
+            uses_default_features: self.uses_default_features,
+        }
+    }
+
+    /// The name under which the dependency is declared in the manifest.
+    fn key(&self) -> &str {
+        self.rename.as_deref().unwrap_or(&self.name)
+    }
+}
+
+/// Returns the Levenshtein distance between the given strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Returns the crate name for the given package or dependency name.
+///
+/// The dependency names in the resolve graph use underscores instead of dashes.
+fn crate_name(name: &str) -> Cow<'_, str> {
+    if name.contains('-') {
+        Cow::Owned(name.replace('-', "_"))
+    } else {
+        Cow::Borrowed(name)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct Resolve<'a> {
+    #[serde(borrow)]
+    pub nodes: Vec<Node<'a>>,
+    #[serde(borrow, default, deserialize_with = "borrowed_option")]
+    pub root: Option<Cow<'a, str>>,
+}
+
+impl<'a> Resolve<'a> {
+    fn into_owned(self) -> Resolve<'static> {
+        Resolve {
+            nodes: self.nodes.into_iter().map(Node::into_owned).collect(),
+            root: self.root.map(owned),
+        }
+    }
+
+    pub fn node(&self, id: &str) -> Option<&Node<'a>> {
+        self.nodes.iter().find(|n| n.id == id)
+    }
+
+    /// Looks up the node with the given ID, which must exist in valid metadata.
+    fn expect_node(&self, id: &str) -> Result<&Node<'a>, LocateError> {
+        self.node(id)
+            .ok_or_else(|| invalid(format!("resolve.nodes[{}]", id)))
+    }
+}
+
+/// Creates the error for a missing or invalid field of the metadata.
+fn invalid(field: impl Into<String>) -> LocateError {
+    LocateError::MetadataInvalid {
+        field: field.into(),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct Node<'a> {
+    #[serde(borrow)]
+    pub id: Cow<'a, str>,
+    #[serde(borrow)]
+    pub deps: Vec<Dep<'a>>,
+    /// The features that are enabled for the package in the resolved dependency graph.
+    #[serde(borrow, default, deserialize_with = "borrowed_vec")]
+    pub features: Vec<Cow<'a, str>>,
+}
+
+impl Node<'_> {
+    fn into_owned(self) -> Node<'static> {
+        Node {
+            id: owned(self.id),
+            deps: self.deps.into_iter().map(Dep::into_owned).collect(),
+            features: self.features.into_iter().map(owned).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct Dep<'a> {
+    #[serde(borrow)]
+    pub name: Cow<'a, str>,
+    #[serde(borrow)]
+    pub pkg: Cow<'a, str>,
+    /// Only available since cargo 1.41.
+    #[serde(borrow, default)]
+    pub dep_kinds: Vec<DepKindEntry<'a>>,
+}
+
+impl Dep<'_> {
+    fn into_owned(self) -> Dep<'static> {
+        Dep {
+            name: owned(self.name),
+            pkg: owned(self.pkg),
+            dep_kinds: self
+                .dep_kinds
+                .into_iter()
+                .map(DepKindEntry::into_owned)
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct DepKindEntry<'a> {
+    /// `null` for normal dependencies, `"dev"` or `"build"` otherwise.
+    #[serde(borrow, default, deserialize_with = "borrowed_option")]
+    pub kind: Option<Cow<'a, str>>,
+    /// The target platform (triple or `cfg` expression) the dependency is restricted to.
+    #[serde(borrow, default, deserialize_with = "borrowed_option")]
+    pub target: Option<Cow<'a, str>>,
+}
+
+impl DepKindEntry<'_> {
+    fn into_owned(self) -> DepKindEntry<'static> {
+        DepKindEntry {
+            kind: self.kind.map(owned),
+            target: self.target.map(owned),
+        }
+    }
+
+    fn dep_kind(&self) -> DepKind {
+        dep_kind(self.kind.as_deref())
+    }
+}
+
+/// Converts the `kind` field of a dependency, `None` for normal dependencies.
+fn dep_kind(kind: Option<&str>) -> DepKind {
+    match kind {
+        None => DepKind::Normal,
+        Some("dev") => DepKind::Development,
+        Some("build") => DepKind::Build,
+        Some(other) => DepKind::Unknown(other.to_owned()),
+    }
+}
+
+/// Returns the path of the executable that `cargo build` produces for the given binary in the
+/// given target directory.
+pub(crate) fn artifact_path(
+    target_directory: &Path,
+    target: &str,
+    profile: &str,
+    bin_name: &str,
+) -> PathBuf {
+    // custom target specifications are named after the file stem of the JSON file
+    let target_name = match target.strip_suffix(".json") {
+        Some(_) => Path::new(target)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or(target),
+        None => target,
+    };
+    let profile_dir = match profile {
+        "dev" | "test" => "debug",
+        "release" | "bench" => "release",
+        custom => custom,
+    };
+    target_directory
+        .join(target_name)
+        .join(profile_dir)
+        .join(bin_name)
+}
+
+/// Splits off the lines before the first line that starts a JSON object.
+///
+/// Returns `None` as first element if the output starts with the JSON object. If no line starts
+/// a JSON object, the full output is returned as the first element.
+pub(crate) fn split_leading_noise(output: &str) -> (Option<&str>, &str) {
+    if output.trim_start().starts_with('{') {
+        return (None, output);
+    }
+    let mut offset = 0;
+    for line in output.split_inclusive('\n') {
+        if line.trim_start().starts_with('{') {
+            return (Some(&output[..offset]), &output[offset..]);
+        }
+        offset += line.len();
+    }
+    (Some(output), "")
+}
+
+/// Returns the names of the packages in the `[replace]` section of the workspace manifest.
+///
+/// The entries are keyed by package ID specifications like `bootloader:0.9.23`.
+fn replaced_packages(workspace_root: &Path) -> Vec<String> {
+    let manifest = match fs::read_to_string(workspace_root.join("Cargo.toml")) {
+        Ok(manifest) => manifest,
+        Err(_) => return Vec::new(),
+    };
+    let manifest: toml::Table = match manifest.parse() {
+        Ok(manifest) => manifest,
+        Err(_) => return Vec::new(),
+    };
+    let replace = match manifest.get("replace").and_then(|r| r.as_table()) {
+        Some(replace) => replace,
+        None => return Vec::new(),
+    };
+    replace
+        .keys()
+        .filter_map(|spec| {
+            let spec = spec.rsplit('#').next().unwrap_or(spec);
+            spec.split([':', '@']).next().map(String::from)
+        })
+        .collect()
+}
+
+/// Deserializes the `packages` array and keeps the JSON text of every package.
+fn deserialize_packages<'de, D>(deserializer: D) -> Result<Vec<Package<'de>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let packages = <Vec<&'de RawValue>>::deserialize(deserializer)?;
+    packages
+        .into_iter()
+        .map(|json| {
+            let mut package: Package<'de> =
+                serde_json::from_str(json.get()).map_err(de::Error::custom)?;
+            // store the address of the object for now, `Metadata::parse` turns it into an offset
+            let start = json.get().as_ptr() as usize;
+            package.json_span = start..start + json.get().len();
+            Ok(package)
+        })
+        .collect()
+}
+
+/// A string that borrows from the JSON output unless it contains escape sequences.
+///
+/// `#[serde(borrow)]` only borrows `Cow<str>` fields themselves. Strings nested in an `Option`,
+/// a `Vec`, or a map are always allocated by serde, so fields of these types are deserialized
+/// through this type instead.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct BorrowedStr<'a>(Cow<'a, str>);
+
+impl<'de> Deserialize<'de> for BorrowedStr<'de> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct Visitor;
+
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = BorrowedStr<'de>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a string")
+            }
+
+            fn visit_borrowed_str<E>(self, value: &'de str) -> Result<Self::Value, E> {
+                Ok(BorrowedStr(Cow::Borrowed(value)))
+            }
+
+            fn visit_str<E>(self, value: &str) -> Result<Self::Value, E> {
+                Ok(BorrowedStr(Cow::Owned(value.to_owned())))
+            }
+
+            fn visit_string<E>(self, value: String) -> Result<Self::Value, E> {
+                Ok(BorrowedStr(Cow::Owned(value)))
+            }
+        }
+
+        deserializer.deserialize_str(Visitor)
+    }
+}
+
+fn borrowed_option<'de, D>(deserializer: D) -> Result<Option<Cow<'de, str>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = <Option<BorrowedStr<'de>>>::deserialize(deserializer)?;
+    Ok(value.map(|value| value.0))
+}
+
+fn borrowed_vec<'de, D>(deserializer: D) -> Result<Vec<Cow<'de, str>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let values = <Vec<BorrowedStr<'de>>>::deserialize(deserializer)?;
+    Ok(values.into_iter().map(|value| value.0).collect())
+}
+
+fn borrowed_option_vec<'de, D>(deserializer: D) -> Result<Option<Vec<Cow<'de, str>>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let values = <Option<Vec<BorrowedStr<'de>>>>::deserialize(deserializer)?;
+    Ok(values.map(|values| values.into_iter().map(|value| value.0).collect()))
+}
+
+/// The features of a package with the features and dependencies they enable.
+pub(crate) type Features<'a> = BTreeMap<Cow<'a, str>, Vec<Cow<'a, str>>>;
+
+/// Deserializes the `features` table of a package.
+fn borrowed_features<'de, D>(deserializer: D) -> Result<Features<'de>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct Visitor;
+
+    impl<'de> de::Visitor<'de> for Visitor {
+        type Value = Features<'de>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a map of features")
+        }
+
+        fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+        where
+            A: de::MapAccess<'de>,
+        {
+            let mut features = BTreeMap::new();
+            while let Some((name, enabled)) =
+                map.next_entry::<BorrowedStr<'de>, Vec<BorrowedStr<'de>>>()?
+            {
+                let enabled = enabled.into_iter().map(|feature| feature.0).collect();
+                features.insert(name.0, enabled);
+            }
+            Ok(features)
+        }
+    }
+
+    deserializer.deserialize_map(Visitor)
+}
+
+/// A queried path that is compared with the paths reported by cargo.
+///
+/// Cargo reports absolute paths, which are not necessarily canonical, e.g. if the project is
+/// reached through a symlink. Instead of canonicalizing every reported path, which would access
+/// the file system for every package, only the queried path is canonicalized, once, and both
+/// its given and its canonical form are compared with the reported paths.
+struct PathQuery {
+    given: PathBuf,
+    canonical: PathBuf,
+}
+
+impl PathQuery {
+    fn new(path: &Path) -> Self {
+        PathQuery {
+            given: path.to_owned(),
+            canonical: canonicalize(path),
+        }
+    }
+
+    fn matches(&self, reported: &Path) -> bool {
+        reported == self.given || reported == self.canonical
+    }
+}
+
+/// Replaces the given path with its [`simplify_path`] form if that differs.
+fn simplify(path: &mut Cow<'_, str>) {
+    let simplified = match simplify_path(path) {
+        Cow::Owned(simplified) => Some(simplified),
+        Cow::Borrowed(_) => None,
+    };
+    if let Some(simplified) = simplified {
+        *path = Cow::Owned(simplified);
+    }
+}
+
+fn owned(value: Cow<'_, str>) -> Cow<'static, str> {
+    Cow::Owned(value.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{self, BOOTLOADER_ID, KERNEL_ID};
+    use serde_json::{json, Value};
+
+    /// Returns a virtual workspace with the members `kernel` and `bootloader`.
+    fn virtual_workspace(default_members: Value) -> String {
+        let mut json = fixtures::kernel_with_bootloader("/project/bootloader/Cargo.toml");
+        json["resolve"]["root"] = Value::Null;
+        json["workspace_members"] = json!([KERNEL_ID, BOOTLOADER_ID]);
+        json["workspace_default_members"] = default_members;
+        json.to_string()
+    }
+
+    #[test]
+    fn keeps_package_json_after_leading_noise() {
+        let json = fixtures::kernel_with_bootloader("/bootloader/Cargo.toml").to_string();
+        let output = format!("warning: unused manifest key\n{}", json);
+        let metadata = Metadata::parse(&output).unwrap();
+        assert_eq!(
+            metadata.ignored_output.as_deref(),
+            Some("warning: unused manifest key\n")
+        );
+        assert_eq!(metadata.json(), json);
+        let owned = Metadata::parse_owned(output.clone()).unwrap();
+        for metadata in [&metadata, &owned] {
+            let package = metadata.package(BOOTLOADER_ID).unwrap();
+            let package_json: Value = serde_json::from_str(metadata.package_json(package)).unwrap();
+            assert_eq!(package_json["id"], BOOTLOADER_ID);
+            assert_eq!(package_json["manifest_path"], "/bootloader/Cargo.toml");
+        }
+    }
+
+    #[test]
+    fn rejects_unsupported_format_versions() {
+        let mut json = fixtures::kernel_with_bootloader("");
+        json["version"] = json!(2);
+        assert!(matches!(
+            Metadata::parse(&json.to_string()),
+            Err(CargoMetadataError::UnsupportedFormatVersion { version: 2 })
+        ));
+        assert!(matches!(
+            Metadata::parse("error: unexpected argument"),
+            Err(CargoMetadataError::ParseJsonAfterIgnoredOutput { .. })
+        ));
+    }
+
+    #[test]
+    fn determines_root_package() {
+        let json = fixtures::kernel_with_bootloader("").to_string();
+        let metadata = Metadata::parse(&json).unwrap();
+        assert_eq!(metadata.root_id(&Query::default()).unwrap(), KERNEL_ID);
+
+        let json = virtual_workspace(json!([BOOTLOADER_ID]));
+        let metadata = Metadata::parse(&json).unwrap();
+        assert_eq!(metadata.root_id(&Query::default()).unwrap(), BOOTLOADER_ID);
+        let query = Query {
+            workspace_member: Some("kernel"),
+            ..Query::default()
+        };
+        assert_eq!(metadata.root_id(&query).unwrap(), KERNEL_ID);
+
+        let json = virtual_workspace(Value::Null);
+        let metadata = Metadata::parse(&json).unwrap();
+        match metadata.root_id(&Query::default()) {
+            Err(LocateError::VirtualWorkspace {
+                manifest_path,
+                members,
+            }) => {
+                assert_eq!(manifest_path, Path::new("/project/Cargo.toml"));
+                assert_eq!(members, ["bootloader", "kernel"]);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn matches_paths_that_do_not_exist() {
+        let json = virtual_workspace(Value::Null);
+        let metadata = Metadata::parse(&json).unwrap();
+        let package = metadata
+            .package_for_manifest(Path::new("/project/bootloader/Cargo.toml"))
+            .unwrap();
+        assert_eq!(package.name, "bootloader");
+        assert!(matches!(
+            metadata.package_for_manifest(Path::new("/project/missing/Cargo.toml")),
+            Err(LocateError::PackageNotFound { .. })
+        ));
+        assert_eq!(metadata.workspace_member_id(".").unwrap(), KERNEL_ID);
+        assert_eq!(
+            metadata
+                .workspace_member_id("bootloader/Cargo.toml")
+                .unwrap(),
+            BOOTLOADER_ID
+        );
+
+        let mut json: Value = serde_json::from_str(&json).unwrap();
+        json["packages"][0]["dependencies"][0]["path"] = json!("/project/bootloader");
+        let json = json.to_string();
+        let metadata = Metadata::parse(&json).unwrap();
+        let located = metadata
+            .declared_path_dependency(
+                "bootloader",
+                &Query::default(),
+                Some(Path::new("/project/Cargo.toml")),
+            )
+            .unwrap();
+        assert_eq!(
+            located,
+            Some(PathBuf::from("/project/bootloader/Cargo.toml"))
+        );
+    }
+
+    #[test]
+    fn matches_hyphenated_renamed_packages_by_crate_name() {
+        let mut json = fixtures::kernel_with_bootloader("/bootloader/Cargo.toml");
+        json["packages"][0]["dependencies"][0]["name"] = json!("boot-loader");
+        json["packages"][0]["dependencies"][0]["rename"] = json!("loader");
+        json["packages"][1]["name"] = json!("boot-loader");
+        json["resolve"]["nodes"][0]["deps"][0]["name"] = json!("loader");
+        let json = json.to_string();
+        let metadata = Metadata::parse(&json).unwrap();
+        for name in ["boot-loader", "boot_loader"] {
+            let query = Query {
+                name_matching: NameMatching::PackageName,
+                ..Query::default()
+            };
+            let manifest_path = metadata.locate(name, &query).unwrap();
+            assert_eq!(manifest_path, Path::new("/bootloader/Cargo.toml"));
+        }
+        let query = Query {
+            name_matching: NameMatching::PackageName,
+            exact_names: true,
+            ..Query::default()
+        };
+        assert!(metadata.locate("boot-loader", &query).is_ok());
+        assert!(metadata.locate("boot_loader", &query).is_err());
+    }
+
+    #[test]
+    fn computes_edit_distance() {
+        assert_eq!(edit_distance("bootloader", "bootloader"), 0);
+        assert_eq!(edit_distance("bootloader", "bootlaoder"), 2);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "uefi"), 4);
+    }
+
+    #[test]
+    fn splits_leading_noise() {
+        assert_eq!(split_leading_noise("  {}"), (None, "  {}"));
+        assert_eq!(split_leading_noise("a\nb\n{}"), (Some("a\nb\n"), "{}"));
+        assert_eq!(split_leading_noise("a\nb"), (Some("a\nb"), ""));
+    }
+
+    #[test]
+    fn borrows_nested_strings() {
+        let mut json = fixtures::kernel_with_bootloader("/bootloader/Cargo.toml");
+        json["packages"][0]["manifest_path"] = json!("/project/\"kernel\"/Cargo.toml");
+        let json = json.to_string();
+        let metadata = Metadata::parse(&json).unwrap();
+        let bootloader = metadata.package(BOOTLOADER_ID).unwrap();
+        assert!(matches!(bootloader.source, Some(Cow::Borrowed(_))));
+        assert!(matches!(metadata.workspace_members[0], Cow::Borrowed(_)));
+        // strings with escape sequences can't be borrowed
+        let kernel = metadata.package(KERNEL_ID).unwrap();
+        assert!(matches!(kernel.manifest_path, Cow::Owned(_)));
+    }
+}
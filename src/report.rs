@@ -0,0 +1,126 @@
+//! Machine-readable reports of lookups, e.g. for CI tooling written in other languages.
+//!
+//! A [`Report`] is created through [`Locator::report`](crate::Locator::report) and records the
+//! full outcome of a lookup, including failures. It serializes to JSON through
+//! [`Report::to_json`] or through its `serde` implementation:
+//!
+//! ```json
+//! {
+//!   "dependency_name": "bootloader",
+//!   "package": {
+//!     "id": "registry+https://github.com/rust-lang/crates.io-index#bootloader@0.9.23",
+//!     "name": "bootloader",
+//!     "version": "0.9.23",
+//!     "manifest_path": "/home/user/.cargo/registry/src/index.crates.io-6f17d22bba15001f/bootloader-0.9.23/Cargo.toml",
+//!     "source": "registry+https://github.com/rust-lang/crates.io-index",
+//!     "features": ["default"]
+//!   },
+//!   "error": null,
+//!   "duration_ms": 112,
+//!   "command": ["cargo", "metadata", "--color", "never", "--format-version", "1"]
+//! }
+//! ```
+
+use crate::{LocateError, LocatedPackage};
+use serde::Serialize;
+use std::{convert::TryFrom, path::PathBuf, process::Command, time::Duration};
+
+/// The outcome of a lookup.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Report {
+    /// The name of the dependency that was looked up.
+    pub dependency_name: String,
+    /// The located package, `None` if the lookup failed.
+    pub package: Option<PackageReport>,
+    /// The error of a failed lookup, `None` if the lookup succeeded.
+    pub error: Option<ErrorReport>,
+    /// The duration of the lookup in milliseconds.
+    pub duration_ms: u64,
+    /// The command line of the `cargo metadata` run of the lookup, starting with the cargo
+    /// executable.
+    ///
+    /// `None` if the command could not be created, e.g. because the manifest doesn't exist.
+    /// The command is not run if the lookup uses a custom
+    /// [`invoker`](crate::Context::invoker) or a memoized output.
+    pub command: Option<Vec<String>>,
+}
+
+impl Report {
+    pub(crate) fn new(
+        dependency_name: &str,
+        result: Result<LocatedPackage, LocateError>,
+        duration: Duration,
+        command: Option<&Command>,
+    ) -> Self {
+        let (package, error) = match result {
+            Ok(package) => (Some(PackageReport::from(package)), None),
+            Err(err) => (None, Some(ErrorReport::from(&err))),
+        };
+        Report {
+            dependency_name: dependency_name.to_owned(),
+            package,
+            error,
+            duration_ms: u64::try_from(duration.as_millis()).unwrap_or(u64::MAX),
+            command: command.map(|command| {
+                let program = command.get_program().to_string_lossy().into_owned();
+                let args = command
+                    .get_args()
+                    .map(|arg| arg.to_string_lossy().into_owned());
+                std::iter::once(program).chain(args).collect()
+            }),
+        }
+    }
+
+    /// Serializes the report as compact JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("report is always serializable")
+    }
+}
+
+/// The located package of a successful lookup.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PackageReport {
+    /// The package ID used by cargo.
+    pub id: String,
+    /// The name of the package.
+    pub name: String,
+    /// The version of the package.
+    pub version: String,
+    /// The path to the `Cargo.toml` of the package.
+    pub manifest_path: PathBuf,
+    /// The source of the package as reported by cargo, `None` for path dependencies.
+    pub source: Option<String>,
+    /// The features that are enabled for the package in the resolved dependency graph.
+    pub features: Vec<String>,
+}
+
+impl From<LocatedPackage> for PackageReport {
+    fn from(package: LocatedPackage) -> Self {
+        PackageReport {
+            id: package.id.to_string(),
+            name: package.name,
+            version: package.version,
+            manifest_path: package.manifest_path,
+            source: package.source,
+            features: package.features,
+        }
+    }
+}
+
+/// The error of a failed lookup.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ErrorReport {
+    /// The stable identifier of the error, see [`LocateError::message`].
+    pub id: String,
+    /// The error message.
+    pub message: String,
+}
+
+impl From<&LocateError> for ErrorReport {
+    fn from(err: &LocateError) -> Self {
+        ErrorReport {
+            id: err.message().id().to_owned(),
+            message: err.to_string(),
+        }
+    }
+}
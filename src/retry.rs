@@ -0,0 +1,51 @@
+use std::time::Duration;
+
+/// Retries `cargo metadata` runs that failed because of transient lock contention.
+///
+/// When several cargo processes run in parallel, e.g. for building multiple kernels at the
+/// same time, they compete for the lock of the package cache and `cargo metadata` sometimes
+/// fails. With a retry policy set through [`Context::retry`](crate::Context::retry), such
+/// failures are detected through
+/// [`CargoMetadataError::is_lock_contention`](crate::CargoMetadataError::is_lock_contention)
+/// and the command is run again after a delay. Other failures are reported immediately.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub(crate) max_attempts: u32,
+    pub(crate) backoff: Duration,
+    pub(crate) max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Creates a policy that runs `cargo metadata` at most `max_attempts` times in total.
+    ///
+    /// The delay before the first retry is 500 milliseconds and doubles for every further
+    /// retry, up to 10 seconds.
+    pub fn new(max_attempts: u32) -> Self {
+        RetryPolicy {
+            max_attempts,
+            backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(10),
+        }
+    }
+
+    /// Sets the delay before the first retry.
+    pub fn backoff(&mut self, backoff: Duration) -> &mut Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Sets the maximum delay between two attempts.
+    pub fn max_backoff(&mut self, max_backoff: Duration) -> &mut Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// Returns the delay before the given retry, starting with `1` for the first retry.
+    pub(crate) fn delay(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        self.backoff
+            .checked_mul(factor)
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff)
+    }
+}
@@ -0,0 +1,309 @@
+//! Runs disk images in QEMU, e.g. for boot tests.
+//!
+//! Kernels report their test results by writing to the `isa-debug-exit` device, which makes QEMU
+//! exit with the status `(value << 1) | 1`. The [`QemuRunner`] maps this exit status to a
+//! [`TestOutcome`].
+
+use crate::{arch::Arch, process};
+use std::{
+    convert,
+    ffi::{OsStr, OsString},
+    fmt, io,
+    path::PathBuf,
+    process::{Command, ExitStatus},
+    time::Duration,
+};
+
+/// The I/O port of the `isa-debug-exit` device.
+const DEBUG_EXIT_IOBASE: u16 = 0xf4;
+
+/// Launches QEMU for a disk image.
+///
+/// ```no_run
+/// use bootloader_locator::runner::{QemuRunner, TestOutcome};
+///
+/// let outcome = QemuRunner::new("target/bios.img").run()?;
+/// assert_eq!(outcome, TestOutcome::Success);
+/// # Ok::<(), bootloader_locator::runner::RunError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct QemuRunner {
+    image: PathBuf,
+    arch: Arch,
+    qemu: Option<PathBuf>,
+    success_code: u32,
+    args: Vec<OsString>,
+    timeout: Option<Duration>,
+}
+
+impl QemuRunner {
+    /// Creates a runner for the given disk image.
+    pub fn new(image: impl Into<PathBuf>) -> Self {
+        QemuRunner {
+            image: image.into(),
+            arch: Arch::default(),
+            qemu: None,
+            success_code: 0x10,
+            args: Vec::new(),
+            timeout: None,
+        }
+    }
+
+    /// Sets the architecture of the disk image.
+    ///
+    /// Defaults to [`Arch::X86_64`]. The architecture determines the default
+    /// [`qemu`](Self::qemu) executable and whether the `isa-debug-exit` device is available.
+    pub fn arch(&mut self, arch: Arch) -> &mut Self {
+        self.arch = arch;
+        self
+    }
+
+    /// Sets the QEMU executable.
+    ///
+    /// Defaults to the [`Arch::qemu_binary`] of the [`arch`](Self::arch) on the `PATH`, e.g.
+    /// `qemu-system-x86_64`.
+    pub fn qemu(&mut self, qemu: impl Into<PathBuf>) -> &mut Self {
+        self.qemu = Some(qemu.into());
+        self
+    }
+
+    /// Sets the value that the kernel writes to the `isa-debug-exit` device on success.
+    ///
+    /// Defaults to `0x10`. All other values are reported as [`TestOutcome::Failure`].
+    pub fn success_code(&mut self, success_code: u32) -> &mut Self {
+        self.success_code = success_code;
+        self
+    }
+
+    /// Appends an additional argument for QEMU, e.g. `-display none`.
+    pub fn arg(&mut self, arg: impl Into<OsString>) -> &mut Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Sets the maximum time that the run may take.
+    ///
+    /// If QEMU doesn't exit in time, it is killed and [`RunError::TimedOut`] is returned. By
+    /// default, there is no timeout.
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Returns the QEMU command that [`run`](Self::run) executes.
+    ///
+    /// The disk image is passed as `-drive format=raw,file=<image>`. QEMU separates the options
+    /// of `-drive` by commas, so commas in the image path are escaped by doubling them. The
+    /// following arguments are passed in addition to the disk image and the arguments given
+    /// through [`arg`](Self::arg):
+    ///
+    /// - `-serial stdio`, so that the serial output of the kernel appears on stdout
+    /// - `-device isa-debug-exit,iobase=0xf4,iosize=0x04` for reporting test results, only on
+    ///   [`Arch::X86_64`] since the other architectures have no ISA bus. Kernels for them
+    ///   report their results through other devices, which are passed through [`arg`](Self::arg)
+    /// - `-no-reboot`, so that triple faults end the run instead of rebooting forever
+    pub fn command(&self) -> Command {
+        let mut drive = OsString::from("format=raw,file=");
+        drive.push(escape_commas(self.image.as_os_str()));
+
+        let mut cmd = match &self.qemu {
+            Some(qemu) => Command::new(qemu),
+            None => Command::new(self.arch.qemu_binary()),
+        };
+        cmd.arg("-drive").arg(drive);
+        cmd.arg("-serial").arg("stdio");
+        if self.arch == Arch::X86_64 {
+            cmd.arg("-device").arg(format!(
+                "isa-debug-exit,iobase={:#x},iosize=0x04",
+                DEBUG_EXIT_IOBASE
+            ));
+        }
+        cmd.arg("-no-reboot");
+        cmd.args(&self.args);
+        cmd
+    }
+
+    /// Runs QEMU until it exits and returns the outcome reported by the kernel.
+    pub fn run(&self) -> Result<TestOutcome, RunError> {
+        if !self.image.is_file() {
+            return Err(RunError::ImageNotFound {
+                path: self.image.clone(),
+            });
+        }
+        let mut child = self.command().spawn()?;
+        match process::wait_timeout(&mut child, self.timeout)? {
+            Some(status) => Ok(self.outcome(status)),
+            None => Err(RunError::TimedOut {
+                timeout: self.timeout.unwrap_or_default(),
+            }),
+        }
+    }
+
+    /// Returns a copy of this runner for a different disk image.
+    pub(crate) fn for_image(&self, image: PathBuf) -> QemuRunner {
+        QemuRunner {
+            image,
+            ..self.clone()
+        }
+    }
+
+    fn outcome(&self, status: ExitStatus) -> TestOutcome {
+        match status.code() {
+            Some(code) if code == ((self.success_code << 1) | 1) as i32 => TestOutcome::Success,
+            Some(code) => TestOutcome::Failure {
+                exit_code: Some(code),
+            },
+            None => TestOutcome::Failure { exit_code: None },
+        }
+    }
+}
+
+/// Doubles all commas in the given option value, which is how QEMU escapes them.
+fn escape_commas(value: &OsStr) -> OsString {
+    if let Some(value) = value.to_str() {
+        return OsString::from(value.replace(',', ",,"));
+    }
+    // paths that are not valid Unicode are escaped in their native encoding
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::{OsStrExt, OsStringExt};
+        let mut escaped = Vec::with_capacity(value.len());
+        for &byte in value.as_bytes() {
+            escaped.push(byte);
+            if byte == b',' {
+                escaped.push(byte);
+            }
+        }
+        OsString::from_vec(escaped)
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::ffi::{OsStrExt, OsStringExt};
+        let mut escaped = Vec::with_capacity(value.len());
+        for unit in value.encode_wide() {
+            escaped.push(unit);
+            if unit == u16::from(b',') {
+                escaped.push(unit);
+            }
+        }
+        OsString::from_wide(&escaped)
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        OsString::from(value.to_string_lossy().replace(',', ",,"))
+    }
+}
+
+/// The outcome of a QEMU run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestOutcome {
+    /// The kernel wrote the success code to the `isa-debug-exit` device.
+    Success,
+    /// QEMU exited in some other way.
+    ///
+    /// This includes a kernel that wrote a different value to the `isa-debug-exit` device and a
+    /// kernel that rebooted, e.g. because of a triple fault.
+    Failure {
+        /// The exit status of QEMU.
+        ///
+        /// `None` if QEMU was terminated by a signal.
+        exit_code: Option<i32>,
+    },
+}
+
+/// Failed to run QEMU.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum RunError {
+    /// An I/O error that occurred while trying to execute QEMU.
+    Io(io::Error),
+    /// The given disk image does not exist.
+    ImageNotFound {
+        /// The path of the disk image as given by the caller.
+        path: PathBuf,
+    },
+    /// QEMU did not exit within the timeout and was killed.
+    TimedOut {
+        /// The timeout that expired.
+        timeout: Duration,
+    },
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunError::Io(err) => write!(f, "Failed to execute QEMU: {}", err),
+            RunError::ImageNotFound { path } => {
+                write!(f, "No disk image found at `{}`", path.display())
+            }
+            RunError::TimedOut { timeout } => {
+                write!(f, "QEMU did not exit within {:?} and was killed", timeout)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RunError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RunError::Io(err) => Some(err),
+            RunError::ImageNotFound { path: _ } => None,
+            RunError::TimedOut { timeout: _ } => None,
+        }
+    }
+}
+
+impl convert::From<io::Error> for RunError {
+    fn from(source: io::Error) -> Self {
+        RunError::Io(source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drive_arg(image: impl Into<PathBuf>) -> OsString {
+        let cmd = QemuRunner::new(image).command();
+        let mut args = cmd.get_args().skip_while(|arg| *arg != "-drive");
+        args.nth(1).unwrap().to_owned()
+    }
+
+    #[test]
+    fn escapes_commas_in_image_path() {
+        assert_eq!(
+            drive_arg("target/bios.img"),
+            "format=raw,file=target/bios.img"
+        );
+        assert_eq!(
+            drive_arg("target/a,b/bios,,1.img"),
+            "format=raw,file=target/a,,b/bios,,,,1.img"
+        );
+    }
+
+    #[test]
+    fn arch_selects_qemu_and_debug_exit_device() {
+        let cmd = QemuRunner::new("bios.img").command();
+        assert_eq!(cmd.get_program(), "qemu-system-x86_64");
+        assert!(cmd.get_args().any(|arg| arg == "-device"));
+
+        let cmd = QemuRunner::new("image.img").arch(Arch::Riscv64).command();
+        assert_eq!(cmd.get_program(), "qemu-system-riscv64");
+        assert!(!cmd.get_args().any(|arg| arg == "-device"));
+
+        let cmd = QemuRunner::new("image.img")
+            .qemu("/opt/qemu/bin/qemu-system-x86_64")
+            .arch(Arch::Aarch64)
+            .command();
+        assert_eq!(cmd.get_program(), "/opt/qemu/bin/qemu-system-x86_64");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn escapes_commas_in_non_unicode_paths() {
+        use std::os::unix::ffi::OsStringExt;
+        let image = OsString::from_vec(b"a,\xff.img".to_vec());
+        let expected = OsString::from_vec(b"format=raw,file=a,,\xff.img".to_vec());
+        assert_eq!(drive_arg(image), expected);
+    }
+}
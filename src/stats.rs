@@ -0,0 +1,48 @@
+//! In-process usage counters, e.g. for reporting them to the metrics system of a tool.
+//!
+//! The counters are plain atomic integers that are shared by all lookups of the process. The
+//! crate never reports them anywhere itself.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static CARGO_INVOCATIONS: AtomicU64 = AtomicU64::new(0);
+static METADATA_PARSES: AtomicU64 = AtomicU64::new(0);
+static BYTES_PARSED: AtomicU64 = AtomicU64::new(0);
+
+/// A snapshot of the usage counters, as returned by [`counters`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counters {
+    /// The number of spawned cargo processes, e.g. `cargo metadata` or `cargo build`.
+    pub cargo_invocations: u64,
+    /// The number of parsed `cargo metadata` outputs.
+    pub metadata_parses: u64,
+    /// The total size of the parsed `cargo metadata` outputs in bytes.
+    pub bytes_parsed: u64,
+}
+
+/// Returns the current values of the usage counters.
+pub fn counters() -> Counters {
+    Counters {
+        cargo_invocations: CARGO_INVOCATIONS.load(Ordering::Relaxed),
+        metadata_parses: METADATA_PARSES.load(Ordering::Relaxed),
+        bytes_parsed: BYTES_PARSED.load(Ordering::Relaxed),
+    }
+}
+
+/// Resets all usage counters to zero and returns their previous values.
+pub fn reset() -> Counters {
+    Counters {
+        cargo_invocations: CARGO_INVOCATIONS.swap(0, Ordering::Relaxed),
+        metadata_parses: METADATA_PARSES.swap(0, Ordering::Relaxed),
+        bytes_parsed: BYTES_PARSED.swap(0, Ordering::Relaxed),
+    }
+}
+
+pub(crate) fn record_cargo_invocation() {
+    CARGO_INVOCATIONS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn record_metadata_parse(bytes: usize) {
+    METADATA_PARSES.fetch_add(1, Ordering::Relaxed);
+    BYTES_PARSED.fetch_add(bytes as u64, Ordering::Relaxed);
+}
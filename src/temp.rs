@@ -0,0 +1,99 @@
+//! Temporary directories that are safe to use from concurrent processes.
+
+use crate::process;
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+/// A temporary directory that is removed with its contents when it's dropped.
+///
+/// The directory name is unique among all processes, so parallel test runs and builds never
+/// share a directory. Set [`keep`](TempDir::keep) to inspect the contents after a failure.
+#[derive(Debug)]
+pub struct TempDir {
+    path: PathBuf,
+    keep: bool,
+}
+
+impl TempDir {
+    /// Creates a new directory in the temporary directory of the system.
+    pub fn new() -> io::Result<Self> {
+        Self::new_in(env::temp_dir())
+    }
+
+    /// Creates a new directory in the given parent directory, creating the parent if needed.
+    pub fn new_in(parent: impl AsRef<Path>) -> io::Result<Self> {
+        let parent = parent.as_ref();
+        fs::create_dir_all(parent)?;
+        let path = parent.join(process::unique_file_name("tmp"));
+        // fails if the directory exists, so that two instances never share it
+        fs::create_dir(&path)?;
+        Ok(TempDir { path, keep: false })
+    }
+
+    /// Returns the path of the directory.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Sets whether the directory is kept when this instance is dropped, e.g. for debugging.
+    pub fn keep(&mut self, keep: bool) -> &mut Self {
+        self.keep = keep;
+        self
+    }
+
+    /// Writes the given file relative to the directory, creating its parent directories.
+    ///
+    /// Returns the absolute path of the written file.
+    pub fn write(
+        &self,
+        relative: impl AsRef<Path>,
+        contents: impl AsRef<[u8]>,
+    ) -> io::Result<PathBuf> {
+        let path = self.path.join(relative);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, contents)?;
+        Ok(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        if !self.keep {
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removes_directory_unless_kept() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.write("a/b.txt", "content").unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "content");
+        let path = dir.path().to_owned();
+        drop(dir);
+        assert!(!path.exists());
+
+        let mut dir = TempDir::new().unwrap();
+        dir.keep(true);
+        let path = dir.path().to_owned();
+        drop(dir);
+        assert!(path.is_dir());
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn instances_never_share_a_directory() {
+        let parent = TempDir::new().unwrap();
+        let a = TempDir::new_in(parent.path()).unwrap();
+        let b = TempDir::new_in(parent.path()).unwrap();
+        assert_ne!(a.path(), b.path());
+    }
+}
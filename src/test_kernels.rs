@@ -0,0 +1,455 @@
+//! Builds and runs the integration tests of a kernel as separate bootable executables.
+//!
+//! Kernels following the pattern of the _Writing an OS in Rust_ blog build each file in their
+//! `tests` directory as a separate kernel executable, which reports its result through the
+//! `isa-debug-exit` device. The [`TestKernels`] pipeline compiles these executables through
+//! `cargo test --no-run`, creates a disk image for each of them through the [`build`] and
+//! [`image`] modules, and runs the images one after another in QEMU.
+//!
+//! The integration test targets of a kernel can also be listed without building them through
+//! [`Metadata::test_targets`](crate::Metadata::test_targets).
+//!
+//! [`build`]: crate::build
+//! [`image`]: crate::image
+
+use crate::{
+    arch::Arch,
+    build::{absolute, BootloaderBuilder, BuildError},
+    image::{self, ImageError},
+    layout::LayoutStrategy,
+    process,
+    runner::{QemuRunner, RunError, TestOutcome},
+    Context,
+};
+use serde::Deserialize;
+use std::{
+    borrow::Cow,
+    convert, fmt, fs, io,
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::Duration,
+};
+
+/// Builds disk images for the integration tests of a kernel and runs them in QEMU.
+///
+/// ```no_run
+/// use bootloader_locator::{locate_bootloader, test_kernels::TestKernels};
+///
+/// let bootloader_manifest = locate_bootloader("bootloader")?;
+/// let report = TestKernels::new("Cargo.toml", bootloader_manifest).run()?;
+/// for result in &report.results {
+///     println!("{}: {:?}", result.name, result.outcome);
+/// }
+/// assert!(report.success());
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct TestKernels {
+    kernel_manifest: PathBuf,
+    bootloader_manifest: PathBuf,
+    arch: Arch,
+    target: Option<PathBuf>,
+    out_dir: Option<PathBuf>,
+    layout: LayoutStrategy,
+    runner: QemuRunner,
+    context: Option<Context>,
+}
+
+impl TestKernels {
+    /// Creates a pipeline for the integration tests of the kernel with the given manifest,
+    /// using the bootloader with the given manifest.
+    pub fn new(
+        kernel_manifest: impl Into<PathBuf>,
+        bootloader_manifest: impl Into<PathBuf>,
+    ) -> Self {
+        TestKernels {
+            kernel_manifest: kernel_manifest.into(),
+            bootloader_manifest: bootloader_manifest.into(),
+            arch: Arch::default(),
+            target: None,
+            out_dir: None,
+            layout: LayoutStrategy::default(),
+            runner: QemuRunner::new(PathBuf::new()),
+            context: None,
+        }
+    }
+
+    /// Sets the architecture of the bootloader, its disk images, and the QEMU runs.
+    ///
+    /// Defaults to [`Arch::X86_64`]. The architecture overrides the one of the
+    /// [`runner`](Self::runner).
+    pub fn arch(&mut self, arch: Arch) -> &mut Self {
+        self.arch = arch;
+        self
+    }
+
+    /// Sets the target that the test kernels are compiled for, passed as `--target` to cargo.
+    ///
+    /// By default, the `build.target` of the cargo configuration of the kernel is used.
+    pub fn target(&mut self, target: impl Into<PathBuf>) -> &mut Self {
+        self.target = Some(target.into());
+        self
+    }
+
+    /// Sets the directory that the disk images are written to.
+    ///
+    /// Defaults to the `test-kernels` directory of the [`layout`](Self::layout) in the
+    /// directory of the kernel manifest, i.e. `target/test-kernels` for the default layout. The
+    /// image of each test is named after the test target, e.g. `basic_boot.img`.
+    pub fn out_dir(&mut self, out_dir: impl Into<PathBuf>) -> &mut Self {
+        self.out_dir = Some(out_dir.into());
+        self
+    }
+
+    /// Sets the directory layout that determines the default [`out_dir`](Self::out_dir) and
+    /// the target directory of the bootloader builds.
+    pub fn layout(&mut self, layout: LayoutStrategy) -> &mut Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Sets the runner whose settings are used for running the test images, e.g. its QEMU
+    /// arguments and timeout.
+    ///
+    /// The disk image of the given runner is replaced with the image of each test.
+    pub fn runner(&mut self, runner: QemuRunner) -> &mut Self {
+        self.runner = runner;
+        self
+    }
+
+    /// Sets the context that is used for running `cargo test` and `cargo build`.
+    ///
+    /// Defaults to [`Context::from_env`], evaluated when the pipeline runs.
+    pub fn context(&mut self, context: Context) -> &mut Self {
+        self.context = Some(context);
+        self
+    }
+
+    /// Compiles the integration tests of the kernel and returns the produced executables.
+    ///
+    /// The executables are returned in the order in which cargo reported them.
+    pub fn build(&self) -> Result<Vec<TestKernel>, TestBuildError> {
+        let context = self.effective_context();
+        let mut cmd = context.cargo_command("test");
+        cmd.arg("--no-run");
+        cmd.arg("--tests");
+        cmd.arg("--message-format").arg("json");
+        cmd.arg("--manifest-path")
+            .arg(absolute(&self.kernel_manifest)?);
+        if let Some(target) = &self.target {
+            cmd.arg("--target").arg(target);
+        }
+        if !context.features.is_empty() {
+            cmd.arg("--features").arg(context.features.join(","));
+        }
+        if context.all_features {
+            cmd.arg("--all-features");
+        }
+        if context.no_default_features {
+            cmd.arg("--no-default-features");
+        }
+        // cargo looks up the configuration of the kernel (e.g. `.cargo/config.toml`) starting
+        // from the working directory
+        if let Some(kernel_dir) = self.kernel_dir() {
+            cmd.current_dir(kernel_dir);
+        }
+        cmd.stdin(Stdio::null());
+        cmd.stdout(Stdio::piped());
+        cmd.stderr(Stdio::piped());
+
+        let output = match process::capture(cmd.spawn()?, &context)? {
+            Some(output) => output,
+            None => {
+                return Err(TestBuildError::TimedOut {
+                    timeout: context.timeout.unwrap_or_default(),
+                })
+            }
+        };
+        if !output.status.success() {
+            return Err(TestBuildError::Failed {
+                stderr: output.stderr,
+            });
+        }
+        test_kernels(&output.stdout)
+    }
+
+    /// Compiles the integration tests, creates a disk image for each of them, and runs the
+    /// images one after another.
+    ///
+    /// A test that fails to build or run doesn't prevent running the others, its failure is
+    /// reported in its [`TestResult`] instead. The only error returned directly is a failure to
+    /// compile the tests.
+    pub fn run(&self) -> Result<TestReport, TestBuildError> {
+        let kernels = self.build()?;
+        let context = self.effective_context();
+        let out_dir = match &self.out_dir {
+            Some(out_dir) => out_dir.clone(),
+            None => self.layout.dir(
+                self.kernel_dir().unwrap_or_else(|| Path::new(".")),
+                "test-kernels",
+            ),
+        };
+        fs::create_dir_all(&out_dir)?;
+
+        let results = kernels
+            .into_iter()
+            .map(|kernel| {
+                let image = out_dir.join(format!("{}.img", kernel.name));
+                let outcome = self.run_kernel(&context, &kernel, &image);
+                TestResult {
+                    name: kernel.name,
+                    executable: kernel.executable,
+                    image,
+                    outcome,
+                }
+            })
+            .collect();
+        Ok(TestReport { results })
+    }
+
+    fn run_kernel(
+        &self,
+        context: &Context,
+        kernel: &TestKernel,
+        image: &Path,
+    ) -> Result<TestOutcome, TestKernelError> {
+        let artifacts = BootloaderBuilder::new(&self.bootloader_manifest, &kernel.executable)
+            .kernel_manifest(&self.kernel_manifest)
+            .arch(self.arch)
+            .layout(self.layout.clone())
+            .context(context.clone())
+            .build()?;
+        image::create_disk_image(self.arch, &artifacts.bootloader_elf, image)?;
+        Ok(self
+            .runner
+            .for_image(image.to_owned())
+            .arch(self.arch)
+            .run()?)
+    }
+
+    fn kernel_dir(&self) -> Option<&Path> {
+        self.kernel_manifest
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+    }
+
+    fn effective_context(&self) -> Cow<'_, Context> {
+        match &self.context {
+            Some(context) => Cow::Borrowed(context),
+            None => Cow::Owned(Context::from_env()),
+        }
+    }
+}
+
+/// A message of the JSON output of `cargo test --no-run`.
+#[derive(Deserialize)]
+struct Message {
+    reason: String,
+    #[serde(default)]
+    target: Option<MessageTarget>,
+    #[serde(default)]
+    executable: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+struct MessageTarget {
+    name: String,
+    kind: Vec<String>,
+}
+
+/// Collects the integration test executables from the JSON output of `cargo test --no-run`.
+fn test_kernels(stdout: &[u8]) -> Result<Vec<TestKernel>, TestBuildError> {
+    let stdout = String::from_utf8_lossy(stdout);
+    let mut kernels = Vec::new();
+    for line in stdout.lines().filter(|line| line.starts_with('{')) {
+        let message: Message =
+            serde_json::from_str(line).map_err(|err| TestBuildError::InvalidOutput {
+                message: err.to_string(),
+            })?;
+        if message.reason != "compiler-artifact" {
+            continue;
+        }
+        if let (Some(target), Some(executable)) = (message.target, message.executable) {
+            if target.kind.iter().any(|kind| kind == "test") {
+                kernels.push(TestKernel {
+                    name: target.name,
+                    executable,
+                });
+            }
+        }
+    }
+    Ok(kernels)
+}
+
+/// An integration test executable, as returned by [`TestKernels::build`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestKernel {
+    /// The name of the test target, e.g. `basic_boot` for `tests/basic_boot.rs`.
+    pub name: String,
+    /// The path of the compiled kernel executable.
+    pub executable: PathBuf,
+}
+
+/// The results of all test kernels, as returned by [`TestKernels::run`].
+#[derive(Debug)]
+pub struct TestReport {
+    /// The result of each test kernel, in the order in which they were run.
+    pub results: Vec<TestResult>,
+}
+
+impl TestReport {
+    /// Returns whether all test kernels reported success.
+    ///
+    /// Also returns `true` if the kernel has no integration tests.
+    pub fn success(&self) -> bool {
+        self.results.iter().all(TestResult::success)
+    }
+
+    /// Returns the results of the test kernels that didn't report success.
+    pub fn failures(&self) -> impl Iterator<Item = &TestResult> {
+        self.results.iter().filter(|result| !result.success())
+    }
+}
+
+/// The result of a single test kernel.
+#[derive(Debug)]
+pub struct TestResult {
+    /// The name of the test target.
+    pub name: String,
+    /// The path of the compiled kernel executable.
+    pub executable: PathBuf,
+    /// The path of the disk image of the test kernel.
+    ///
+    /// The image might not exist if the test failed before it was created.
+    pub image: PathBuf,
+    /// The outcome reported by the test kernel, or the error that prevented running it.
+    pub outcome: Result<TestOutcome, TestKernelError>,
+}
+
+impl TestResult {
+    /// Returns whether the test kernel reported success.
+    pub fn success(&self) -> bool {
+        matches!(self.outcome, Ok(TestOutcome::Success))
+    }
+}
+
+/// Failed to compile the integration tests of the kernel.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum TestBuildError {
+    /// An I/O error that occurred while trying to execute `cargo test`.
+    Io(io::Error),
+    /// The command `cargo test --no-run` did not exit successfully.
+    Failed {
+        /// The standard error output of `cargo test`.
+        ///
+        /// Only the first megabyte of the output is kept.
+        stderr: Vec<u8>,
+    },
+    /// The command `cargo test --no-run` did not finish within the timeout of the [`Context`].
+    ///
+    /// The process was killed.
+    TimedOut {
+        /// The timeout that expired.
+        timeout: Duration,
+    },
+    /// The JSON output of `cargo test --no-run` could not be parsed.
+    InvalidOutput {
+        /// The error message of the JSON parser.
+        message: String,
+    },
+}
+
+impl fmt::Display for TestBuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TestBuildError::Io(err) => write!(f, "Failed to execute `cargo test`: {}", err),
+            TestBuildError::Failed { stderr } => write!(
+                f,
+                "`cargo test --no-run` for the kernel was not successful: {}",
+                String::from_utf8_lossy(stderr)
+            ),
+            TestBuildError::TimedOut { timeout } => write!(
+                f,
+                "`cargo test --no-run` for the kernel did not finish within {:?} and was killed",
+                timeout
+            ),
+            TestBuildError::InvalidOutput { message } => write!(
+                f,
+                "Failed to parse `cargo test --no-run` output as JSON: {}",
+                message
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TestBuildError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TestBuildError::Io(err) => Some(err),
+            TestBuildError::Failed { stderr: _ } => None,
+            TestBuildError::TimedOut { timeout: _ } => None,
+            TestBuildError::InvalidOutput { message: _ } => None,
+        }
+    }
+}
+
+impl convert::From<io::Error> for TestBuildError {
+    fn from(source: io::Error) -> Self {
+        TestBuildError::Io(source)
+    }
+}
+
+/// Failed to build or run a single test kernel.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum TestKernelError {
+    /// Failed to build the bootloader with the embedded test kernel.
+    Build(BuildError),
+    /// Failed to create the disk image.
+    Image(ImageError),
+    /// Failed to run the disk image in QEMU.
+    Run(RunError),
+}
+
+impl fmt::Display for TestKernelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TestKernelError::Build(source) => {
+                write!(f, "Failed to build the test kernel: {}", source)
+            }
+            TestKernelError::Image(source) => {
+                write!(f, "Failed to create the test kernel image: {}", source)
+            }
+            TestKernelError::Run(source) => write!(f, "Failed to run the test kernel: {}", source),
+        }
+    }
+}
+
+impl std::error::Error for TestKernelError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TestKernelError::Build(source) => Some(source),
+            TestKernelError::Image(source) => Some(source),
+            TestKernelError::Run(source) => Some(source),
+        }
+    }
+}
+
+impl convert::From<BuildError> for TestKernelError {
+    fn from(source: BuildError) -> Self {
+        TestKernelError::Build(source)
+    }
+}
+
+impl convert::From<ImageError> for TestKernelError {
+    fn from(source: ImageError) -> Self {
+        TestKernelError::Image(source)
+    }
+}
+
+impl convert::From<RunError> for TestKernelError {
+    fn from(source: RunError) -> Self {
+        TestKernelError::Run(source)
+    }
+}
@@ -0,0 +1,240 @@
+//! Probes for the active Rust toolchain.
+//!
+//! The probe results are cached per `rustc` executable, so repeated checks don't spawn new
+//! processes.
+
+use crate::{messages::Message, ParseValueError};
+use std::{
+    collections::HashMap,
+    convert,
+    ffi::OsString,
+    fmt, io,
+    process::{Command, Stdio},
+    str,
+    sync::Mutex,
+};
+
+/// Information about a `rustc` executable, as reported by `rustc --version --verbose`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RustcInfo {
+    /// The release version, e.g. `1.70.0` or `1.72.0-nightly`.
+    pub version: String,
+    /// The release channel.
+    pub channel: Channel,
+    /// The target triple of the host system, e.g. `x86_64-unknown-linux-gnu`.
+    pub host: String,
+    /// The hash of the commit that the compiler was built from.
+    ///
+    /// `None` for compilers built outside of a git checkout.
+    pub commit_hash: Option<String>,
+    /// The date of the commit that the compiler was built from.
+    pub commit_date: Option<String>,
+}
+
+impl RustcInfo {
+    /// Returns the numeric `(major, minor, patch)` components of the version.
+    ///
+    /// Pre-release suffixes such as `-nightly` are ignored.
+    pub fn version_components(&self) -> Option<(u64, u64, u64)> {
+        parse_version(&self.version)
+    }
+}
+
+/// The release channel of a Rust toolchain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    /// A stable release.
+    Stable,
+    /// A beta release.
+    Beta,
+    /// A nightly release.
+    Nightly,
+    /// A locally built development version.
+    Dev,
+}
+
+impl fmt::Display for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Channel::Stable => write!(f, "stable"),
+            Channel::Beta => write!(f, "beta"),
+            Channel::Nightly => write!(f, "nightly"),
+            Channel::Dev => write!(f, "dev"),
+        }
+    }
+}
+
+/// Parses the spellings of the `Display` implementation, e.g. `nightly`.
+impl str::FromStr for Channel {
+    type Err = ParseValueError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stable" => Ok(Channel::Stable),
+            "beta" => Ok(Channel::Beta),
+            "nightly" => Ok(Channel::Nightly),
+            "dev" => Ok(Channel::Dev),
+            _ => Err(ParseValueError::new(
+                s,
+                &["stable", "beta", "nightly", "dev"],
+            )),
+        }
+    }
+}
+
+/// Returns information about the active `rustc`.
+///
+/// The `rustc` executable is taken from the `RUSTC` environment variable, falling back to
+/// `rustc` on the `PATH`. This is the same executable that cargo uses.
+pub fn rustc_info() -> Result<RustcInfo, ProbeError> {
+    let rustc = std::env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+    rustc_info_for(rustc)
+}
+
+/// Returns information about the given `rustc` executable.
+pub fn rustc_info_for(rustc: impl Into<OsString>) -> Result<RustcInfo, ProbeError> {
+    static CACHE: Mutex<Option<HashMap<OsString, RustcInfo>>> = Mutex::new(None);
+
+    let rustc = rustc.into();
+    if let Some(info) = CACHE
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(|cache| cache.get(&rustc))
+    {
+        return Ok(info.clone());
+    }
+
+    let output = Command::new(&rustc)
+        .arg("--version")
+        .arg("--verbose")
+        .stdin(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        return Err(ProbeError::Failed {
+            stderr: output.stderr,
+        });
+    }
+    let info = parse_verbose_version(&String::from_utf8_lossy(&output.stdout))?;
+
+    CACHE
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .insert(rustc, info.clone());
+    Ok(info)
+}
+
+/// Parses the output of `rustc --version --verbose`.
+fn parse_verbose_version(output: &str) -> Result<RustcInfo, ProbeError> {
+    let field = |name: &str| {
+        output
+            .lines()
+            .filter_map(|line| line.split_once(": "))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value.trim().to_owned())
+    };
+
+    let version = field("release").ok_or_else(|| ProbeError::InvalidOutput(output.into()))?;
+    let host = field("host").ok_or_else(|| ProbeError::InvalidOutput(output.into()))?;
+    let channel = if version.contains("-nightly") {
+        Channel::Nightly
+    } else if version.contains("-beta") {
+        Channel::Beta
+    } else if version.contains("-dev") {
+        Channel::Dev
+    } else {
+        Channel::Stable
+    };
+    let unknown_to_none = |value: Option<String>| value.filter(|v| v != "unknown");
+
+    Ok(RustcInfo {
+        version,
+        channel,
+        host,
+        commit_hash: unknown_to_none(field("commit-hash")),
+        commit_date: unknown_to_none(field("commit-date")),
+    })
+}
+
+/// Parses a version like `1.70`, `1.70.1`, or `1.72.0-nightly` into its numeric components.
+///
+/// Missing components are treated as zero and pre-release suffixes are ignored, so a nightly
+/// toolchain counts as the release it precedes.
+pub(crate) fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let version = version.split('-').next()?;
+    let mut components = version.split('.').map(|c| c.parse::<u64>());
+    let major = components.next()?.ok()?;
+    let minor = components.next().unwrap_or(Ok(0)).ok()?;
+    let patch = components.next().unwrap_or(Ok(0)).ok()?;
+    if components.next().is_some() {
+        return None;
+    }
+    Some((major, minor, patch))
+}
+
+/// Failed to probe the Rust toolchain.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ProbeError {
+    /// An I/O error that occurred while trying to execute `rustc`.
+    Io(io::Error),
+    /// The command `rustc --version --verbose` did not exit successfully.
+    Failed {
+        /// The standard error output of `rustc`.
+        stderr: Vec<u8>,
+    },
+    /// The output of `rustc --version --verbose` did not have the expected format.
+    InvalidOutput(String),
+}
+
+impl fmt::Display for ProbeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProbeError::Io(err) => write!(f, "Failed to execute `rustc`: {}", err),
+            ProbeError::Failed { stderr } => write!(
+                f,
+                "`rustc --version --verbose` was not successful: {}",
+                String::from_utf8_lossy(stderr)
+            ),
+            ProbeError::InvalidOutput(output) => write!(
+                f,
+                "Unexpected output of `rustc --version --verbose`: {}",
+                output
+            ),
+        }
+    }
+}
+
+impl ProbeError {
+    /// Returns the error as a [`Message`] that can be translated.
+    ///
+    /// See the [`messages`](crate::messages) module for details.
+    pub fn message(&self) -> Message {
+        match self {
+            ProbeError::Io(err) => Message::new("toolchain.io").with_arg("error", err.to_string()),
+            ProbeError::Failed { stderr } => {
+                Message::new("toolchain.failed").with_arg("stderr", String::from_utf8_lossy(stderr))
+            }
+            ProbeError::InvalidOutput(output) => {
+                Message::new("toolchain.invalid-output").with_arg("output", output.as_str())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProbeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ProbeError::Io(err) => Some(err),
+            ProbeError::Failed { stderr: _ } => None,
+            ProbeError::InvalidOutput(_) => None,
+        }
+    }
+}
+
+impl convert::From<io::Error> for ProbeError {
+    fn from(source: io::Error) -> Self {
+        ProbeError::Io(source)
+    }
+}
@@ -0,0 +1,65 @@
+//! Optional instrumentation through the [`tracing`](https://docs.rs/tracing) crate.
+//!
+//! With the `tracing` feature, the crate emits `DEBUG` events for every `cargo metadata`
+//! invocation and for the steps of resolving a dependency. Without it, the helpers of this
+//! module compile to nothing.
+
+use std::process::{Command, ExitStatus};
+#[cfg(feature = "tracing")]
+use std::time::Instant;
+
+/// Emits a `DEBUG` event with the given fields and message if the `tracing` feature is enabled.
+///
+/// Takes the same arguments as [`tracing::debug!`](https://docs.rs/tracing/latest/tracing/macro.debug.html).
+/// Without the feature, the arguments are not evaluated.
+macro_rules! debug_event {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::debug!($($arg)*);
+    };
+}
+
+/// Records the start and the end of a cargo invocation.
+pub(crate) struct Invocation {
+    #[cfg(feature = "tracing")]
+    command: String,
+    #[cfg(feature = "tracing")]
+    start: Instant,
+}
+
+impl Invocation {
+    /// Emits an event with the full command line of the given command.
+    pub fn start(command: &Command) -> Self {
+        #[cfg(feature = "tracing")]
+        {
+            let command = format!("{:?}", command);
+            debug_event!(command = %command, "running cargo");
+            Invocation {
+                command,
+                start: Instant::now(),
+            }
+        }
+        #[cfg(not(feature = "tracing"))]
+        {
+            let _ = command;
+            Invocation {}
+        }
+    }
+
+    /// Emits an event with the duration, the exit status, and the stderr output of the
+    /// invocation.
+    ///
+    /// A status of `None` means that the process was killed because of a timeout or a
+    /// cancellation.
+    pub fn finish(self, status: Option<ExitStatus>, stderr: &[u8]) {
+        debug_event!(
+            command = %self.command,
+            duration = ?self.start.elapsed(),
+            status = ?status,
+            stderr = %String::from_utf8_lossy(stderr),
+            "cargo finished"
+        );
+        #[cfg(not(feature = "tracing"))]
+        let _ = (self, status, stderr);
+    }
+}
@@ -0,0 +1,144 @@
+//! Push-based notifications about changes of the dependency location.
+//!
+//! Requires the `notify` feature.
+
+use crate::{locate_bootloader, metadata, raw, Context, LocateError};
+use std::{
+    convert, fmt,
+    path::{Path, PathBuf},
+};
+
+use notify::{RecursiveMode, Watcher as _};
+
+/// Watches the project for changes that might affect the location of the given dependency.
+///
+/// The project manifests of all workspace members, the `Cargo.lock` file, and the `vendor`
+/// directory of the workspace (if it exists) are monitored. Whenever one of them changes, the
+/// dependency is located again and the given callback is invoked with the result. Results that
+/// point to the same location as the previous result are not reported again.
+///
+/// The callback is invoked on a background thread. Watching stops when the returned [`Watcher`]
+/// is dropped.
+pub fn watch<F>(dependency_name: &str, mut callback: F) -> Result<Watcher, WatchError>
+where
+    F: FnMut(Result<PathBuf, LocateError>) + Send + 'static,
+{
+    let output = metadata(&Context::from_env(), None).map_err(LocateError::from)?;
+    let metadata = raw::Metadata::parse(&output).map_err(LocateError::from)?;
+
+    let workspace_root = PathBuf::from(&*metadata.workspace_root);
+    let member_dirs: Vec<_> = metadata
+        .packages
+        .iter()
+        .filter(|p| metadata.workspace_members.contains(&p.id))
+        .filter_map(|p| Path::new(&*p.manifest_path).parent())
+        .map(Path::to_owned)
+        .collect();
+    let vendor_dir = workspace_root.join("vendor");
+
+    let dependency_name = dependency_name.to_owned();
+    let mut last_location = locate_bootloader(&dependency_name).ok();
+    let relevant_vendor_dir = vendor_dir.clone();
+    let mut inner = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let event = match event {
+            Ok(event) => event,
+            Err(_) => return,
+        };
+        if !(event.kind.is_create() || event.kind.is_modify() || event.kind.is_remove()) {
+            return;
+        }
+        if !event
+            .paths
+            .iter()
+            .any(|path| is_relevant(path, &relevant_vendor_dir))
+        {
+            return;
+        }
+
+        let result = locate_bootloader(&dependency_name);
+        if let Ok(location) = &result {
+            if last_location.as_ref() == Some(location) {
+                return;
+            }
+            last_location = Some(location.clone());
+        } else {
+            last_location = None;
+        }
+        callback(result);
+    })?;
+
+    inner.watch(&workspace_root, RecursiveMode::NonRecursive)?;
+    for dir in member_dirs.iter().filter(|dir| **dir != workspace_root) {
+        inner.watch(dir, RecursiveMode::NonRecursive)?;
+    }
+    if vendor_dir.is_dir() {
+        inner.watch(&vendor_dir, RecursiveMode::Recursive)?;
+    }
+
+    Ok(Watcher { _inner: inner })
+}
+
+/// Checks whether a change of the given path might affect the dependency location.
+fn is_relevant(path: &Path, vendor_dir: &Path) -> bool {
+    if path.starts_with(vendor_dir) {
+        return true;
+    }
+    matches!(
+        path.file_name().and_then(|name| name.to_str()),
+        Some("Cargo.toml") | Some("Cargo.lock")
+    )
+}
+
+/// Watches a project for changes of a dependency location.
+///
+/// Created through the [`watch`] function. Watching stops when this type is dropped.
+pub struct Watcher {
+    _inner: notify::RecommendedWatcher,
+}
+
+impl fmt::Debug for Watcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Watcher").finish_non_exhaustive()
+    }
+}
+
+/// Failed to start watching for dependency location changes.
+#[derive(Debug)]
+pub enum WatchError {
+    /// Failed to determine the paths that should be watched.
+    Locate(LocateError),
+    /// Failed to set up the file system watcher.
+    Notify(notify::Error),
+}
+
+impl fmt::Display for WatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WatchError::Locate(err) => {
+                write!(f, "Failed to determine the paths to watch: {}", err)
+            }
+            WatchError::Notify(err) => write!(f, "Failed to watch the project files: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for WatchError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            WatchError::Locate(err) => Some(err),
+            WatchError::Notify(err) => Some(err),
+        }
+    }
+}
+
+impl convert::From<LocateError> for WatchError {
+    fn from(source: LocateError) -> Self {
+        WatchError::Locate(source)
+    }
+}
+
+impl convert::From<notify::Error> for WatchError {
+    fn from(source: notify::Error) -> Self {
+        WatchError::Notify(source)
+    }
+}
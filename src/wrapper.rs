@@ -0,0 +1,202 @@
+//! Support for running cargo through a wrapper like `cross`.
+//!
+//! Wrappers that run cargo in a container report the paths inside the container, e.g.
+//! `/project/Cargo.toml` instead of the path on the host. The path mappings of the [`Context`]
+//! translate these paths back, so that all returned paths are valid on the host.
+
+use crate::{raw, Context};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+/// Applies the path mappings of the given context to the JSON output of `cargo metadata`.
+///
+/// All string values are rewritten, so paths in nested fields like `workspace_root` or
+/// the `path+file://` sources of package IDs are mapped too. Output that is not valid JSON is
+/// returned unchanged, so that parse errors are reported with the original output.
+pub(crate) fn map_output(context: &Context, output: String) -> String {
+    if context.path_mappings.is_empty() {
+        return output;
+    }
+    let (ignored_output, json) = raw::split_leading_noise(&output);
+    let mut value: Value = match serde_json::from_str(json) {
+        Ok(value) => value,
+        Err(_) => return output,
+    };
+    map_value(&mut value, &context.path_mappings);
+    let json = serde_json::to_string(&value).expect("JSON values are always serializable");
+    match ignored_output {
+        Some(ignored_output) => format!("{}{}", ignored_output, json),
+        None => json,
+    }
+}
+
+fn map_value(value: &mut Value, mappings: &[(PathBuf, PathBuf)]) {
+    match value {
+        Value::String(s) => {
+            if let Some(mapped) = map_str(s, mappings) {
+                *s = mapped;
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(|v| map_value(v, mappings)),
+        Value::Object(map) => map.values_mut().for_each(|v| map_value(v, mappings)),
+        Value::Null | Value::Bool(_) | Value::Number(_) => {}
+    }
+}
+
+/// Maps a single string, which is either a path or contains a `file://` URL.
+///
+/// Returns `None` if no mapping applies. The first matching mapping wins.
+fn map_str(s: &str, mappings: &[(PathBuf, PathBuf)]) -> Option<String> {
+    for (container, host) in mappings {
+        let container = container.to_str()?.trim_end_matches('/');
+        if let Some(rest) = strip_path_prefix(s, container) {
+            return Some(join(host, rest));
+        }
+        let url = format!("file://{}", container);
+        if let Some(start) = s.find(&url) {
+            let after = &s[start + url.len()..];
+            let end = after.find(['#', '?', ')']).unwrap_or(after.len());
+            if let Some(rest) = strip_path_prefix(&after[..end], "") {
+                let host_url = format!("file://{}", join(host, rest).replace('\\', "/"));
+                return Some(format!("{}{}{}", &s[..start], host_url, &after[end..]));
+            }
+        }
+    }
+    None
+}
+
+/// Strips the given path prefix, respecting path component boundaries.
+fn strip_path_prefix<'s>(s: &'s str, prefix: &str) -> Option<&'s str> {
+    let rest = s.strip_prefix(prefix)?;
+    if rest.is_empty() || rest.starts_with('/') {
+        Some(rest.trim_start_matches('/'))
+    } else {
+        None
+    }
+}
+
+fn join(host: &Path, rest: &str) -> String {
+    let mut path = host.to_owned();
+    path.extend(rest.split('/').filter(|part| !part.is_empty()));
+    path.to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cargo_config, fixtures, Metadata};
+    use serde_json::json;
+
+    fn context(mappings: &[(&str, &str)]) -> Context {
+        let mut context = Context::from_env();
+        for (container, host) in mappings {
+            context.map_path(container, host);
+        }
+        context
+    }
+
+    fn host_path(host: &str, rest: &str) -> String {
+        join(Path::new(host), rest)
+    }
+
+    #[test]
+    fn maps_paths_and_file_urls() {
+        let context = context(&[("/project", "/home/user/kernel")]);
+        let output = json!({
+            "manifest_path": "/project/kernel/Cargo.toml",
+            "workspace_root": "/project/",
+            "id": "path+file:///project/kernel#0.1.0",
+            "source": "path+file:///project/bootloader?rev=1",
+            "nested": [{ "path": "/project" }, 1, null],
+        });
+        let mapped: Value =
+            serde_json::from_str(&map_output(&context, output.to_string())).unwrap();
+        let url = |rest| {
+            format!(
+                "file://{}",
+                host_path("/home/user/kernel", rest).replace('\\', "/")
+            )
+        };
+        assert_eq!(
+            mapped,
+            json!({
+                "manifest_path": host_path("/home/user/kernel", "kernel/Cargo.toml"),
+                "workspace_root": host_path("/home/user/kernel", ""),
+                "id": format!("path+{}#0.1.0", url("kernel")),
+                "source": format!("path+{}?rev=1", url("bootloader")),
+                "nested": [{ "path": host_path("/home/user/kernel", "") }, 1, null],
+            })
+        );
+    }
+
+    #[test]
+    fn matches_whole_components_in_order() {
+        let mappings = [
+            (PathBuf::from("/project/target"), PathBuf::from("/build")),
+            (
+                PathBuf::from("/project"),
+                PathBuf::from("/home/user/kernel"),
+            ),
+        ];
+        assert_eq!(
+            map_str("/project/target/debug", &mappings),
+            Some(host_path("/build", "debug"))
+        );
+        assert_eq!(
+            map_str("/project/src", &mappings),
+            Some(host_path("/home/user/kernel", "src"))
+        );
+        assert_eq!(map_str("/projects/kernel", &mappings), None);
+        assert_eq!(map_str("file:///projects/kernel", &mappings), None);
+        assert_eq!(map_str("/home/project", &mappings), None);
+    }
+
+    #[test]
+    fn keeps_other_output() {
+        let context = context(&[("/project", "/home/user/kernel")]);
+        let invalid = String::from("warning: wrapper banner\n{ invalid /project");
+        assert_eq!(map_output(&context, invalid.clone()), invalid);
+
+        let mapped = map_output(
+            &context,
+            String::from("warning: wrapper banner\n{\"root\": \"/project\"}"),
+        );
+        let (noise, json) = raw::split_leading_noise(&mapped);
+        assert_eq!(noise, Some("warning: wrapper banner\n"));
+        assert_eq!(
+            serde_json::from_str::<Value>(json).unwrap(),
+            json!({ "root": host_path("/home/user/kernel", "") })
+        );
+
+        let unmapped = String::from("{\"root\": \"/project\"}");
+        assert_eq!(map_output(&Context::from_env(), unmapped.clone()), unmapped);
+    }
+
+    #[test]
+    fn cross_runs_cargo_in_container() {
+        let project_dir = PathBuf::from("/home/user/kernel");
+        let mut context = Context::from_env();
+        context.cross(&project_dir);
+        let (program, args) = context.wrapper.as_ref().unwrap();
+        assert_eq!(program, Path::new("cross"));
+        assert!(args.is_empty());
+        let cmd = context.uncounted_cargo_command("metadata");
+        assert_eq!(cmd.get_program(), "cross");
+        assert_eq!(cmd.get_args().next().unwrap(), "metadata");
+
+        let cargo_home = cargo_config::cargo_home().unwrap();
+        let registry = "registry/src/index.crates.io-6f17d22bba15001f/bootloader-0.11.3";
+        let mut output =
+            fixtures::kernel_with_bootloader(&format!("/cargo/{}/Cargo.toml", registry));
+        output["packages"][0]["manifest_path"] = json!("/project/Cargo.toml");
+        output["target_directory"] = json!("/target");
+        let output = map_output(&context, output.to_string());
+
+        let metadata = Metadata::parse(&output).unwrap();
+        assert_eq!(metadata.target_directory(), project_dir.join("target"));
+        let bootloader = metadata.locate("bootloader").unwrap();
+        assert_eq!(bootloader, cargo_home.join(registry).join("Cargo.toml"));
+        let kernel = metadata.root_package(None).unwrap();
+        assert_eq!(kernel.manifest_path, project_dir.join("Cargo.toml"));
+    }
+}
@@ -0,0 +1,68 @@
+//! Checks that a build without default features only contains the dependency lookup.
+//!
+//! Embedded CI setups depend on this crate with `default-features = false` to keep their
+//! dependency tree small, so optional modules must not leak into the core build.
+
+use std::{path::Path, process::Command};
+
+/// The only crates that the core build may depend on.
+const CORE_DEPENDENCIES: &[&str] = &["semver", "serde", "serde_json", "toml"];
+
+fn cargo(args: &[&str]) -> String {
+    let manifest_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+    let output = Command::new(env!("CARGO"))
+        .args(args)
+        .arg("--manifest-path")
+        .arg(&manifest_path)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "cargo {:?} failed:\n{}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn core_build_has_no_optional_dependencies() {
+    let tree = cargo(&[
+        "tree",
+        "--no-default-features",
+        "--edges",
+        "normal",
+        "--depth",
+        "1",
+        "--prefix",
+        "none",
+        "--format",
+        "{p}",
+    ]);
+    let dependencies: Vec<_> = tree
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().next())
+        .collect();
+    for dependency in &dependencies {
+        assert!(
+            CORE_DEPENDENCIES.contains(dependency),
+            "the core build depends on `{}`",
+            dependency
+        );
+    }
+}
+
+#[test]
+fn core_build_compiles() {
+    // a separate target directory, since the one of the test run is locked by cargo
+    let target_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("target/no-default-features");
+    cargo(&[
+        "check",
+        "--lib",
+        "--no-default-features",
+        "--quiet",
+        "--target-dir",
+        target_dir.to_str().unwrap(),
+    ]);
+}
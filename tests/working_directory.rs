@@ -0,0 +1,133 @@
+//! Checks that lookups don't depend on the working directory of the process.
+//!
+//! The tests change the working directory, which is shared by all threads of the test binary,
+//! so they are serialized through a lock.
+
+use bootloader_locator::{Context, Locator, Metadata};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+    sync::Mutex,
+};
+
+static WORKING_DIRECTORY: Mutex<()> = Mutex::new(());
+
+/// A workspace with a `kernel` package that depends on a `bootloader` path dependency.
+///
+/// The workspace root contains a cargo configuration that moves the target directory, so that
+/// lookups that use the configuration of the wrong directory are detected.
+struct Workspace {
+    root: PathBuf,
+}
+
+impl Workspace {
+    fn new(name: &str) -> Self {
+        let root = env::temp_dir().join(format!("bootloader-locator-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&root);
+        let write = |path: &str, contents: &str| {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        };
+        write("Cargo.toml", "[workspace]\nmembers = [\"kernel\"]\n");
+        write(".cargo/config.toml", "[build]\ntarget-dir = \"build\"\n");
+        write(
+            "kernel/Cargo.toml",
+            "[package]\nname = \"kernel\"\nversion = \"0.1.0\"\n\n\
+             [dependencies]\nbootloader = { path = \"../bootloader\" }\n",
+        );
+        write("kernel/src/main.rs", "fn main() {}\n");
+        write(
+            "bootloader/Cargo.toml",
+            "[package]\nname = \"bootloader\"\nversion = \"0.11.3\"\n",
+        );
+        write("bootloader/src/lib.rs", "");
+        // a directory with a configuration that must not apply to the workspace
+        write(
+            "unrelated/.cargo/config.toml",
+            "[build]\ntarget-dir = \"wrong\"\n",
+        );
+        Workspace {
+            root: root.canonicalize().unwrap(),
+        }
+    }
+
+    fn kernel_manifest(&self) -> PathBuf {
+        self.root.join("kernel").join("Cargo.toml")
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+fn context() -> Context {
+    let mut context = Context::from_env();
+    // memoized outputs would hide differences between the working directories
+    context.memoize(false);
+    context
+}
+
+/// Runs the given closure with the given working directory.
+fn in_dir<T>(dir: &Path, f: impl FnOnce() -> T) -> T {
+    let _lock = WORKING_DIRECTORY
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let previous = env::current_dir().unwrap();
+    env::set_current_dir(dir).unwrap();
+    let result = f();
+    env::set_current_dir(previous).unwrap();
+    result
+}
+
+fn locate(manifest_path: &Path) -> PathBuf {
+    Locator::new("bootloader")
+        .manifest_path(manifest_path)
+        .context(context())
+        .locate()
+        .unwrap()
+}
+
+#[test]
+fn absolute_manifest_path_from_unrelated_directories() {
+    let workspace = Workspace::new("absolute");
+    let manifest_path = workspace.kernel_manifest();
+    let expected = workspace.root.join("bootloader").join("Cargo.toml");
+    let unrelated = workspace.root.join("unrelated");
+    let root_dir = manifest_path.ancestors().last().unwrap().to_owned();
+    for dir in [&workspace.root, &unrelated, &root_dir, &env::temp_dir()] {
+        let located = in_dir(dir, || locate(&manifest_path));
+        assert_eq!(located, expected, "located from {}", dir.display());
+
+        let metadata = in_dir(dir, || {
+            Metadata::load_with_context(&context(), Some(&manifest_path)).unwrap()
+        });
+        // the environment variable overrides the configuration files
+        if env::var_os("CARGO_TARGET_DIR").is_some() {
+            continue;
+        }
+        assert_eq!(
+            metadata.target_directory(),
+            workspace.root.join("build"),
+            "loaded from {}",
+            dir.display()
+        );
+    }
+}
+
+#[test]
+fn relative_manifest_path_is_resolved_against_working_directory() {
+    let workspace = Workspace::new("relative");
+    let expected = workspace.root.join("bootloader").join("Cargo.toml");
+    let located = in_dir(&workspace.root, || locate(Path::new("kernel/Cargo.toml")));
+    assert_eq!(located, expected);
+    let located = in_dir(&workspace.root.join("kernel"), || locate(Path::new(".")));
+    assert_eq!(located, expected);
+    let located = in_dir(&workspace.root.join("unrelated"), || {
+        locate(Path::new("../kernel"))
+    });
+    assert_eq!(located, expected);
+}